@@ -0,0 +1,374 @@
+use std::sync::Arc;
+
+use async_compression::tokio::write::GzipEncoder;
+use axum::{
+    body::StreamBody,
+    extract::Query,
+    headers::{authorization::Basic, Authorization, Cookie},
+    response::{IntoResponse, Response},
+    Extension, TypedHeader,
+};
+use http_negotiator::{ContentTypeNegotiation, Negotiation};
+use hyper::{
+    header::{HeaderValue, ACCEPT_ENCODING, CONTENT_ENCODING, CONTENT_TYPE, VARY},
+    HeaderMap,
+};
+use itertools::Itertools;
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, SqliteConnection, SqlitePool};
+use tokio::io::{duplex, AsyncWrite, AsyncWriteExt};
+use tokio_util::io::ReaderStream;
+
+use crate::{
+    access::AccessUser,
+    alias::Alias,
+    auth::{Authenticator, ClientCertificate},
+    error::{admin as AdminError, Error},
+    include_query,
+    query::with_timeout,
+    response::{ApiHeader, ApiResponse, ResponseType, SingleLine},
+};
+
+// Downloads listed per page when the request doesn't specify it, and at most.
+const DEFAULT_LIMIT: u32 = 100;
+const MAX_LIMIT: u32 = 1000;
+// Downloads fetched per query by exports, which are streamed.
+const EXPORT_PAGE: u32 = 500;
+const EXPORT_BUFFER_SIZE: usize = 64 * 1024;
+
+#[derive(Deserialize)]
+pub struct HistoryParams {
+    #[serde(default = "default_limit")]
+    limit: u32,
+    #[serde(default)]
+    offset: u32,
+}
+
+fn default_limit() -> u32 {
+    DEFAULT_LIMIT
+}
+
+#[derive(Serialize, FromRow)]
+struct DownloadEntry {
+    downloaded: i64,
+    address: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct DownloadHistory {
+    downloads: Vec<DownloadEntry>,
+    total: i64,
+    limit: u32,
+    offset: u32,
+}
+
+impl ApiHeader for DownloadHistory {}
+
+impl SingleLine for DownloadHistory {
+    fn single_lined(&self) -> String {
+        self.downloads
+            .iter()
+            .map(|d| d.address.as_deref().unwrap_or("unknown"))
+            .join(" ")
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+pub async fn handler(
+    Extension(pool): Extension<SqlitePool>,
+    authenticator: Extension<Arc<Authenticator>>,
+    auth_header: Option<TypedHeader<Authorization<Basic>>>,
+    cookie: Option<TypedHeader<Cookie>>,
+    certificate: Option<Extension<ClientCertificate>>,
+    user: AccessUser,
+    response_type: Negotiation<ContentTypeNegotiation, ResponseType>,
+    alias: Alias,
+    Query(params): Query<HistoryParams>,
+) -> Result<ApiResponse<DownloadHistory>, ApiResponse<Error>> {
+    super::authorize(&authenticator, auth_header, cookie, certificate, &user)
+        .await
+        .map_err(|err| ApiResponse(*response_type, err))?;
+
+    process_history(pool, alias, params)
+        .await
+        .map(|history| ApiResponse(*response_type, history))
+        .map_err(|err| ApiResponse(*response_type, err))
+}
+
+// Most recent downloads first, trashed files included.
+async fn process_history(
+    pool: SqlitePool,
+    alias: Alias,
+    params: HistoryParams,
+) -> Result<DownloadHistory, Error> {
+    let mut conn = with_timeout(pool.acquire())
+        .await?
+        .map_err(|_| AdminError::Database)?;
+    let id = file_id(&mut conn, &alias).await?;
+
+    let limit = params.limit.min(MAX_LIMIT);
+    let downloads = with_timeout(
+        sqlx::query_as::<_, DownloadEntry>(include_query!("get_download_log"))
+            .bind(&id)
+            .bind(limit)
+            .bind(params.offset)
+            .fetch_all(&mut conn),
+    )
+    .await?
+    .map_err(|_| AdminError::Database)?;
+    let (total,) = with_timeout(
+        sqlx::query_as::<_, (i64,)>(include_query!("get_download_log_count"))
+            .bind(&id)
+            .fetch_one(&mut conn),
+    )
+    .await?
+    .map_err(|_| AdminError::Database)?;
+
+    Ok(DownloadHistory {
+        downloads,
+        total,
+        limit,
+        offset: params.offset,
+    })
+}
+
+async fn file_id(conn: &mut SqliteConnection, alias: &Alias) -> Result<String, Error> {
+    let (id,) = with_timeout(
+        sqlx::query_as::<_, (String,)>(include_query!("get_file_id"))
+            .bind(alias.inner())
+            .bind(alias.inner())
+            .fetch_optional(conn),
+    )
+    .await?
+    .map_err(|_| AdminError::Database)?
+    .ok_or(AdminError::FileNotFound)?;
+    Ok(id)
+}
+
+// Whole history of a file as NDJSON, oldest downloads first. The lines are written while the
+// response is sent, gzipped on the fly if the client accepts it.
+#[allow(clippy::too_many_arguments)]
+pub async fn export_handler(
+    Extension(pool): Extension<SqlitePool>,
+    authenticator: Extension<Arc<Authenticator>>,
+    auth_header: Option<TypedHeader<Authorization<Basic>>>,
+    cookie: Option<TypedHeader<Cookie>>,
+    certificate: Option<Extension<ClientCertificate>>,
+    user: AccessUser,
+    alias: Alias,
+    headers: HeaderMap,
+) -> Result<Response, Error> {
+    super::authorize(&authenticator, auth_header, cookie, certificate, &user).await?;
+    let mut conn = with_timeout(pool.acquire())
+        .await?
+        .map_err(|_| AdminError::Database)?;
+    let id = file_id(&mut conn, &alias).await?;
+    drop(conn);
+    Ok(export(pool, id, accepts_gzip(&headers)))
+}
+
+fn accepts_gzip(headers: &HeaderMap) -> bool {
+    headers
+        .get_all(ACCEPT_ENCODING)
+        .iter()
+        .filter_map(|h| h.to_str().ok())
+        .flat_map(|h| h.split(','))
+        .any(|coding| {
+            let mut params = coding.split(';').map(str::trim);
+            params.next() == Some("gzip")
+                && params.all(|param| {
+                    param
+                        .strip_prefix("q=")
+                        .and_then(|q| q.parse::<f32>().ok())
+                        .map_or(true, |q| q > 0.0)
+                })
+        })
+}
+
+fn export(pool: SqlitePool, id: String, gzip: bool) -> Response {
+    let (mut w, r) = duplex(EXPORT_BUFFER_SIZE);
+    tokio::spawn(async move {
+        let written = if gzip {
+            let mut encoder = GzipEncoder::new(w);
+            match write_history(&pool, &id, &mut encoder).await {
+                Ok(()) => encoder.shutdown().await.map_err(|err| err.to_string()),
+                Err(err) => Err(err),
+            }
+        } else {
+            write_history(&pool, &id, &mut w).await
+        };
+        // Also the case of clients closing the connection early.
+        if let Err(err) = written {
+            tracing::warn!("Download log export of file {} interrupted: {}", id, err);
+        }
+    });
+
+    let mut response = StreamBody::new(ReaderStream::new(r)).into_response();
+    let headers = response.headers_mut();
+    headers.insert(
+        CONTENT_TYPE,
+        HeaderValue::from_static("application/x-ndjson"),
+    );
+    headers.insert(VARY, HeaderValue::from_static("accept-encoding"));
+    if gzip {
+        headers.insert(CONTENT_ENCODING, HeaderValue::from_static("gzip"));
+    }
+    response
+}
+
+async fn write_history<W: AsyncWrite + Unpin>(
+    pool: &SqlitePool,
+    id: &str,
+    writer: &mut W,
+) -> Result<(), String> {
+    let mut last = 0;
+    loop {
+        let page = with_timeout(
+            sqlx::query_as::<_, (i64, i64, Option<String>)>(include_query!(
+                "get_download_log_export"
+            ))
+            .bind(id)
+            .bind(last)
+            .bind(EXPORT_PAGE)
+            .fetch_all(pool),
+        )
+        .await
+        .map_err(|err| err.to_string())?
+        .map_err(|err| format!("cannot fetch download log: {:?}", err))?;
+        for (rowid, downloaded, address) in &page {
+            let mut line = serde_json::to_vec(&DownloadEntry {
+                downloaded: *downloaded,
+                address: address.clone(),
+            })
+            .map_err(|err| err.to_string())?;
+            line.push(b'\n');
+            writer
+                .write_all(&line)
+                .await
+                .map_err(|err| err.to_string())?;
+            last = *rowid;
+        }
+        if page.len() < EXPORT_PAGE as usize {
+            return writer.flush().await.map_err(|err| err.to_string());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use async_compression::tokio::bufread::GzipDecoder;
+    use hyper::{
+        header::{ACCEPT_ENCODING, CONTENT_ENCODING},
+        HeaderMap,
+    };
+    use tokio::io::AsyncReadExt;
+
+    use super::{accepts_gzip, export, process_history, HistoryParams};
+    use crate::{alias::Alias, error::Error, include_query, query};
+
+    #[tokio::test]
+    async fn history() {
+        let pool = query::memory_pool().await;
+        sqlx::query(include_query!("insert_file"))
+            .bind("id")
+            .bind("admin")
+            .bind("127.0.0.1")
+            .bind(i64::MAX)
+            .bind("name")
+            .bind(0)
+            .bind("short")
+            .bind("long-alias")
+            .bind(false)
+            .bind(false)
+            .execute(&pool)
+            .await
+            .unwrap();
+        for (downloaded, address) in [(1, Some("127.0.0.2")), (3, None), (2, Some("::1"))] {
+            sqlx::query(include_query!("insert_download_log"))
+                .bind("id")
+                .bind(downloaded)
+                .bind(address)
+                .execute(&pool)
+                .await
+                .unwrap();
+        }
+
+        let history = process_history(
+            pool.clone(),
+            Alias::Long("long-alias".to_owned()),
+            HistoryParams {
+                limit: 2,
+                offset: 0,
+            },
+        )
+        .await
+        .unwrap();
+        assert_eq!(history.total, 3);
+        let downloaded = history
+            .downloads
+            .iter()
+            .map(|d| d.downloaded)
+            .collect::<Vec<_>>();
+        assert_eq!(downloaded, [3, 2]);
+        assert_eq!(history.downloads[1].address.as_deref(), Some("::1"));
+
+        assert!(matches!(
+            process_history(
+                pool,
+                Alias::Long("other-alias".to_owned()),
+                HistoryParams {
+                    limit: 2,
+                    offset: 0,
+                },
+            )
+            .await,
+            Err(Error::FileNotFound)
+        ));
+    }
+
+    #[tokio::test]
+    async fn export_history() {
+        let pool = query::memory_pool().await;
+        for (downloaded, address) in [(1, Some("127.0.0.2")), (3, None), (2, Some("::1"))] {
+            sqlx::query(include_query!("insert_download_log"))
+                .bind("exported")
+                .bind(downloaded)
+                .bind(address)
+                .execute(&pool)
+                .await
+                .unwrap();
+        }
+        let expected = "{\"downloaded\":1,\"address\":\"127.0.0.2\"}\n\
+            {\"downloaded\":3,\"address\":null}\n\
+            {\"downloaded\":2,\"address\":\"::1\"}\n";
+
+        let response = export(pool.clone(), "exported".to_owned(), false);
+        assert!(!response.headers().contains_key(CONTENT_ENCODING));
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        assert_eq!(body, expected);
+
+        let response = export(pool, "exported".to_owned(), true);
+        assert_eq!(response.headers()[CONTENT_ENCODING], "gzip");
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let mut decoded = String::new();
+        GzipDecoder::new(&body[..])
+            .read_to_string(&mut decoded)
+            .await
+            .unwrap();
+        assert_eq!(decoded, expected);
+    }
+
+    #[test]
+    fn gzip_negotiation() {
+        let accepts = |value: &'static str| {
+            let mut headers = HeaderMap::new();
+            headers.insert(ACCEPT_ENCODING, value.parse().unwrap());
+            accepts_gzip(&headers)
+        };
+        assert!(accepts("gzip"));
+        assert!(accepts("br, gzip;q=0.5"));
+        assert!(!accepts("gzip;q=0"));
+        assert!(!accepts("deflate, br"));
+        assert!(!accepts_gzip(&HeaderMap::new()));
+    }
+}
@@ -0,0 +1,204 @@
+use std::sync::Arc;
+
+use axum::{
+    extract::Query,
+    headers::{authorization::Basic, Authorization, Cookie},
+    Extension, TypedHeader,
+};
+use http_negotiator::{ContentTypeNegotiation, Negotiation};
+use itertools::Itertools;
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, SqlitePool};
+
+use crate::{
+    access::AccessUser,
+    auth::{Authenticator, ClientCertificate},
+    error::{admin as AdminError, Error},
+    include_query,
+    query::with_timeout,
+    response::{ApiHeader, ApiResponse, ResponseType, SingleLine},
+};
+
+// Files listed per page when the request doesn't specify it, and at most.
+const DEFAULT_LIMIT: u32 = 100;
+const MAX_LIMIT: u32 = 1000;
+
+#[derive(Deserialize)]
+pub struct ListParams {
+    #[serde(default)]
+    sort: Sort,
+    #[serde(default)]
+    order: Order,
+    #[serde(default = "default_limit")]
+    limit: u32,
+    #[serde(default)]
+    offset: u32,
+}
+
+fn default_limit() -> u32 {
+    DEFAULT_LIMIT
+}
+
+#[derive(Copy, Clone, Debug, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum Sort {
+    #[default]
+    Created,
+    #[serde(alias = "expiration")]
+    Expires,
+    Size,
+    Downloads,
+}
+
+impl Sort {
+    // Only these hardcoded column names are ever inserted in the query.
+    fn column(self) -> &'static str {
+        match self {
+            Sort::Created => "created",
+            Sort::Expires => "expiration",
+            Sort::Size => "size",
+            Sort::Downloads => "downloads",
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum Order {
+    Asc,
+    #[default]
+    Desc,
+}
+
+impl Order {
+    fn keyword(self) -> &'static str {
+        match self {
+            Order::Asc => "ASC",
+            Order::Desc => "DESC",
+        }
+    }
+}
+
+#[derive(Serialize, FromRow)]
+struct FileEntry {
+    short_alias: String,
+    long_alias: String,
+    name: String,
+    size: i64,
+    origin: String,
+    created: Option<i64>,
+    expiration: i64,
+    downloads: Option<i64>,
+}
+
+#[derive(Serialize)]
+pub struct FileList {
+    files: Vec<FileEntry>,
+    total: i64,
+    limit: u32,
+    offset: u32,
+}
+
+impl ApiHeader for FileList {}
+
+impl SingleLine for FileList {
+    fn single_lined(&self) -> String {
+        self.files.iter().map(|f| &f.short_alias).join(" ")
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+pub async fn handler(
+    Extension(pool): Extension<SqlitePool>,
+    authenticator: Extension<Arc<Authenticator>>,
+    auth_header: Option<TypedHeader<Authorization<Basic>>>,
+    cookie: Option<TypedHeader<Cookie>>,
+    certificate: Option<Extension<ClientCertificate>>,
+    user: AccessUser,
+    response_type: Negotiation<ContentTypeNegotiation, ResponseType>,
+    Query(params): Query<ListParams>,
+) -> Result<ApiResponse<FileList>, ApiResponse<Error>> {
+    super::authorize(&authenticator, auth_header, cookie, certificate, &user)
+        .await
+        .map_err(|err| ApiResponse(*response_type, err))?;
+
+    process_list(pool, params)
+        .await
+        .map(|list| ApiResponse(*response_type, list))
+        .map_err(|err| ApiResponse(*response_type, err))
+}
+
+// Only metadata is listed, the content of the files is never read.
+async fn process_list(pool: SqlitePool, params: ListParams) -> Result<FileList, Error> {
+    let mut conn = with_timeout(pool.acquire())
+        .await?
+        .map_err(|_| AdminError::Database)?;
+
+    let limit = params.limit.min(MAX_LIMIT);
+    let query = include_query!("get_files")
+        .replacen("$SORT", params.sort.column(), 1)
+        .replacen("$ORDER", params.order.keyword(), 1);
+    let files = with_timeout(
+        sqlx::query_as::<_, FileEntry>(&query)
+            .bind(limit)
+            .bind(params.offset)
+            .fetch_all(&mut conn),
+    )
+    .await?
+    .map_err(|_| AdminError::Database)?;
+    let (total,) = with_timeout(
+        sqlx::query_as::<_, (i64,)>(include_query!("get_files_count")).fetch_one(&mut conn),
+    )
+    .await?
+    .map_err(|_| AdminError::Database)?;
+
+    Ok(FileList {
+        files,
+        total,
+        limit,
+        offset: params.offset,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{process_list, ListParams, Order, Sort};
+    use crate::{include_query, query};
+
+    #[tokio::test]
+    async fn pages() {
+        let pool = query::memory_pool().await;
+        for (i, size) in [300, 100, 200].into_iter().enumerate() {
+            sqlx::query(include_query!("insert_file"))
+                .bind(i.to_string())
+                .bind("admin")
+                .bind("127.0.0.1")
+                .bind(i64::MAX)
+                .bind("name")
+                .bind(size)
+                .bind(format!("short{}", i))
+                .bind(format!("long-{}", i))
+                .bind(false)
+                .bind(false)
+                .execute(&pool)
+                .await
+                .unwrap();
+        }
+
+        let list = process_list(
+            pool,
+            ListParams {
+                sort: Sort::Size,
+                order: Order::Asc,
+                limit: 2,
+                offset: 1,
+            },
+        )
+        .await
+        .unwrap();
+        assert_eq!(list.total, 3);
+        let sizes = list.files.iter().map(|f| f.size).collect::<Vec<_>>();
+        assert_eq!(sizes, [200, 300]);
+        assert_eq!(list.files[0].origin, "127.0.0.1");
+    }
+}
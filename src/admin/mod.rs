@@ -0,0 +1,57 @@
+use std::sync::Arc;
+
+use axum::{
+    headers::{authorization::Basic, Authorization, Cookie},
+    routing::{get, post},
+    Extension, Router, TypedHeader,
+};
+use sqlx::SqlitePool;
+
+use crate::{
+    access::AccessUser,
+    auth::{AuthStatus, Authenticator, ClientCertificate},
+    error::{auth as AuthError, Error},
+    storage::Dir,
+};
+
+mod downloads;
+mod files;
+mod restore;
+
+// Administration endpoints are only available to the users listed as admins.
+async fn authorize(
+    authenticator: &Authenticator,
+    auth_header: Option<TypedHeader<Authorization<Basic>>>,
+    cookie: Option<TypedHeader<Cookie>>,
+    certificate: Option<Extension<ClientCertificate>>,
+    user: &AccessUser,
+) -> Result<(), Error> {
+    match authenticator
+        .allows_admin(
+            auth_header.map(|h| h.0),
+            cookie.map(|h| h.0),
+            certificate.and_then(|Extension(ClientCertificate(cn))| cn),
+            user,
+        )
+        .await
+    {
+        AuthStatus::Valid(_) => Ok(()),
+        AuthStatus::NotNeeded => Err(AuthError::AccessForbidden),
+        AuthStatus::Error(err) => Err(err),
+        AuthStatus::Prompt => Err(AuthError::MissingAuthorization),
+    }
+}
+
+pub fn router(pool: SqlitePool, authenticator: Arc<Authenticator>, dir: Dir) -> Router {
+    Router::new()
+        .route("/admin/files", get(files::handler))
+        .route("/admin/:alias/restore", post(restore::handler))
+        .route("/admin/:alias/downloads", get(downloads::handler))
+        .route(
+            "/admin/:alias/downloads/export",
+            get(downloads::export_handler),
+        )
+        .route_layer(Extension(pool))
+        .route_layer(Extension(authenticator))
+        .route_layer(Extension(dir))
+}
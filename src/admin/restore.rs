@@ -0,0 +1,76 @@
+use std::sync::Arc;
+
+use axum::{
+    headers::{authorization::Basic, Authorization, Cookie},
+    Extension, TypedHeader,
+};
+use http_negotiator::{ContentTypeNegotiation, Negotiation};
+use sqlx::SqlitePool;
+
+use crate::{
+    access::AccessUser,
+    alias::Alias,
+    auth::{Authenticator, ClientCertificate},
+    error::{admin as AdminError, Error},
+    include_query,
+    query::with_timeout,
+    response::{ApiResponse, ResponseType},
+    storage::{blob, Dir},
+};
+
+#[allow(clippy::too_many_arguments)]
+pub async fn handler(
+    Extension(pool): Extension<SqlitePool>,
+    authenticator: Extension<Arc<Authenticator>>,
+    auth_header: Option<TypedHeader<Authorization<Basic>>>,
+    cookie: Option<TypedHeader<Cookie>>,
+    certificate: Option<Extension<ClientCertificate>>,
+    user: AccessUser,
+    response_type: Negotiation<ContentTypeNegotiation, ResponseType>,
+    alias: Alias,
+    Extension(dir): Extension<Dir>,
+) -> Result<ApiResponse<()>, ApiResponse<Error>> {
+    super::authorize(&authenticator, auth_header, cookie, certificate, &user)
+        .await
+        .map_err(|err| ApiResponse(*response_type, err))?;
+
+    process_restore(pool, alias, dir)
+        .await
+        .map_err(|err| ApiResponse(*response_type, err))?;
+    Ok(ApiResponse(*response_type, ()))
+}
+
+async fn process_restore(pool: SqlitePool, alias: Alias, dir: Dir) -> Result<(), Error> {
+    let mut conn = with_timeout(pool.acquire())
+        .await?
+        .map_err(|_| AdminError::Database)?;
+
+    let (id, origin) = with_timeout(
+        sqlx::query_as::<_, (String, String)>(include_query!("get_file_trashed"))
+            .bind(alias.inner())
+            .bind(alias.inner())
+            .fetch_optional(&mut conn),
+    )
+    .await?
+    .map_err(|_| AdminError::Database)?
+    .ok_or(AdminError::FileNotFound)?;
+
+    // Shared content was left in place when the file was trashed.
+    let content = with_timeout(blob::content(&mut conn, &id))
+        .await?
+        .map_err(|_| AdminError::Database)?
+        .ok_or(AdminError::FileNotFound)?;
+    if !content.is_shared() {
+        dir.restore_file(&origin, &id)
+            .await
+            .map_err(|_| AdminError::RestoreFile)?;
+    }
+    with_timeout(
+        sqlx::query(include_query!("restore_file"))
+            .bind(&id)
+            .execute(&mut conn),
+    )
+    .await?
+    .map_err(|_| AdminError::Database)?;
+    Ok(())
+}
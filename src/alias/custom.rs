@@ -0,0 +1,58 @@
+use lazy_static::lazy_static;
+use regex::Regex;
+
+use crate::alias::short;
+
+// Paths of the other routes, files using them as alias couldn't be downloaded.
+const RESERVED: &[&str] = &[
+    "index.html",
+    "style.css",
+    "app.js",
+    "icon.png",
+    "theme.css",
+    "feed.xml",
+    "metrics",
+    "upload",
+    "quota",
+    "healthz",
+    "readyz",
+    "archive",
+    "auth",
+    "login",
+    "admin",
+    "valid",
+    "thresholds",
+];
+
+lazy_static! {
+    static ref REGEX: Regex = Regex::new("^[a-z0-9][a-z0-9-]{1,62}[a-z0-9]$").unwrap();
+}
+
+// Aliases looking like short ones would be looked up as such.
+pub fn is_match(alias: &str) -> bool {
+    REGEX.is_match(alias) && !short::is_match(alias) && !RESERVED.contains(&alias)
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn policy() {
+        ["report-q3", "boat-surface-soon", "2022-taxes"]
+            .iter()
+            .for_each(|a| assert!(super::is_match(a)));
+
+        [
+            "cv",
+            "Report",
+            "-report",
+            "report-",
+            "report.pdf",
+            "index.html",
+            "metrics",
+            "abcdef",
+        ]
+        .iter()
+        .for_each(|a| assert!(!super::is_match(a)));
+        assert!(!super::is_match(&"a".repeat(65)));
+    }
+}
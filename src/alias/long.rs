@@ -12,41 +12,72 @@ lazy_static! {
             .try_into()
             .unwrap()
     };
-    static ref REGEX: Regex = Regex::new("^[a-z]{3,}(?:-[a-z]{3,}){2}$").unwrap();
 }
 
+pub struct LongFormat {
+    words: usize,
+    regex: Regex,
+}
+
+impl LongFormat {
+    pub fn new(words: usize) -> Self {
+        Self {
+            words,
+            regex: Regex::new(&format!(
+                "^[a-z]{{3,}}(?:-[a-z]{{3,}}){{{}}}$",
+                words.saturating_sub(1)
+            ))
+            .unwrap(),
+        }
+    }
+
+    fn is_match(&self, alias: &str) -> bool {
+        self.regex.is_match(alias)
+    }
+
+    fn random(&self) -> Option<String> {
+        let chosen = WORDS
+            .choose_multiple(&mut thread_rng(), self.words)
+            .copied()
+            .collect::<Vec<_>>();
+
+        if chosen.len() == self.words {
+            Some(chosen.join("-"))
+        } else {
+            None
+        }
+    }
+}
+
+impl Default for LongFormat {
+    fn default() -> Self {
+        Self::new(3)
+    }
+}
+
+// Long aliases of another number of words are still found as custom ones.
 pub fn is_match(alias: &str) -> bool {
-    REGEX.is_match(alias)
+    super::formats().long.is_match(alias)
 }
 
 pub fn random() -> Option<String> {
-    let chosen = WORDS
-        .choose_multiple(&mut thread_rng(), 3)
-        .copied()
-        .collect::<Vec<_>>();
-
-    if chosen.len() == 3 {
-        Some(chosen.join("-"))
-    } else {
-        None
-    }
+    super::formats().long.random()
 }
 
 #[cfg(test)]
 mod tests {
-    fn has_no_repetition(alias: &str) -> bool {
-        let parts = alias.split('-').collect::<Vec<_>>();
-        if parts.len() != 3 {
-            return false;
-        }
-        if parts[0] == parts[1] || parts[0] == parts[2] || parts[1] == parts[2] {
-            return false;
-        }
-        true
+    use super::LongFormat;
+
+    fn has_no_repetition(alias: &str, words: usize) -> bool {
+        let mut parts = alias.split('-').collect::<Vec<_>>();
+        parts.sort_unstable();
+        parts.dedup();
+        parts.len() == words
     }
 
     #[test]
     fn random() {
+        let format = LongFormat::default();
         [
             "boat-surface-soon",
             "way-finish-then",
@@ -54,19 +85,32 @@ mod tests {
             "slow-while-stand",
         ]
         .iter()
-        .for_each(|a| assert!(super::REGEX.is_match(a)));
+        .for_each(|a| assert!(format.is_match(a)));
 
         ["hello", "hello-world", "hi-world-home"]
             .iter()
-            .for_each(|a| assert!(!super::REGEX.is_match(a)));
+            .for_each(|a| assert!(!format.is_match(a)));
 
         for _ in 0..1000 {
-            let alias = super::random();
+            let alias = format.random();
             assert!(alias.is_some());
-            assert!(super::REGEX.is_match(&alias.unwrap()));
-            assert!(super::random()
-                .map(|a| has_no_repetition(&a))
+            assert!(format.is_match(&alias.unwrap()));
+            assert!(format
+                .random()
+                .map(|a| has_no_repetition(&a, 3))
                 .unwrap_or(false));
         }
     }
+
+    #[test]
+    fn configured() {
+        let format = LongFormat::new(5);
+        assert!(format.is_match("boat-surface-soon-way-finish"));
+        assert!(!format.is_match("boat-surface-soon"));
+        for _ in 0..1000 {
+            let alias = format.random().unwrap();
+            assert!(format.is_match(&alias));
+            assert!(has_no_repetition(&alias, 5));
+        }
+    }
 }
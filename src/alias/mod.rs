@@ -1,10 +1,13 @@
-use std::{collections::HashMap, str::FromStr};
+use std::{collections::HashMap, str::FromStr, sync::OnceLock};
 
 use async_trait::async_trait;
 use axum::extract::{FromRequest, Path, RequestParts};
 pub use group::AliasGroup;
 use hyper::Body;
+use long::LongFormat;
 use serde::{de::Error as SerdeError, Deserialize, Deserializer};
+pub use short::ShortAlphabet;
+use short::ShortFormat;
 use sqlx::SqliteConnection;
 
 use crate::{
@@ -13,12 +16,33 @@ use crate::{
     include_query,
 };
 
+mod custom;
 mod group;
 mod long;
 mod short;
 
 const GENERATION_MAX_TENTATIVES: u8 = 20;
 
+static FORMATS: OnceLock<Formats> = OnceLock::new();
+
+// Shape of the random aliases, the defaults are used until configured.
+#[derive(Default)]
+struct Formats {
+    short: ShortFormat,
+    long: LongFormat,
+}
+
+pub fn set_formats(alphabet: ShortAlphabet, short_length: usize, long_words: usize) {
+    let _ = FORMATS.set(Formats {
+        short: ShortFormat::new(alphabet, short_length),
+        long: LongFormat::new(long_words),
+    });
+}
+
+fn formats() -> &'static Formats {
+    FORMATS.get_or_init(Formats::default)
+}
+
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub enum Alias {
     Short(String),
@@ -47,7 +71,7 @@ impl FromStr for Alias {
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         if short::is_match(s) {
             Ok(Short(s.to_owned()))
-        } else if long::is_match(s) {
+        } else if long::is_match(s) || custom::is_match(s) {
             Ok(Long(s.to_owned()))
         } else {
             Err(Error::InvalidAlias)
@@ -104,6 +128,11 @@ where
             return Some(alias);
         }
     }
+    // Too short aliases may run out of unused ones.
+    tracing::warn!(
+        "No unused alias found after {} tentatives, consider longer aliases",
+        GENERATION_MAX_TENTATIVES
+    );
     None
 }
 
@@ -115,6 +144,11 @@ pub async fn random_unused_long(conn: &mut SqliteConnection) -> Option<String> {
     random_unused(conn, long::random, include_query!("exist_alias_long")).await
 }
 
+// Long aliases chosen by uploaders instead of random words.
+pub fn is_valid_custom(alias: &str) -> bool {
+    custom::is_match(alias)
+}
+
 pub async fn random_unused_aliases(conn: &mut SqliteConnection) -> Option<(String, String)> {
     Some((
         random_unused_short(conn).await?,
@@ -1,45 +1,108 @@
-use std::str;
-
+use clap::ValueEnum;
 use lazy_static::lazy_static;
 use rand::{seq::SliceRandom, thread_rng};
 use regex::Regex;
 
 lazy_static! {
-    static ref CHARS: &'static [u8; 55] =
-        b"ABCDEFGHJKLMNPQRSTUVWXYZabcdefghjkmnpqrstuvwxyz23456789";
-    static ref REGEX: Regex =
-        Regex::new(&format!("^[{}]{{6}}$", str::from_utf8(*CHARS).unwrap())).unwrap();
+    // Links shared before the format was changed keep working.
+    static ref LEGACY: ShortFormat = ShortFormat::default();
+}
+
+#[derive(Copy, Clone, Debug, ValueEnum)]
+pub enum ShortAlphabet {
+    // Letters and digits, without the ones looking alike (0/O/o, 1/I/l/i).
+    Unambiguous,
+    Alphanumeric,
+    // Easier to dictate, without the ones looking alike.
+    Lowercase,
+}
+
+impl ShortAlphabet {
+    fn chars(self) -> &'static str {
+        match self {
+            ShortAlphabet::Unambiguous => "ABCDEFGHJKLMNPQRSTUVWXYZabcdefghjkmnpqrstuvwxyz23456789",
+            ShortAlphabet::Alphanumeric => {
+                "ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789"
+            }
+            ShortAlphabet::Lowercase => "abcdefghjkmnpqrstuvwxyz23456789",
+        }
+    }
+}
+
+pub struct ShortFormat {
+    chars: &'static [u8],
+    length: usize,
+    regex: Regex,
+}
+
+impl ShortFormat {
+    pub fn new(alphabet: ShortAlphabet, length: usize) -> Self {
+        Self {
+            chars: alphabet.chars().as_bytes(),
+            length,
+            regex: Regex::new(&format!("^[{}]{{{}}}$", alphabet.chars(), length)).unwrap(),
+        }
+    }
+
+    fn is_match(&self, alias: &str) -> bool {
+        self.regex.is_match(alias)
+    }
+
+    fn random(&self) -> Option<String> {
+        let mut rng = thread_rng();
+        let mut alias = String::with_capacity(self.length);
+        for _ in 0..self.length {
+            alias.push(self.chars.choose(&mut rng).map(|&c| c as char)?);
+        }
+        Some(alias)
+    }
+}
+
+impl Default for ShortFormat {
+    fn default() -> Self {
+        Self::new(ShortAlphabet::Unambiguous, 6)
+    }
 }
 
 pub fn is_match(alias: &str) -> bool {
-    REGEX.is_match(alias)
+    super::formats().short.is_match(alias) || LEGACY.is_match(alias)
 }
 
 pub fn random() -> Option<String> {
-    let mut rng = thread_rng();
-    let mut alias = String::with_capacity(6);
-    for _ in 0..6 {
-        alias.push(CHARS.choose(&mut rng).map(|&c| c as char)?);
-    }
-    Some(alias)
+    super::formats().short.random()
 }
 
 #[cfg(test)]
 mod tests {
+    use super::{ShortAlphabet, ShortFormat};
+
     #[test]
     fn random() {
+        let format = ShortFormat::default();
         ["nXL4fq", "hT8cFn", "bEC9v8", "aBvyRK"]
             .iter()
-            .for_each(|a| assert!(super::REGEX.is_match(a)));
+            .for_each(|a| assert!(format.is_match(a)));
 
         ["AAAAA", "AAAAAAA", "iAAAAA", "0AAAAA"]
             .iter()
-            .for_each(|a| assert!(!super::REGEX.is_match(a)));
+            .for_each(|a| assert!(!format.is_match(a)));
 
         for _ in 0..1000 {
-            let alias = super::random();
+            let alias = format.random();
             assert!(alias.is_some());
-            assert!(super::REGEX.is_match(&alias.unwrap()));
+            assert!(format.is_match(&alias.unwrap()));
+        }
+    }
+
+    #[test]
+    fn configured() {
+        let format = ShortFormat::new(ShortAlphabet::Lowercase, 8);
+        assert!(format.is_match("ab23cdef"));
+        assert!(!format.is_match("Ab23cdef"));
+        assert!(!format.is_match("ab10cdef"));
+        assert!(!format.is_match("ab23cde"));
+        for _ in 0..1000 {
+            assert!(format.is_match(&format.random().unwrap()));
         }
     }
 }
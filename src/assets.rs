@@ -1,6 +1,17 @@
-use axum::{response::IntoResponse, routing::get, Router};
-use hyper::{header, Body, Response, StatusCode, Uri};
+use std::{
+    borrow::Cow,
+    path::{Component, Path, PathBuf},
+};
+
+use axum::{
+    headers::{ETag, HeaderMapExt, IfNoneMatch},
+    response::IntoResponse,
+    routing::get,
+    Extension, Router,
+};
+use hyper::{header, Body, HeaderMap, Response, StatusCode, Uri};
 use rust_embed::RustEmbed;
+use sha2::{Digest, Sha256};
 
 use crate::error::{assets as AssetsError, Error};
 
@@ -9,7 +20,70 @@ use crate::error::{assets as AssetsError, Error};
 #[prefix = "/"]
 struct Assets;
 
-pub async fn handler(uri: Uri) -> Result<impl IntoResponse, Error> {
+// Files that an external UI directory must provide.
+const REQUIRED_FILES: [&str; 3] = ["index.html", "style.css", "app.js"];
+
+// Optional operator-supplied directory overriding the embedded assets.
+#[derive(Clone, Debug)]
+pub struct UiDir(Option<PathBuf>);
+
+impl UiDir {
+    pub fn new(path: Option<PathBuf>) -> Result<Self, String> {
+        if let Some(path) = &path {
+            for file in REQUIRED_FILES {
+                if !path.join(file).is_file() {
+                    return Err(format!(
+                        "UI directory is missing {}",
+                        path.join(file).display()
+                    ));
+                }
+            }
+        }
+        Ok(Self(path))
+    }
+
+    async fn read(&self, path: &str) -> Option<Vec<u8>> {
+        let dir = self.0.as_ref()?;
+        let relative = Path::new(path.trim_start_matches('/'));
+        // Never read outside of the directory.
+        if !relative
+            .components()
+            .all(|c| matches!(c, Component::Normal(_)))
+        {
+            return None;
+        }
+        tokio::fs::read(dir.join(relative)).await.ok()
+    }
+}
+
+struct Asset {
+    mime_type: &'static str,
+    data: Cow<'static, [u8]>,
+    // Strong validator, the hash of the content.
+    etag: String,
+}
+
+fn etag(hash: &[u8]) -> String {
+    let hex = hash
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect::<String>();
+    format!("\"{}\"", hex)
+}
+
+// The browser's copy is still the current one.
+fn is_cached(etag: &str, headers: &HeaderMap) -> bool {
+    match (headers.typed_get::<IfNoneMatch>(), etag.parse::<ETag>()) {
+        (Some(if_none_match), Ok(etag)) => !if_none_match.precondition_passes(&etag),
+        _ => false,
+    }
+}
+
+// The URLs of the assets aren't versioned, browsers keep their copy but revalidate it with its
+// ETag so they get the new assets right after an upgrade.
+const CACHE_CONTROL: &str = "no-cache";
+
+async fn asset(uri: &Uri, ui_dir: &UiDir) -> Result<Asset, Error> {
     let path = uri.path();
     let path = if path.ends_with('/') {
         format!("{}index.html", path)
@@ -25,16 +99,66 @@ pub async fn handler(uri: Uri) -> Result<impl IntoResponse, Error> {
         _ => "text/plain",
     };
 
+    if let Some(data) = ui_dir.read(&path).await {
+        return Ok(Asset {
+            mime_type,
+            etag: etag(&Sha256::digest(&data)),
+            data: Cow::Owned(data),
+        });
+    }
+    // Hashed once, when the assets are embedded.
     let asset = Assets::get(&path).ok_or(AssetsError::AssetNotFound)?;
-    Ok(Response::builder()
+    Ok(Asset {
+        mime_type,
+        etag: etag(&asset.metadata.sha256_hash()),
+        data: asset.data,
+    })
+}
+
+// Embedded pages rendered by other handlers.
+pub fn template(path: &str) -> Option<String> {
+    Assets::get(path).and_then(|asset| String::from_utf8(asset.data.into_owned()).ok())
+}
+
+pub async fn handler(
+    uri: Uri,
+    Extension(ui_dir): Extension<UiDir>,
+    headers: HeaderMap,
+) -> Result<impl IntoResponse, Error> {
+    let asset = asset(&uri, &ui_dir).await?;
+    let cached = is_cached(&asset.etag, &headers);
+    let response = Response::builder()
+        .header(header::ETAG, asset.etag)
+        .header(header::CACHE_CONTROL, CACHE_CONTROL);
+    if cached {
+        return Ok(response
+            .status(StatusCode::NOT_MODIFIED)
+            .body(Body::empty())?);
+    }
+    Ok(response
         .status(StatusCode::OK)
-        .header(header::CONTENT_TYPE, mime_type)
+        .header(header::CONTENT_TYPE, asset.mime_type)
         .body(Body::from(asset.data))?)
 }
 
-pub fn router() -> Router {
-    Router::new()
-        .route("/", get(handler))
+// Same headers as the GET handler, for uptime checkers probing the root.
+pub async fn head_handler(
+    uri: Uri,
+    Extension(ui_dir): Extension<UiDir>,
+) -> Result<impl IntoResponse, Error> {
+    let asset = asset(&uri, &ui_dir).await?;
+    Ok(Response::builder()
+        .status(StatusCode::OK)
+        .header(header::ETAG, asset.etag)
+        .header(header::CONTENT_TYPE, asset.mime_type)
+        .header(header::CONTENT_LENGTH, asset.data.len())
+        .header(header::CACHE_CONTROL, CACHE_CONTROL)
+        .body(Body::empty())?)
+}
+
+pub fn router(ui_dir: UiDir) -> Router {
+    let router = Router::new()
+        .route("/", get(handler).head(head_handler))
         .route("/index.html", get(handler))
         .route("/style.css", get(handler))
         .route("/app.js", get(handler))
@@ -42,5 +166,55 @@ pub fn router() -> Router {
         .route("/login/", get(handler))
         .route("/login/index.html", get(handler))
         .route("/login/style.css", get(handler))
-        .route("/login/app.js", get(handler))
+        .route("/login/app.js", get(handler));
+    // Additional files of an external UI directory aren't known in advance.
+    let router = if ui_dir.0.is_some() {
+        router.fallback(get(handler))
+    } else {
+        router
+    };
+    router.layer(Extension(ui_dir))
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{response::IntoResponse, Extension};
+    use hyper::{header, HeaderMap, StatusCode, Uri};
+    use sha2::{Digest, Sha256};
+
+    use super::{handler, Assets, UiDir};
+
+    #[tokio::test]
+    async fn revalidation() {
+        let get = |headers: HeaderMap| async {
+            handler(
+                Uri::from_static("/style.css"),
+                Extension(UiDir::new(None).unwrap()),
+                headers,
+            )
+            .await
+            .unwrap()
+            .into_response()
+        };
+        let response = get(HeaderMap::new()).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        // Unversioned URLs, the copy of the browser is always revalidated.
+        assert_eq!(response.headers()[header::CACHE_CONTROL], "no-cache");
+        let etag = response.headers()[header::ETAG].clone();
+        assert_eq!(
+            etag,
+            super::etag(&Sha256::digest(Assets::get("/style.css").unwrap().data))
+        );
+
+        let mut headers = HeaderMap::new();
+        headers.insert(header::IF_NONE_MATCH, etag.clone());
+        let response = get(headers).await;
+        assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+        assert_eq!(response.headers()[header::ETAG], etag);
+        assert!(response.headers().contains_key(header::CACHE_CONTROL));
+
+        let mut headers = HeaderMap::new();
+        headers.insert(header::IF_NONE_MATCH, "\"outdated\"".parse().unwrap());
+        assert_eq!(get(headers).await.status(), StatusCode::OK);
+    }
 }
@@ -1,11 +1,12 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 
 use axum::headers::{authorization::Basic, Authorization, Cookie};
 use tokio::sync::RwLock;
 use uuid::Uuid;
 
 use crate::{
-    auth::{Credential, Features, LdapAuthenticator},
+    access::AccessUser,
+    auth::{ldap::LdapFailure, Credential, Features, LdapAuthenticator, LdapFallback},
     error::{auth as AuthError, Error},
 };
 
@@ -26,6 +27,7 @@ pub struct Authenticator {
     protected: Features,
     static_credentials: HashMap<String, String>,
     ldap: Option<LdapAuthenticator>,
+    admins: HashSet<String>,
     sessions: RwLock<HashMap<String, String>>,
 }
 
@@ -34,14 +36,17 @@ impl Authenticator {
         protected: Features,
         credentials: Vec<Credential>,
         ldap: Option<LdapAuthenticator>,
+        admins: Vec<String>,
     ) -> Self {
         Self {
-            protected,
+            // Administration endpoints always require authentication.
+            protected: protected | Features::ADMIN,
             static_credentials: credentials
                 .into_iter()
                 .map(|Credential(u, p)| (u, p))
                 .collect(),
             ldap,
+            admins: admins.into_iter().collect(),
             sessions: Default::default(),
         }
     }
@@ -50,12 +55,35 @@ impl Authenticator {
         &self,
         authorization: Option<Authorization<Basic>>,
         cookie: Option<Cookie>,
+        certificate: Option<String>,
+        feature: Features,
+        user: &AccessUser,
+    ) -> AuthStatus {
+        let status = self
+            .status(authorization, cookie, certificate, feature)
+            .await;
+        if let AuthStatus::Valid(username) = &status {
+            user.record(username);
+        }
+        status
+    }
+
+    async fn status(
+        &self,
+        authorization: Option<Authorization<Basic>>,
+        cookie: Option<Cookie>,
+        certificate: Option<String>,
         feature: Features,
     ) -> AuthStatus {
         if !self.protected.contains(feature) {
             return AuthStatus::NotNeeded;
         }
 
+        // Client certificates were already verified during the TLS handshake.
+        if let Some(username) = certificate {
+            return AuthStatus::Valid(username);
+        }
+
         match self.verify_authorization_header(authorization).await {
             AuthProcess::Valid(username) => return AuthStatus::Valid(username),
             AuthProcess::Continue => (),
@@ -71,6 +99,31 @@ impl Authenticator {
         AuthStatus::Prompt
     }
 
+    pub async fn allows_admin(
+        &self,
+        authorization: Option<Authorization<Basic>>,
+        cookie: Option<Cookie>,
+        certificate: Option<String>,
+        user: &AccessUser,
+    ) -> AuthStatus {
+        match self
+            .allows(authorization, cookie, certificate, Features::ADMIN, user)
+            .await
+        {
+            AuthStatus::Valid(username) if self.admins.contains(&username) => {
+                AuthStatus::Valid(username)
+            }
+            AuthStatus::Valid(_) | AuthStatus::NotNeeded => {
+                AuthStatus::Error(AuthError::AccessForbidden)
+            }
+            status => status,
+        }
+    }
+
+    pub fn is_admin(&self, username: &str) -> bool {
+        self.admins.contains(username)
+    }
+
     async fn verify_authorization_header(
         &self,
         header: Option<Authorization<Basic>>,
@@ -104,26 +157,35 @@ impl Authenticator {
             return AuthProcess::Stop;
         }
 
-        if let Some(p) = self.static_credentials.get(username) {
-            return if password == p {
-                AuthProcess::Valid(username.to_owned())
-            } else {
-                AuthProcess::Stop
-            };
-        }
-
+        // The static credentials are only checked while the LDAP is unreachable, if allowed.
         if let Some(ldap) = &self.ldap {
             return match ldap.is_authorized(username, password).await {
                 Ok(true) => AuthProcess::Valid(username.to_owned()),
                 Ok(false) => AuthProcess::Stop,
-                Err(err) => {
-                    log::error!("Cannot authenticate user using LDAP: {:?}", err);
+                Err(LdapFailure::Unreachable(err))
+                    if ldap.fallback() == LdapFallback::StaticOnly =>
+                {
+                    tracing::warn!(
+                        "LDAP is unreachable, falling back to static credentials: {:?}",
+                        err
+                    );
+                    self.verify_static_credentials(username, password)
+                }
+                Err(LdapFailure::Unreachable(err) | LdapFailure::Failed(err)) => {
+                    tracing::error!("Cannot authenticate user using LDAP: {:?}", err);
                     AuthProcess::Stop
                 }
             };
         }
 
-        AuthProcess::Stop
+        self.verify_static_credentials(username, password)
+    }
+
+    fn verify_static_credentials(&self, username: &str, password: &str) -> AuthProcess {
+        match self.static_credentials.get(username) {
+            Some(p) if password == p => AuthProcess::Valid(username.to_owned()),
+            _ => AuthProcess::Stop,
+        }
     }
 
     pub async fn create_session(&self, username: &str, password: &str) -> Result<String, Error> {
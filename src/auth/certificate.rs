@@ -0,0 +1,20 @@
+use tokio_rustls::rustls::ServerConnection;
+use x509_parser::parse_x509_certificate;
+
+// Subject common name of the certificate presented by the client during the TLS handshake.
+#[derive(Clone, Debug)]
+pub struct ClientCertificate(pub Option<String>);
+
+impl ClientCertificate {
+    pub fn from_connection(conn: &ServerConnection) -> Self {
+        Self(
+            conn.peer_certificates()
+                .and_then(|certs| certs.first())
+                .and_then(|cert| {
+                    let (_, cert) = parse_x509_certificate(&cert.0).ok()?;
+                    let name = cert.subject().iter_common_name().next()?.as_str().ok()?;
+                    Some(name.to_owned())
+                }),
+        )
+    }
+}
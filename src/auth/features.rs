@@ -4,5 +4,6 @@ bitflags! {
     pub struct Features: u8 {
         const UPLOAD = 1 << 0;
         const DOWNLOAD = 1 << 1;
+        const ADMIN = 1 << 2;
     }
 }
@@ -1,18 +1,80 @@
+use std::time::Duration;
+
+use clap::ValueEnum;
 use ldap3::{ldap_escape, Ldap, LdapConnAsync, LdapError, Scope, SearchEntry};
 
+// Behavior when the LDAP is still unreachable after the retries.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum)]
+pub enum LdapFallback {
+    Deny,
+    StaticOnly,
+}
+
+// Only an unreachable LDAP can fall back to the static credentials.
+#[derive(Debug)]
+pub enum LdapFailure {
+    Unreachable(LdapError),
+    Failed(LdapError),
+}
+
+impl From<LdapError> for LdapFailure {
+    fn from(err: LdapError) -> Self {
+        Self::Failed(err)
+    }
+}
+
 pub struct LdapAuthenticator {
     address: String,
     process: LdapAuthProcess,
+    retries: u8,
+    fallback: LdapFallback,
 }
 
 impl LdapAuthenticator {
-    pub fn new(address: String, process: LdapAuthProcess) -> Self {
-        Self { address, process }
+    pub fn new(
+        address: String,
+        process: LdapAuthProcess,
+        retries: u8,
+        fallback: LdapFallback,
+    ) -> Self {
+        Self {
+            address,
+            process,
+            retries,
+            fallback,
+        }
+    }
+
+    pub fn fallback(&self) -> LdapFallback {
+        self.fallback
+    }
+
+    // Only connection failures are retried, the authentication itself is never replayed.
+    async fn connect(&self) -> Result<Ldap, LdapError> {
+        let mut attempt = 0;
+        loop {
+            match LdapConnAsync::new(&self.address).await {
+                Ok((conn, ldap)) => {
+                    ldap3::drive!(conn);
+                    return Ok(ldap);
+                }
+                Err(err) if attempt < self.retries => {
+                    attempt += 1;
+                    tracing::warn!(
+                        "Cannot connect to LDAP (attempt {}/{}): {:?}",
+                        attempt,
+                        self.retries + 1,
+                        err
+                    );
+                    tokio::time::sleep(Duration::from_millis(200) * attempt as u32).await;
+                }
+                Err(err) => return Err(err),
+            }
+        }
     }
 
-    pub async fn is_authorized(&self, username: &str, password: &str) -> Result<bool, LdapError> {
-        let (conn, mut ldap) = LdapConnAsync::new(&self.address).await?;
-        ldap3::drive!(conn);
+    pub async fn is_authorized(&self, username: &str, password: &str) -> Result<bool, LdapFailure> {
+        let mut ldap = self.connect().await.map_err(LdapFailure::Unreachable)?;
 
         let bind_dn = match self.process.resolve_dn(&mut ldap, username).await? {
             None => return Ok(false),
@@ -2,12 +2,14 @@ use std::sync::Arc;
 
 pub use authenticator::{AuthStatus, Authenticator};
 use axum::{routing::get, Extension, Router};
+pub use certificate::ClientCertificate;
 pub use credential::Credential;
 pub use features::Features;
-pub use ldap::{LdapAuthProcess, LdapAuthenticator};
+pub use ldap::{LdapAuthProcess, LdapAuthenticator, LdapFallback};
 pub use origin::Origin;
 
 mod authenticator;
+mod certificate;
 mod credential;
 mod features;
 mod ldap;
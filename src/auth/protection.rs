@@ -4,7 +4,10 @@ use axum::{headers::Cookie, response::IntoResponse, Extension, Json, TypedHeader
 use hyper::StatusCode;
 use serde::Serialize;
 
-use crate::auth::{AuthStatus, Authenticator, Features};
+use crate::{
+    access::AccessUser,
+    auth::{AuthStatus, Authenticator, ClientCertificate, Features},
+};
 
 #[derive(Serialize)]
 struct RequiresAuth {
@@ -14,9 +17,17 @@ struct RequiresAuth {
 pub(super) async fn handler(
     Extension(auth): Extension<Arc<Authenticator>>,
     cookie: Option<TypedHeader<Cookie>>,
+    certificate: Option<Extension<ClientCertificate>>,
+    user: AccessUser,
 ) -> impl IntoResponse {
     let required = match auth
-        .allows(None, cookie.map(|c| c.0), Features::UPLOAD)
+        .allows(
+            None,
+            cookie.map(|c| c.0),
+            certificate.and_then(|Extension(ClientCertificate(cn))| cn),
+            Features::UPLOAD,
+            &user,
+        )
         .await
     {
         AuthStatus::NotNeeded | AuthStatus::Valid(_) => false,
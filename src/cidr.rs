@@ -0,0 +1,130 @@
+use std::{net::IpAddr, sync::Arc};
+
+use axum::{
+    middleware::{self, Next},
+    response::Response,
+    Router,
+};
+use hyper::{Body, Request};
+use ipnet::IpNet;
+
+use crate::{
+    error::{auth as AuthError, Error},
+    upload::RealIp,
+};
+
+// Networks allowed to reach some routes. The most specific range matching an address decides,
+// deny wins over allow for the same range. Without allowed ranges, any other address is allowed.
+#[derive(Clone, Debug, Default)]
+pub struct CidrFilter {
+    allow: Vec<IpNet>,
+    deny: Vec<IpNet>,
+}
+
+impl CidrFilter {
+    pub fn new(allow: Vec<IpNet>, deny: Vec<IpNet>) -> Self {
+        Self { allow, deny }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.allow.is_empty() && self.deny.is_empty()
+    }
+
+    pub fn allows(&self, ip: IpAddr) -> bool {
+        // Dual-stack sockets report IPv4 clients as mapped IPv6 addresses.
+        let ip = match ip {
+            IpAddr::V6(ip) => ip.to_ipv4_mapped().map_or(IpAddr::V6(ip), IpAddr::V4),
+            ip => ip,
+        };
+        let longest = |nets: &[IpNet]| {
+            nets.iter()
+                .filter(|net| net.contains(&ip))
+                .map(IpNet::prefix_len)
+                .max()
+        };
+        match (longest(&self.allow), longest(&self.deny)) {
+            (Some(allow), Some(deny)) => allow > deny,
+            (Some(_), None) => true,
+            (None, Some(_)) => false,
+            (None, None) => self.allow.is_empty(),
+        }
+    }
+}
+
+async fn layer(
+    req: Request<Body>,
+    next: Next<Body>,
+    filter: Arc<CidrFilter>,
+    real_ip: RealIp,
+) -> Result<Response, Error> {
+    let allowed = real_ip
+        .client(req.headers(), req.extensions())
+        .map_or(false, |ip| filter.allows(ip));
+    if !allowed {
+        return Err(AuthError::AddressForbidden);
+    }
+    Ok(next.run(req).await)
+}
+
+// Applies the filter to all the routes of the router, if it filters anything.
+pub fn restrict(router: Router, filter: CidrFilter, real_ip: RealIp) -> Router {
+    if filter.is_empty() {
+        return router;
+    }
+    let filter = Arc::new(filter);
+    router.route_layer(middleware::from_fn(move |req, next| {
+        layer(req, next, Arc::clone(&filter), real_ip.clone())
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::CidrFilter;
+
+    fn filter(allow: &[&str], deny: &[&str]) -> CidrFilter {
+        CidrFilter::new(
+            allow.iter().map(|net| net.parse().unwrap()).collect(),
+            deny.iter().map(|net| net.parse().unwrap()).collect(),
+        )
+    }
+
+    #[test]
+    fn ipv4() {
+        let office = filter(&["192.168.1.0/24"], &[]);
+        assert!(office.allows("192.168.1.20".parse().unwrap()));
+        assert!(!office.allows("192.168.2.20".parse().unwrap()));
+        // Mapped addresses of dual-stack sockets.
+        assert!(office.allows("::ffff:192.168.1.20".parse().unwrap()));
+
+        let blocked = filter(&[], &["10.0.0.0/8"]);
+        assert!(!blocked.allows("10.1.2.3".parse().unwrap()));
+        assert!(blocked.allows("11.1.2.3".parse().unwrap()));
+
+        assert!(CidrFilter::default().allows("10.1.2.3".parse().unwrap()));
+    }
+
+    #[test]
+    fn ipv6() {
+        let office = filter(&["2001:db8::/32"], &["::1/128"]);
+        assert!(office.allows("2001:db8::1".parse().unwrap()));
+        assert!(!office.allows("2001:db9::1".parse().unwrap()));
+        assert!(!office.allows("::1".parse().unwrap()));
+        assert!(!office.allows("192.168.1.20".parse().unwrap()));
+    }
+
+    #[test]
+    fn precedence() {
+        // The most specific range wins.
+        let filter_out = filter(&["10.0.0.0/8"], &["10.1.0.0/16"]);
+        assert!(filter_out.allows("10.2.0.1".parse().unwrap()));
+        assert!(!filter_out.allows("10.1.0.1".parse().unwrap()));
+
+        let filter_in = filter(&["10.1.2.0/24"], &["10.0.0.0/8"]);
+        assert!(filter_in.allows("10.1.2.3".parse().unwrap()));
+        assert!(!filter_in.allows("10.1.3.3".parse().unwrap()));
+
+        // Deny wins for the same range.
+        let same = filter(&["10.0.0.0/8"], &["10.0.0.0/8"]);
+        assert!(!same.allows("10.1.2.3".parse().unwrap()));
+    }
+}
@@ -0,0 +1,128 @@
+use hyper::{
+    body::HttpBody,
+    header::{ACCEPT_RANGES, CONTENT_DISPOSITION, CONTENT_ENCODING, CONTENT_LENGTH, CONTENT_TYPE},
+    Response,
+};
+use tower_http::compression::{predicate::Predicate, CompressionLayer};
+
+// Responses generated by the server (web UI, JSON and text) are compressed when large enough,
+// downloaded files and archives are sent as stored.
+#[derive(Copy, Clone, Debug)]
+pub struct Compressible {
+    min_size: u64,
+}
+
+impl Predicate for Compressible {
+    fn should_compress<B>(&self, response: &Response<B>) -> bool
+    where
+        B: HttpBody,
+    {
+        let headers = response.headers();
+        if [CONTENT_DISPOSITION, ACCEPT_RANGES, CONTENT_ENCODING]
+            .iter()
+            .any(|header| headers.contains_key(header))
+        {
+            return false;
+        }
+        let compressible = headers
+            .get(CONTENT_TYPE)
+            .and_then(|ct| ct.to_str().ok())
+            .map_or(false, is_compressible);
+        // Streamed bodies of unknown size are compressed.
+        let size = response.body().size_hint().exact().or_else(|| {
+            headers
+                .get(CONTENT_LENGTH)
+                .and_then(|cl| cl.to_str().ok())
+                .and_then(|cl| cl.parse().ok())
+        });
+        compressible && size.map_or(true, |size| size >= self.min_size)
+    }
+}
+
+fn is_compressible(content_type: &str) -> bool {
+    let essence = content_type.split(';').next().unwrap_or_default().trim();
+    essence.starts_with("text/")
+        || matches!(
+            essence,
+            "application/javascript" | "application/json" | "application/atom+xml"
+        )
+}
+
+pub fn layer(min_size: u64) -> CompressionLayer<Compressible> {
+    CompressionLayer::new()
+        .gzip(true)
+        .deflate(true)
+        .br(true)
+        .compress_when(Compressible { min_size })
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{routing::get, Router};
+    use hyper::{
+        header::{ACCEPT_ENCODING, CONTENT_DISPOSITION, CONTENT_ENCODING, CONTENT_TYPE, VARY},
+        Body, Request,
+    };
+    use tower::ServiceExt;
+
+    async fn encoding(router: &Router, path: &str) -> Option<String> {
+        let response = router
+            .clone()
+            .oneshot(
+                Request::get(path)
+                    .header(ACCEPT_ENCODING, "gzip")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        let encoding = response
+            .headers()
+            .get(CONTENT_ENCODING)
+            .map(|ce| ce.to_str().unwrap().to_owned());
+        if encoding.is_some() {
+            assert_eq!(response.headers()[VARY], "accept-encoding");
+        }
+        encoding
+    }
+
+    #[tokio::test]
+    async fn compressible() {
+        let json = "[0]".repeat(1000);
+        let router = Router::new()
+            .route(
+                "/json",
+                get({
+                    let json = json.clone();
+                    move || {
+                        let json = json.clone();
+                        async move { ([(CONTENT_TYPE, "application/json")], json) }
+                    }
+                }),
+            )
+            .route(
+                "/tiny",
+                get(|| async { ([(CONTENT_TYPE, "application/json")], "[]") }),
+            )
+            .route(
+                "/download",
+                get(move || {
+                    let json = json.clone();
+                    async move {
+                        (
+                            [
+                                (CONTENT_TYPE, "text/plain"),
+                                (CONTENT_DISPOSITION, "attachment; filename=\"notes.txt\""),
+                            ],
+                            json,
+                        )
+                    }
+                }),
+            )
+            .layer(super::layer(1000));
+
+        assert_eq!(encoding(&router, "/json").await.as_deref(), Some("gzip"));
+        assert_eq!(encoding(&router, "/tiny").await, None);
+        assert_eq!(encoding(&router, "/download").await, None);
+    }
+}
@@ -1,27 +1,152 @@
-use std::collections::HashMap;
+use std::{collections::HashMap, io::Error as IoError, time::SystemTime};
 
+use async_compression::tokio::write::GzipEncoder;
 use axum::{
     body::StreamBody,
     response::{IntoResponse, Response},
 };
+use clap::ValueEnum;
 use hyper::{
-    header::{HeaderValue, CONTENT_DISPOSITION, CONTENT_LENGTH, CONTENT_TYPE},
-    StatusCode,
+    header::{HeaderValue, ACCEPT_RANGES, CONTENT_DISPOSITION, CONTENT_LENGTH, CONTENT_TYPE},
+    HeaderMap, StatusCode,
 };
+use serde::Deserialize;
 use sqlx::SqlitePool;
-use tokio::io::duplex;
+use tokio::io::{duplex, AsyncRead, AsyncWriteExt, DuplexStream};
+use tokio_tar::{Builder, Header};
 use tokio_util::io::ReaderStream;
 use zipit::{archive_size, Archive, FileDateTime};
 
-use crate::{download::FileInfo, error::Error, storage::Dir};
+use crate::{
+    download::{FileInfo, WriteRetries},
+    error::{download as DownloadError, Error},
+    metrics,
+    storage::Dir,
+};
 
-pub(super) async fn handler(
-    pool: SqlitePool,
+// Archives are written to the response through a pipe of this capacity, the writer waits for the
+// client to read them. Each file is read from disk as it's sent, whatever the archive size.
+const BUFFER_SIZE: usize = 64000;
+
+#[derive(Copy, Clone, Debug, ValueEnum, Deserialize)]
+pub enum ArchiveFormat {
+    #[value(name = "zip")]
+    #[serde(rename = "zip")]
+    Zip,
+    #[value(name = "tar")]
+    #[serde(rename = "tar")]
+    Tar,
+    #[value(name = "tar.gz")]
+    #[serde(rename = "tar.gz")]
+    TarGz,
+}
+
+impl ArchiveFormat {
+    fn content_type(self) -> &'static str {
+        match self {
+            ArchiveFormat::Zip => "application/zip",
+            ArchiveFormat::Tar => "application/x-tar",
+            ArchiveFormat::TarGz => "application/gzip",
+        }
+    }
+
+    fn extension(self) -> &'static str {
+        match self {
+            ArchiveFormat::Zip => "zip",
+            ArchiveFormat::Tar => "tar",
+            ArchiveFormat::TarGz => "tar.gz",
+        }
+    }
+}
+
+enum ArchiveWriter {
+    Zip(Archive<DuplexStream>),
+    Tar(Builder<DuplexStream>),
+    TarGz(Builder<GzipEncoder<DuplexStream>>),
+}
+
+impl ArchiveWriter {
+    fn new(format: ArchiveFormat, w: DuplexStream) -> Self {
+        match format {
+            ArchiveFormat::Zip => ArchiveWriter::Zip(Archive::new(w)),
+            ArchiveFormat::Tar => ArchiveWriter::Tar(Builder::new(w)),
+            ArchiveFormat::TarGz => ArchiveWriter::TarGz(Builder::new(GzipEncoder::new(w))),
+        }
+    }
+
+    async fn append<R>(&mut self, name: String, size: u64, reader: &mut R) -> Result<(), IoError>
+    where
+        R: AsyncRead + Unpin + Send,
+    {
+        match self {
+            ArchiveWriter::Zip(archive) => archive
+                .append(name, FileDateTime::now(), reader)
+                .await
+                .map(|_| ()),
+            ArchiveWriter::Tar(builder) => {
+                builder
+                    .append_data(&mut tar_header(size), name, reader)
+                    .await
+            }
+            ArchiveWriter::TarGz(builder) => {
+                builder
+                    .append_data(&mut tar_header(size), name, reader)
+                    .await
+            }
+        }
+    }
+
+    async fn finalize(self) -> Result<(), IoError> {
+        match self {
+            ArchiveWriter::Zip(archive) => archive.finalize().await.map(|_| ()),
+            ArchiveWriter::Tar(builder) => builder.into_inner().await?.shutdown().await,
+            ArchiveWriter::TarGz(builder) => builder.into_inner().await?.shutdown().await,
+        }
+    }
+}
+
+fn tar_header(size: u64) -> Header {
+    let mut header = Header::new_gnu();
+    header.set_size(size);
+    header.set_mode(0o644);
+    header.set_mtime(
+        SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default(),
+    );
+    header
+}
+
+// Headers of the archive only, nothing is read from the disk.
+pub(super) fn head(
     mut files_info: Vec<FileInfo>,
-    dir: Dir,
+    filename: Option<String>,
+    format: ArchiveFormat,
+    max_bytes: Option<u64>,
 ) -> Result<Response, Error> {
+    let mut headers = headers(&mut files_info, filename, format, max_bytes)?;
+    headers.insert(ACCEPT_RANGES, HeaderValue::from_static("none"));
+    Ok((StatusCode::OK, headers).into_response())
+}
+
+// Members sharing a name are renamed, as they are in the archive.
+fn headers(
+    files_info: &mut [FileInfo],
+    filename: Option<String>,
+    format: ArchiveFormat,
+    max_bytes: Option<u64>,
+) -> Result<HeaderMap, Error> {
+    // Checked before anything is streamed, the size of the files is known from their metadata.
+    if let Some(max_bytes) = max_bytes {
+        let total = files_info.iter().map(|f| f.size.max(0) as u64).sum::<u64>();
+        if total > max_bytes {
+            return Err(DownloadError::ArchiveTooLarge(total, max_bytes));
+        }
+    }
+
     let mut name_occurrences = HashMap::new();
-    for info in &mut files_info {
+    for info in files_info.iter_mut() {
         let occurrence = name_occurrences.entry(info.name.clone()).or_insert(0u16);
         *occurrence += 1;
         if *occurrence >= 2 {
@@ -32,58 +157,239 @@ pub(super) async fn handler(
             }
         }
     }
-    let archive_size = archive_size(
-        files_info
-            .iter()
-            .map(|f| (f.name.as_ref(), f.size as usize)),
+
+    let mut headers = HeaderMap::new();
+    // Only the size of zip archives is known in advance, the other ones are sent chunked.
+    if let ArchiveFormat::Zip = format {
+        let archive_size = archive_size(
+            files_info
+                .iter()
+                .map(|f| (f.name.as_ref(), f.size as usize)),
+        );
+        headers.insert(CONTENT_LENGTH, HeaderValue::from(archive_size));
+    }
+    headers.insert(
+        CONTENT_TYPE,
+        HeaderValue::from_static(format.content_type()),
     );
+    headers.insert(
+        CONTENT_DISPOSITION,
+        super::content_disposition(
+            &filename.unwrap_or_else(|| format!("archive.{}", format.extension())),
+            false,
+        )?,
+    );
+    Ok(headers)
+}
 
-    let (w, r) = duplex(64000);
+#[allow(clippy::too_many_arguments)]
+pub(super) async fn handler(
+    pool: SqlitePool,
+    mut files_info: Vec<FileInfo>,
+    filename: Option<String>,
+    format: ArchiveFormat,
+    max_bytes: Option<u64>,
+    dir: Dir,
+    retries: WriteRetries,
+) -> Result<Response, Error> {
+    let headers = headers(&mut files_info, filename, format, max_bytes)?;
+
+    let (w, r) = duplex(BUFFER_SIZE);
     tokio::spawn(async move {
-        let mut archive = Archive::new(w);
+        let mut archive = ArchiveWriter::new(format, w);
+        let mut burnt = Vec::new();
+        let mut complete = true;
         for info in files_info {
-            let mut fd = match dir.open_file(&info.id).await {
-                Ok(fd) => fd,
-                Err(err) => {
-                    log::error!("Failed to open file for archive streaming: {}", err);
-                    break;
-                }
-            };
-            match archive
-                .append(info.name, FileDateTime::now(), &mut fd)
+            let _guard = dir.track(&info.blob_origin, &info.blob_id);
+            let mut fd = match dir
+                .open_reader(
+                    &info.blob_origin,
+                    &info.blob_id,
+                    info.compressed,
+                    info.nonce.as_deref(),
+                )
                 .await
             {
                 Ok(fd) => fd,
                 Err(err) => {
-                    log::error!("Failed to append file to archive: {}", err);
+                    tracing::error!("Failed to open file for archive streaming: {}", err);
+                    complete = false;
                     break;
                 }
+            };
+            if let Err(err) = archive.append(info.name, info.size as u64, &mut fd).await {
+                tracing::error!("Failed to append file to archive: {}", err);
+                complete = false;
+                break;
+            }
+            if !info.repeated {
+                metrics::DOWNLOADS.increment();
+            }
+            if info.burn {
+                burnt.push((info.id, info.requester));
+                continue;
             }
-            match super::file_downloaded(&pool, &dir, &info.id).await {
+            if info.repeated {
+                super::log_download(&pool, &info.id, info.requester.as_deref()).await;
+                continue;
+            }
+            match super::file_downloaded(&pool, &dir, &info.id, info.requester.as_deref(), retries)
+                .await
+            {
                 Ok(_) => (),
                 Err(err) => {
-                    log::error!("Failed to process file downloads counter update: {}", err);
+                    tracing::error!("Failed to process file downloads counter update: {}", err);
+                    complete = false;
                     break;
                 }
             }
         }
         match archive.finalize().await {
             Ok(_) => (),
-            Err(err) => log::error!("Failed to write archive's completion data: {}", err),
+            Err(err) => {
+                tracing::error!("Failed to write archive's completion data: {}", err);
+                complete = false;
+            }
+        }
+        // Burnt files are only deleted once the whole archive is written.
+        if complete {
+            for (id, requester) in burnt {
+                if let Err(err) =
+                    super::file_downloaded(&pool, &dir, &id, requester.as_deref(), retries).await
+                {
+                    tracing::error!("Failed to delete burnt file: {}", err);
+                }
+            }
         }
     });
 
     Ok((
         StatusCode::OK,
-        [
-            (CONTENT_LENGTH, HeaderValue::from(archive_size)),
-            (CONTENT_TYPE, HeaderValue::from_static("application/zip")),
-            (
-                CONTENT_DISPOSITION,
-                HeaderValue::from_static(r#"attachment; filename="archive.zip""#),
-            ),
-        ],
+        headers,
         StreamBody::new(ReaderStream::new(r)),
     )
         .into_response())
 }
+
+#[cfg(test)]
+mod tests {
+    use hyper::{body::HttpBody, header::CONTENT_LENGTH};
+    use uuid::Uuid;
+
+    use super::{handler, ArchiveFormat, BUFFER_SIZE};
+    use crate::{
+        download::{FileInfo, WriteRetries},
+        error::Error,
+        query,
+        storage::Dir,
+    };
+
+    fn info(id: String, size: i64) -> FileInfo {
+        FileInfo {
+            id: id.clone(),
+            name: "large.bin".to_owned(),
+            size,
+            origin: "127.0.0.1".to_owned(),
+            downloads: None,
+            mime: None,
+            compressed: false,
+            burn: false,
+            nonce: None,
+            password: None,
+            hash: None,
+            short_alias: String::new(),
+            long_alias: String::new(),
+            previous_short_alias: None,
+            previous_long_alias: None,
+            blob_origin: "127.0.0.1".to_owned(),
+            blob_id: id,
+            // Not counted, the files aren't in the database.
+            repeated: true,
+            requester: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn streamed() {
+        let path = std::env::temp_dir().join(Uuid::new_v4().as_hyphenated().to_string());
+        let dir = Dir::new(&path, false);
+        dir.create(true).await.unwrap();
+        let mut files_info = Vec::new();
+        for i in 0..4 {
+            let id = format!("file-{}", i);
+            let file = dir.create_file("127.0.0.1", &id).await.unwrap();
+            file.set_len(16_000_000).await.unwrap();
+            files_info.push(info(id, 16_000_000));
+        }
+        let pool = query::memory_pool().await;
+
+        let response = handler(
+            pool,
+            files_info,
+            None,
+            ArchiveFormat::Zip,
+            None,
+            dir,
+            WriteRetries(0),
+        )
+        .await
+        .unwrap();
+        let length = response.headers()[CONTENT_LENGTH]
+            .to_str()
+            .unwrap()
+            .parse::<usize>()
+            .unwrap();
+        assert!(length > 64_000_000);
+        // The archive is never held in memory, only chunks of the pipe's size are sent.
+        let mut body = response.into_body();
+        let mut received = 0;
+        while let Some(chunk) = body.data().await {
+            let chunk = chunk.unwrap();
+            assert!(chunk.len() <= BUFFER_SIZE);
+            received += chunk.len();
+        }
+        assert_eq!(received, length);
+
+        tokio::fs::remove_dir_all(&path).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn max_bytes() {
+        let path = std::env::temp_dir().join(Uuid::new_v4().as_hyphenated().to_string());
+        let dir = Dir::new(&path, false);
+        dir.create(true).await.unwrap();
+        for id in ["first", "second"] {
+            dir.create_file("127.0.0.1", id)
+                .await
+                .unwrap()
+                .set_len(500)
+                .await
+                .unwrap();
+        }
+        let files_info = vec![
+            info("first".to_owned(), 500),
+            info("second".to_owned(), 500),
+        ];
+        let pool = query::memory_pool().await;
+        let archive = |max_bytes| {
+            handler(
+                pool.clone(),
+                files_info.clone(),
+                None,
+                ArchiveFormat::Tar,
+                Some(max_bytes),
+                dir.clone(),
+                WriteRetries(0),
+            )
+        };
+
+        // Exactly at the limit.
+        assert!(archive(1000).await.is_ok());
+        match archive(999).await {
+            Err(Error::ArchiveTooLarge(total, limit)) => assert_eq!((total, limit), (1000, 999)),
+            _ => panic!("archive over the limit accepted"),
+        }
+
+        tokio::fs::remove_dir_all(&path).await.unwrap();
+    }
+}
@@ -5,77 +5,264 @@ use std::{
 
 use axum::{
     body::StreamBody,
+    headers::{ETag, HeaderMapExt, IfNoneMatch},
     response::{IntoResponse, Response},
+    TypedHeader,
 };
 use futures::Stream;
 use hyper::{
-    header::{CONTENT_DISPOSITION, CONTENT_LENGTH, CONTENT_TYPE},
+    header::{
+        ACCEPT_RANGES, CONTENT_DISPOSITION, CONTENT_LENGTH, CONTENT_RANGE, CONTENT_TYPE, RANGE,
+    },
     http::HeaderValue,
-    StatusCode,
+    HeaderMap, StatusCode,
 };
-use percent_encoding::utf8_percent_encode;
 use sqlx::SqlitePool;
-use tokio::fs::File;
+use tokio::io::{AsyncReadExt, AsyncSeekExt, SeekFrom, Take};
 use tokio_util::io::ReaderStream;
 
 use crate::{
-    download::FileInfo,
+    download::{FileInfo, WriteRetries},
     error::{download as DownloadError, Error},
-    storage::Dir,
+    metrics,
+    storage::{Dir, FileReader, StreamGuard},
 };
 
 pub(super) async fn handler(
     pool: SqlitePool,
     info: &FileInfo,
+    headers: &HeaderMap,
+    inline: bool,
     dir: Dir,
+    retries: WriteRetries,
 ) -> Result<Response, Error> {
-    let fd = dir
-        .open_file(&info.id)
+    let etag = etag(info);
+    // The client already has the file, it isn't counted as a download.
+    if let Some(if_none_match) = headers.typed_get::<IfNoneMatch>() {
+        if !if_none_match.precondition_passes(&etag) {
+            return Ok((StatusCode::NOT_MODIFIED, TypedHeader(etag)).into_response());
+        }
+    }
+
+    let seekable = is_seekable(info);
+    if seekable {
+        let range = headers.get(RANGE).and_then(|r| r.to_str().ok());
+        if let Some(range) = range.and_then(|r| parse_range(r, info.size as u64)) {
+            return partial(pool, info, range, inline, dir, etag).await;
+        }
+    }
+
+    let guard = dir.track(&info.blob_origin, &info.blob_id);
+    let reader = dir
+        .open_reader(
+            &info.blob_origin,
+            &info.blob_id,
+            info.compressed,
+            info.nonce.as_deref(),
+        )
         .await
         .map_err(|_| DownloadError::OpenFile)?;
-    let streamer = FileStreamer::new(fd, info, dir, pool);
+    if !info.repeated {
+        metrics::DOWNLOADS.increment();
+    }
+    let streamer = FileStreamer::new(
+        reader.take(info.size as u64),
+        guard,
+        info,
+        (!info.repeated || info.burn).then_some(retries),
+        dir,
+        pool,
+    );
 
     Ok((
         StatusCode::OK,
         [
             (CONTENT_LENGTH, HeaderValue::from(info.size as u64)),
+            (CONTENT_TYPE, content_type(info)),
+            (CONTENT_DISPOSITION, disposition(info, inline)?),
             (
-                CONTENT_TYPE,
-                HeaderValue::from_static("application/octet-stream"),
+                ACCEPT_RANGES,
+                HeaderValue::from_static(if seekable { "bytes" } else { "none" }),
             ),
+        ],
+        TypedHeader(etag),
+        StreamBody::new(streamer),
+    )
+        .into_response())
+}
+
+// Headers of a full download, without opening the file nor counting a download.
+pub(super) fn head(info: &FileInfo, inline: bool) -> Result<Response, Error> {
+    Ok((
+        StatusCode::OK,
+        [
+            (CONTENT_LENGTH, HeaderValue::from(info.size as u64)),
+            (CONTENT_TYPE, content_type(info)),
+            (CONTENT_DISPOSITION, disposition(info, inline)?),
             (
-                CONTENT_DISPOSITION,
-                HeaderValue::try_from(format!(
-                    r#"attachment; filename*=UTF-8''{}; filename="{}""#,
-                    utf8_percent_encode(&info.name, percent_encoding::NON_ALPHANUMERIC),
-                    &info.name
-                ))
-                .map_err(|_| DownloadError::FilenameHeader)?,
+                ACCEPT_RANGES,
+                HeaderValue::from_static(if is_seekable(info) { "bytes" } else { "none" }),
             ),
         ],
+        TypedHeader(etag(info)),
+    )
+        .into_response())
+}
+
+// Strong validator, the content of a file never changes. Files uploaded before their hash was
+// computed fall back to their id and size.
+fn etag(info: &FileInfo) -> ETag {
+    let tag = match &info.hash {
+        Some(hash) => format!("\"{}\"", hash),
+        None => format!("\"{}-{}\"", info.id, info.size),
+    };
+    tag.parse().expect("invalid entity tag")
+}
+
+// Files with a downloads limit or burnt after their download are always fully fetched,
+// so each download is counted. Compressed or encrypted files cannot be seeked.
+fn is_seekable(info: &FileInfo) -> bool {
+    info.downloads.is_none() && !info.burn && !info.compressed && info.nonce.is_none()
+}
+
+// Partial requests never decrement the downloads counter.
+async fn partial(
+    pool: SqlitePool,
+    info: &FileInfo,
+    range: Result<(u64, u64), ()>,
+    inline: bool,
+    dir: Dir,
+    etag: ETag,
+) -> Result<Response, Error> {
+    let (start, end) = match range {
+        Ok(range) => range,
+        Err(_) => {
+            return Ok((
+                StatusCode::RANGE_NOT_SATISFIABLE,
+                [(
+                    CONTENT_RANGE,
+                    HeaderValue::try_from(format!("bytes */{}", info.size))
+                        .map_err(|_| Error::Generic)?,
+                )],
+            )
+                .into_response())
+        }
+    };
+
+    let guard = dir.track(&info.blob_origin, &info.blob_id);
+    let mut fd = dir
+        .open_file(&info.blob_origin, &info.blob_id)
+        .await
+        .map_err(|_| DownloadError::OpenFile)?;
+    fd.seek(SeekFrom::Start(start))
+        .await
+        .map_err(|_| DownloadError::OpenFile)?;
+    let reader: FileReader = Box::new(fd);
+    let streamer = FileStreamer::new(reader.take(end - start + 1), guard, info, None, dir, pool);
+
+    Ok((
+        StatusCode::PARTIAL_CONTENT,
+        [
+            (CONTENT_LENGTH, HeaderValue::from(end - start + 1)),
+            (CONTENT_TYPE, content_type(info)),
+            (CONTENT_DISPOSITION, disposition(info, inline)?),
+            (
+                CONTENT_RANGE,
+                HeaderValue::try_from(format!("bytes {}-{}/{}", start, end, info.size))
+                    .map_err(|_| Error::Generic)?,
+            ),
+            (ACCEPT_RANGES, HeaderValue::from_static("bytes")),
+        ],
+        TypedHeader(etag),
         StreamBody::new(streamer),
     )
         .into_response())
 }
 
+// Only the types browsers display without running anything can be shown inline.
+fn disposition(info: &FileInfo, inline: bool) -> Result<HeaderValue, Error> {
+    let previewable = match info.mime.as_deref() {
+        Some("image/svg+xml") | None => false,
+        Some(mime) => {
+            mime.starts_with("image/")
+                || mime.starts_with("audio/")
+                || mime.starts_with("video/")
+                || mime == "application/pdf"
+                || mime == "text/plain"
+        }
+    };
+    super::content_disposition(&info.name, inline && previewable)
+}
+
+// MIME type detected during the upload, if any.
+fn content_type(info: &FileInfo) -> HeaderValue {
+    info.mime
+        .as_deref()
+        .and_then(|mime| HeaderValue::from_str(mime).ok())
+        .unwrap_or_else(|| HeaderValue::from_static("application/octet-stream"))
+}
+
+// Only single byte ranges are supported, other forms are ignored and the whole file is sent.
+fn parse_range(header: &str, size: u64) -> Option<Result<(u64, u64), ()>> {
+    let (start, end) = header.strip_prefix("bytes=")?.split_once('-')?;
+    let (start, end) = match (start.trim(), end.trim()) {
+        ("", "") => return None,
+        // Suffix range, the last bytes of the file.
+        ("", suffix) => {
+            let suffix = suffix.parse::<u64>().ok()?;
+            if suffix == 0 || size == 0 {
+                return Some(Err(()));
+            }
+            (size.saturating_sub(suffix), size - 1)
+        }
+        (start, "") => (start.parse().ok()?, size.saturating_sub(1)),
+        (start, end) => (
+            start.parse().ok()?,
+            end.parse::<u64>().ok()?.min(size.saturating_sub(1)),
+        ),
+    };
+    if start > end || start >= size {
+        return Some(Err(()));
+    }
+    Some(Ok((start, end)))
+}
+
 struct FileStreamer {
     streamed: usize,
     total: usize,
     decremented: bool,
-    file: ReaderStream<File>,
+    // Repeated downloads are only logged, without counting them.
+    counted: bool,
+    burn: bool,
+    retries: WriteRetries,
+    file: ReaderStream<Take<FileReader>>,
+    _guard: StreamGuard,
     id: String,
+    requester: Option<String>,
     dir: Dir,
     pool: SqlitePool,
 }
 
 impl FileStreamer {
-    fn new(file: File, info: &FileInfo, dir: Dir, pool: SqlitePool) -> Self {
+    fn new(
+        file: Take<FileReader>,
+        guard: StreamGuard,
+        info: &FileInfo,
+        counted: Option<WriteRetries>,
+        dir: Dir,
+        pool: SqlitePool,
+    ) -> Self {
         Self {
             streamed: 0,
             total: info.size as usize,
             decremented: false,
+            counted: counted.is_some(),
+            burn: info.burn,
+            retries: counted.unwrap_or(WriteRetries(0)),
             file: ReaderStream::new(file),
+            _guard: guard,
             id: info.id.clone(),
+            requester: info.requester.clone(),
             dir,
             pool,
         }
@@ -84,28 +271,132 @@ impl FileStreamer {
     fn downloaded(&mut self) {
         self.decremented = true;
         let id = self.id.clone();
+        let requester = self.requester.clone();
         let dir = self.dir.clone();
         let pool = self.pool.clone();
+        let retries = self.retries;
+        let counted = self.counted;
         tokio::spawn(async move {
-            match super::file_downloaded(&pool, &dir, &id).await {
+            if !counted {
+                super::log_download(&pool, &id, requester.as_deref()).await;
+                return;
+            }
+            match super::file_downloaded(&pool, &dir, &id, requester.as_deref(), retries).await {
                 Ok(_) => (),
-                Err(err) => log::error!("Failed to process file downloads counter update: {}", err),
+                Err(err) => {
+                    tracing::error!("Failed to process file downloads counter update: {}", err)
+                }
             }
         });
     }
 }
 
 impl Stream for FileStreamer {
-    type Item = <ReaderStream<File> as Stream>::Item;
+    type Item = <ReaderStream<Take<FileReader>> as Stream>::Item;
 
     fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
         let polled = Pin::new(&mut self.file).poll_next(cx);
-        if let Poll::Ready(Some(Ok(data))) = &polled {
-            self.streamed += data.len();
-            if !self.decremented && self.streamed * 100 / self.total >= 95 {
+        match &polled {
+            Poll::Ready(Some(Ok(data))) => {
+                self.streamed += data.len();
+                // Burnt files are only deleted once fully streamed, an aborted download keeps them.
+                // HTTP/1 bodies aren't polled past their Content-Length, the last chunk is the end.
+                let downloaded = if self.burn {
+                    self.streamed == self.total
+                } else {
+                    self.streamed * 100 / self.total >= 95
+                };
+                if !self.decremented && downloaded {
+                    self.downloaded();
+                }
+            }
+            // Empty files don't have any chunk.
+            Poll::Ready(None) if self.burn && !self.decremented && self.streamed == self.total => {
                 self.downloaded();
             }
+            _ => (),
         }
         polled
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use axum::headers::{HeaderMapExt, IfNoneMatch};
+    use hyper::{
+        header::{ETAG, IF_NONE_MATCH},
+        HeaderMap,
+    };
+
+    use super::{etag, parse_range};
+    use crate::download::FileInfo;
+
+    #[test]
+    fn range() {
+        assert_eq!(parse_range("bytes=0-99", 1000), Some(Ok((0, 99))));
+        assert_eq!(parse_range("bytes=500-", 1000), Some(Ok((500, 999))));
+        assert_eq!(parse_range("bytes=-100", 1000), Some(Ok((900, 999))));
+        assert_eq!(parse_range("bytes=900-2000", 1000), Some(Ok((900, 999))));
+        assert_eq!(parse_range("bytes=-2000", 1000), Some(Ok((0, 999))));
+
+        // Unsatisfiable.
+        assert_eq!(parse_range("bytes=1000-", 1000), Some(Err(())));
+        assert_eq!(parse_range("bytes=50-10", 1000), Some(Err(())));
+        assert_eq!(parse_range("bytes=-0", 1000), Some(Err(())));
+
+        // Unsupported or invalid, the whole file is sent.
+        assert_eq!(parse_range("bytes=0-10,20-30", 1000), None);
+        assert_eq!(parse_range("items=0-10", 1000), None);
+        assert_eq!(parse_range("bytes=-", 1000), None);
+    }
+
+    #[test]
+    fn entity_tag() {
+        let mut info = FileInfo {
+            id: "f0ab36c1-2a2c-4d2b-9d41-8b1a2c7f4e3d".to_owned(),
+            name: "report.pdf".to_owned(),
+            size: 1000,
+            origin: "127.0.0.1".to_owned(),
+            downloads: None,
+            mime: None,
+            compressed: false,
+            burn: false,
+            nonce: None,
+            password: None,
+            hash: None,
+            short_alias: "aaaaaa".to_owned(),
+            long_alias: "boat-surface-soon".to_owned(),
+            previous_short_alias: None,
+            previous_long_alias: None,
+            blob_origin: "127.0.0.1".to_owned(),
+            blob_id: "f0ab36c1-2a2c-4d2b-9d41-8b1a2c7f4e3d".to_owned(),
+            repeated: false,
+            requester: None,
+        };
+        let header = |info: &FileInfo| {
+            let mut headers = HeaderMap::new();
+            headers.typed_insert(etag(info));
+            headers[ETAG].clone()
+        };
+        assert_eq!(
+            header(&info),
+            r#""f0ab36c1-2a2c-4d2b-9d41-8b1a2c7f4e3d-1000""#
+        );
+        info.hash = Some("9f86d081884c7d65".to_owned());
+        assert_eq!(header(&info), r#""9f86d081884c7d65""#);
+
+        let matches = |value: &'static str| {
+            let mut headers = HeaderMap::new();
+            headers.insert(IF_NONE_MATCH, value.parse().unwrap());
+            !headers
+                .typed_get::<IfNoneMatch>()
+                .unwrap()
+                .precondition_passes(&etag(&info))
+        };
+        assert!(matches(r#""9f86d081884c7d65""#));
+        assert!(matches(r#"W/"9f86d081884c7d65""#));
+        assert!(matches(r#""other", "9f86d081884c7d65""#));
+        assert!(matches("*"));
+        assert!(!matches(r#""other""#));
+    }
+}
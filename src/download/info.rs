@@ -0,0 +1,173 @@
+use std::{
+    sync::Arc,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use axum::{
+    headers::{authorization::Basic, Authorization, Cookie},
+    Extension, TypedHeader,
+};
+use http_negotiator::{ContentTypeNegotiation, Negotiation};
+use serde::Serialize;
+use sqlx::{FromRow, SqlitePool};
+
+use crate::{
+    access::AccessUser,
+    alias::Alias,
+    auth::{Authenticator, ClientCertificate},
+    download::{AllowedLookup, DownloadPassword},
+    error::{download as DownloadError, upload as UploadError, Error},
+    include_query,
+    query::with_timeout,
+    response::{ApiHeader, ApiResponse, ResponseType, SingleLine},
+    upload::{ExpirationDate, Size},
+};
+
+#[derive(FromRow)]
+struct FileRow {
+    name: String,
+    size: i64,
+    downloads: Option<i64>,
+    expiration: i64,
+    pinned: bool,
+    mime: Option<String>,
+    password: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct FileMetadata {
+    name: String,
+    size: Size,
+    // Pinned files ignore both their downloads limit and their expiration.
+    downloads_remaining: Option<u64>,
+    expiration: Option<ExpirationDate>,
+    content_type: String,
+    password_protected: bool,
+}
+
+impl ApiHeader for FileMetadata {}
+
+impl SingleLine for FileMetadata {
+    fn single_lined(&self) -> String {
+        format!("{} {}", self.name, self.size.readable)
+    }
+}
+
+// Metadata of a downloadable file, without counting a download. The metadata of protected files
+// would leak without their password.
+#[allow(clippy::too_many_arguments)]
+pub async fn handler(
+    _: AllowedLookup,
+    Extension(pool): Extension<SqlitePool>,
+    authenticator: Extension<Arc<Authenticator>>,
+    auth_header: Option<TypedHeader<Authorization<Basic>>>,
+    cookie: Option<TypedHeader<Cookie>>,
+    certificate: Option<Extension<ClientCertificate>>,
+    user: AccessUser,
+    response_type: Negotiation<ContentTypeNegotiation, ResponseType>,
+    alias: Alias,
+    password: DownloadPassword,
+) -> Result<ApiResponse<FileMetadata>, ApiResponse<Error>> {
+    super::authorize(&authenticator, auth_header, cookie, certificate, &user)
+        .await
+        .map_err(|err| ApiResponse(*response_type, err))?;
+    let (metadata, hash) = fetch_metadata(&pool, &alias)
+        .await
+        .map_err(|err| ApiResponse(*response_type, err))?;
+    password
+        .check_hashes(hash)
+        .await
+        .map_err(|err| ApiResponse(*response_type, err))?;
+    Ok(ApiResponse(*response_type, metadata))
+}
+
+// Metadata of the file, and the hash of its password if it's protected.
+async fn fetch_metadata(
+    pool: &SqlitePool,
+    alias: &Alias,
+) -> Result<(FileMetadata, Option<String>), Error> {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_err(|_| UploadError::TimeCalculation)?
+        .as_secs();
+    let row = with_timeout(
+        sqlx::query_as::<_, FileRow>(include_query!("get_file_info"))
+            .bind(alias.inner())
+            .bind(alias.inner())
+            .bind(now as i64)
+            .fetch_optional(pool),
+    )
+    .await?
+    .map_err(|_| DownloadError::Database)?
+    .ok_or(DownloadError::FileNotFound)?;
+
+    let metadata = FileMetadata {
+        name: row.name,
+        size: Size::from(row.size as u64),
+        downloads_remaining: row
+            .downloads
+            .filter(|_| !row.pinned)
+            .map(|downloads| downloads as u64),
+        expiration: (!row.pinned).then(|| ExpirationDate::from_timestamp(row.expiration as u64)),
+        content_type: row
+            .mime
+            .unwrap_or_else(|| "application/octet-stream".to_owned()),
+        password_protected: row.password.is_some(),
+    };
+    Ok((metadata, row.password))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    use super::fetch_metadata;
+    use crate::{alias::Alias, error::Error, query};
+
+    #[tokio::test]
+    async fn file_metadata() {
+        let pool = query::memory_pool().await;
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs() as i64;
+        sqlx::query(
+            "INSERT INTO files (id, admin, origin, expiration, name, size, short_alias, long_alias, downloads, hash, mime)
+            VALUES ('id', 'admin', '127.0.0.1', ?, 'name', 1000, 'short', 'long', 3, 'hash', 'text/plain')",
+        )
+        .bind(now + 3600)
+        .execute(&pool)
+        .await
+        .unwrap();
+
+        let (metadata, hash) = fetch_metadata(&pool, &Alias::Long("long".to_owned()))
+            .await
+            .unwrap();
+        assert_eq!(metadata.name, "name");
+        assert_eq!(metadata.size.bytes, 1000);
+        assert_eq!(metadata.downloads_remaining, Some(3));
+        assert_eq!(metadata.content_type, "text/plain");
+        assert!(!metadata.password_protected);
+        assert_eq!(hash, None);
+        assert_eq!(
+            metadata.expiration.map(|e| e.timestamp as i64),
+            Some(now + 3600)
+        );
+        // Reading the metadata doesn't count as a download.
+        let (downloads,) = sqlx::query_as::<_, (i64,)>("SELECT downloads FROM files")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(downloads, 3);
+
+        sqlx::query("UPDATE files SET expiration = ?")
+            .bind(now - 1)
+            .execute(&pool)
+            .await
+            .unwrap();
+        assert!(matches!(
+            fetch_metadata(&pool, &Alias::Short("short".to_owned())).await,
+            Err(Error::FileNotFound)
+        ));
+    }
+}
@@ -0,0 +1,47 @@
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use axum::extract::{FromRequest, RequestParts};
+use hyper::Body;
+
+use crate::{
+    error::{download as DownloadError, Error},
+    limit::{Rate, UploadRate},
+    upload::RealIp,
+};
+
+// Metadata lookups allowed from the same client, shared by the sub-resources of the aliases.
+#[derive(Clone)]
+pub struct LookupRate(Arc<Rate>);
+
+impl LookupRate {
+    pub fn new(rate: Option<UploadRate>) -> Self {
+        Self(Arc::new(Rate::new(rate)))
+    }
+}
+
+// Requests under the lookup rate of their client, the others are rejected.
+pub struct AllowedLookup;
+
+#[async_trait]
+impl FromRequest<Body> for AllowedLookup {
+    type Rejection = Error;
+
+    async fn from_request(req: &mut RequestParts<Body>) -> Result<Self, Self::Rejection> {
+        let extensions = req.extensions();
+        // The clients without an address share their lookups.
+        let client = extensions
+            .get::<RealIp>()
+            .ok_or(Error::Generic)?
+            .client(req.headers(), extensions)
+            .map(|address| address.to_string())
+            .unwrap_or_default();
+        extensions
+            .get::<LookupRate>()
+            .ok_or(Error::Generic)?
+            .0
+            .acquire(&client, None)
+            .map_err(DownloadError::LookupRateLimited)?;
+        Ok(Self)
+    }
+}
@@ -1,39 +1,136 @@
-use std::sync::Arc;
+use std::{
+    future::Future,
+    io::ErrorKind,
+    sync::Arc,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
 
 use axum::{
-    extract::Query,
-    headers::{authorization::Basic, Authorization, Cookie, UserAgent},
-    response::IntoResponse,
-    routing::get,
-    Extension, Router, TypedHeader,
+    extract::{rejection::JsonRejection, Query},
+    headers::{authorization::Basic, Authorization, Cookie, HeaderMapExt, UserAgent},
+    middleware,
+    response::{IntoResponse, Response},
+    routing::{get, post},
+    Extension, Json, Router, TypedHeader,
+};
+use hyper::{
+    header::{CONTENT_DISPOSITION, CONTENT_TYPE},
+    http::HeaderValue,
+    HeaderMap,
 };
-use serde::Deserialize;
+use percent_encoding::utf8_percent_encode;
+use sanitize_filename::sanitize;
+use serde::{Deserialize, Deserializer};
 use sqlx::{FromRow, SqlitePool};
 
 use crate::{
-    alias::AliasGroup,
-    auth::{AuthStatus, Authenticator, Features},
+    access::AccessUser,
+    alias::{Alias, AliasGroup},
+    auth::{AuthStatus, Authenticator, ClientCertificate, Features},
     error::{auth as AuthError, download as DownloadError, Error},
     include_query,
-    storage::Dir,
+    query::with_timeout,
+    response::ApiHeader,
+    storage::{
+        blob::{self, Content},
+        Dir,
+    },
+    timing::ServerTiming,
+    upload::RealIp,
+    webhook::{self, Notification, WebhookEvent},
 };
 
 mod archive;
 mod file;
+mod info;
+mod lookup;
 mod open_graph;
+mod password;
+mod qr;
+mod recent;
+mod referer;
+mod throttle;
 
-#[derive(FromRow)]
+pub use archive::ArchiveFormat;
+use lookup::AllowedLookup;
+pub use lookup::LookupRate;
+use password::DownloadPassword;
+pub use password::{hash as hash_password, PasswordAttempts};
+use recent::DownloadClient;
+pub use recent::RecentDownloads;
+use referer::AllowedReferer;
+pub use referer::RefererPolicy;
+pub use throttle::DownloadRate;
+
+#[derive(FromRow, Clone)]
 struct FileInfo {
     id: String,
     name: String,
     size: i64,
+    origin: String,
+    downloads: Option<u16>,
+    mime: Option<String>,
+    compressed: bool,
+    // Deleted after its first complete download.
+    burn: bool,
+    // Set if the file is encrypted at rest.
+    nonce: Option<Vec<u8>>,
+    // Argon2 hash of the password required to download the file, if any.
+    password: Option<String>,
+    // SHA-256 of the uploaded content, missing for files uploaded before it was computed.
+    hash: Option<String>,
+    short_alias: String,
+    long_alias: String,
+    // Aliases replaced by a renewal, kept working until the next one.
+    previous_short_alias: Option<String>,
+    previous_long_alias: Option<String>,
+    // Where the content is stored, it may be shared with identical uploads.
+    blob_origin: String,
+    blob_id: String,
+    // Not counted, the same client already downloaded the file recently.
+    #[sqlx(default)]
+    repeated: bool,
+    // Address of the client, recorded in the download log.
+    #[sqlx(default)]
+    requester: Option<String>,
 }
 
+// Maximum number of files fetched by a single download request, and their maximum total size.
+#[derive(Copy, Clone, Debug)]
+pub struct ArchiveLimit {
+    pub files: usize,
+    pub bytes: Option<u64>,
+}
+
+// Number of times a failed downloads counter write is retried.
+#[derive(Copy, Clone, Debug)]
+pub struct WriteRetries(pub u8);
+
 #[derive(Deserialize)]
 #[serde(rename_all = "kebab-case")]
-pub struct ForceDownload {
+pub struct DownloadParams {
     #[serde(default)]
     force_download: bool,
+    #[serde(default, deserialize_with = "flag")]
+    inline: bool,
+    filename: Option<String>,
+    format: Option<ArchiveFormat>,
+}
+
+// Query flags are set with `1` or `true`.
+fn flag<'de, D: Deserializer<'de>>(deserializer: D) -> Result<bool, D::Error> {
+    Ok(matches!(
+        String::deserialize(deserializer)?.as_str(),
+        "1" | "true"
+    ))
+}
+
+#[derive(Deserialize)]
+pub struct ArchiveRequest {
+    aliases: Vec<String>,
+    filename: Option<String>,
+    format: Option<ArchiveFormat>,
+    password: Option<String>,
 }
 
 #[allow(clippy::too_many_arguments)]
@@ -42,107 +139,1237 @@ pub async fn handler(
     authenticator: Extension<Arc<Authenticator>>,
     auth_header: Option<TypedHeader<Authorization<Basic>>>,
     cookie: Option<TypedHeader<Cookie>>,
+    certificate: Option<Extension<ClientCertificate>>,
+    user: AccessUser,
     AliasGroup(aliases): AliasGroup,
-    force_download: Query<ForceDownload>,
-    user_agent: Option<TypedHeader<UserAgent>>,
+    Query(params): Query<DownloadParams>,
+    headers: HeaderMap,
     Extension(dir): Extension<Dir>,
+    // Extracted together, a handler takes at most 16 extractors.
+    (Extension(limit), Extension(default_format)): (
+        Extension<ArchiveLimit>,
+        Extension<ArchiveFormat>,
+    ),
+    _: AllowedReferer,
+    Extension(retries): Extension<WriteRetries>,
+    client: DownloadClient,
+    timing: ServerTiming,
+    password: DownloadPassword,
 ) -> Result<impl IntoResponse, Error> {
+    let start = Instant::now();
+    let username = authorize(&authenticator, auth_header, cookie, certificate, &user).await?;
+    timing.record("auth", start);
+    let start = Instant::now();
+    let mut files_info = fetch_files(&pool, &dir, &aliases, limit.files).await?;
+    timing.record("db", start);
+    // Checked before the link previews, they would leak the name of the files.
+    password.check(&files_info).await?;
+    let filename = sanitize_override(params.filename)?;
+
+    if !params.force_download {
+        if let Some(user_agent) = headers.typed_get::<UserAgent>() {
+            if let Some(og_resp) =
+                open_graph::proxy_request(user_agent.as_str().to_lowercase(), &files_info)
+            {
+                return Ok(og_resp);
+            }
+        }
+    }
+    client.mark(username.as_deref(), &mut files_info);
+
+    match files_info.len() {
+        0 => Err(DownloadError::AliasExtract),
+        1 => {
+            if let Some(filename) = filename {
+                files_info[0].name = filename;
+            }
+            file::handler(pool, &files_info[0], &headers, params.inline, dir, retries).await
+        }
+        _ => {
+            let format = params.format.unwrap_or(default_format);
+            archive::handler(
+                pool,
+                files_info,
+                filename,
+                format,
+                limit.bytes,
+                dir,
+                retries,
+            )
+            .await
+        }
+    }
+}
+
+// Same as a "+" joined download, without the URL length limit.
+#[allow(clippy::too_many_arguments)]
+pub async fn archive_handler(
+    Extension(pool): Extension<SqlitePool>,
+    authenticator: Extension<Arc<Authenticator>>,
+    auth_header: Option<TypedHeader<Authorization<Basic>>>,
+    cookie: Option<TypedHeader<Cookie>>,
+    certificate: Option<Extension<ClientCertificate>>,
+    user: AccessUser,
+    Extension(dir): Extension<Dir>,
+    _: AllowedReferer,
+    Extension(limit): Extension<ArchiveLimit>,
+    Extension(default_format): Extension<ArchiveFormat>,
+    Extension(retries): Extension<WriteRetries>,
+    client: DownloadClient,
+    password: DownloadPassword,
+    request: Result<Json<ArchiveRequest>, JsonRejection>,
+) -> Result<Response, Error> {
+    let username = authorize(&authenticator, auth_header, cookie, certificate, &user).await?;
+    let Json(request) = request.map_err(|_| DownloadError::InvalidArchiveRequest)?;
+    if request.aliases.is_empty() {
+        return Err(DownloadError::InvalidArchiveRequest);
+    }
+    let aliases = request
+        .aliases
+        .iter()
+        .map(|alias| alias.parse())
+        .collect::<Result<Vec<Alias>, _>>()?;
+    let mut files_info = fetch_files(&pool, &dir, &aliases, limit.files).await?;
+    password.or(request.password).check(&files_info).await?;
+    client.mark(username.as_deref(), &mut files_info);
+    let filename = sanitize_override(request.filename)?;
+
+    let format = request.format.unwrap_or(default_format);
+    archive::handler(
+        pool,
+        files_info,
+        filename,
+        format,
+        limit.bytes,
+        dir,
+        retries,
+    )
+    .await
+}
+
+// Headers of the download only, errors are reduced to their status and headers too.
+#[allow(clippy::too_many_arguments)]
+pub async fn head_handler(
+    Extension(pool): Extension<SqlitePool>,
+    authenticator: Extension<Arc<Authenticator>>,
+    auth_header: Option<TypedHeader<Authorization<Basic>>>,
+    cookie: Option<TypedHeader<Cookie>>,
+    certificate: Option<Extension<ClientCertificate>>,
+    user: AccessUser,
+    AliasGroup(aliases): AliasGroup,
+    Query(params): Query<DownloadParams>,
+    Extension(dir): Extension<Dir>,
+    Extension(limit): Extension<ArchiveLimit>,
+    Extension(default_format): Extension<ArchiveFormat>,
+    referer: Result<AllowedReferer, Error>,
+    password: DownloadPassword,
+) -> Response {
+    let head = async {
+        referer?;
+        authorize(&authenticator, auth_header, cookie, certificate, &user).await?;
+        let mut files_info = fetch_files(&pool, &dir, &aliases, limit.files).await?;
+        password.check(&files_info).await?;
+        let filename = sanitize_override(params.filename)?;
+        match files_info.len() {
+            0 => Err(DownloadError::AliasExtract),
+            1 => {
+                if let Some(filename) = filename {
+                    files_info[0].name = filename;
+                }
+                file::head(&files_info[0], params.inline)
+            }
+            _ => archive::head(
+                files_info,
+                filename,
+                params.format.unwrap_or(default_format),
+                limit.bytes,
+            ),
+        }
+    };
+    head.await
+        .unwrap_or_else(|err: Error| (err.status_code(), err.additional_headers()).into_response())
+}
+
+// Bytes only, without any browser oriented header, for scripts piping the file into another tool.
+#[allow(clippy::too_many_arguments)]
+pub async fn raw_handler(
+    Extension(pool): Extension<SqlitePool>,
+    authenticator: Extension<Arc<Authenticator>>,
+    auth_header: Option<TypedHeader<Authorization<Basic>>>,
+    cookie: Option<TypedHeader<Cookie>>,
+    certificate: Option<Extension<ClientCertificate>>,
+    user: AccessUser,
+    alias: Alias,
+    headers: HeaderMap,
+    Extension(dir): Extension<Dir>,
+    _: AllowedReferer,
+    Extension(retries): Extension<WriteRetries>,
+    client: DownloadClient,
+    password: DownloadPassword,
+) -> Result<Response, Error> {
+    let username = authorize(&authenticator, auth_header, cookie, certificate, &user).await?;
+    let mut files_info = fetch_files(&pool, &dir, &[alias], 1).await?;
+    password.check(&files_info).await?;
+    client.mark(username.as_deref(), &mut files_info);
+    let mut response = file::handler(pool, &files_info[0], &headers, false, dir, retries).await?;
+    let headers = response.headers_mut();
+    headers.remove(CONTENT_DISPOSITION);
+    headers.insert(
+        CONTENT_TYPE,
+        HeaderValue::from_static("application/octet-stream"),
+    );
+    Ok(response)
+}
+
+async fn authorize(
+    authenticator: &Authenticator,
+    auth_header: Option<TypedHeader<Authorization<Basic>>>,
+    cookie: Option<TypedHeader<Cookie>>,
+    certificate: Option<Extension<ClientCertificate>>,
+    user: &AccessUser,
+) -> Result<Option<String>, Error> {
     match authenticator
         .allows(
             auth_header.map(|h| h.0),
             cookie.map(|h| h.0),
+            certificate.and_then(|Extension(ClientCertificate(cn))| cn),
             Features::DOWNLOAD,
+            user,
         )
         .await
     {
-        AuthStatus::NotNeeded | AuthStatus::Valid(_) => (),
-        AuthStatus::Error(err) => return Err(err),
-        AuthStatus::Prompt => {
-            return Err(AuthError::MissingAuthorization);
+        AuthStatus::NotNeeded => Ok(None),
+        AuthStatus::Valid(username) => Ok(Some(username)),
+        AuthStatus::Error(err) => Err(err),
+        AuthStatus::Prompt => Err(AuthError::MissingAuthorization),
+    }
+}
+
+async fn fetch_files(
+    pool: &SqlitePool,
+    dir: &Dir,
+    aliases: &[Alias],
+    max_files: usize,
+) -> Result<Vec<FileInfo>, Error> {
+    if aliases.len() > max_files {
+        return Err(DownloadError::TooManyFiles);
+    }
+
+    // All the files are fetched at once, then ordered as requested.
+    let inners = serde_json::to_string(&aliases.iter().map(Alias::inner).collect::<Vec<_>>())
+        .map_err(|_| Error::Generic)?;
+    let found = with_timeout(
+        sqlx::query_as::<_, FileInfo>(include_query!("get_files_by_aliases"))
+            .bind(&inners)
+            .bind(&inners)
+            .bind(&inners)
+            .bind(&inners)
+            .fetch_all(pool),
+    )
+    .await?
+    .map_err(|_| DownloadError::Database)?;
+    let files_info = aliases
+        .iter()
+        .map(|alias| {
+            found
+                .iter()
+                .find(|info| match alias {
+                    Alias::Short(short) => {
+                        &info.short_alias == short
+                            || info.previous_short_alias.as_ref() == Some(short)
+                    }
+                    Alias::Long(long) => {
+                        &info.long_alias == long || info.previous_long_alias.as_ref() == Some(long)
+                    }
+                })
+                .cloned()
+                .ok_or(DownloadError::FileNotFound)
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    // Cleanup may lag behind a file reaching its downloads limit.
+    let exhausted = files_info
+        .iter()
+        .filter(|info| info.downloads == Some(0))
+        .map(|info| info.id.clone())
+        .collect::<Vec<_>>();
+    if !exhausted.is_empty() {
+        let (pool, dir) = (pool.clone(), dir.clone());
+        tokio::spawn(async move {
+            for id in exhausted {
+                if let Err(err) = delete_exhausted(&pool, &dir, &id).await {
+                    tracing::error!("{}", err);
+                }
+            }
+        });
+        return Err(DownloadError::FileGone);
+    }
+
+    Ok(files_info)
+}
+
+// Sanitize the filename override the same way upload filenames are.
+fn sanitize_override(filename: Option<String>) -> Result<Option<String>, Error> {
+    match filename {
+        Some(filename) => {
+            let filename = sanitize(filename);
+            if filename.is_empty() {
+                return Err(DownloadError::InvalidFilename);
+            }
+            Ok(Some(filename))
+        }
+        None => Ok(None),
+    }
+}
+
+// The quoted filename is an ASCII fallback for the clients ignoring the RFC 5987 encoded one.
+fn content_disposition(filename: &str, inline: bool) -> Result<HeaderValue, Error> {
+    let fallback = filename
+        .chars()
+        .map(|c| match c {
+            '"' | '\\' => '_',
+            c if c.is_ascii() && !c.is_ascii_control() => c,
+            _ => '_',
+        })
+        .collect::<String>();
+    HeaderValue::try_from(format!(
+        r#"{}; filename*=UTF-8''{}; filename="{}""#,
+        if inline { "inline" } else { "attachment" },
+        utf8_percent_encode(filename, percent_encoding::NON_ALPHANUMERIC),
+        fallback
+    ))
+    .map_err(|_| DownloadError::FilenameHeader)
+}
+
+async fn file_downloaded(
+    pool: &SqlitePool,
+    dir: &Dir,
+    id: &str,
+    requester: Option<&str>,
+    retries: WriteRetries,
+) -> Result<(), String> {
+    log_download(pool, id, requester).await;
+    let mut conn = timeout(pool.acquire())
+        .await
+        .map_err(|err| format!("Cannot acquire database connect: {}", err))?;
+    let file = timeout(
+        sqlx::query_as::<_, DownloadedFile>(include_query!("get_file_downloads"))
+            .bind(id)
+            .fetch_optional(&mut conn),
+    )
+    .await
+    .map_err(|err| format!("Cannot fetch downloads count: {}", err))?
+    .ok_or("Cannot find file for downloads count decrement")?;
+    drop(conn);
+    let remaining = count_download(pool, dir, id, &file, retries).await?;
+    webhook::notify(Notification {
+        event: WebhookEvent::Download,
+        short_alias: file.short_alias,
+        long_alias: file.long_alias,
+        name: file.name,
+        size: file.size as u64,
+        origin: file.origin,
+        expiration: None,
+        downloads_remaining: remaining,
+    });
+    Ok(())
+}
+
+// Best-effort, a failure to record the download never fails it.
+async fn log_download(pool: &SqlitePool, id: &str, requester: Option<&str>) {
+    let now = match SystemTime::now().duration_since(UNIX_EPOCH) {
+        Ok(timestamp) => timestamp.as_secs(),
+        Err(err) => {
+            tracing::warn!("Cannot generate timestamp: {}", err);
+            return;
         }
     };
-    let mut conn = pool.acquire().await.map_err(|_| DownloadError::Database)?;
-
-    let mut files_info = Vec::with_capacity(aliases.len());
-    for alias in aliases {
-        files_info.push(
-            sqlx::query_as::<_, FileInfo>(include_query!("get_file"))
-                .bind(alias.inner())
-                .bind(alias.inner())
-                .fetch_optional(&mut conn)
-                .await
-                .map_err(|_| DownloadError::Database)?
-                .ok_or(DownloadError::FileNotFound)?,
-        );
+    if let Err(err) = timeout(
+        sqlx::query(include_query!("insert_download_log"))
+            .bind(id)
+            .bind(now as i64)
+            .bind(requester)
+            .execute(pool),
+    )
+    .await
+    {
+        tracing::warn!("Cannot log download of file with id {}: {}", id, err);
     }
+}
 
-    if !force_download.force_download {
-        if let Some(user_agent) = user_agent {
-            if let Some(og_resp) =
-                open_graph::proxy_request(user_agent.as_str().to_lowercase(), &files_info)
-            {
-                return Ok(og_resp);
+#[derive(FromRow)]
+struct DownloadedFile {
+    downloads: Option<u16>,
+    origin: String,
+    burn: bool,
+    pinned: bool,
+    short_alias: String,
+    long_alias: String,
+    name: String,
+    size: i64,
+}
+
+// Remaining downloads of the file, if limited.
+async fn count_download(
+    pool: &SqlitePool,
+    dir: &Dir,
+    id: &str,
+    file: &DownloadedFile,
+    retries: WriteRetries,
+) -> Result<Option<u64>, String> {
+    // Whatever their downloads limit, burnt files don't survive their first complete download.
+    if file.burn {
+        retry_write(retries, || delete_exhausted(pool, dir, id)).await?;
+        return Ok(Some(0));
+    }
+    // Non-expiring files keep their downloads limit, as they are never deleted.
+    if file.downloads.is_none() || file.pinned {
+        return Ok(file.downloads.map(Into::into));
+    }
+
+    // Decrement atomically, a concurrent download may already have reached zero.
+    let remaining = retry_write(retries, || async move {
+        timeout(
+            sqlx::query_as::<_, (u16,)>(include_query!("decrement_file_downloads"))
+                .bind(id)
+                .fetch_optional(pool),
+        )
+        .await
+        .map_err(|err| format!("Failed to decremented file from database {}: {}", id, err))
+    })
+    .await?;
+    match remaining {
+        // Only the download reaching zero deletes the file.
+        Some((0,)) => {
+            retry_write(retries, || delete_exhausted(pool, dir, id)).await?;
+            Ok(Some(0))
+        }
+        Some((remaining,)) => Ok(Some(remaining.into())),
+        // Already exhausted by a concurrent download, which deletes it.
+        None => Ok(Some(0)),
+    }
+}
+
+// A momentary failure (e.g. a locked database) must neither lose a download nor keep an exhausted file.
+async fn retry_write<T, F, Fut>(
+    WriteRetries(retries): WriteRetries,
+    mut write: F,
+) -> Result<T, String>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, String>>,
+{
+    let retries = u32::from(retries);
+    let mut attempt = 0;
+    loop {
+        match write().await {
+            Ok(value) => return Ok(value),
+            Err(err) if attempt < retries => {
+                attempt += 1;
+                tracing::warn!("{} (attempt {}/{})", err, attempt, retries + 1);
+                tokio::time::sleep(Duration::from_millis(50) * attempt).await;
+            }
+            Err(err) => {
+                return Err(format!(
+                    "{} (giving up after {} attempts)",
+                    err,
+                    attempt + 1
+                ))
             }
         }
     }
+}
 
-    match files_info.len() {
-        0 => Err(DownloadError::AliasExtract),
-        1 => file::handler(pool, &files_info[0], dir).await,
-        _ => archive::handler(pool, files_info, dir).await,
+// The statement timeout applies to the downloads bookkeeping too, it fails like a database error.
+async fn timeout<T>(future: impl Future<Output = Result<T, sqlx::Error>>) -> Result<T, String> {
+    match with_timeout(future).await {
+        Ok(res) => res.map_err(|err| format!("{:?}", err)),
+        Err(err) => Err(err.to_string()),
     }
 }
 
-async fn file_downloaded(pool: &SqlitePool, dir: &Dir, id: &str) -> Result<(), String> {
-    let mut conn = pool
-        .acquire()
-        .await
-        .map_err(|err| format!("Cannot acquire database connect: {:?}", err))?;
-    let (downloads,) = sqlx::query_as::<_, (Option<u16>,)>(include_query!("get_file_downloads"))
-        .bind(id)
-        .fetch_optional(&mut conn)
-        .await
-        .map_err(|err| format!("Cannot fetch downloads count: {:?}", err))?
-        .ok_or("Cannot find file for downloads count decrement")?;
-    match downloads {
-        None => (),
-        Some(0) => return Err(format!("Found a zero downloads counter file: {}", id)),
-        Some(1) => {
-            dir.delete_file(id).await.map_err(|err| {
-                format!(
+// Files sharing their content with identical uploads leave it in place, only the last one deletes it.
+async fn delete_exhausted(pool: &SqlitePool, dir: &Dir, id: &str) -> Result<(), String> {
+    let content = timeout(blob::content(pool, id)).await.map_err(|err| {
+        format!(
+            "Failed to fetch decremented to zero file content from database {}: {}",
+            id, err
+        )
+    })?;
+    if let Some(content) = content.filter(Content::is_last) {
+        match dir.delete_file(&content.origin, &content.id).await {
+            Ok(_) => (),
+            Err(err) if err.kind() == ErrorKind::NotFound => (),
+            Err(err) => {
+                return Err(format!(
                     "Failed to delete decremented to zero file from fs {}: {:?}",
                     id, err
-                )
-            })?;
-            sqlx::query(include_query!("delete_file"))
-                .bind(id)
-                .execute(&mut conn)
-                .await
-                .map_err(|err| {
-                    format!(
-                        "Failed to delete decremented to zero file from database {}: {:?}",
-                        id, err
-                    )
-                })?;
-        }
-        Some(count) => {
-            sqlx::query(include_query!("update_file_downloads"))
-                .bind(count - 1)
-                .bind(id)
-                .execute(&mut conn)
-                .await
-                .map_err(|err| {
-                    format!("Failed to decremented file from database {}: {:?}", id, err)
-                })?;
+                ))
+            }
         }
-    };
+    }
+    timeout(blob::delete_file(pool, id)).await.map_err(|err| {
+        format!(
+            "Failed to delete decremented to zero file from database {}: {}",
+            id, err
+        )
+    })?;
     Ok(())
 }
 
-pub fn router(pool: SqlitePool, authenticator: Arc<Authenticator>, dir: Dir) -> Router {
-    Router::new()
-        .route("/:alias", get(handler))
+#[allow(clippy::too_many_arguments)]
+pub fn router(
+    pool: SqlitePool,
+    authenticator: Arc<Authenticator>,
+    dir: Dir,
+    limit: ArchiveLimit,
+    format: ArchiveFormat,
+    referers: RefererPolicy,
+    retries: WriteRetries,
+    real_ip: RealIp,
+    recent: RecentDownloads,
+    attempts: PasswordAttempts,
+    lookups: LookupRate,
+    DownloadRate(rate): DownloadRate,
+) -> Router {
+    let router = Router::new()
+        .route("/:alias", get(handler).post(handler).head(head_handler))
+        .route("/:alias/raw", get(raw_handler))
+        .route("/:alias/info", get(info::handler))
+        .route("/:alias/qr", get(qr::handler))
+        .route("/archive", post(archive_handler))
         .route_layer(Extension(pool))
         .route_layer(Extension(authenticator))
         .route_layer(Extension(dir))
+        .route_layer(Extension(limit))
+        .route_layer(Extension(format))
+        .route_layer(Extension(referers))
+        .route_layer(Extension(retries))
+        .route_layer(Extension(real_ip))
+        .route_layer(Extension(recent))
+        .route_layer(Extension(attempts))
+        .route_layer(Extension(lookups));
+    // Ranges and archives are throttled too, the whole response body is.
+    match rate {
+        Some(rate) => router.route_layer(middleware::from_fn(move |req, next| {
+            throttle::layer(req, next, rate)
+        })),
+        None => router,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        net::SocketAddr,
+        path::PathBuf,
+        sync::{
+            atomic::{AtomicU8, Ordering},
+            Arc,
+        },
+        time::Duration,
+    };
+
+    use hyper::{HeaderMap, Server};
+    use sqlx::SqlitePool;
+    use tokio::{
+        fs,
+        io::{self, AsyncReadExt, AsyncWriteExt},
+        net::TcpStream,
+    };
+    use uuid::Uuid;
+
+    use super::{
+        content_disposition, count_download, fetch_files, file_downloaded, retry_write,
+        ArchiveFormat, ArchiveLimit, DownloadRate, DownloadedFile, LookupRate, PasswordAttempts,
+        RecentDownloads, RefererPolicy, WriteRetries,
+    };
+    use crate::{
+        alias::Alias,
+        auth::{Authenticator, Features},
+        error::Error,
+        include_query, query,
+        storage::{blob, Dir},
+        upload::RealIp,
+    };
+
+    async fn insert(pool: &SqlitePool, id: &str, short: &str, long: &str) {
+        sqlx::query(include_query!("insert_file"))
+            .bind(id)
+            .bind("admin")
+            .bind("127.0.0.1")
+            .bind(i64::MAX)
+            .bind("name")
+            .bind(0)
+            .bind(short)
+            .bind(long)
+            .bind(false)
+            .bind(false)
+            .execute(pool)
+            .await
+            .unwrap();
+    }
+
+    // Storage directory removed when dropped, even if the test failed.
+    struct Stored {
+        path: PathBuf,
+        dir: Dir,
+    }
+
+    impl Drop for Stored {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.path);
+        }
+    }
+
+    // File "id" with the "short" and "long" aliases, stored in its own directory.
+    async fn store(pool: &SqlitePool, content: &[u8]) -> Stored {
+        let path = std::env::temp_dir().join(Uuid::new_v4().as_hyphenated().to_string());
+        fs::create_dir_all(&path).await.unwrap();
+        let dir = Dir::new(&path, false);
+        let mut file = dir.create_file("127.0.0.1", "id").await.unwrap();
+        file.write_all(content).await.unwrap();
+        file.flush().await.unwrap();
+        insert(pool, "id", "short", "long").await;
+        Stored { path, dir }
+    }
+
+    // Server of the downloads, without authentication.
+    async fn serve(pool: SqlitePool, dir: Dir) -> SocketAddr {
+        serve_with_referers(pool, dir, RefererPolicy::new(Vec::new(), false)).await
+    }
+
+    async fn serve_with_referers(
+        pool: SqlitePool,
+        dir: Dir,
+        referers: RefererPolicy,
+    ) -> SocketAddr {
+        let router = super::router(
+            pool,
+            Arc::new(Authenticator::new(
+                Features::empty(),
+                Vec::new(),
+                None,
+                Vec::new(),
+            )),
+            dir,
+            ArchiveLimit {
+                files: 10,
+                bytes: None,
+            },
+            ArchiveFormat::Zip,
+            referers,
+            WriteRetries(0),
+            RealIp::new(false),
+            RecentDownloads::new(None),
+            PasswordAttempts::new("2/1m".parse().unwrap()),
+            LookupRate::new(Some("5/1m".parse().unwrap())),
+            DownloadRate(None),
+        );
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(
+            Server::from_tcp(listener)
+                .unwrap()
+                .serve(router.into_make_service_with_connect_info::<SocketAddr>()),
+        );
+        address
+    }
+
+    // Sends the request, the response is read until the server closes the connection.
+    async fn send(address: SocketAddr, request: &str) -> String {
+        let mut stream = TcpStream::connect(address).await.unwrap();
+        stream.write_all(request.as_bytes()).await.unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        response
+    }
+
+    #[tokio::test]
+    async fn fetch_order() {
+        let pool = query::memory_pool().await;
+        let dir = Dir::new(std::env::temp_dir(), false);
+        insert(&pool, "first", "aaaaaa", "first-long").await;
+        insert(&pool, "second", "bbbbbb", "second-long").await;
+
+        let aliases = [
+            Alias::Long("second-long".to_owned()),
+            Alias::Short("aaaaaa".to_owned()),
+            Alias::Short("bbbbbb".to_owned()),
+        ];
+        let files = fetch_files(&pool, &dir, &aliases, 3).await.unwrap();
+        assert_eq!(
+            files.iter().map(|f| f.id.as_str()).collect::<Vec<_>>(),
+            ["second", "first", "second"]
+        );
+
+        assert!(matches!(
+            fetch_files(&pool, &dir, &[Alias::Short("cccccc".to_owned())], 3).await,
+            Err(Error::FileNotFound)
+        ));
+        // A single missing member fails the whole archive.
+        assert!(matches!(
+            fetch_files(
+                &pool,
+                &dir,
+                &[
+                    Alias::Short("aaaaaa".to_owned()),
+                    Alias::Short("cccccc".to_owned())
+                ],
+                3
+            )
+            .await,
+            Err(Error::FileNotFound)
+        ));
+        assert!(matches!(
+            fetch_files(&pool, &dir, &aliases, 2).await,
+            Err(Error::TooManyFiles)
+        ));
+    }
+
+    #[tokio::test]
+    async fn kept_aliases() {
+        let pool = query::memory_pool().await;
+        let dir = Dir::new(std::env::temp_dir(), false);
+        insert(&pool, "id", "bbbbbb", "second-long").await;
+        sqlx::query(
+            "UPDATE files SET previous_short_alias = 'aaaaaa', previous_long_alias = 'first-long'",
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+
+        // Renewed with the previous aliases kept, both generations download the file.
+        let aliases = [
+            Alias::Short("aaaaaa".to_owned()),
+            Alias::Long("first-long".to_owned()),
+            Alias::Short("bbbbbb".to_owned()),
+        ];
+        let files = fetch_files(&pool, &dir, &aliases, 3).await.unwrap();
+        assert!(files.iter().all(|f| f.id == "id"));
+        assert!(matches!(
+            fetch_files(&pool, &dir, &[Alias::Short("first-long".to_owned())], 1).await,
+            Err(Error::FileNotFound)
+        ));
+    }
+
+    #[tokio::test]
+    async fn concurrent_last_download() {
+        let pool = query::memory_pool().await;
+        let stored = store(&pool, b"").await;
+        sqlx::query(include_query!("update_file_downloads"))
+            .bind(1)
+            .bind("id")
+            .execute(&pool)
+            .await
+            .unwrap();
+
+        // A concurrent download reached zero first, it is the one deleting the file.
+        sqlx::query(include_query!("update_file_downloads"))
+            .bind(0)
+            .bind("id")
+            .execute(&pool)
+            .await
+            .unwrap();
+        assert!(
+            file_downloaded(&pool, &stored.dir, "id", None, WriteRetries(0))
+                .await
+                .is_ok()
+        );
+        assert!(stored.dir.open_file("127.0.0.1", "id").await.is_ok());
+
+        // The last download deletes the file.
+        sqlx::query(include_query!("update_file_downloads"))
+            .bind(1)
+            .bind("id")
+            .execute(&pool)
+            .await
+            .unwrap();
+        assert!(
+            file_downloaded(&pool, &stored.dir, "id", None, WriteRetries(0))
+                .await
+                .is_ok()
+        );
+
+        let (count,) = sqlx::query_as::<_, (i64,)>("SELECT COUNT(*) FROM files")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(count, 0);
+        assert!(stored.dir.open_file("127.0.0.1", "id").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn shared_content_downloads() {
+        let pool = query::memory_pool().await;
+        let stored = store(&pool, b"").await;
+        insert(&pool, "other", "other-short", "other-long").await;
+        blob::share(&pool, "id", "other").await.unwrap();
+        sqlx::query("UPDATE files SET downloads = 1")
+            .execute(&pool)
+            .await
+            .unwrap();
+
+        // The file storing the content is exhausted first, the identical upload still reads it.
+        file_downloaded(&pool, &stored.dir, "id", None, WriteRetries(0))
+            .await
+            .unwrap();
+        assert!(stored.dir.open_file("127.0.0.1", "id").await.is_ok());
+        let content = blob::content(&pool, "other").await.unwrap().unwrap();
+        assert_eq!((content.id.as_str(), content.is_last()), ("id", true));
+
+        // The last reference deletes the content.
+        file_downloaded(&pool, &stored.dir, "other", None, WriteRetries(0))
+            .await
+            .unwrap();
+        assert!(stored.dir.open_file("127.0.0.1", "id").await.is_err());
+        let (count,) = sqlx::query_as::<_, (i64,)>("SELECT COUNT(*) FROM blobs")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(count, 0);
+    }
+
+    #[tokio::test]
+    async fn parallel_downloads() {
+        let pool = query::memory_pool().await;
+        let stored = store(&pool, b"").await;
+        sqlx::query(include_query!("update_file_downloads"))
+            .bind(3)
+            .bind("id")
+            .execute(&pool)
+            .await
+            .unwrap();
+        let file = sqlx::query_as::<_, DownloadedFile>(include_query!("get_file_downloads"))
+            .bind("id")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+
+        // More downloads than allowed finish at the same time, each one is counted once.
+        let mut remaining = futures::future::join_all(
+            (0..8).map(|_| count_download(&pool, &stored.dir, "id", &file, WriteRetries(3))),
+        )
+        .await
+        .into_iter()
+        .map(|remaining| remaining.unwrap().unwrap())
+        .collect::<Vec<_>>();
+        remaining.sort_unstable();
+        assert_eq!(remaining, [0, 0, 0, 0, 0, 0, 1, 2]);
+
+        let (count,) = sqlx::query_as::<_, (i64,)>("SELECT COUNT(*) FROM files")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(count, 0);
+        assert!(stored.dir.open_file("127.0.0.1", "id").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn pinned_download() {
+        let pool = query::memory_pool().await;
+        let stored = store(&pool, b"").await;
+        sqlx::query("UPDATE files SET downloads = 1, pinned = 1 WHERE id = ?")
+            .bind("id")
+            .execute(&pool)
+            .await
+            .unwrap();
+
+        file_downloaded(&pool, &stored.dir, "id", None, WriteRetries(0))
+            .await
+            .unwrap();
+        assert!(stored.dir.open_file("127.0.0.1", "id").await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn logged_download() {
+        let pool = query::memory_pool().await;
+        let stored = store(&pool, b"").await;
+
+        file_downloaded(&pool, &stored.dir, "id", Some("127.0.0.2"), WriteRetries(0))
+            .await
+            .unwrap();
+        file_downloaded(&pool, &stored.dir, "id", None, WriteRetries(0))
+            .await
+            .unwrap();
+        let log = sqlx::query_as::<_, (i64, Option<String>)>(include_query!("get_download_log"))
+            .bind("id")
+            .bind(10)
+            .bind(0)
+            .fetch_all(&pool)
+            .await
+            .unwrap();
+        assert_eq!(log.len(), 2);
+        assert_eq!(log[0].1, None);
+        assert_eq!(log[1].1.as_deref(), Some("127.0.0.2"));
+
+        // A broken log doesn't fail the download.
+        sqlx::query("DROP TABLE download_log")
+            .execute(&pool)
+            .await
+            .unwrap();
+        file_downloaded(&pool, &stored.dir, "id", Some("127.0.0.2"), WriteRetries(0))
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn logged_repeated_download() {
+        let pool = query::memory_pool().await;
+        let stored = store(&pool, b"content").await;
+        sqlx::query("UPDATE files SET size = 7, downloads = 5 WHERE id = ?")
+            .bind("id")
+            .execute(&pool)
+            .await
+            .unwrap();
+
+        let mut files_info =
+            fetch_files(&pool, &stored.dir, &[Alias::Short("short".to_owned())], 1)
+                .await
+                .unwrap();
+        files_info[0].repeated = true;
+        files_info[0].requester = Some("127.0.0.2".to_owned());
+        let response = super::file::handler(
+            pool.clone(),
+            &files_info[0],
+            &HeaderMap::new(),
+            false,
+            stored.dir.clone(),
+            WriteRetries(0),
+        )
+        .await
+        .unwrap();
+        hyper::body::to_bytes(response.into_body()).await.unwrap();
+
+        // Logged in the background, without counting the download.
+        let mut log = Vec::new();
+        for _ in 0..100 {
+            log = sqlx::query_as::<_, (i64, Option<String>)>(include_query!("get_download_log"))
+                .bind("id")
+                .bind(10)
+                .bind(0)
+                .fetch_all(&pool)
+                .await
+                .unwrap();
+            if !log.is_empty() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert_eq!(log.len(), 1);
+        assert_eq!(log[0].1.as_deref(), Some("127.0.0.2"));
+        let downloads = sqlx::query_as::<_, (i64,)>("SELECT downloads FROM files WHERE id = ?")
+            .bind("id")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(downloads.0, 5);
+    }
+
+    #[tokio::test]
+    async fn burnt_after_download() {
+        let pool = query::memory_pool().await;
+        let stored = store(&pool, b"").await;
+        // Still has downloads left, but is burnt.
+        sqlx::query("UPDATE files SET downloads = 5, burn = 1 WHERE id = ?")
+            .bind("id")
+            .execute(&pool)
+            .await
+            .unwrap();
+
+        file_downloaded(&pool, &stored.dir, "id", None, WriteRetries(0))
+            .await
+            .unwrap();
+        let (count,) = sqlx::query_as::<_, (i64,)>("SELECT COUNT(*) FROM files")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(count, 0);
+        assert!(stored.dir.open_file("127.0.0.1", "id").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn burnt_over_http() {
+        let pool = query::memory_pool().await;
+        // Larger than the socket buffers, the downloads cannot complete before being read.
+        const SIZE: usize = 32 * 1024 * 1024;
+        let stored = store(&pool, &vec![0; SIZE]).await;
+        sqlx::query("UPDATE files SET size = ?, burn = 1 WHERE id = ?")
+            .bind(SIZE as i64)
+            .bind("id")
+            .execute(&pool)
+            .await
+            .unwrap();
+        let address = serve(pool, stored.dir.clone()).await;
+
+        // Started before the file is burnt, all the downloads get the whole file.
+        let mut downloads = Vec::new();
+        for _ in 0..3 {
+            let mut stream = TcpStream::connect(address).await.unwrap();
+            stream
+                .write_all(
+                    b"GET /short HTTP/1.1\r\n\
+                    Host: localhost\r\n\
+                    Connection: close\r\n\r\n",
+                )
+                .await
+                .unwrap();
+            let mut status = [0; 12];
+            stream.read_exact(&mut status).await.unwrap();
+            assert_eq!(&status, b"HTTP/1.1 200");
+            downloads.push(stream);
+        }
+        let received = futures::future::join_all(
+            downloads
+                .iter_mut()
+                .map(|stream| async move { io::copy(stream, &mut io::sink()).await }),
+        )
+        .await;
+        for received in received {
+            // The body follows the rest of the headers.
+            assert!(received.unwrap() > SIZE as u64);
+        }
+
+        // Deleted in the background, once the last transfer completed.
+        let mut response = String::new();
+        for _ in 0..100 {
+            response = send(
+                address,
+                "HEAD /short HTTP/1.1\r\n\
+                Host: localhost\r\n\
+                Connection: close\r\n\r\n",
+            )
+            .await;
+            if !response.starts_with("HTTP/1.1 200")
+                && stored.dir.open_file("127.0.0.1", "id").await.is_err()
+            {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert!(response.starts_with("HTTP/1.1 404"));
+        assert!(stored.dir.open_file("127.0.0.1", "id").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn head_requests() {
+        let pool = query::memory_pool().await;
+        let dir = Dir::new(std::env::temp_dir(), false);
+        insert(&pool, "id", "short", "long").await;
+        insert(&pool, "other", "other", "other-long").await;
+        sqlx::query("UPDATE files SET size = 7, downloads = 5")
+            .execute(&pool)
+            .await
+            .unwrap();
+        let address = serve(pool.clone(), dir).await;
+        let head = |path: &str| {
+            format!(
+                "HEAD {path} HTTP/1.1\r\n\
+                Host: localhost\r\n\
+                Connection: close\r\n\r\n"
+            )
+        };
+
+        let response = send(address, &head("/short")).await;
+        assert!(response.starts_with("HTTP/1.1 200"));
+        assert!(response.contains("\r\ncontent-length: 7\r\n"));
+        // Fully fetched to be counted.
+        assert!(response.contains("\r\naccept-ranges: none\r\n"));
+        assert!(response.ends_with("\r\n\r\n"));
+
+        // Archives of "+" joined aliases.
+        let response = send(address, &head("/short+other")).await;
+        assert!(response.starts_with("HTTP/1.1 200"));
+        assert!(response.contains("\r\ncontent-type: application/zip\r\n"));
+        assert!(response.contains(r#"filename="archive.zip""#));
+        assert!(response.ends_with("\r\n\r\n"));
+        let response = send(address, &head("/short+other?format=tar")).await;
+        assert!(response.contains("\r\ncontent-type: application/x-tar\r\n"));
+        let response = send(address, &head("/short+missing")).await;
+        assert!(response.starts_with("HTTP/1.1 404"));
+        assert!(response.ends_with("\r\n\r\n"));
+
+        let (downloads,) = sqlx::query_as::<_, (i64,)>("SELECT SUM(downloads) FROM files")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(downloads, 10);
+    }
+
+    #[tokio::test]
+    async fn raw_download() {
+        let pool = query::memory_pool().await;
+        let stored = store(&pool, b"content").await;
+        sqlx::query("UPDATE files SET size = 7, downloads = 2")
+            .execute(&pool)
+            .await
+            .unwrap();
+        let address = serve(pool.clone(), stored.dir.clone()).await;
+
+        let response = send(
+            address,
+            "GET /short/raw HTTP/1.1\r\n\
+            Host: localhost\r\n\
+            Connection: close\r\n\r\n",
+        )
+        .await;
+        assert!(response.starts_with("HTTP/1.1 200"));
+        assert!(response.contains("\r\ncontent-type: application/octet-stream\r\n"));
+        assert!(!response.contains("content-disposition"));
+        assert!(response.ends_with("\r\n\r\ncontent"));
+
+        // Counted like any other download, once the transfer completed.
+        let mut downloads = 0;
+        for _ in 0..100 {
+            (downloads,) = sqlx::query_as::<_, (i64,)>("SELECT downloads FROM files")
+                .fetch_one(&pool)
+                .await
+                .unwrap();
+            if downloads == 1 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert_eq!(downloads, 1);
+    }
+
+    #[tokio::test]
+    async fn archive_referer() {
+        let pool = query::memory_pool().await;
+        let stored = store(&pool, b"").await;
+        let address = serve_with_referers(
+            pool,
+            stored.dir.clone(),
+            RefererPolicy::new(vec!["allowed.example.com".to_owned()], false),
+        )
+        .await;
+        let request = |referer: &str| {
+            let body = r#"{"aliases":["short"]}"#;
+            format!(
+                "POST /archive HTTP/1.1\r\n\
+                Host: localhost\r\n\
+                Referer: {referer}\r\n\
+                Content-Type: application/json\r\n\
+                Content-Length: {}\r\n\
+                Connection: close\r\n\r\n{body}",
+                body.len()
+            )
+        };
+
+        // Archives are hotlinked like "+" joined downloads.
+        let response = send(address, &request("https://other.example.com/")).await;
+        assert!(response.starts_with("HTTP/1.1 403"));
+        assert!(response.contains("referer_forbidden"));
+        let response = send(address, &request("https://allowed.example.com/")).await;
+        assert!(response.starts_with("HTTP/1.1 200"));
+    }
+
+    #[tokio::test]
+    async fn password_over_http() {
+        let pool = query::memory_pool().await;
+        let dir = Dir::new(std::env::temp_dir(), false);
+        insert(&pool, "id", "short", "long").await;
+        sqlx::query("UPDATE files SET password = ? WHERE id = ?")
+            .bind(super::hash_password("secret".to_owned()).await.unwrap())
+            .bind("id")
+            .execute(&pool)
+            .await
+            .unwrap();
+        let address = serve(pool, dir).await;
+        let request = |path: &str, password: Option<&str>| {
+            format!(
+                "GET {path} HTTP/1.1\r\n\
+                Host: localhost\r\n\
+                {}\
+                Connection: close\r\n\r\n",
+                password.map_or(String::new(), |password| format!(
+                    "X-Download-Password: {password}\r\n"
+                ))
+            )
+        };
+
+        let response = send(address, &request("/short", None)).await;
+        assert!(response.starts_with("HTTP/1.1 401"));
+        assert!(response.contains("invalid_download_password"));
+        // The info of the file is protected as well.
+        let response = send(address, &request("/short/info", None)).await;
+        assert!(response.starts_with("HTTP/1.1 401"));
+        let response = send(address, &request("/short/info", Some("secret"))).await;
+        assert!(response.starts_with("HTTP/1.1 200"));
+        let response = send(address, &request("/short", Some("wrong"))).await;
+        assert!(response.starts_with("HTTP/1.1 401"));
+
+        // Only two attempts per minute, even a valid password is refused.
+        let response = send(address, &request("/short/info", Some("secret"))).await;
+        assert!(response.starts_with("HTTP/1.1 429"));
+        assert!(response.contains("password_attempts_exceeded"));
+        assert!(response.to_lowercase().contains("retry-after:"));
+    }
+
+    #[tokio::test]
+    async fn lookup_rate() {
+        let pool = query::memory_pool().await;
+        let dir = Dir::new(std::env::temp_dir(), false);
+        insert(&pool, "id", "short", "long").await;
+        let address = serve(pool, dir).await;
+        let request = |path: &str| {
+            format!(
+                "GET {path} HTTP/1.1\r\n\
+                Host: localhost\r\n\
+                Connection: close\r\n\r\n"
+            )
+        };
+
+        // The sub-resources share the five lookups per minute, missing files included.
+        for path in ["/short/info", "/long/info", "/short/qr", "/missing/info"] {
+            assert!(!send(address, &request(path))
+                .await
+                .starts_with("HTTP/1.1 429"));
+        }
+        assert!(send(address, &request("/short/info"))
+            .await
+            .starts_with("HTTP/1.1 200"));
+        let response = send(address, &request("/short/qr")).await;
+        assert!(response.starts_with("HTTP/1.1 429"));
+        assert!(response.contains("lookup_rate_limited"));
+        assert!(response.to_lowercase().contains("retry-after: 12"));
+        let response = send(address, &request("/long/info")).await;
+        assert!(response.starts_with("HTTP/1.1 429"));
+
+        // Downloads aren't lookups.
+        assert!(send(address, &request("/short"))
+            .await
+            .starts_with("HTTP/1.1 200"));
+    }
+
+    #[tokio::test]
+    async fn transient_write_failure() {
+        // Fails the first two writes, as a locked database would.
+        let attempts = AtomicU8::new(0);
+        let write = || async {
+            match attempts.fetch_add(1, Ordering::SeqCst) {
+                0 | 1 => Err("database is locked".to_owned()),
+                _ => Ok(()),
+            }
+        };
+
+        assert!(retry_write(WriteRetries(1), write).await.is_err());
+        assert_eq!(attempts.load(Ordering::SeqCst), 2);
+
+        attempts.store(0, Ordering::SeqCst);
+        assert!(retry_write(WriteRetries(2), write).await.is_ok());
+        assert_eq!(attempts.load(Ordering::SeqCst), 3);
+    }
+
+    #[test]
+    fn disposition_escaping() {
+        assert_eq!(
+            content_disposition(r#"my "best" file.txt"#, false).unwrap(),
+            r#"attachment; filename*=UTF-8''my%20%22best%22%20file%2Etxt; filename="my _best_ file.txt""#
+        );
+        assert_eq!(
+            content_disposition("résumé.pdf", true).unwrap(),
+            r#"inline; filename*=UTF-8''r%C3%A9sum%C3%A9%2Epdf; filename="r_sum_.pdf""#
+        );
+    }
 }
@@ -0,0 +1,162 @@
+use argon2::{
+    password_hash::{rand_core::OsRng, PasswordHash, PasswordHasher, PasswordVerifier, SaltString},
+    Argon2,
+};
+use async_trait::async_trait;
+use axum::{
+    extract::{FromRequest, RequestParts},
+    Form,
+};
+use std::sync::Arc;
+
+use hyper::{header::CONTENT_TYPE, Body};
+use serde::Deserialize;
+
+use crate::{
+    download::FileInfo,
+    error::{download as DownloadError, Error},
+    limit::{Rate, UploadRate},
+    upload::RealIp,
+};
+
+// Salted argon2 hash stored along a file, its password is never stored.
+pub async fn hash(password: String) -> Result<String, Error> {
+    // Hashing is purposely slow, it would block the other tasks of the runtime.
+    tokio::task::spawn_blocking(move || {
+        Argon2::default()
+            .hash_password(password.as_bytes(), &SaltString::generate(&mut OsRng))
+            .map(|hash| hash.to_string())
+    })
+    .await
+    .map_err(|_| Error::Generic)?
+    .map_err(|_| Error::Generic)
+}
+
+async fn verify(password: String, hash: String) -> bool {
+    tokio::task::spawn_blocking(move || {
+        PasswordHash::new(&hash).map_or(false, |hash| {
+            Argon2::default()
+                .verify_password(password.as_bytes(), &hash)
+                .is_ok()
+        })
+    })
+    .await
+    .unwrap_or(false)
+}
+
+#[derive(Deserialize)]
+struct PasswordForm {
+    password: String,
+}
+
+// Password verifications allowed from the same client, each one runs argon2.
+#[derive(Clone)]
+pub struct PasswordAttempts(Arc<Rate>);
+
+impl PasswordAttempts {
+    pub fn new(rate: UploadRate) -> Self {
+        Self(Arc::new(Rate::new(Some(rate))))
+    }
+}
+
+// Password sent in the X-Download-Password header, or in the password field of a POSTed form.
+pub struct DownloadPassword {
+    password: Option<String>,
+    attempts: PasswordAttempts,
+    // Address of the client, the clients without one share their attempts.
+    client: String,
+}
+
+impl DownloadPassword {
+    pub fn or(self, password: Option<String>) -> Self {
+        Self {
+            password: self.password.or(password),
+            ..self
+        }
+    }
+
+    // All the protected files of a download must share the given password.
+    pub async fn check(self, files_info: &[FileInfo]) -> Result<(), Error> {
+        self.check_hashes(files_info.iter().filter_map(|info| info.password.clone()))
+            .await
+    }
+
+    pub async fn check_hashes(self, hashes: impl IntoIterator<Item = String>) -> Result<(), Error> {
+        let mut hashes = hashes.into_iter().peekable();
+        if hashes.peek().is_none() {
+            return Ok(());
+        }
+        let password = self
+            .password
+            .ok_or(DownloadError::InvalidDownloadPassword)?;
+        self.attempts
+            .0
+            .acquire(&self.client, None)
+            .map_err(DownloadError::PasswordAttemptsExceeded)?;
+        for hash in hashes {
+            if !verify(password.clone(), hash).await {
+                return Err(DownloadError::InvalidDownloadPassword);
+            }
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl FromRequest<Body> for DownloadPassword {
+    type Rejection = Error;
+
+    async fn from_request(req: &mut RequestParts<Body>) -> Result<Self, Self::Rejection> {
+        let extensions = req.extensions();
+        let attempts = extensions
+            .get::<PasswordAttempts>()
+            .cloned()
+            .ok_or(Error::Generic)?;
+        let client = extensions
+            .get::<RealIp>()
+            .ok_or(Error::Generic)?
+            .client(req.headers(), extensions)
+            .map(|address| address.to_string())
+            .unwrap_or_default();
+        let password = Self {
+            password: None,
+            attempts,
+            client,
+        };
+        if let Some(header) = req.headers().get("X-Download-Password") {
+            return Ok(password.or(Some(
+                String::from_utf8_lossy(header.as_bytes()).into_owned(),
+            )));
+        }
+        let form = req
+            .headers()
+            .get(CONTENT_TYPE)
+            .and_then(|ct| ct.to_str().ok())
+            .map_or(false, |ct| {
+                ct.starts_with("application/x-www-form-urlencoded")
+            });
+        if form {
+            let Form(form) = Form::<PasswordForm>::from_request(req)
+                .await
+                .map_err(|_| DownloadError::InvalidDownloadPassword)?;
+            return Ok(password.or(Some(form.password)));
+        }
+        Ok(password)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{hash, verify};
+
+    #[tokio::test]
+    async fn salted_hash() {
+        let first = hash("secret".to_owned()).await.unwrap();
+        let second = hash("secret".to_owned()).await.unwrap();
+        assert_ne!(first, second);
+        assert!(!first.contains("secret"));
+        assert!(verify("secret".to_owned(), first.clone()).await);
+        assert!(!verify("Secret".to_owned(), first).await);
+        assert!(!verify("secret".to_owned(), "invalid".to_owned()).await);
+    }
+}
@@ -0,0 +1,126 @@
+use std::sync::Arc;
+
+use axum::{
+    extract::Query,
+    headers::{authorization::Basic, Authorization, Cookie},
+    response::{IntoResponse, Response},
+    Extension, TypedHeader,
+};
+use hyper::{
+    header::{CACHE_CONTROL, CONTENT_TYPE},
+    http::HeaderValue,
+    StatusCode,
+};
+use image::{codecs::png::PngEncoder, ColorType, Luma};
+use itertools::Itertools;
+use qrcode::{render::svg, QrCode};
+use serde::Deserialize;
+use sqlx::SqlitePool;
+
+use crate::{
+    access::AccessUser,
+    alias::{Alias, AliasGroup},
+    auth::{Authenticator, ClientCertificate},
+    download::{fetch_files, AllowedLookup, ArchiveLimit},
+    error::{download as DownloadError, Error},
+    storage::Dir,
+    upload::DomainUri,
+};
+
+// Minimum width of the generated image, in pixels, when the request doesn't specify it, and at most.
+const DEFAULT_SIZE: u32 = 256;
+const MAX_SIZE: u32 = 2048;
+
+#[derive(Copy, Clone, Debug, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum QrFormat {
+    #[default]
+    Png,
+    Svg,
+}
+
+#[derive(Deserialize)]
+pub struct QrParams {
+    #[serde(default)]
+    format: QrFormat,
+    #[serde(default = "default_size")]
+    size: u32,
+}
+
+fn default_size() -> u32 {
+    DEFAULT_SIZE
+}
+
+// QR code of the download link, generated for each request. Protected like the file metadata.
+#[allow(clippy::too_many_arguments)]
+pub async fn handler(
+    _: AllowedLookup,
+    Extension(pool): Extension<SqlitePool>,
+    authenticator: Extension<Arc<Authenticator>>,
+    auth_header: Option<TypedHeader<Authorization<Basic>>>,
+    cookie: Option<TypedHeader<Cookie>>,
+    certificate: Option<Extension<ClientCertificate>>,
+    user: AccessUser,
+    AliasGroup(aliases): AliasGroup,
+    Query(params): Query<QrParams>,
+    DomainUri(domain_uri): DomainUri,
+    Extension(dir): Extension<Dir>,
+    Extension(limit): Extension<ArchiveLimit>,
+) -> Result<Response, Error> {
+    super::authorize(&authenticator, auth_header, cookie, certificate, &user).await?;
+    // Links to missing files aren't worth sharing.
+    fetch_files(&pool, &dir, &aliases, limit.files).await?;
+    let link = format!(
+        "{}/{}",
+        domain_uri,
+        aliases.iter().map(Alias::inner).join("+")
+    );
+    let (content_type, body) = render(&link, params.format, params.size.clamp(1, MAX_SIZE))?;
+    Ok((
+        StatusCode::OK,
+        [
+            (CONTENT_TYPE, HeaderValue::from_static(content_type)),
+            (CACHE_CONTROL, HeaderValue::from_static("no-store")),
+        ],
+        body,
+    )
+        .into_response())
+}
+
+fn render(link: &str, format: QrFormat, size: u32) -> Result<(&'static str, Vec<u8>), Error> {
+    let code = QrCode::new(link.as_bytes()).map_err(|_| DownloadError::QrGeneration)?;
+    match format {
+        QrFormat::Png => {
+            let image = code.render::<Luma<u8>>().min_dimensions(size, size).build();
+            let mut png = Vec::new();
+            PngEncoder::new(&mut png)
+                .encode(&image, image.width(), image.height(), ColorType::L8)
+                .map_err(|_| DownloadError::QrGeneration)?;
+            Ok(("image/png", png))
+        }
+        QrFormat::Svg => Ok((
+            "image/svg+xml",
+            code.render::<svg::Color>()
+                .min_dimensions(size, size)
+                .build()
+                .into_bytes(),
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{render, QrFormat};
+
+    #[test]
+    fn formats() {
+        let (content_type, png) = render("http://localhost/short", QrFormat::Png, 128).unwrap();
+        assert_eq!(content_type, "image/png");
+        assert!(png.starts_with(b"\x89PNG\r\n\x1a\n"));
+
+        let (content_type, svg) = render("http://localhost/short", QrFormat::Svg, 128).unwrap();
+        assert_eq!(content_type, "image/svg+xml");
+        let svg = String::from_utf8(svg).unwrap();
+        assert!(svg.contains("<svg"));
+    }
+}
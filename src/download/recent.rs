@@ -0,0 +1,108 @@
+use std::{
+    collections::{hash_map::Entry, HashMap},
+    net::IpAddr,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use async_trait::async_trait;
+use axum::extract::{FromRequest, RequestParts};
+use hyper::Body;
+
+use crate::{download::FileInfo, error::Error, upload::RealIp};
+
+// Downloads of the same file by the same client within the window are only counted once.
+#[derive(Clone)]
+pub struct RecentDownloads {
+    window: Option<Duration>,
+    seen: Arc<Mutex<HashMap<(String, String), Instant>>>,
+}
+
+impl RecentDownloads {
+    pub fn new(window: Option<Duration>) -> Self {
+        Self {
+            window,
+            seen: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    fn is_repeated(&self, id: &str, client: &str) -> bool {
+        let window = match self.window {
+            Some(window) => window,
+            None => return false,
+        };
+        let now = Instant::now();
+        let mut seen = self.seen.lock().unwrap();
+        seen.retain(|_, first| now.duration_since(*first) < window);
+        match seen.entry((id.to_owned(), client.to_owned())) {
+            Entry::Occupied(_) => true,
+            Entry::Vacant(entry) => {
+                entry.insert(now);
+                false
+            }
+        }
+    }
+}
+
+// Client of a download, identified by its username when authenticated, by its address otherwise.
+pub struct DownloadClient {
+    recent: RecentDownloads,
+    address: Option<IpAddr>,
+}
+
+impl DownloadClient {
+    // Flags the files recently downloaded by the client, and records its address for the download log.
+    pub fn mark(&self, username: Option<&str>, files_info: &mut [FileInfo]) {
+        for info in files_info.iter_mut() {
+            info.requester = self.address.map(|address| address.to_string());
+        }
+        let client = match (username, self.address) {
+            (Some(username), _) => format!("user:{}", username),
+            (None, Some(address)) => address.to_string(),
+            (None, None) => return,
+        };
+        for info in files_info {
+            info.repeated = self.recent.is_repeated(&info.id, &client);
+        }
+    }
+}
+
+#[async_trait]
+impl FromRequest<Body> for DownloadClient {
+    type Rejection = Error;
+
+    async fn from_request(req: &mut RequestParts<Body>) -> Result<Self, Self::Rejection> {
+        let extensions = req.extensions();
+        let real_ip = extensions.get::<RealIp>().ok_or(Error::Generic)?;
+        Ok(Self {
+            recent: extensions
+                .get::<RecentDownloads>()
+                .cloned()
+                .ok_or(Error::Generic)?,
+            address: real_ip.client(req.headers(), extensions),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::RecentDownloads;
+
+    #[test]
+    fn coalesce() {
+        let recent = RecentDownloads::new(Some(Duration::from_millis(50)));
+        assert!(!recent.is_repeated("id", "127.0.0.1"));
+        assert!(recent.is_repeated("id", "127.0.0.1"));
+        assert!(!recent.is_repeated("id", "127.0.0.2"));
+        assert!(!recent.is_repeated("other", "127.0.0.1"));
+
+        std::thread::sleep(Duration::from_millis(60));
+        assert!(!recent.is_repeated("id", "127.0.0.1"));
+
+        let disabled = RecentDownloads::new(None);
+        assert!(!disabled.is_repeated("id", "127.0.0.1"));
+        assert!(!disabled.is_repeated("id", "127.0.0.1"));
+    }
+}
@@ -0,0 +1,134 @@
+use async_trait::async_trait;
+use axum::extract::{FromRequest, RequestParts};
+use hyper::{
+    header::{HOST, REFERER},
+    Body, HeaderMap, Uri,
+};
+
+use crate::{
+    error::{download as DownloadError, Error},
+    upload::trusts_peer,
+};
+
+// Anti-hotlinking: downloads linked from other websites than the allowed ones are rejected.
+#[derive(Clone, Debug)]
+pub struct RefererPolicy {
+    allowed: Vec<String>,
+    deny_direct: bool,
+}
+
+impl RefererPolicy {
+    pub fn new(allowed: Vec<String>, deny_direct: bool) -> Self {
+        Self {
+            allowed: allowed
+                .into_iter()
+                .map(|host| host.to_ascii_lowercase())
+                .collect(),
+            deny_direct,
+        }
+    }
+
+    // The X-Forwarded-Host header is only honored for trusted proxies.
+    fn check(&self, headers: &HeaderMap, forwarded: bool) -> Result<(), Error> {
+        if self.allowed.is_empty() {
+            return Ok(());
+        }
+        let referer = match headers.get(REFERER) {
+            Some(referer) => referer,
+            None if self.deny_direct => return Err(DownloadError::RefererForbidden),
+            None => return Ok(()),
+        };
+        let host = referer
+            .to_str()
+            .ok()
+            .and_then(|referer| referer.parse::<Uri>().ok())
+            .and_then(|uri| uri.host().map(str::to_ascii_lowercase))
+            .ok_or(DownloadError::RefererForbidden)?;
+        // Links of the web UI are always allowed.
+        let own = headers
+            .get("X-Forwarded-Host")
+            .filter(|_| forwarded)
+            .or_else(|| headers.get(HOST))
+            .and_then(|h| h.to_str().ok())
+            .map(|h| h.split(':').next().unwrap_or(h).to_ascii_lowercase());
+        if own.as_deref() == Some(host.as_str()) || self.allowed.contains(&host) {
+            Ok(())
+        } else {
+            Err(DownloadError::RefererForbidden)
+        }
+    }
+}
+
+// Requests allowed by the referer policy of the routes, the others are rejected.
+pub struct AllowedReferer;
+
+#[async_trait]
+impl FromRequest<Body> for AllowedReferer {
+    type Rejection = Error;
+
+    async fn from_request(req: &mut RequestParts<Body>) -> Result<Self, Self::Rejection> {
+        req.extensions()
+            .get::<RefererPolicy>()
+            .ok_or(Error::Generic)?
+            .check(req.headers(), trusts_peer(req.extensions()))?;
+        Ok(Self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hyper::{
+        header::{HOST, REFERER},
+        http::HeaderValue,
+        HeaderMap,
+    };
+
+    use super::RefererPolicy;
+
+    fn headers(referer: Option<&'static str>) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(HOST, HeaderValue::from_static("drop.example.com:8080"));
+        if let Some(referer) = referer {
+            headers.insert(REFERER, HeaderValue::from_static(referer));
+        }
+        headers
+    }
+
+    #[test]
+    fn check() {
+        let policy = RefererPolicy::new(vec!["Blog.example.com".to_owned()], false);
+        assert!(policy.check(&headers(None), false).is_ok());
+        assert!(policy
+            .check(&headers(Some("https://blog.example.com/post")), false)
+            .is_ok());
+        assert!(policy
+            .check(&headers(Some("http://drop.example.com:8080/")), false)
+            .is_ok());
+        assert!(policy
+            .check(&headers(Some("https://forum.example.net/thread")), false)
+            .is_err());
+        assert!(policy.check(&headers(Some("not a uri")), false).is_err());
+
+        let policy = RefererPolicy::new(vec!["blog.example.com".to_owned()], true);
+        assert!(policy.check(&headers(None), false).is_err());
+
+        // Disabled without allowed referers.
+        let policy = RefererPolicy::new(Vec::new(), true);
+        assert!(policy
+            .check(&headers(Some("https://forum.example.net/thread")), false)
+            .is_ok());
+    }
+
+    #[test]
+    fn forwarded_host() {
+        let policy = RefererPolicy::new(vec!["blog.example.com".to_owned()], false);
+        let mut headers = headers(Some("https://drop.example.net/"));
+        headers.insert(
+            "X-Forwarded-Host",
+            HeaderValue::from_static("drop.example.net"),
+        );
+        // Only a trusted proxy can tell the public host of the instance.
+        assert!(policy.check(&headers, true).is_ok());
+        assert!(policy.check(&headers, false).is_err());
+    }
+}
@@ -0,0 +1,111 @@
+use std::{
+    future::Future,
+    pin::Pin,
+    task::{ready, Context, Poll},
+    time::Duration,
+};
+
+use axum::{
+    body::{boxed, Bytes, HttpBody, StreamBody},
+    middleware::Next,
+    response::Response,
+};
+use futures::{stream, Stream};
+use hyper::{Body, Request};
+use tokio::time::{Instant, Sleep};
+
+// Maximum number of bytes per second sent by each download, unlimited if unset.
+#[derive(Copy, Clone, Debug)]
+pub struct DownloadRate(pub Option<u64>);
+
+pub async fn layer(req: Request<Body>, next: Next<Body>, rate: u64) -> Response {
+    let (parts, body) = next.run(req).await.into_parts();
+    let chunks = stream::unfold(body, |mut body| async move {
+        body.data().await.map(|chunk| (chunk, body))
+    });
+    Response::from_parts(
+        parts,
+        boxed(StreamBody::new(Throttled::new(Box::pin(chunks), rate))),
+    )
+}
+
+// Token bucket holding up to a second of transfer. A chunk larger than the available tokens
+// is still sent, the next one is delayed until the debt is paid back.
+pub struct Throttled<S> {
+    inner: S,
+    rate: f64,
+    tokens: f64,
+    updated: Instant,
+    sleep: Option<Pin<Box<Sleep>>>,
+}
+
+impl<S> Throttled<S> {
+    pub fn new(inner: S, rate: u64) -> Self {
+        Self {
+            inner,
+            rate: rate as f64,
+            tokens: rate as f64,
+            updated: Instant::now(),
+            sleep: None,
+        }
+    }
+
+    fn consume(&mut self, len: usize) {
+        let now = Instant::now();
+        self.tokens = (self.tokens + (now - self.updated).as_secs_f64() * self.rate).min(self.rate);
+        self.updated = now;
+        self.tokens -= len as f64;
+        if self.tokens < 0.0 {
+            self.sleep = Some(Box::pin(tokio::time::sleep(Duration::from_secs_f64(
+                -self.tokens / self.rate,
+            ))));
+        }
+    }
+}
+
+impl<S, E> Stream for Throttled<S>
+where
+    S: Stream<Item = Result<Bytes, E>> + Unpin,
+{
+    type Item = Result<Bytes, E>;
+
+    // Waiting doesn't block the executor, the task is woken up by the timer.
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        if let Some(sleep) = this.sleep.as_mut() {
+            ready!(sleep.as_mut().poll(cx));
+            this.sleep = None;
+        }
+        let item = ready!(Pin::new(&mut this.inner).poll_next(cx));
+        if let Some(Ok(chunk)) = &item {
+            this.consume(chunk.len());
+        }
+        Poll::Ready(item)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{convert::Infallible, time::Duration};
+
+    use futures::{stream, StreamExt};
+    use hyper::body::Bytes;
+    use tokio::time::Instant;
+
+    use super::Throttled;
+
+    #[tokio::test(start_paused = true)]
+    async fn rate_limited() {
+        let chunks =
+            stream::iter((0..5).map(|_| Ok::<_, Infallible>(Bytes::from(vec![0; 50_000]))));
+        let start = Instant::now();
+        let sent = Throttled::new(chunks, 100_000)
+            .fold(0, |sent, chunk| async move { sent + chunk.unwrap().len() })
+            .await;
+        assert_eq!(sent, 250_000);
+        // The first second of transfer is sent immediately, the clock is paused and only
+        // advances through the throttling delays.
+        let elapsed = start.elapsed();
+        assert!(elapsed >= Duration::from_millis(1500) && elapsed < Duration::from_millis(1510));
+    }
+}
@@ -1,12 +1,18 @@
-use axum::{
-    response::{IntoResponse, Response},
-    Json,
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use axum::response::{IntoResponse, Response};
+use hyper::{
+    header::{self, HeaderName},
+    http::HeaderValue,
+    HeaderMap, StatusCode,
 };
-use hyper::{header, http::HeaderValue, HeaderMap, StatusCode};
 use serde::{ser::SerializeStruct, Serialize, Serializer};
 use thiserror::Error;
 
-use crate::response::{ApiHeader, SingleLine};
+use crate::{
+    response::{json_body, ApiHeader, SingleLine},
+    upload::Size,
+};
 
 #[derive(Error, Debug)]
 pub enum Error {
@@ -14,42 +20,104 @@ pub enum Error {
     Generic,
     #[error("invalid filename header")]
     FilenameHeader,
-    #[error("file too large")]
-    TooLarge,
+    #[error("invalid filename")]
+    InvalidFilename,
+    #[error("file is {} but the limit is {}", readable(.0), readable(.1))]
+    TooLarge(u64, u64),
+    #[error("transformed file exceeds the limit of {}", readable(.0))]
+    OutputTooLarge(u64),
     #[error("cannot calculate expiration")]
     TimeCalculation,
     #[error("expiration duration request too high")]
     ExpirationTooHigh,
+    #[error("file is {} but the limit is {}", readable(.0), readable(.1))]
+    ExpirationTooLarge(u64, u64),
+    #[error("requested expiration date is already passed")]
+    ExpirationPassed,
     #[error("cannot generate alias")]
     AliasGeneration,
+    #[error("alias already in use")]
+    AliasConflict,
+    #[error("a file with the same name was already uploaded: {0}")]
+    NameConflict(String),
     #[error("cannot determine origin")]
     Origin,
     #[error("cannot determine upload target")]
     Target,
     #[error("database connection failure")]
     Database,
+    #[error("database operation timed out")]
+    DatabaseTimeout,
     #[error("quota determination failure")]
     QuotaAccess,
-    #[error("too many uploads")]
-    QuotaExceeded,
+    #[error("{}", quota_exceeded(.0, .1))]
+    QuotaExceeded(u64, Option<u64>),
+    #[error("too many uploads, {0} is the next of your files to expire")]
+    QuotaExceededUntil(String, u64),
+    #[error("too many upload requests, retry in {0} second(s)")]
+    RateLimited(u64),
+    #[error("server is busy, try again later")]
+    ServerBusy,
     #[error("cannot create file")]
     CreateFile,
     #[error("cannot copy file")]
     CopyFile,
     #[error("not matching file size")]
     SizeMismatch,
+    #[error("invalid upload form")]
+    InvalidForm,
+    #[error("downloads limit must be between 1 and {0}")]
+    InvalidMaxDownloads(u16),
+    #[error("weak download password, it must contain {0}")]
+    WeakDownloadPassword(String),
+    #[error("invalid upload check request")]
+    InvalidCheckRequest,
+    #[error("invalid expiration duration")]
+    InvalidExpireIn,
+    #[error("upload transformation failed")]
+    TransformFailed,
+    #[error("only administrators can upload non-expiring files")]
+    NoExpireForbidden,
+    #[error("cannot find upload session")]
+    UploadSessionNotFound,
+    #[error("missing or invalid upload range")]
+    InvalidUploadRange,
+    #[error("upload range doesn't start at the received offset ({0})")]
+    RangeMismatch(u64),
+    #[error("another chunk of the upload is being received")]
+    UploadSessionBusy,
     #[error("cannot extract alias")]
     AliasExtract,
     #[error("invalid alias format")]
     InvalidAlias,
+    #[error("invalid archive request")]
+    InvalidArchiveRequest,
+    #[error("too many files requested")]
+    TooManyFiles,
+    #[error("cannot generate QR code")]
+    QrGeneration,
+    #[error("archive is {} but the limit is {}", readable(.0), readable(.1))]
+    ArchiveTooLarge(u64, u64),
     #[error("cannot find file")]
     FileNotFound,
+    #[error("file reached its downloads limit")]
+    FileGone,
+    #[error("downloads from this referer are not allowed")]
+    RefererForbidden,
+    #[error("missing or invalid download password")]
+    InvalidDownloadPassword,
+    #[error("too many password attempts, retry in {0} second(s)")]
+    PasswordAttemptsExceeded(u64),
+    #[error("too many lookups, retry in {0} second(s)")]
+    LookupRateLimited(u64),
     #[error("cannot open file")]
     OpenFile,
     #[error("cannot remove file")]
     RemoveFile,
     #[error("file was partially removed")]
     PartialRemove,
+    #[error("cannot restore file")]
+    RestoreFile,
     #[error("missing authorization header")]
     MissingAuthorization,
     #[error("missing or invalid authorization header")]
@@ -58,6 +126,8 @@ pub enum Error {
     InvalidAdminToken,
     #[error("invalid credentials or authentication process")]
     AccessForbidden,
+    #[error("requests from this address are not allowed")]
+    AddressForbidden,
     #[error("an unexpected error happen while updating file metadata")]
     UnexpectedFileModification,
     #[error("asset not found")]
@@ -70,32 +140,132 @@ impl Error {
         match self {
             Generic => StatusCode::INTERNAL_SERVER_ERROR,
             FilenameHeader => StatusCode::BAD_REQUEST,
-            TooLarge => StatusCode::BAD_REQUEST,
+            InvalidFilename => StatusCode::BAD_REQUEST,
+            TooLarge(_, _) => StatusCode::PAYLOAD_TOO_LARGE,
+            OutputTooLarge(_) => StatusCode::PAYLOAD_TOO_LARGE,
             TimeCalculation => StatusCode::INTERNAL_SERVER_ERROR,
             ExpirationTooHigh => StatusCode::BAD_REQUEST,
+            // The file is already stored, only its expiration can't be determined anymore.
+            ExpirationTooLarge(_, _) => StatusCode::BAD_REQUEST,
+            ExpirationPassed => StatusCode::BAD_REQUEST,
             AliasGeneration => StatusCode::INTERNAL_SERVER_ERROR,
+            AliasConflict => StatusCode::CONFLICT,
+            NameConflict(_) => StatusCode::CONFLICT,
             Origin => StatusCode::BAD_REQUEST,
             Target => StatusCode::BAD_REQUEST,
             Database => StatusCode::INTERNAL_SERVER_ERROR,
+            DatabaseTimeout => StatusCode::SERVICE_UNAVAILABLE,
             QuotaAccess => StatusCode::INTERNAL_SERVER_ERROR,
-            QuotaExceeded => StatusCode::TOO_MANY_REQUESTS,
+            // Exceeding the size left in the quotas, rather than the files count.
+            QuotaExceeded(_, Some(_)) => StatusCode::PAYLOAD_TOO_LARGE,
+            QuotaExceeded(_, None) => StatusCode::TOO_MANY_REQUESTS,
+            QuotaExceededUntil(_, _) => StatusCode::TOO_MANY_REQUESTS,
+            RateLimited(_) => StatusCode::TOO_MANY_REQUESTS,
+            ServerBusy => StatusCode::SERVICE_UNAVAILABLE,
             CreateFile => StatusCode::INTERNAL_SERVER_ERROR,
             CopyFile => StatusCode::INTERNAL_SERVER_ERROR,
             SizeMismatch => StatusCode::BAD_REQUEST,
+            InvalidForm => StatusCode::BAD_REQUEST,
+            InvalidMaxDownloads(_) => StatusCode::BAD_REQUEST,
+            WeakDownloadPassword(_) => StatusCode::BAD_REQUEST,
+            InvalidCheckRequest => StatusCode::BAD_REQUEST,
+            InvalidExpireIn => StatusCode::BAD_REQUEST,
+            TransformFailed => StatusCode::UNPROCESSABLE_ENTITY,
+            NoExpireForbidden => StatusCode::FORBIDDEN,
+            UploadSessionNotFound => StatusCode::NOT_FOUND,
+            InvalidUploadRange => StatusCode::BAD_REQUEST,
+            RangeMismatch(_) => StatusCode::CONFLICT,
+            UploadSessionBusy => StatusCode::CONFLICT,
             AliasExtract => StatusCode::INTERNAL_SERVER_ERROR,
             InvalidAlias => StatusCode::BAD_REQUEST,
+            InvalidArchiveRequest => StatusCode::BAD_REQUEST,
+            TooManyFiles => StatusCode::BAD_REQUEST,
+            QrGeneration => StatusCode::INTERNAL_SERVER_ERROR,
+            ArchiveTooLarge(_, _) => StatusCode::PAYLOAD_TOO_LARGE,
             FileNotFound => StatusCode::NOT_FOUND,
+            FileGone => StatusCode::GONE,
+            RefererForbidden => StatusCode::FORBIDDEN,
+            InvalidDownloadPassword => StatusCode::UNAUTHORIZED,
+            PasswordAttemptsExceeded(_) => StatusCode::TOO_MANY_REQUESTS,
+            LookupRateLimited(_) => StatusCode::TOO_MANY_REQUESTS,
             OpenFile => StatusCode::INTERNAL_SERVER_ERROR,
             RemoveFile => StatusCode::INTERNAL_SERVER_ERROR,
             PartialRemove => StatusCode::INTERNAL_SERVER_ERROR,
+            RestoreFile => StatusCode::INTERNAL_SERVER_ERROR,
             MissingAuthorization => StatusCode::UNAUTHORIZED,
             InvalidAuthorizationHeader => StatusCode::UNAUTHORIZED,
             InvalidAdminToken => StatusCode::FORBIDDEN,
             AccessForbidden => StatusCode::FORBIDDEN,
+            AddressForbidden => StatusCode::FORBIDDEN,
             UnexpectedFileModification => StatusCode::INTERNAL_SERVER_ERROR,
             AssetNotFound => StatusCode::NOT_FOUND,
         }
     }
+
+    // Stable identifier of the error kind, unlike its message.
+    pub fn code(&self) -> &'static str {
+        use Error::*;
+        match self {
+            Generic => "internal_error",
+            FilenameHeader => "invalid_filename_header",
+            InvalidFilename => "invalid_filename",
+            TooLarge(_, _) => "too_large",
+            OutputTooLarge(_) => "output_too_large",
+            TimeCalculation => "time_calculation_failed",
+            ExpirationTooHigh => "expiration_too_high",
+            ExpirationTooLarge(_, _) => "too_large",
+            ExpirationPassed => "expiration_passed",
+            AliasGeneration => "alias_generation_failed",
+            AliasConflict => "alias_conflict",
+            NameConflict(_) => "name_conflict",
+            Origin => "origin_unknown",
+            Target => "target_unknown",
+            Database => "database_error",
+            DatabaseTimeout => "database_timeout",
+            QuotaAccess => "quota_access_failed",
+            QuotaExceeded(_, _) => "quota_exceeded",
+            QuotaExceededUntil(_, _) => "quota_exceeded",
+            RateLimited(_) => "rate_limited",
+            ServerBusy => "server_busy",
+            CreateFile => "create_file_failed",
+            CopyFile => "copy_file_failed",
+            SizeMismatch => "size_mismatch",
+            InvalidForm => "invalid_form",
+            InvalidMaxDownloads(_) => "invalid_max_downloads",
+            WeakDownloadPassword(_) => "weak_download_password",
+            InvalidCheckRequest => "invalid_check_request",
+            InvalidExpireIn => "invalid_expire_in",
+            TransformFailed => "transform_failed",
+            NoExpireForbidden => "no_expire_forbidden",
+            UploadSessionNotFound => "upload_session_not_found",
+            InvalidUploadRange => "invalid_upload_range",
+            RangeMismatch(_) => "range_mismatch",
+            UploadSessionBusy => "upload_session_busy",
+            AliasExtract => "alias_extract_failed",
+            InvalidAlias => "invalid_alias",
+            InvalidArchiveRequest => "invalid_archive_request",
+            TooManyFiles => "too_many_files",
+            QrGeneration => "qr_generation",
+            ArchiveTooLarge(_, _) => "archive_too_large",
+            FileNotFound => "file_not_found",
+            FileGone => "file_gone",
+            RefererForbidden => "referer_forbidden",
+            InvalidDownloadPassword => "invalid_download_password",
+            PasswordAttemptsExceeded(_) => "password_attempts_exceeded",
+            LookupRateLimited(_) => "lookup_rate_limited",
+            OpenFile => "open_file_failed",
+            RemoveFile => "remove_file_failed",
+            PartialRemove => "partial_remove",
+            RestoreFile => "restore_file_failed",
+            MissingAuthorization => "missing_authorization",
+            InvalidAuthorizationHeader => "invalid_authorization_header",
+            InvalidAdminToken => "invalid_admin_token",
+            AccessForbidden => "access_forbidden",
+            AddressForbidden => "address_forbidden",
+            UnexpectedFileModification => "unexpected_file_modification",
+            AssetNotFound => "asset_not_found",
+        }
+    }
 }
 
 impl ApiHeader for Error {
@@ -109,6 +279,31 @@ impl ApiHeader for Error {
             MissingAuthorization => [(header::WWW_AUTHENTICATE, HeaderValue::from_static("Basic"))]
                 .into_iter()
                 .collect(),
+            ServerBusy => [(header::RETRY_AFTER, HeaderValue::from_static("10"))]
+                .into_iter()
+                .collect(),
+            RateLimited(retry) | PasswordAttemptsExceeded(retry) | LookupRateLimited(retry) => {
+                [(header::RETRY_AFTER, HeaderValue::from(*retry))]
+                    .into_iter()
+                    .collect()
+            }
+            RangeMismatch(offset) => [(
+                HeaderName::from_static("upload-offset"),
+                HeaderValue::from(*offset),
+            )]
+            .into_iter()
+            .collect(),
+            QuotaExceededUntil(_, expiration) => {
+                let now = SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .map_or(0, |d| d.as_secs());
+                [(
+                    header::RETRY_AFTER,
+                    HeaderValue::from(expiration.saturating_sub(now)),
+                )]
+                .into_iter()
+                .collect()
+            }
             _ => HeaderMap::default(),
         }
     }
@@ -123,8 +318,22 @@ impl Serialize for Error {
     where
         S: Serializer,
     {
-        let mut state = serializer.serialize_struct("Error", 3)?;
-        state.serialize_field("error", &self.to_string())?;
+        let mut state = serializer.serialize_struct("Error", 4)?;
+        state.serialize_field("code", self.code())?;
+        state.serialize_field("message", &self.to_string())?;
+        match self {
+            Error::TooLarge(size, limit)
+            | Error::ExpirationTooLarge(size, limit)
+            | Error::ArchiveTooLarge(size, limit)
+            | Error::QuotaExceeded(size, Some(limit)) => {
+                state.serialize_field("size", &Size::binary(*size))?;
+                state.serialize_field("limit", &Size::binary(*limit))?;
+            }
+            Error::OutputTooLarge(limit) => {
+                state.serialize_field("limit", &Size::binary(*limit))?
+            }
+            _ => (),
+        }
         state.end()
     }
 }
@@ -139,22 +348,39 @@ impl SingleLine for Error {
 impl IntoResponse for Error {
     fn into_response(self) -> Response {
         #[derive(Serialize)]
-        struct JsonResponse {
+        struct JsonResponse<'a> {
             success: bool,
-            error: String,
+            #[serde(flatten)]
+            error: &'a Error,
         }
         (
             self.status_code(),
             self.additional_headers(),
-            Json(JsonResponse {
+            json_body(&JsonResponse {
                 success: false,
-                error: self.to_string(),
+                error: &self,
             }),
         )
             .into_response()
     }
 }
 
+fn readable(bytes: &u64) -> String {
+    Size::binary(*bytes).readable
+}
+
+// The size left in the quotas is only known when it's what the upload exceeds, not the files count.
+fn quota_exceeded(size: &u64, left: &Option<u64>) -> String {
+    match left {
+        Some(left) => format!(
+            "upload is {} but only {} of quota is left",
+            readable(size),
+            readable(left)
+        ),
+        None => "too many uploads".to_owned(),
+    }
+}
+
 impl From<hyper::http::Error> for Error {
     fn from(_: hyper::http::Error) -> Self {
         Self::Generic
@@ -170,15 +396,22 @@ impl From<hyper::Error> for Error {
 #[allow(unused_imports)]
 pub mod upload {
     pub use super::Error::{
-        AliasGeneration, CopyFile, CreateFile, Database, FilenameHeader, Origin, QuotaAccess,
-        QuotaExceeded, SizeMismatch, Target, TimeCalculation, TooLarge,
+        AliasConflict, AliasGeneration, CopyFile, CreateFile, Database, FilenameHeader,
+        InvalidAlias, InvalidCheckRequest, InvalidExpireIn, InvalidForm, InvalidMaxDownloads,
+        InvalidUploadRange, NameConflict, NoExpireForbidden, Origin, OutputTooLarge, QuotaAccess,
+        QuotaExceeded, QuotaExceededUntil, RangeMismatch, RateLimited, ServerBusy, SizeMismatch,
+        Target, TimeCalculation, TooLarge, TransformFailed, UploadSessionBusy,
+        UploadSessionNotFound, WeakDownloadPassword,
     };
 }
 
 #[allow(unused_imports)]
 pub mod download {
     pub use super::Error::{
-        AliasExtract, Database, FileNotFound, FilenameHeader, InvalidAlias, OpenFile,
+        AliasExtract, ArchiveTooLarge, Database, FileGone, FileNotFound, FilenameHeader,
+        InvalidAlias, InvalidArchiveRequest, InvalidDownloadPassword, InvalidFilename,
+        LookupRateLimited, OpenFile, PasswordAttemptsExceeded, QrGeneration, RefererForbidden,
+        TooManyFiles,
     };
 }
 
@@ -186,7 +419,7 @@ pub mod download {
 pub mod admin {
     pub use super::Error::{
         AliasExtract, Database, FileNotFound, InvalidAdminToken, InvalidAlias,
-        InvalidAuthorizationHeader,
+        InvalidAuthorizationHeader, RestoreFile,
     };
 }
 
@@ -202,7 +435,9 @@ pub mod alias {
 
 #[allow(unused_imports)]
 pub mod expiration {
-    pub use super::Error::{Database, ExpirationTooHigh, TimeCalculation, TooLarge};
+    pub use super::Error::{
+        Database, ExpirationPassed, ExpirationTooHigh, ExpirationTooLarge, TimeCalculation,
+    };
 }
 
 #[allow(unused_imports)]
@@ -215,6 +450,16 @@ pub mod valid {
     pub use super::Error::{AliasExtract, Database, InvalidAlias};
 }
 
+#[allow(unused_imports)]
+pub mod feed {
+    pub use super::Error::{Database, TimeCalculation};
+}
+
+#[allow(unused_imports)]
+pub mod metrics {
+    pub use super::Error::Database;
+}
+
 #[allow(unused_imports)]
 pub mod assets {
     pub use super::Error::AssetNotFound;
@@ -222,5 +467,80 @@ pub mod assets {
 
 #[allow(unused_imports)]
 pub mod auth {
-    pub use super::Error::{AccessForbidden, InvalidAuthorizationHeader, MissingAuthorization};
+    pub use super::Error::{
+        AccessForbidden, AddressForbidden, InvalidAuthorizationHeader, MissingAuthorization,
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    use hyper::{header, StatusCode};
+
+    use super::Error;
+    use crate::response::ApiHeader;
+
+    #[test]
+    fn too_large_payload() {
+        let err = Error::TooLarge(2_470_000_000, 536_870_912);
+        assert_eq!(
+            err.to_string(),
+            "file is 2.30 GiB but the limit is 512.00 MiB"
+        );
+        assert_eq!(
+            serde_json::to_value(&err).unwrap(),
+            serde_json::json!({
+                "code": "too_large",
+                "message": "file is 2.30 GiB but the limit is 512.00 MiB",
+                "size": { "bytes": 2_470_000_000u64, "readable": "2.30 GiB" },
+                "limit": { "bytes": 536_870_912u64, "readable": "512.00 MiB" },
+            })
+        );
+    }
+
+    #[test]
+    fn quota_exceeded_payload() {
+        let err = Error::QuotaExceeded(3_221_225_472, Some(1_073_741_824));
+        assert_eq!(err.status_code(), StatusCode::PAYLOAD_TOO_LARGE);
+        assert_eq!(
+            err.to_string(),
+            "upload is 3.00 GiB but only 1.00 GiB of quota is left"
+        );
+        assert_eq!(
+            serde_json::to_value(&err).unwrap()["limit"],
+            serde_json::json!({ "bytes": 1_073_741_824u64, "readable": "1.00 GiB" })
+        );
+
+        // Out of files, no size is reported.
+        let err = Error::QuotaExceeded(1_024, None);
+        assert_eq!(err.status_code(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(err.to_string(), "too many uploads");
+        assert!(serde_json::to_value(&err).unwrap().get("limit").is_none());
+    }
+
+    #[test]
+    fn quota_exceeded_until() {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        let err = Error::QuotaExceededUntil("aaaaaa".to_owned(), now + 60);
+        assert_eq!(err.status_code(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(err.code(), "quota_exceeded");
+        assert_eq!(
+            err.to_string(),
+            "too many uploads, aaaaaa is the next of your files to expire"
+        );
+        let retry = err.additional_headers()[header::RETRY_AFTER]
+            .to_str()
+            .unwrap()
+            .parse::<u64>()
+            .unwrap();
+        assert!((59..=60).contains(&retry));
+
+        // Already expired, waiting for the next cleaning pass.
+        let err = Error::QuotaExceededUntil("aaaaaa".to_owned(), now - 60);
+        assert_eq!(err.additional_headers()[header::RETRY_AFTER], "0");
+    }
 }
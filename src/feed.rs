@@ -0,0 +1,247 @@
+use std::{
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use axum::{
+    headers::{authorization::Basic, Authorization, Cookie},
+    response::Response,
+    routing::get,
+    Extension, Router, TypedHeader,
+};
+use humantime::format_rfc3339_seconds;
+use hyper::{header::CACHE_CONTROL, http::HeaderValue};
+use sqlx::{FromRow, SqlitePool};
+
+use crate::{
+    access::AccessUser,
+    auth::{AuthStatus, Authenticator, ClientCertificate, Features},
+    error::{auth as AuthError, feed as FeedError, Error},
+    include_query,
+    misc::escape_html,
+    query::with_timeout,
+    response::sized_body,
+    upload::{DomainUri, Size},
+};
+
+// Number of uploads listed in the feed.
+const FEED_LENGTH: i64 = 50;
+
+#[derive(FromRow)]
+struct FeedEntry {
+    short_alias: String,
+    name: String,
+    size: i64,
+    created: i64,
+}
+
+async fn handler(
+    Extension(pool): Extension<SqlitePool>,
+    authenticator: Extension<Arc<Authenticator>>,
+    auth_header: Option<TypedHeader<Authorization<Basic>>>,
+    cookie: Option<TypedHeader<Cookie>>,
+    certificate: Option<Extension<ClientCertificate>>,
+    user: AccessUser,
+    DomainUri(domain_uri): DomainUri,
+) -> Result<Response, Error> {
+    // The feed links to the files, it is protected like their downloads.
+    let authenticated = match authenticator
+        .allows(
+            auth_header.map(|h| h.0),
+            cookie.map(|h| h.0),
+            certificate.and_then(|Extension(ClientCertificate(cn))| cn),
+            Features::DOWNLOAD,
+            &user,
+        )
+        .await
+    {
+        AuthStatus::NotNeeded => false,
+        AuthStatus::Valid(_) => true,
+        AuthStatus::Error(err) => return Err(err),
+        AuthStatus::Prompt => return Err(AuthError::MissingAuthorization),
+    };
+
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_err(|_| FeedError::TimeCalculation)?
+        .as_secs();
+    let entries = public_files(&pool, now).await?;
+
+    let mut response = sized_body(
+        "application/atom+xml; charset=utf-8",
+        atom(&domain_uri, &entries, now).into_bytes(),
+    );
+    // Feed readers poll frequently, a few minutes of staleness is fine. Shared caches must not
+    // serve a protected feed to unauthenticated clients.
+    response.headers_mut().insert(
+        CACHE_CONTROL,
+        HeaderValue::from_static(if authenticated {
+            "private, max-age=300"
+        } else {
+            "public, max-age=300"
+        }),
+    );
+    Ok(response)
+}
+
+// Most recent public files, private, expired and exhausted ones are never listed.
+async fn public_files(pool: &SqlitePool, now: u64) -> Result<Vec<FeedEntry>, Error> {
+    with_timeout(
+        sqlx::query_as::<_, FeedEntry>(include_query!("get_files_public"))
+            .bind(now as i64)
+            .bind(FEED_LENGTH)
+            .fetch_all(pool),
+    )
+    .await?
+    .map_err(|_| FeedError::Database)
+}
+
+fn rfc3339(timestamp: i64) -> String {
+    format_rfc3339_seconds(UNIX_EPOCH + Duration::from_secs(timestamp.max(0) as u64)).to_string()
+}
+
+fn atom(domain_uri: &str, entries: &[FeedEntry], now: u64) -> String {
+    let domain_uri = escape_html(domain_uri);
+    let updated = entries.first().map_or(now as i64, |entry| entry.created);
+    let mut feed = format!(
+        concat!(
+            r#"<?xml version="1.0" encoding="utf-8"?>"#,
+            r#"<feed xmlns="http://www.w3.org/2005/Atom">"#,
+            "<id>{0}/feed.xml</id><title>Recent uploads</title><updated>{1}</updated>",
+            r#"<link rel="self" href="{0}/feed.xml"/>"#,
+        ),
+        domain_uri,
+        rfc3339(updated)
+    );
+    for entry in entries {
+        let link = format!("{}/{}", domain_uri, escape_html(&entry.short_alias));
+        feed.push_str(&format!(
+            concat!(
+                "<entry><id>{0}</id><title>{1}</title><updated>{2}</updated>",
+                r#"<link href="{0}"/><summary>{1} ({3})</summary></entry>"#,
+            ),
+            link,
+            escape_html(&entry.name),
+            rfc3339(entry.created),
+            Size::from(entry.size as u64).readable,
+        ));
+    }
+    feed.push_str("</feed>");
+    feed
+}
+
+pub fn router(pool: SqlitePool, authenticator: Arc<Authenticator>) -> Router {
+    Router::new()
+        .route("/feed.xml", get(handler))
+        .route_layer(Extension(pool))
+        .route_layer(Extension(authenticator))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use axum::{headers::Authorization, Extension, TypedHeader};
+    use hyper::header::CACHE_CONTROL;
+
+    use super::{atom, handler, public_files, FeedEntry};
+    use crate::{
+        auth::{Authenticator, Features},
+        include_query, query,
+        upload::DomainUri,
+    };
+
+    #[tokio::test]
+    async fn public_only() {
+        let pool = query::memory_pool().await;
+        for (id, expiration, public, hash) in [
+            ("public", i64::MAX, true, Some("hash")),
+            ("private", i64::MAX, false, Some("hash")),
+            ("expired", 0, true, Some("hash")),
+            ("uploading", i64::MAX, true, None),
+        ] {
+            sqlx::query(include_query!("insert_file"))
+                .bind(id)
+                .bind("admin")
+                .bind("127.0.0.1")
+                .bind(expiration)
+                .bind(id)
+                .bind(0)
+                .bind(id)
+                .bind(id)
+                .bind(false)
+                .bind(public)
+                .execute(&pool)
+                .await
+                .unwrap();
+            sqlx::query("UPDATE files SET hash = ? WHERE id = ?")
+                .bind(hash)
+                .bind(id)
+                .execute(&pool)
+                .await
+                .unwrap();
+        }
+
+        let entries = public_files(&pool, 1).await.unwrap();
+        assert_eq!(
+            entries.iter().map(|e| e.name.as_str()).collect::<Vec<_>>(),
+            ["public"]
+        );
+    }
+
+    #[tokio::test]
+    async fn cache_control() {
+        let pool = query::memory_pool().await;
+        let cache_control = |authenticator: Authenticator| {
+            let pool = pool.clone();
+            async move {
+                let response = handler(
+                    Extension(pool),
+                    Extension(Arc::new(authenticator)),
+                    Some(TypedHeader(Authorization::basic("admin", "password"))),
+                    None,
+                    None,
+                    DomainUri("http://localhost".to_owned()),
+                )
+                .await
+                .unwrap();
+                response.headers()[CACHE_CONTROL].clone()
+            }
+        };
+
+        let public = Authenticator::new(Features::empty(), Vec::new(), None, Vec::new());
+        assert_eq!(cache_control(public).await, "public, max-age=300");
+        // Behind authentication, only the client may cache it.
+        let protected = Authenticator::new(
+            Features::DOWNLOAD,
+            vec!["admin:password".parse().unwrap()],
+            None,
+            Vec::new(),
+        );
+        assert_eq!(cache_control(protected).await, "private, max-age=300");
+    }
+
+    #[test]
+    fn escaped_entries() {
+        let entries = [FeedEntry {
+            short_alias: "aaaaaa".to_owned(),
+            name: "<b>&.txt".to_owned(),
+            size: 2048,
+            created: 0,
+        }];
+        assert_eq!(
+            atom("http://localhost", &entries, 60),
+            concat!(
+                r#"<?xml version="1.0" encoding="utf-8"?>"#,
+                r#"<feed xmlns="http://www.w3.org/2005/Atom">"#,
+                "<id>http://localhost/feed.xml</id><title>Recent uploads</title>",
+                "<updated>1970-01-01T00:00:00Z</updated>",
+                r#"<link rel="self" href="http://localhost/feed.xml"/>"#,
+                "<entry><id>http://localhost/aaaaaa</id><title>&lt;b&gt;&amp;.txt</title>",
+                "<updated>1970-01-01T00:00:00Z</updated>",
+                r#"<link href="http://localhost/aaaaaa"/>"#,
+                "<summary>&lt;b&gt;&amp;.txt (2.05 KB)</summary></entry></feed>"
+            )
+        );
+    }
+}
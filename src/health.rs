@@ -0,0 +1,110 @@
+use std::time::Duration;
+
+use axum::{response::IntoResponse, routing::get, Extension, Json, Router};
+use hyper::StatusCode;
+use serde::Serialize;
+use sqlx::SqlitePool;
+
+use crate::{include_query, storage::Dir};
+
+// A stuck database or disk makes the instance not ready, instead of hanging the probe.
+const PROBE_TIMEOUT: Duration = Duration::from_secs(2);
+
+#[derive(Serialize)]
+struct Health {
+    status: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    database: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    storage: Option<&'static str>,
+}
+
+async fn liveness() -> impl IntoResponse {
+    Json(Health {
+        status: "ok",
+        database: None,
+        storage: None,
+    })
+}
+
+async fn readiness(
+    Extension(pool): Extension<SqlitePool>,
+    Extension(dir): Extension<Dir>,
+) -> impl IntoResponse {
+    let database = tokio::time::timeout(PROBE_TIMEOUT, async {
+        sqlx::query(include_query!("ping"))
+            .execute(&mut pool.acquire().await?)
+            .await
+    })
+    .await
+    .map_or(false, |res| res.is_ok());
+    let storage = tokio::time::timeout(PROBE_TIMEOUT, dir.probe())
+        .await
+        .map_or(false, |res| res.is_ok());
+    let status = |ok| if ok { "ok" } else { "error" };
+    (
+        if database && storage {
+            StatusCode::OK
+        } else {
+            StatusCode::SERVICE_UNAVAILABLE
+        },
+        Json(Health {
+            status: if database && storage {
+                "ready"
+            } else {
+                "not_ready"
+            },
+            database: Some(status(database)),
+            storage: Some(status(storage)),
+        }),
+    )
+}
+
+// Probes of orchestrators, never authenticated.
+pub fn router(pool: SqlitePool, dir: Dir) -> Router {
+    Router::new()
+        .route("/healthz", get(liveness))
+        .route("/readyz", get(readiness))
+        .route_layer(Extension(pool))
+        .route_layer(Extension(dir))
+}
+
+#[cfg(test)]
+mod tests {
+    use hyper::{body::HttpBody, Body, Request, StatusCode};
+    use tower::ServiceExt;
+    use uuid::Uuid;
+
+    use crate::{query, storage::Dir};
+
+    #[tokio::test]
+    async fn readiness() {
+        let pool = query::memory_pool().await;
+        let path = std::env::temp_dir().join(Uuid::new_v4().as_hyphenated().to_string());
+        let dir = Dir::new(&path, false);
+        dir.create(true).await.unwrap();
+        let router = super::router(pool, dir);
+
+        let response = router
+            .clone()
+            .oneshot(Request::get("/readyz").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = response.into_body().data().await.unwrap().unwrap();
+        assert_eq!(
+            serde_json::from_slice::<serde_json::Value>(&body).unwrap(),
+            serde_json::json!({ "status": "ready", "database": "ok", "storage": "ok" })
+        );
+        // No leftover of the write probe.
+        assert_eq!(std::fs::read_dir(&path).unwrap().count(), 0);
+
+        // Uploads directory gone.
+        tokio::fs::remove_dir_all(&path).await.unwrap();
+        let response = router
+            .oneshot(Request::get("/readyz").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+    }
+}
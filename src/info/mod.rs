@@ -1,10 +1,22 @@
+use std::sync::Arc;
+
 use axum::{routing::get, Extension, Router};
 use sqlx::SqlitePool;
 
+use crate::{auth::Authenticator, upload::Determiner};
+
+mod thresholds;
 mod valid;
 
-pub fn router(pool: SqlitePool) -> Router {
+pub fn router(
+    pool: SqlitePool,
+    authenticator: Arc<Authenticator>,
+    determiner: Arc<Determiner>,
+) -> Router {
     Router::new()
         .route("/valid/:alias", get(valid::handler))
+        .route("/thresholds", get(thresholds::handler))
         .route_layer(Extension(pool))
+        .route_layer(Extension(authenticator))
+        .route_layer(Extension(determiner))
 }
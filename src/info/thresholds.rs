@@ -0,0 +1,83 @@
+use std::sync::Arc;
+
+use axum::{
+    headers::{authorization::Basic, Authorization, Cookie},
+    Extension, TypedHeader,
+};
+use http_negotiator::{ContentTypeNegotiation, Negotiation};
+use itertools::Itertools;
+use serde::Serialize;
+
+use crate::{
+    access::AccessUser,
+    auth::{AuthStatus, Authenticator, ClientCertificate, Features},
+    error::{auth as AuthError, Error},
+    response::{ApiHeader, ApiResponse, ResponseType, SingleLine},
+    upload::{Determiner, ExpirationDuration, Size},
+};
+
+#[derive(Serialize)]
+pub struct ThresholdsInfo {
+    thresholds: Vec<ThresholdInfo>,
+}
+
+#[derive(Serialize)]
+struct ThresholdInfo {
+    size: Size,
+    default: ExpirationDuration,
+    allowed: Option<ExpirationDuration>,
+}
+
+impl ApiHeader for ThresholdsInfo {}
+
+impl SingleLine for ThresholdsInfo {
+    fn single_lined(&self) -> String {
+        self.thresholds
+            .iter()
+            .map(|t| format!("{}: {}", t.size.readable, t.default.readable))
+            .join(", ")
+    }
+}
+
+// Available to the users allowed to download files.
+pub async fn handler(
+    Extension(determiner): Extension<Arc<Determiner>>,
+    authenticator: Extension<Arc<Authenticator>>,
+    auth_header: Option<TypedHeader<Authorization<Basic>>>,
+    cookie: Option<TypedHeader<Cookie>>,
+    certificate: Option<Extension<ClientCertificate>>,
+    user: AccessUser,
+    response_type: Negotiation<ContentTypeNegotiation, ResponseType>,
+) -> Result<ApiResponse<ThresholdsInfo>, ApiResponse<Error>> {
+    match authenticator
+        .allows(
+            auth_header.map(|h| h.0),
+            cookie.map(|h| h.0),
+            certificate.and_then(|Extension(ClientCertificate(cn))| cn),
+            Features::DOWNLOAD,
+            &user,
+        )
+        .await
+    {
+        AuthStatus::NotNeeded | AuthStatus::Valid(_) => (),
+        AuthStatus::Error(err) => return Err(ApiResponse(*response_type, err)),
+        AuthStatus::Prompt => {
+            return Err(ApiResponse(*response_type, AuthError::MissingAuthorization));
+        }
+    };
+
+    Ok(ApiResponse(
+        *response_type,
+        ThresholdsInfo {
+            thresholds: determiner
+                .thresholds()
+                .iter()
+                .map(|t| ThresholdInfo {
+                    size: Size::from(t.size),
+                    default: ExpirationDuration::from(t.default),
+                    allowed: t.allowed.map(ExpirationDuration::from),
+                })
+                .collect(),
+        },
+    ))
+}
@@ -0,0 +1,68 @@
+use std::str::FromStr;
+
+use byte_unit::Byte;
+
+use super::UploadRate;
+
+// Per-origin quota and upload rate overrides for a static credential's username.
+#[derive(Clone, Debug)]
+pub struct CredentialLimit {
+    pub username: String,
+    pub size_sum: u64,
+    pub file_count: usize,
+    pub rate: Option<UploadRate>,
+}
+
+impl FromStr for CredentialLimit {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.split(':');
+        let (username, size_sum, file_count) = match (parts.next(), parts.next(), parts.next()) {
+            (Some(username), Some(size_sum), Some(file_count)) if !username.is_empty() => {
+                (username, size_sum, file_count)
+            }
+            _ => return Err("invalid format (should be USERNAME:SIZE_SUM:FILE_COUNT[:RATE])"),
+        };
+        let rate = parts.next();
+        if parts.next().is_some() {
+            return Err("invalid format (should be USERNAME:SIZE_SUM:FILE_COUNT[:RATE])");
+        }
+
+        Ok(Self {
+            username: username.to_owned(),
+            size_sum: size_sum
+                .parse::<Byte>()
+                .map_err(|_| "invalid size")?
+                .get_bytes(),
+            file_count: file_count.parse().map_err(|_| "invalid file count")?,
+            rate: rate
+                .map(|rate| rate.parse().map_err(|_| "invalid rate"))
+                .transpose()?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::CredentialLimit;
+
+    #[test]
+    fn parse() {
+        let limit = "service:1GB:100".parse::<CredentialLimit>().unwrap();
+        assert_eq!(limit.username, "service");
+        assert_eq!(limit.size_sum, 1_000_000_000);
+        assert_eq!(limit.file_count, 100);
+        assert!(limit.rate.is_none());
+
+        let limit = "service:1GB:100:50/1m".parse::<CredentialLimit>().unwrap();
+        assert!(limit.rate.is_some());
+
+        assert!("service:1GB".parse::<CredentialLimit>().is_err());
+        assert!("service:1GB:100:1".parse::<CredentialLimit>().is_err());
+        assert!("service:1GB:100:1/1m:1".parse::<CredentialLimit>().is_err());
+        assert!(":1GB:100".parse::<CredentialLimit>().is_err());
+        assert!("service:big:100".parse::<CredentialLimit>().is_err());
+        assert!("service:1GB:many".parse::<CredentialLimit>().is_err());
+    }
+}
@@ -1,7 +1,9 @@
 use async_trait::async_trait;
-use sqlx::SqliteConnection;
 
-use crate::{include_query, limit::Limiter, upload::UploadRequest};
+use crate::{
+    limit::{Limiter, Remaining, Store},
+    upload::UploadRequest,
+};
 
 pub struct Global {
     size_sum: u64,
@@ -15,11 +17,19 @@ impl Global {
 
 #[async_trait]
 impl Limiter for Global {
-    async fn accept(&self, req: &UploadRequest, conn: &mut SqliteConnection) -> Option<bool> {
-        let (size,) = sqlx::query_as::<_, (i64,)>(include_query!("get_limit_global"))
-            .fetch_one(conn)
-            .await
-            .ok()?;
-        Some(size as u64 + req.size <= self.size_sum)
+    async fn accept(&self, req: &UploadRequest, store: &mut dyn Store) -> Option<bool> {
+        let size = store.global_usage().await?;
+        Some(size + req.size <= self.size_sum)
+    }
+
+    async fn remaining(
+        &self,
+        _req: &UploadRequest,
+        store: &mut dyn Store,
+        remaining: &mut Remaining,
+    ) -> Option<()> {
+        let size = store.global_usage().await?;
+        remaining.global_size = Some(self.size_sum.saturating_sub(size));
+        Some(())
     }
 }
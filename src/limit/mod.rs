@@ -1,17 +1,40 @@
 use async_trait::async_trait;
-use sqlx::SqliteConnection;
 
 use crate::upload::UploadRequest;
 
+mod credential;
 mod global;
 mod origin;
+mod rate;
+mod store;
 
+pub use credential::CredentialLimit;
 pub use global::Global;
 pub use origin::Origin;
+pub use rate::{Rate, UploadRate};
+pub use store::{Sqlite as SqliteStore, Store};
+
+// What's left of the quotas of an uploader, unlimited if unset.
+#[derive(Default, Debug, PartialEq, Eq)]
+pub struct Remaining {
+    pub origin_size: Option<u64>,
+    pub origin_files: Option<usize>,
+    pub global_size: Option<u64>,
+}
 
 #[async_trait]
 pub trait Limiter {
-    async fn accept(&self, req: &UploadRequest, conn: &mut SqliteConnection) -> Option<bool>;
+    async fn accept(&self, req: &UploadRequest, store: &mut dyn Store) -> Option<bool>;
+
+    // Fill the part of the remaining quotas checked by the limiter.
+    async fn remaining(
+        &self,
+        _req: &UploadRequest,
+        _store: &mut dyn Store,
+        _remaining: &mut Remaining,
+    ) -> Option<()> {
+        Some(())
+    }
 }
 
 pub struct Chain(Vec<Box<dyn Limiter + Send + Sync>>);
@@ -24,12 +47,85 @@ impl Chain {
 
 #[async_trait]
 impl Limiter for Chain {
-    async fn accept(&self, req: &UploadRequest, conn: &mut SqliteConnection) -> Option<bool> {
+    async fn accept(&self, req: &UploadRequest, store: &mut dyn Store) -> Option<bool> {
         for l in self.0.iter() {
-            if !l.accept(req, conn).await? {
+            if !l.accept(req, store).await? {
                 return Some(false);
             }
         }
         Some(true)
     }
+
+    async fn remaining(
+        &self,
+        req: &UploadRequest,
+        store: &mut dyn Store,
+        remaining: &mut Remaining,
+    ) -> Option<()> {
+        for l in self.0.iter() {
+            l.remaining(req, store, remaining).await?;
+        }
+        Some(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use async_trait::async_trait;
+
+    use super::{Chain, Global, Limiter, Origin, Remaining, Store};
+    use crate::{limit::CredentialLimit, upload::UploadRequest};
+
+    struct Usage;
+
+    #[async_trait]
+    impl Store for Usage {
+        async fn global_usage(&mut self) -> Option<u64> {
+            Some(9_000)
+        }
+
+        async fn origin_usage(&mut self, _origin: &str) -> Option<(u64, usize)> {
+            Some((600, 2))
+        }
+    }
+
+    #[tokio::test]
+    async fn remaining() {
+        let chain = Chain::new(vec![
+            Box::new(Origin::new(
+                1_000,
+                3,
+                vec!["admin:2000:1".parse::<CredentialLimit>().unwrap()],
+            )),
+            Box::new(Global::new(10_000)),
+        ]);
+        let mut req = UploadRequest {
+            filename: None,
+            size: 0,
+            origin: "127.0.0.1".to_owned(),
+            username: None,
+        };
+        let mut remaining = Remaining::default();
+        chain
+            .remaining(&req, &mut Usage, &mut remaining)
+            .await
+            .unwrap();
+        assert_eq!(
+            remaining,
+            Remaining {
+                origin_size: Some(400),
+                origin_files: Some(1),
+                global_size: Some(1_000),
+            }
+        );
+
+        // Credential limits apply, usage over them leaves nothing.
+        req.username = Some("admin".to_owned());
+        chain
+            .remaining(&req, &mut Usage, &mut remaining)
+            .await
+            .unwrap();
+        assert_eq!(remaining.origin_size, Some(1_400));
+        assert_eq!(remaining.origin_files, Some(0));
+    }
 }
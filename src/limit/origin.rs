@@ -1,31 +1,59 @@
+use std::collections::HashMap;
+
 use async_trait::async_trait;
-use sqlx::SqliteConnection;
 
-use crate::{include_query, limit::Limiter, upload::UploadRequest};
+use crate::{
+    limit::{CredentialLimit, Limiter, Remaining, Store},
+    upload::UploadRequest,
+};
 
 pub struct Origin {
     size_sum: u64,
     file_count: usize,
+    overrides: HashMap<String, (u64, usize)>,
 }
 
 impl Origin {
-    pub fn new(size_sum: u64, file_count: usize) -> Self {
+    pub fn new(size_sum: u64, file_count: usize, overrides: Vec<CredentialLimit>) -> Self {
         Self {
             size_sum,
             file_count,
+            overrides: overrides
+                .into_iter()
+                .map(|l| (l.username, (l.size_sum, l.file_count)))
+                .collect(),
         }
     }
+
+    // Authenticated uploaders may have their own limits.
+    fn limits(&self, req: &UploadRequest) -> (u64, usize) {
+        req.username
+            .as_ref()
+            .and_then(|username| self.overrides.get(username))
+            .copied()
+            .unwrap_or((self.size_sum, self.file_count))
+    }
 }
 
 #[async_trait]
 impl Limiter for Origin {
     #[allow(clippy::int_plus_one)]
-    async fn accept(&self, req: &UploadRequest, conn: &mut SqliteConnection) -> Option<bool> {
-        let (size, count) = sqlx::query_as::<_, (i64, i64)>(include_query!("get_limit_origin"))
-            .bind(&req.origin)
-            .fetch_one(conn)
-            .await
-            .ok()?;
-        Some(size as u64 + req.size <= self.size_sum && count as usize + 1 <= self.file_count)
+    async fn accept(&self, req: &UploadRequest, store: &mut dyn Store) -> Option<bool> {
+        let (size_sum, file_count) = self.limits(req);
+        let (size, count) = store.origin_usage(&req.origin).await?;
+        Some(size + req.size <= size_sum && count + 1 <= file_count)
+    }
+
+    async fn remaining(
+        &self,
+        req: &UploadRequest,
+        store: &mut dyn Store,
+        remaining: &mut Remaining,
+    ) -> Option<()> {
+        let (size_sum, file_count) = self.limits(req);
+        let (size, count) = store.origin_usage(&req.origin).await?;
+        remaining.origin_size = Some(size_sum.saturating_sub(size));
+        remaining.origin_files = Some(file_count.saturating_sub(count));
+        Some(())
     }
 }
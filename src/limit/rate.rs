@@ -0,0 +1,178 @@
+use std::{
+    collections::HashMap,
+    str::FromStr,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use super::CredentialLimit;
+
+// Maximum number of uploads from the same uploader during a window (COUNT/DURATION).
+#[derive(Copy, Clone, Debug)]
+pub struct UploadRate {
+    count: u32,
+    window: Duration,
+}
+
+impl FromStr for UploadRate {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (count, window) = s.split_once('/').ok_or("missing window")?;
+        Ok(Self {
+            count: count
+                .parse()
+                .ok()
+                .filter(|&count| count > 0)
+                .ok_or("invalid count")?,
+            window: humantime::parse_duration(window)
+                .ok()
+                .filter(|window| !window.is_zero())
+                .ok_or("invalid window")?,
+        })
+    }
+}
+
+// Token bucket per uploader, refilled continuously up to the allowed count.
+pub struct Rate {
+    rate: Option<UploadRate>,
+    buckets: Mutex<Buckets>,
+    // Separate buckets for the usernames of static credentials with their own rate.
+    overrides: HashMap<String, Rate>,
+}
+
+struct Buckets {
+    tokens: HashMap<String, (f64, Instant)>,
+    pruned: Instant,
+}
+
+impl Rate {
+    pub fn new(rate: Option<UploadRate>) -> Self {
+        Self {
+            rate,
+            buckets: Mutex::new(Buckets {
+                tokens: HashMap::new(),
+                pruned: Instant::now(),
+            }),
+            overrides: HashMap::new(),
+        }
+    }
+
+    pub fn with_overrides(mut self, limits: &[CredentialLimit]) -> Self {
+        self.overrides = limits
+            .iter()
+            .filter_map(|limit| Some((limit.username.clone(), Rate::new(Some(limit.rate?)))))
+            .collect();
+        self
+    }
+
+    // Number of seconds before the next upload is allowed if the origin is limited.
+    pub fn acquire(&self, origin: &str, username: Option<&str>) -> Result<(), u64> {
+        self.acquire_at(origin, username, Instant::now())
+    }
+
+    fn acquire_at(&self, origin: &str, username: Option<&str>, now: Instant) -> Result<(), u64> {
+        if let Some(rate) = username.and_then(|username| self.overrides.get(username)) {
+            return rate.acquire_at(origin, None, now);
+        }
+        let rate = match self.rate {
+            Some(rate) => rate,
+            None => return Ok(()),
+        };
+        let capacity = rate.count as f64;
+        let per_second = capacity / rate.window.as_secs_f64();
+        let refilled = |tokens: f64, updated: Instant| {
+            (tokens + now.saturating_duration_since(updated).as_secs_f64() * per_second)
+                .min(capacity)
+        };
+
+        let mut buckets = self.buckets.lock().unwrap();
+        // Full buckets are equivalent to missing ones.
+        if now.saturating_duration_since(buckets.pruned) >= rate.window {
+            buckets
+                .tokens
+                .retain(|_, &mut (tokens, updated)| refilled(tokens, updated) < capacity);
+            buckets.pruned = now;
+        }
+        let (tokens, updated) = buckets
+            .tokens
+            .entry(origin.to_owned())
+            .or_insert((capacity, now));
+        *tokens = refilled(*tokens, *updated);
+        *updated = now;
+        if *tokens >= 1.0 {
+            *tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(((1.0 - *tokens) / per_second).ceil() as u64)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::{Rate, UploadRate};
+    use crate::limit::CredentialLimit;
+
+    #[test]
+    fn token_bucket() {
+        let rate = Rate::new(Some("2/10s".parse::<UploadRate>().unwrap()));
+        let now = Instant::now();
+        assert_eq!(rate.acquire_at("127.0.0.1", None, now), Ok(()));
+        assert_eq!(rate.acquire_at("127.0.0.1", None, now), Ok(()));
+        assert_eq!(rate.acquire_at("127.0.0.1", None, now), Err(5));
+        assert_eq!(rate.acquire_at("127.0.0.2", None, now), Ok(()));
+
+        // One token every 5 seconds.
+        let later = now + Duration::from_secs(6);
+        assert_eq!(rate.acquire_at("127.0.0.1", None, later), Ok(()));
+        assert_eq!(rate.acquire_at("127.0.0.1", None, later), Err(4));
+
+        // Refilled buckets are pruned.
+        let much_later = now + Duration::from_secs(60);
+        assert_eq!(rate.acquire_at("127.0.0.1", None, much_later), Ok(()));
+        assert_eq!(
+            rate.buckets
+                .lock()
+                .unwrap()
+                .tokens
+                .keys()
+                .collect::<Vec<_>>(),
+            ["127.0.0.1"]
+        );
+
+        let unlimited = Rate::new(None);
+        for _ in 0..10 {
+            assert_eq!(unlimited.acquire_at("127.0.0.1", None, now), Ok(()));
+        }
+    }
+
+    #[test]
+    fn credential_override() {
+        let rate = Rate::new(Some("1/10s".parse::<UploadRate>().unwrap())).with_overrides(&[
+            "service:1GB:100:3/10s".parse::<CredentialLimit>().unwrap(),
+            "other:1GB:100".parse::<CredentialLimit>().unwrap(),
+        ]);
+        let now = Instant::now();
+        for _ in 0..3 {
+            assert_eq!(rate.acquire_at("service", Some("service"), now), Ok(()));
+        }
+        assert_eq!(rate.acquire_at("service", Some("service"), now), Err(4));
+
+        // Credentials without a rate use the global one.
+        assert_eq!(rate.acquire_at("other", Some("other"), now), Ok(()));
+        assert_eq!(rate.acquire_at("other", Some("other"), now), Err(10));
+        assert_eq!(rate.acquire_at("127.0.0.1", None, now), Ok(()));
+        assert_eq!(rate.acquire_at("127.0.0.1", None, now), Err(10));
+    }
+
+    #[test]
+    fn parse() {
+        assert!("10/1m".parse::<UploadRate>().is_ok());
+        assert!("0/1m".parse::<UploadRate>().is_err());
+        assert!("10/0s".parse::<UploadRate>().is_err());
+        assert!("10".parse::<UploadRate>().is_err());
+    }
+}
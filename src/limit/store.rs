@@ -0,0 +1,66 @@
+use async_trait::async_trait;
+use sqlx::SqliteConnection;
+
+use crate::include_query;
+
+// Source of the current quota usage, allowing limits to be shared by multiple instances.
+#[async_trait]
+pub trait Store: Send {
+    async fn global_usage(&mut self) -> Option<u64>;
+    async fn origin_usage(&mut self, origin: &str) -> Option<(u64, usize)>;
+}
+
+// Usage read from the files table, using the connection held by the upload so the
+// check and the file insertion are serialized. Upload sessions count for their whole size.
+pub struct Sqlite<'a> {
+    conn: &'a mut SqliteConnection,
+    excluded_file: Option<&'a str>,
+    excluded_session: Option<&'a str>,
+}
+
+impl<'a> Sqlite<'a> {
+    pub fn new(conn: &'a mut SqliteConnection) -> Self {
+        Self {
+            conn,
+            excluded_file: None,
+            excluded_session: None,
+        }
+    }
+
+    // Leave a file out of the usage, when checking it again with its actual size.
+    pub fn excluding_file(mut self, id: Option<&'a str>) -> Self {
+        self.excluded_file = id;
+        self
+    }
+
+    // Leave a session out of the usage, when checking the file completing it.
+    pub fn excluding_session(mut self, id: Option<&'a str>) -> Self {
+        self.excluded_session = id;
+        self
+    }
+}
+
+#[async_trait]
+impl Store for Sqlite<'_> {
+    async fn global_usage(&mut self) -> Option<u64> {
+        let (size,) = sqlx::query_as::<_, (i64,)>(include_query!("get_limit_global"))
+            .bind(self.excluded_file)
+            .bind(self.excluded_session)
+            .fetch_one(&mut *self.conn)
+            .await
+            .ok()?;
+        Some(size as u64)
+    }
+
+    async fn origin_usage(&mut self, origin: &str) -> Option<(u64, usize)> {
+        let (size, count) = sqlx::query_as::<_, (i64, i64)>(include_query!("get_limit_origin"))
+            .bind(origin)
+            .bind(self.excluded_file)
+            .bind(origin)
+            .bind(self.excluded_session)
+            .fetch_one(&mut *self.conn)
+            .await
+            .ok()?;
+        Some((size as u64, count as usize))
+    }
+}
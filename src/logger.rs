@@ -0,0 +1,136 @@
+use std::{io, time::Instant};
+
+use axum::{middleware::Next, response::Response};
+use clap::ValueEnum;
+use hyper::{Body, Request};
+use tracing::{field::Empty, level_filters::LevelFilter, Instrument, Level, Subscriber};
+use tracing_subscriber::{
+    fmt::{self, writer::MakeWriterExt, MakeWriter},
+    layer::SubscriberExt,
+    registry::LookupSpan,
+    util::SubscriberInitExt,
+    Layer,
+};
+
+use crate::upload::RealIp;
+
+#[derive(Copy, Clone, Debug, ValueEnum)]
+pub enum LogFormat {
+    Pretty,
+    Json,
+    // Apache-style access log lines, the application logs are written to stderr.
+    Clf,
+    Combined,
+}
+
+// Logs of the dependencies still using the log crate are forwarded to the subscriber.
+pub fn init(format: LogFormat, level: LevelFilter) {
+    let registry = tracing_subscriber::registry().with(level);
+    match format {
+        LogFormat::Pretty | LogFormat::Clf | LogFormat::Combined => {
+            registry.with(fmt::layer().with_writer(io::stderr)).init()
+        }
+        // JSON lines on stdout for log collectors, errors are also written for humans on stderr.
+        LogFormat::Json => registry
+            .with(json_layer(io::stdout))
+            .with(fmt::layer().with_writer(io::stderr.with_max_level(Level::ERROR)))
+            .init(),
+    }
+}
+
+// The fields of the enclosing request span are added to each event.
+fn json_layer<S, W>(writer: W) -> impl Layer<S>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    W: for<'w> MakeWriter<'w> + Send + Sync + 'static,
+{
+    fmt::layer()
+        .json()
+        .flatten_event(true)
+        .with_current_span(true)
+        .with_span_list(false)
+        .with_writer(writer)
+}
+
+// Span covering the handling of a request, closed by an event once the response headers are ready.
+pub async fn layer(req: Request<Body>, next: Next<Body>, real_ip: RealIp) -> Response {
+    let remote = real_ip
+        .client(req.headers(), req.extensions())
+        .map_or_else(|| "-".to_owned(), |ip| ip.to_string());
+    let span = tracing::info_span!(
+        "request",
+        method = %req.method(),
+        path = req.uri().path(),
+        remote = remote.as_str(),
+        status = Empty,
+        latency_ms = Empty,
+    );
+
+    let start = Instant::now();
+    let response = next.run(req).instrument(span.clone()).await;
+    span.record("status", response.status().as_u16());
+    span.record("latency_ms", start.elapsed().as_millis() as u64);
+    span.in_scope(|| tracing::info!("Request handled"));
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{self, Write},
+        sync::{Arc, Mutex},
+    };
+
+    use axum::{middleware, routing::get, Router};
+    use hyper::{Body, Request, StatusCode};
+    use tower::ServiceExt;
+    use tracing::level_filters::LevelFilter;
+    use tracing_subscriber::layer::SubscriberExt;
+
+    use super::{json_layer, layer};
+    use crate::upload::RealIp;
+
+    #[derive(Clone, Default)]
+    struct Buffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Buffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn request_span() {
+        let buffer = Buffer::default();
+        let writer = buffer.clone();
+        let _guard = tracing::subscriber::set_default(
+            tracing_subscriber::registry()
+                .with(LevelFilter::INFO)
+                .with(json_layer(move || writer.clone())),
+        );
+
+        let router = Router::new()
+            .route("/teapot", get(|| async { StatusCode::IM_A_TEAPOT }))
+            .layer(middleware::from_fn(|req, next| {
+                layer(req, next, RealIp::new(false))
+            }));
+        router
+            .oneshot(Request::get("/teapot").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+
+        let output = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+        let entry = serde_json::from_str::<serde_json::Value>(output.trim()).unwrap();
+        assert_eq!(entry["level"], "INFO");
+        assert_eq!(entry["message"], "Request handled");
+        assert_eq!(entry["span"]["method"], "GET");
+        assert_eq!(entry["span"]["path"], "/teapot");
+        assert_eq!(entry["span"]["remote"], "-");
+        assert_eq!(entry["span"]["status"], 418);
+        assert!(entry["span"]["latency_ms"].is_u64());
+    }
+}
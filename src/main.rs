@@ -1,48 +1,84 @@
+mod access;
+mod admin;
 mod alias;
 mod assets;
 mod auth;
+mod cidr;
+mod compression;
 mod download;
 mod error;
+mod feed;
+mod health;
 mod info;
 mod limit;
+mod logger;
+mod metrics;
 mod misc;
 mod options;
 mod query;
 mod response;
+mod shutdown;
 mod storage;
 mod theme;
+mod timing;
+mod tls;
 mod update;
 mod upload;
+mod webhook;
 
 mod main {
     use std::{net::SocketAddr, sync::Arc, time::Duration};
 
-    use axum::Router;
-    use clap::Parser;
+    use axum::{middleware, Extension, Router};
     use http_negotiator::{ContentTypeNegotiation, Negotiator};
     use hyper::Server;
     use sqlx::sqlite::{SqliteConnectOptions, SqlitePoolOptions};
+    use tower::ServiceExt;
 
     use crate::{
+        access::AccessLog,
+        assets::UiDir,
         auth::Authenticator,
-        exit_error, include_query,
-        limit::{Chain as LimiterChain, Global as GlobalLimiter, Origin as OriginLimiter},
+        cidr::CidrFilter,
+        download::{
+            ArchiveLimit, DownloadRate, LookupRate, PasswordAttempts, RecentDownloads,
+            RefererPolicy, WriteRetries,
+        },
+        exit_error,
+        limit::{
+            Chain as LimiterChain, Global as GlobalLimiter, Origin as OriginLimiter,
+            Rate as RateLimiter,
+        },
+        logger::LogFormat,
+        misc::normalize_trailing_slash,
         options::Options,
+        query,
         response::ResponseType,
-        storage::{Cleaner, Dir},
-        upload::{Determiner, RealIp},
+        shutdown::Shutdown,
+        storage::{Cleaner, Dir, EncryptionKey, Verifier},
+        update::SoftDelete,
+        upload::{
+            CompressAtRest, DeduplicateUploads, Determiner, GroupDeterminers, InflightBytes,
+            LoadShedder, MaxDownloads, PasswordPolicy, PendingUploads, RealIp, SniffBytes,
+            Transform,
+        },
+        webhook,
     };
 
     pub(super) async fn run() {
-        let options = Options::parse();
-        env_logger::Builder::new()
-            .filter_level(options.log_level())
-            .init();
+        let options = Options::load();
+        super::logger::init(options.log_format, options.log_level());
+        super::alias::set_formats(
+            options.short_alias_alphabet,
+            options.short_alias_length as usize,
+            options.long_alias_length as usize,
+        );
 
         let limiters = LimiterChain::new(vec![
             Box::new(OriginLimiter::new(
                 options.origin_size_sum,
                 options.origin_file_count,
+                options.credential_limits.clone(),
             )),
             Box::new(GlobalLimiter::new(options.global_size_sum)),
         ]);
@@ -50,79 +86,270 @@ mod main {
             Determiner::new(options.thresholds.clone())
                 .unwrap_or_else(|err| exit_error!("Invalid thresholds: {}", err)),
         );
+        let groups = Arc::new(
+            GroupDeterminers::new(options.threshold_groups.clone(), options.groups.clone())
+                .unwrap_or_else(|err| exit_error!("Invalid group thresholds: {}", err)),
+        );
+        let origin = options
+            .origin()
+            .unwrap_or_else(|| exit_error!("Invalid origin method"));
 
-        let pool = SqlitePoolOptions::new()
-            .max_connections(1)
+        let statement_timeout = options.db_statement_timeout.map(Duration::from);
+        let pool_options = SqlitePoolOptions::new().max_connections(1);
+        let pool_options = match statement_timeout {
+            Some(timeout) => query::statement_timeout(pool_options, timeout),
+            None => pool_options,
+        };
+        // A statement waiting for a lock isn't running, the wait is bounded by the timeout too.
+        let busy_timeout = statement_timeout.map_or(Duration::from_secs(30), |timeout| {
+            timeout.min(Duration::from_secs(30))
+        });
+        let pool = pool_options
             .connect_with(
                 SqliteConnectOptions::new()
                     .filename(&options.database)
                     .create_if_missing(!options.no_database_creation)
-                    .busy_timeout(Duration::from_secs(30)),
+                    .busy_timeout(busy_timeout),
             )
             .await
             .unwrap_or_else(|err| exit_error!("Cannot create database pool: {}", err));
-        sqlx::query(include_query!("migration"))
-            .execute(&pool)
+        if let Some(url) = &options.webhook_url {
+            webhook::init(url.clone(), options.webhook_events.clone())
+                .unwrap_or_else(|err| exit_error!("{}", err));
+        }
+        query::migrate(&pool)
             .await
             .unwrap_or_else(|err| exit_error!("Cannot run migration query: {}", err));
 
-        let dir = Dir::new(options.uploads_dir.clone());
+        let key = options
+            .encryption_key_file
+            .as_deref()
+            .map(EncryptionKey::from_file)
+            .transpose()
+            .unwrap_or_else(|err| exit_error!("Invalid encryption key: {}", err));
+        let dir = Dir::new(options.uploads_dir.clone(), options.isolate_origins).with_key(key);
         dir.create(!options.no_uploads_dir_creation)
             .await
             .unwrap_or_else(|err| exit_error!("{}", err));
 
-        let cleaner = Cleaner::new(dir.clone(), pool.clone());
-        tokio::task::spawn(async move {
-            cleaner.start().await;
+        let shutdown = Shutdown::listen();
+        let inflight = InflightBytes::new(options.max_inflight_bytes);
+        let cleaner = Cleaner::new(
+            dir.clone(),
+            pool.clone(),
+            inflight.clone(),
+            options.clean_interval.into(),
+            options.cleanup_disk_threshold,
+            options.cleanup_min_age.into(),
+            options.trash_retention.map(Into::into),
+            options.download_log_retention.into(),
+        );
+        let cleaning = tokio::task::spawn({
+            let shutdown = shutdown.clone();
+            async move {
+                cleaner.start(shutdown).await;
+            }
         });
+        if let Some(interval) = options.verify_interval {
+            let verifier = Verifier::new(dir.clone(), pool.clone(), interval.into());
+            tokio::task::spawn(async move {
+                verifier.start().await;
+            });
+        }
+
+        let ui_dir = UiDir::new(options.ui_dir.clone())
+            .unwrap_or_else(|err| exit_error!("Invalid UI directory: {}", err));
 
         let authenticator = Arc::new(Authenticator::new(
             options.access(),
             options.credentials.clone(),
             options.ldap_authenticator(),
+            options.admins.clone(),
         ));
 
+        let real_ip =
+            RealIp::new(options.behind_proxy).with_trusted_proxies(options.trusted_proxies.clone());
+        let pending = PendingUploads::default();
         let router = Router::new()
-            .merge(super::assets::router())
+            .merge(super::assets::router(ui_dir))
             .merge(super::theme::router(&options.theme))
             .merge(super::auth::router(Arc::clone(&authenticator)))
-            .merge(super::upload::router(
+            .merge(super::cidr::restrict(
+                super::upload::router(
+                    pool.clone(),
+                    Arc::clone(&authenticator),
+                    real_ip.clone(),
+                    origin,
+                    limiters,
+                    RateLimiter::new(options.upload_rate)
+                        .with_overrides(&options.credential_limits),
+                    Arc::clone(&determiner),
+                    Arc::clone(&groups),
+                    dir.clone(),
+                    inflight,
+                    LoadShedder::new(options.shed_load_average),
+                    Transform::new(options.upload_transform.clone()),
+                    SniffBytes(options.sniff_bytes),
+                    CompressAtRest(options.compress_at_rest),
+                    DeduplicateUploads(options.deduplicate_uploads),
+                    MaxDownloads(options.upload_max_downloads),
+                    PasswordPolicy::new(
+                        options.download_password_min_length,
+                        options.download_password_classes.clone(),
+                    ),
+                    options.unique_names_per_origin,
+                    pending.clone(),
+                ),
+                CidrFilter::new(
+                    options.upload_allowed_cidrs.clone(),
+                    options.upload_denied_cidrs.clone(),
+                ),
+                real_ip.clone(),
+            ))
+            .merge(super::cidr::restrict(
+                super::download::router(
+                    pool.clone(),
+                    Arc::clone(&authenticator),
+                    dir.clone(),
+                    ArchiveLimit {
+                        files: options.archive_max_files,
+                        bytes: options.archive_max_bytes,
+                    },
+                    options.default_archive_format,
+                    RefererPolicy::new(
+                        options.allowed_referers.clone(),
+                        options.deny_direct_downloads,
+                    ),
+                    WriteRetries(options.download_write_retries),
+                    real_ip.clone(),
+                    RecentDownloads::new(options.download_dedup_window.map(Into::into)),
+                    PasswordAttempts::new(options.download_password_rate),
+                    LookupRate::new(options.lookup_rate),
+                    DownloadRate(options.download_rate_limit.filter(|&rate| rate > 0)),
+                ),
+                CidrFilter::new(
+                    options.download_allowed_cidrs.clone(),
+                    options.download_denied_cidrs.clone(),
+                ),
+                real_ip.clone(),
+            ))
+            .merge(super::update::router(
                 pool.clone(),
-                Arc::clone(&authenticator),
-                RealIp::new(options.behind_proxy),
-                options
-                    .origin()
-                    .unwrap_or_else(|| exit_error!("Invalid origin method")),
-                limiters,
-                Arc::clone(&determiner),
                 dir.clone(),
+                Arc::clone(&determiner),
+                groups,
+                origin,
+                SoftDelete(options.trash_retention.is_some()),
             ))
-            .merge(super::download::router(
+            .merge(super::admin::router(
                 pool.clone(),
                 Arc::clone(&authenticator),
                 dir.clone(),
             ))
-            .merge(super::update::router(
+            .merge(super::info::router(
                 pool.clone(),
-                dir.clone(),
+                Arc::clone(&authenticator),
                 Arc::clone(&determiner),
             ))
-            .merge(super::info::router(pool.clone()))
+            .merge(if options.enable_feed {
+                super::feed::router(pool.clone(), Arc::clone(&authenticator))
+            } else {
+                Router::new()
+            })
+            .merge(if options.enable_metrics {
+                super::metrics::router(pool.clone(), Arc::clone(&authenticator))
+            } else {
+                Router::new()
+            })
             .route_layer(
                 Negotiator::<ContentTypeNegotiation, _>::new([
                     ResponseType::Json,
                     ResponseType::Text,
                 ])
                 .unwrap_or_else(|err| exit_error!("Invalid mime types: {}", err)),
-            );
+            )
+            .merge(super::health::router(pool.clone(), dir.clone()))
+            // Forwarded headers are trusted for the same peers by all the routes.
+            .layer(Extension(real_ip.clone()));
+        let router = if options.enable_metrics {
+            router.layer(middleware::from_fn(super::metrics::layer))
+        } else {
+            router
+        };
+        let router = router.layer(super::compression::layer(options.compression_min_size));
+        // Timings may leak internal details, they are only sent when explicitly enabled.
+        let router = if options.server_timing {
+            router.layer(middleware::from_fn(super::timing::layer))
+        } else {
+            router
+        };
+        let router = {
+            let real_ip = real_ip.clone();
+            router.layer(middleware::from_fn(move |req, next| {
+                super::logger::layer(req, next, real_ip.clone())
+            }))
+        };
+        let router = match options.log_format {
+            LogFormat::Clf | LogFormat::Combined => {
+                let log = AccessLog::open(options.log_format, options.access_log_file.as_deref())
+                    .unwrap_or_else(|err| exit_error!("Cannot open access log: {}", err));
+                router.layer(middleware::from_fn(move |req, next| {
+                    super::access::layer(req, next, log.clone(), real_ip.clone())
+                }))
+            }
+            _ if options.access_log_file.is_some() => {
+                exit_error!("An access log file requires the clf or combined log format")
+            }
+            _ => router,
+        };
+        // Paths have to be normalized before being routed.
+        let router = Router::new().fallback(router.map_request(normalize_trailing_slash));
 
+        tracing::info!("{}", options.summary());
         let address = SocketAddr::new(options.address, options.port);
-        log::info!("App is running on: {}", address);
-        Server::bind(&address)
-            .http1_title_case_headers(true)
-            .serve(router.into_make_service_with_connect_info::<SocketAddr>())
-            .await
-            .unwrap_or_else(|err| exit_error!("Server stopped: {}", err))
+        let serving = async {
+            match (&options.tls_cert, &options.tls_key) {
+                (Some(cert), Some(key)) => {
+                    let config = super::tls::server_config(
+                        cert,
+                        key,
+                        options.tls_client_ca.as_deref(),
+                        options.tls_min_version,
+                    )
+                    .unwrap_or_else(|err| exit_error!("Invalid TLS configuration: {}", err));
+                    tracing::info!("App is running on: https://{}", address);
+                    super::tls::serve(address, Arc::new(config), router, shutdown.clone())
+                        .await
+                        .unwrap_or_else(|err| exit_error!("Server stopped: {}", err))
+                }
+                _ => {
+                    tracing::info!("App is running on: http://{}", address);
+                    Server::bind(&address)
+                        .http1_title_case_headers(true)
+                        .serve(router.into_make_service_with_connect_info::<SocketAddr>())
+                        .with_graceful_shutdown(shutdown.notified())
+                        .await
+                        .unwrap_or_else(|err| exit_error!("Server stopped: {}", err))
+                }
+            }
+        };
+        // New connections are refused once a signal is received, the active requests are awaited
+        // until the timeout.
+        let timeout = async {
+            shutdown.notified().await;
+            tokio::time::sleep(options.shutdown_timeout.into()).await;
+        };
+        tokio::select! {
+            _ = serving => (),
+            _ = timeout => tracing::warn!("Shutdown timeout reached, interrupting the active requests"),
+        }
+
+        pending.clean(&dir, &pool).await;
+        if let Err(err) = cleaning.await {
+            tracing::error!("Cleaner task failed: {}", err);
+        }
+        pool.close().await;
+        tracing::info!("Server stopped");
     }
 }
 
@@ -0,0 +1,202 @@
+use std::{
+    fmt::Write,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+};
+
+use axum::{
+    headers::{authorization::Basic, Authorization, Cookie},
+    middleware::Next,
+    response::Response,
+    routing::get,
+    Extension, Router, TypedHeader,
+};
+use hyper::{Body, Request};
+use sqlx::{FromRow, SqlitePool};
+
+use crate::{
+    access::AccessUser,
+    auth::{AuthStatus, Authenticator, ClientCertificate, Features},
+    error::{auth as AuthError, metrics as MetricsError, Error},
+    include_query,
+    query::with_timeout,
+    response::sized_body,
+};
+
+pub struct Counter(AtomicU64);
+
+impl Counter {
+    const fn new() -> Self {
+        Self(AtomicU64::new(0))
+    }
+
+    pub fn add(&self, value: u64) {
+        self.0.fetch_add(value, Ordering::Relaxed);
+    }
+
+    pub fn increment(&self) {
+        self.add(1);
+    }
+
+    fn get(&self) -> u64 {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+// Counters since the server started, updated even if the endpoint isn't enabled.
+pub static UPLOADS: Counter = Counter::new();
+pub static UPLOADED_BYTES: Counter = Counter::new();
+pub static DOWNLOADS: Counter = Counter::new();
+pub static CLEANED_FILES: Counter = Counter::new();
+
+// Indexed by status code minus 100, only recorded when the endpoint is enabled.
+#[allow(clippy::declare_interior_mutable_const)]
+const NO_RESPONSE: Counter = Counter::new();
+static RESPONSES: [Counter; 500] = [NO_RESPONSE; 500];
+
+#[derive(FromRow)]
+struct Stored {
+    files: i64,
+    bytes: i64,
+}
+
+pub async fn layer(req: Request<Body>, next: Next<Body>) -> Response {
+    let response = next.run(req).await;
+    if let Some(counter) = RESPONSES.get((response.status().as_u16() as usize).wrapping_sub(100)) {
+        counter.increment();
+    }
+    response
+}
+
+async fn handler(
+    Extension(pool): Extension<SqlitePool>,
+    authenticator: Extension<Arc<Authenticator>>,
+    auth_header: Option<TypedHeader<Authorization<Basic>>>,
+    cookie: Option<TypedHeader<Cookie>>,
+    certificate: Option<Extension<ClientCertificate>>,
+    user: AccessUser,
+) -> Result<Response, Error> {
+    // Metrics are protected like the other information endpoints.
+    match authenticator
+        .allows(
+            auth_header.map(|h| h.0),
+            cookie.map(|h| h.0),
+            certificate.and_then(|Extension(ClientCertificate(cn))| cn),
+            Features::DOWNLOAD,
+            &user,
+        )
+        .await
+    {
+        AuthStatus::NotNeeded | AuthStatus::Valid(_) => (),
+        AuthStatus::Error(err) => return Err(err),
+        AuthStatus::Prompt => return Err(AuthError::MissingAuthorization),
+    }
+
+    // Gauges are read from the database on every scrape, they survive restarts.
+    let stored = with_timeout(
+        sqlx::query_as::<_, Stored>(include_query!("get_files_stored")).fetch_one(&pool),
+    )
+    .await?
+    .map_err(|_| MetricsError::Database)?;
+    Ok(sized_body(
+        "text/plain; version=0.0.4; charset=utf-8",
+        exposition(&stored).into_bytes(),
+    ))
+}
+
+// Prometheus text exposition format.
+fn exposition(stored: &Stored) -> String {
+    let mut text = String::new();
+    for (name, kind, help, value) in [
+        (
+            "dropit_uploads_total",
+            "counter",
+            "Files uploaded since the server started.",
+            UPLOADS.get(),
+        ),
+        (
+            "dropit_uploaded_bytes_total",
+            "counter",
+            "Bytes uploaded since the server started.",
+            UPLOADED_BYTES.get(),
+        ),
+        (
+            "dropit_downloads_total",
+            "counter",
+            "Counted downloads since the server started.",
+            DOWNLOADS.get(),
+        ),
+        (
+            "dropit_cleaned_files_total",
+            "counter",
+            "Expired or evicted files removed since the server started.",
+            CLEANED_FILES.get(),
+        ),
+        (
+            "dropit_files",
+            "gauge",
+            "Files currently stored, excluding revoked ones.",
+            stored.files.max(0) as u64,
+        ),
+        (
+            "dropit_stored_bytes",
+            "gauge",
+            "Cumulative size of the files currently stored, excluding revoked ones.",
+            stored.bytes.max(0) as u64,
+        ),
+    ] {
+        let _ = write!(
+            text,
+            "# HELP {0} {1}\n# TYPE {0} {2}\n{0} {3}\n",
+            name, help, kind, value
+        );
+    }
+    text.push_str(concat!(
+        "# HELP dropit_responses_total HTTP responses sent since the server started.\n",
+        "# TYPE dropit_responses_total counter\n",
+    ));
+    for (index, counter) in RESPONSES.iter().enumerate() {
+        let count = counter.get();
+        if count > 0 {
+            let _ = writeln!(
+                text,
+                r#"dropit_responses_total{{code="{}"}} {}"#,
+                index + 100,
+                count
+            );
+        }
+    }
+    text
+}
+
+pub fn router(pool: SqlitePool, authenticator: Arc<Authenticator>) -> Router {
+    Router::new()
+        .route("/metrics", get(handler))
+        .route_layer(Extension(pool))
+        .route_layer(Extension(authenticator))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{exposition, Stored, RESPONSES};
+
+    #[test]
+    fn exposition_format() {
+        RESPONSES[404 - 100].increment();
+        let text = exposition(&Stored {
+            files: 3,
+            bytes: 2048,
+        });
+        assert!(text.contains(concat!(
+            "# HELP dropit_files Files currently stored, excluding revoked ones.\n",
+            "# TYPE dropit_files gauge\n",
+            "dropit_files 3\n",
+        )));
+        assert!(text.contains("dropit_stored_bytes 2048\n"));
+        assert!(text.contains("# TYPE dropit_uploads_total counter\n"));
+        assert!(text.contains("dropit_responses_total{code=\"404\"} 1\n"));
+        assert!(!text.contains("code=\"500\""));
+    }
+}
@@ -1,10 +1,12 @@
-use std::time::Duration;
+use std::{convert::TryFrom, time::Duration};
+
+use hyper::{http::uri::PathAndQuery, Request, Uri};
 
 #[macro_export]
 macro_rules! exit_error {
     ($($arg:tt)+) => {
         {
-            log::error!($($arg)+);
+            tracing::error!($($arg)+);
             std::process::exit(1)
         }
     }
@@ -53,3 +55,84 @@ pub fn format_duration(duration: Duration) -> String {
         )
     }
 }
+
+pub fn escape_html(input: &str) -> String {
+    let mut escaped = String::with_capacity(input.len());
+    for c in input.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+// Web UI directories, where the trailing slash matters for relative links.
+const DIRECTORIES: &[&str] = &["/", "/login/"];
+
+fn trim_trailing_slash(path: &str) -> Option<&str> {
+    if DIRECTORIES.contains(&path) || !path.ends_with('/') {
+        return None;
+    }
+    match path.trim_end_matches('/') {
+        "" => None,
+        trimmed => Some(trimmed),
+    }
+}
+
+// Makes "/:alias/" and its sub-resources resolve like "/:alias".
+pub fn normalize_trailing_slash<B>(mut req: Request<B>) -> Request<B> {
+    let path = match trim_trailing_slash(req.uri().path()) {
+        Some(path) => path.to_owned(),
+        None => return req,
+    };
+    let path_and_query = match req.uri().query() {
+        Some(query) => format!("{}?{}", path, query),
+        None => path,
+    };
+    let mut parts = req.uri().clone().into_parts();
+    parts.path_and_query = match PathAndQuery::try_from(path_and_query) {
+        Ok(path_and_query) => Some(path_and_query),
+        Err(_) => return req,
+    };
+    if let Ok(uri) = Uri::from_parts(parts) {
+        *req.uri_mut() = uri;
+    }
+    req
+}
+
+#[cfg(test)]
+mod tests {
+    use hyper::Request;
+
+    use super::{normalize_trailing_slash, trim_trailing_slash};
+
+    #[test]
+    fn trailing_slash() {
+        assert_eq!(trim_trailing_slash("/abc123/"), Some("/abc123"));
+        assert_eq!(trim_trailing_slash("/abc123//"), Some("/abc123"));
+        assert_eq!(
+            trim_trailing_slash("/abc123/alias/short/"),
+            Some("/abc123/alias/short")
+        );
+        assert_eq!(trim_trailing_slash("/abc123"), None);
+        assert_eq!(trim_trailing_slash("/"), None);
+        assert_eq!(trim_trailing_slash("//"), None);
+        assert_eq!(trim_trailing_slash("/login/"), None);
+
+        let req = normalize_trailing_slash(
+            Request::get("/abc123/?force-download=true")
+                .body(())
+                .unwrap(),
+        );
+        assert_eq!(req.uri().path(), "/abc123");
+        assert_eq!(req.uri().query(), Some("force-download=true"));
+
+        let req = normalize_trailing_slash(Request::get("/abc123").body(()).unwrap());
+        assert_eq!(req.uri().path(), "/abc123");
+    }
+}
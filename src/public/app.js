@@ -20,8 +20,8 @@ function shouldLogin() {
                 ready();
             }
         } else {
-            alert(`An error occurred while checking for session token: ${req.response.error}.`);
-            console.error(`An error occurred while checking for session token: ${req.response.error}.`);
+            alert(`An error occurred while checking for session token: ${req.response.message}.`);
+            console.error(`An error occurred while checking for session token: ${req.response.message}.`);
         }
     };
     req.send();
@@ -120,7 +120,7 @@ function ready() {
                             }
                         }
                     } else {
-                        console.error(`An error occurred while checking expired remote files: ${req.response.error}.`);
+                        console.error(`An error occurred while checking expired remote files: ${req.response.message}.`);
                     }
                     this.updateButtons();
                 };
@@ -389,7 +389,7 @@ function ready() {
                                 }
                                 FILES.save();
                             } else {
-                                alert(`An error occured while trying to generate ${t === 'both' ? 'new aliases' : 'a new alias'}: ${req.response.error}.`);
+                                alert(`An error occured while trying to generate ${t === 'both' ? 'new aliases' : 'a new alias'}: ${req.response.message}.`);
                             }
                         }
                         req.send();
@@ -431,7 +431,7 @@ function ready() {
                             FILES.save();
                             updateExpirationLabel();
                         } else {
-                            alert(`An error occured while trying to extend expiration: ${req.response.error}.`);
+                            alert(`An error occured while trying to extend expiration: ${req.response.message}.`);
                         }
                     };
                     req.send();
@@ -525,7 +525,7 @@ function ready() {
                     req.onload = () => {
                         if (req.status === 200) {
                         } else {
-                            alert(`An error occurred while trying to set downloads limit: ${req.response.error}.`);
+                            alert(`An error occurred while trying to set downloads limit: ${req.response.message}.`);
                         }
                     };
                     req.send();
@@ -556,7 +556,7 @@ function ready() {
                         if (req.status === 200) {
                             FILES.remove(this);
                         } else {
-                            alert(`An error occurred while trying to revoke this file: ${req.response.error}.`);
+                            alert(`An error occurred while trying to revoke this file: ${req.response.message}.`);
                         }
                     };
                     req.send();
@@ -592,7 +592,7 @@ function ready() {
 
             const error = document.createElement('div');
             error.classList.add('error-message');
-            error.innerText = data.error.toTitleCase();
+            error.innerText = data.message.toTitleCase();
 
             if (this.progressBar) this.progressBar.remove();
             if (this.percent) this.percent.remove();
@@ -12,8 +12,8 @@ document.getElementById('login-form').addEventListener('submit', (event) => {
             document.cookie = `session=${req.response.token}; path=/; max-age=${60*60*24*28}`;
             window.location = '/';
         } else {
-            alert(`An error occurred while login in: ${req.response.error}.`);
-            console.error(`An error occurred while login in: ${req.response.error}.`);
+            alert(`An error occurred while login in: ${req.response.message}.`);
+            console.error(`An error occurred while login in: ${req.response.message}.`);
         }
     };
     req.send(JSON.stringify(
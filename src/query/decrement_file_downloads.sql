@@ -0,0 +1,4 @@
+UPDATE files
+SET downloads = downloads - 1
+WHERE id = ? AND downloads > 0 AND pinned = 0
+RETURNING downloads;
\ No newline at end of file
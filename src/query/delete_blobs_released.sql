@@ -0,0 +1,2 @@
+DELETE FROM blobs
+WHERE refs < 1;
\ No newline at end of file
@@ -0,0 +1,2 @@
+DELETE FROM download_log
+WHERE downloaded < ?;
\ No newline at end of file
@@ -1,3 +1,3 @@
 SELECT 1
 FROM files
-WHERE long_alias = ?;
\ No newline at end of file
+WHERE ? IN (long_alias, previous_long_alias);
\ No newline at end of file
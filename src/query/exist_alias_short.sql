@@ -1,3 +1,3 @@
 SELECT 1
 FROM files
-WHERE short_alias = ?;
\ No newline at end of file
+WHERE ? IN (short_alias, previous_short_alias);
\ No newline at end of file
@@ -0,0 +1,5 @@
+SELECT downloaded, address
+FROM download_log
+WHERE file_id = ?
+ORDER BY downloaded DESC, rowid DESC
+LIMIT ? OFFSET ?;
\ No newline at end of file
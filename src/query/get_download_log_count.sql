@@ -0,0 +1,3 @@
+SELECT COUNT(*)
+FROM download_log
+WHERE file_id = ?;
\ No newline at end of file
@@ -0,0 +1,5 @@
+SELECT rowid, downloaded, address
+FROM download_log
+WHERE file_id = ? AND rowid > ?
+ORDER BY rowid
+LIMIT ?;
\ No newline at end of file
@@ -1,3 +0,0 @@
-SELECT id, IFNULL(name, long_alias) AS name, size
-FROM files
-WHERE short_alias = ? OR long_alias = ?;
\ No newline at end of file
@@ -0,0 +1,4 @@
+SELECT id, compressed, nonce
+FROM files
+WHERE hash = ? AND id != ? AND deleted IS NULL
+LIMIT 1;
\ No newline at end of file
@@ -0,0 +1,4 @@
+SELECT short_alias
+FROM files
+WHERE origin = ? AND name = ? AND expiration > ? AND deleted IS NULL
+LIMIT 1;
\ No newline at end of file
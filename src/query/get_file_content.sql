@@ -0,0 +1,4 @@
+SELECT IFNULL(blobs.origin, files.origin) AS origin, IFNULL(blobs.id, files.id) AS id, blobs.refs
+FROM files
+LEFT JOIN blobs ON blobs.id = files.blob
+WHERE files.id = ?;
\ No newline at end of file
@@ -1,3 +1,3 @@
-SELECT downloads
+SELECT downloads, origin, burn, pinned, short_alias, long_alias, IFNULL(name, long_alias) AS name, size
 FROM files
 WHERE id = ?;
\ No newline at end of file
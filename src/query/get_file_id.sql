@@ -0,0 +1,3 @@
+SELECT id
+FROM files
+WHERE short_alias = ? OR long_alias = ?;
\ No newline at end of file
@@ -0,0 +1,5 @@
+SELECT IFNULL(name, long_alias) AS name, size, downloads, expiration, pinned, mime, password
+FROM files
+WHERE (? IN (short_alias, previous_short_alias) OR ? IN (long_alias, previous_long_alias))
+    AND deleted IS NULL AND hash IS NOT NULL
+    AND (expiration > ? OR pinned = 1) AND (downloads IS NULL OR downloads > 0);
\ No newline at end of file
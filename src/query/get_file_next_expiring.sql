@@ -0,0 +1,5 @@
+SELECT short_alias, expiration
+FROM files
+WHERE origin = ? AND pinned = 0 AND deleted IS NULL
+ORDER BY expiration
+LIMIT 1;
\ No newline at end of file
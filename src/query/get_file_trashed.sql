@@ -0,0 +1,3 @@
+SELECT id, origin
+FROM files
+WHERE (short_alias = ? OR long_alias = ?) AND deleted IS NOT NULL;
\ No newline at end of file
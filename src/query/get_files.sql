@@ -0,0 +1,5 @@
+SELECT short_alias, long_alias, IFNULL(name, long_alias) AS name, size, origin, created, expiration, downloads
+FROM files
+WHERE deleted IS NULL
+ORDER BY $SORT $ORDER
+LIMIT ? OFFSET ?;
\ No newline at end of file
@@ -0,0 +1,6 @@
+SELECT id, IFNULL(name, long_alias) AS name, size, origin, downloads, mime, compressed, burn, nonce, password, hash, short_alias, long_alias, previous_short_alias, previous_long_alias,
+    IFNULL((SELECT blobs.origin FROM blobs WHERE blobs.id = files.blob), origin) AS blob_origin, IFNULL(blob, id) AS blob_id
+FROM files
+WHERE (short_alias IN (SELECT value FROM json_each(?)) OR long_alias IN (SELECT value FROM json_each(?))
+    OR previous_short_alias IN (SELECT value FROM json_each(?)) OR previous_long_alias IN (SELECT value FROM json_each(?)))
+    AND deleted IS NULL;
\ No newline at end of file
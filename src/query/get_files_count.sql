@@ -0,0 +1,3 @@
+SELECT COUNT(*)
+FROM files
+WHERE deleted IS NULL;
\ No newline at end of file
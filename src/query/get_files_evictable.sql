@@ -0,0 +1,4 @@
+SELECT id, size, deleted IS NOT NULL AS trashed
+FROM files
+WHERE deleted IS NOT NULL OR ((created IS NULL OR created < ?) AND pinned = 0)
+ORDER BY deleted IS NULL, expiration;
\ No newline at end of file
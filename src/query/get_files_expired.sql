@@ -1,3 +1,3 @@
-SELECT id
+SELECT id, origin, size, short_alias, long_alias, IFNULL(name, long_alias) AS name, expiration
 FROM files
-WHERE expiration < ?;
\ No newline at end of file
+WHERE expiration < ? AND pinned = 0 AND deleted IS NULL;
\ No newline at end of file
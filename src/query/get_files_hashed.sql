@@ -0,0 +1,3 @@
+SELECT DISTINCT IFNULL(blob, id), IFNULL((SELECT blobs.origin FROM blobs WHERE blobs.id = files.blob), origin), hash, compressed, nonce
+FROM files
+WHERE hash IS NOT NULL AND deleted IS NULL;
\ No newline at end of file
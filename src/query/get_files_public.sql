@@ -0,0 +1,6 @@
+SELECT short_alias, IFNULL(name, long_alias) AS name, size, IFNULL(created, 0) AS created
+FROM files
+WHERE public = 1 AND deleted IS NULL AND hash IS NOT NULL
+    AND (expiration > ? OR pinned = 1) AND (downloads IS NULL OR downloads > 0)
+ORDER BY created DESC
+LIMIT ?;
\ No newline at end of file
@@ -0,0 +1,3 @@
+SELECT COUNT(*) AS files, COALESCE(SUM(size), 0) AS bytes
+FROM files
+WHERE deleted IS NULL;
\ No newline at end of file
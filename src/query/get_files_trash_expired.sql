@@ -0,0 +1,3 @@
+SELECT id, size
+FROM files
+WHERE deleted < ?;
\ No newline at end of file
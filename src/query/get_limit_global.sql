@@ -1,2 +1 @@
-SELECT SUM(size) AS size
-FROM files;
\ No newline at end of file
+SELECT IFNULL((SELECT SUM(size) FROM files WHERE id IS NOT ?), 0) + IFNULL((SELECT SUM(size) FROM upload_sessions WHERE id IS NOT ?), 0) AS size;
\ No newline at end of file
@@ -1,3 +1,6 @@
-SELECT SUM(size) AS size, COUNT(*) AS file
-FROM files
-WHERE origin = ?;
\ No newline at end of file
+SELECT IFNULL(SUM(size), 0) AS size, COUNT(*) AS file
+FROM (
+    SELECT size FROM files WHERE origin = ? AND id IS NOT ?
+    UNION ALL
+    SELECT size FROM upload_sessions WHERE origin = ? AND id IS NOT ?
+);
\ No newline at end of file
@@ -0,0 +1,3 @@
+SELECT origin, username, name, size, received
+FROM upload_sessions
+WHERE id = ?;
\ No newline at end of file
@@ -0,0 +1,3 @@
+SELECT id
+FROM upload_sessions
+WHERE updated < ?;
\ No newline at end of file
@@ -0,0 +1,3 @@
+INSERT INTO blobs (id, origin, refs)
+VALUES (?, ?, 2)
+ON CONFLICT (id) DO UPDATE SET refs = refs + 1;
\ No newline at end of file
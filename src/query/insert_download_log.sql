@@ -0,0 +1,2 @@
+INSERT INTO download_log (file_id, downloaded, address)
+VALUES (?, ?, ?);
\ No newline at end of file
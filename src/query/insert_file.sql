@@ -1,2 +1,2 @@
-INSERT INTO files (id, admin, origin, expiration, name, size, short_alias, long_alias)
-VALUES (?, ?, ?, ?, ?, ?, ?, ?);
\ No newline at end of file
+INSERT INTO files (id, admin, origin, expiration, name, size, short_alias, long_alias, created, pinned, public)
+VALUES (?, ?, ?, ?, ?, ?, ?, ?, CAST(strftime('%s', 'now') AS INTEGER), ?, ?);
\ No newline at end of file
@@ -0,0 +1,2 @@
+INSERT INTO upload_sessions (id, origin, username, name, size, updated)
+VALUES (?, ?, ?, ?, ?, CAST(strftime('%s', 'now') AS INTEGER));
\ No newline at end of file
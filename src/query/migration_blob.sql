@@ -0,0 +1,2 @@
+ALTER TABLE files
+ADD COLUMN blob TEXT;
\ No newline at end of file
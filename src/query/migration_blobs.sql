@@ -0,0 +1,5 @@
+CREATE TABLE IF NOT EXISTS blobs (
+    id TEXT NOT NULL PRIMARY KEY,
+    origin TEXT NOT NULL,
+    refs INTEGER NOT NULL
+);
\ No newline at end of file
@@ -0,0 +1,2 @@
+ALTER TABLE files
+ADD COLUMN created INTEGER;
\ No newline at end of file
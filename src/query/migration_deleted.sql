@@ -0,0 +1,2 @@
+ALTER TABLE files
+ADD COLUMN deleted INTEGER;
\ No newline at end of file
@@ -0,0 +1,5 @@
+CREATE TABLE IF NOT EXISTS download_log (
+    file_id TEXT NOT NULL,
+    downloaded INTEGER NOT NULL,
+    address TEXT
+);
\ No newline at end of file
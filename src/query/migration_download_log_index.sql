@@ -0,0 +1,2 @@
+CREATE INDEX IF NOT EXISTS download_log_file
+ON download_log (file_id, downloaded);
\ No newline at end of file
@@ -0,0 +1,2 @@
+CREATE INDEX IF NOT EXISTS files_hash
+ON files (hash);
\ No newline at end of file
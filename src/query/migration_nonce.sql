@@ -0,0 +1,2 @@
+ALTER TABLE files
+ADD COLUMN nonce BLOB;
\ No newline at end of file
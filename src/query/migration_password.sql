@@ -0,0 +1,2 @@
+ALTER TABLE files
+ADD COLUMN password TEXT;
\ No newline at end of file
@@ -0,0 +1,2 @@
+ALTER TABLE files
+ADD COLUMN pinned INTEGER NOT NULL DEFAULT 0;
\ No newline at end of file
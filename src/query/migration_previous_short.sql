@@ -0,0 +1,2 @@
+ALTER TABLE files
+ADD COLUMN previous_short_alias TEXT;
\ No newline at end of file
@@ -0,0 +1,5 @@
+UPDATE files
+SET long_alias = long_alias || '-' || rowid
+WHERE rowid NOT IN (SELECT MIN(rowid) FROM files GROUP BY long_alias);
+CREATE UNIQUE INDEX IF NOT EXISTS files_long_alias
+ON files (long_alias);
\ No newline at end of file
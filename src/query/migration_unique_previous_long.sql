@@ -0,0 +1,28 @@
+UPDATE files
+SET previous_long_alias = NULL
+WHERE EXISTS (
+    SELECT 1 FROM files AS other
+    WHERE other.id != files.id AND (files.previous_long_alias = other.long_alias
+        OR (files.previous_long_alias = other.previous_long_alias AND other.rowid < files.rowid))
+);
+CREATE INDEX IF NOT EXISTS files_previous_long_alias
+ON files (previous_long_alias);
+CREATE TRIGGER IF NOT EXISTS files_unique_previous_long_alias_insert
+BEFORE INSERT ON files
+WHEN EXISTS (
+    SELECT 1 FROM files
+    WHERE NEW.long_alias = previous_long_alias OR NEW.previous_long_alias IN (long_alias, previous_long_alias)
+)
+BEGIN
+    SELECT RAISE(ABORT, 'UNIQUE constraint failed: files.previous_long_alias');
+END;
+CREATE TRIGGER IF NOT EXISTS files_unique_previous_long_alias_update
+BEFORE UPDATE OF long_alias, previous_long_alias ON files
+WHEN EXISTS (
+    SELECT 1 FROM files
+    WHERE id != NEW.id
+        AND (NEW.long_alias = previous_long_alias OR NEW.previous_long_alias IN (long_alias, previous_long_alias))
+)
+BEGIN
+    SELECT RAISE(ABORT, 'UNIQUE constraint failed: files.previous_long_alias');
+END;
\ No newline at end of file
@@ -0,0 +1,28 @@
+UPDATE files
+SET previous_short_alias = NULL
+WHERE EXISTS (
+    SELECT 1 FROM files AS other
+    WHERE other.id != files.id AND (files.previous_short_alias = other.short_alias
+        OR (files.previous_short_alias = other.previous_short_alias AND other.rowid < files.rowid))
+);
+CREATE INDEX IF NOT EXISTS files_previous_short_alias
+ON files (previous_short_alias);
+CREATE TRIGGER IF NOT EXISTS files_unique_previous_short_alias_insert
+BEFORE INSERT ON files
+WHEN EXISTS (
+    SELECT 1 FROM files
+    WHERE NEW.short_alias = previous_short_alias OR NEW.previous_short_alias IN (short_alias, previous_short_alias)
+)
+BEGIN
+    SELECT RAISE(ABORT, 'UNIQUE constraint failed: files.previous_short_alias');
+END;
+CREATE TRIGGER IF NOT EXISTS files_unique_previous_short_alias_update
+BEFORE UPDATE OF short_alias, previous_short_alias ON files
+WHEN EXISTS (
+    SELECT 1 FROM files
+    WHERE id != NEW.id
+        AND (NEW.short_alias = previous_short_alias OR NEW.previous_short_alias IN (short_alias, previous_short_alias))
+)
+BEGIN
+    SELECT RAISE(ABORT, 'UNIQUE constraint failed: files.previous_short_alias');
+END;
\ No newline at end of file
@@ -0,0 +1,5 @@
+UPDATE files
+SET short_alias = short_alias || '-' || rowid
+WHERE rowid NOT IN (SELECT MIN(rowid) FROM files GROUP BY short_alias);
+CREATE UNIQUE INDEX IF NOT EXISTS files_short_alias
+ON files (short_alias);
\ No newline at end of file
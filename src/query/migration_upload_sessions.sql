@@ -0,0 +1,9 @@
+CREATE TABLE IF NOT EXISTS upload_sessions (
+    id TEXT NOT NULL PRIMARY KEY,
+    origin TEXT NOT NULL,
+    username TEXT,
+    name TEXT,
+    size INTEGER NOT NULL,
+    received INTEGER NOT NULL DEFAULT 0,
+    updated INTEGER NOT NULL
+);
\ No newline at end of file
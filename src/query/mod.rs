@@ -1,3 +1,13 @@
+use std::future::Future;
+
+use sqlx::SqlitePool;
+
+use crate::error::Error;
+
+mod timeout;
+
+pub use timeout::statement_timeout;
+
 #[macro_export]
 macro_rules! include_query {
     ($name:expr) => {
@@ -9,3 +19,276 @@ macro_rules! include_query {
         ))
     };
 }
+
+// Applied in order, the index of the last applied migration is stored as the database's user_version.
+const MIGRATIONS: &[&str] = &[
+    include_query!("migration"),
+    include_query!("migration_created"),
+    include_query!("migration_hash"),
+    include_query!("migration_deleted"),
+    include_query!("migration_unique_short"),
+    include_query!("migration_unique_long"),
+    include_query!("migration_mime"),
+    include_query!("migration_compressed"),
+    include_query!("migration_pinned"),
+    include_query!("migration_public"),
+    include_query!("migration_upload_sessions"),
+    include_query!("migration_burn"),
+    include_query!("migration_nonce"),
+    include_query!("migration_password"),
+    include_query!("migration_hash_index"),
+    include_query!("migration_download_log"),
+    include_query!("migration_download_log_index"),
+    include_query!("migration_previous_short"),
+    include_query!("migration_previous_long"),
+    include_query!("migration_blobs"),
+    include_query!("migration_blob"),
+    include_query!("migration_unique_previous_short"),
+    include_query!("migration_unique_previous_long"),
+];
+
+pub async fn migrate(pool: &SqlitePool) -> Result<(), sqlx::Error> {
+    let (version,) = sqlx::query_as::<_, (i64,)>("PRAGMA user_version")
+        .fetch_one(pool)
+        .await?;
+    for (index, migration) in MIGRATIONS.iter().enumerate().skip(version as usize) {
+        let mut tx = pool.begin().await?;
+        sqlx::query(migration).execute(&mut tx).await?;
+        sqlx::query(&format!("PRAGMA user_version = {}", index + 1))
+            .execute(&mut tx)
+            .await?;
+        tx.commit().await?;
+    }
+    Ok(())
+}
+
+// Two uploads racing for the same alias, only the first insertion succeeds. Previous aliases are
+// spread over two columns, their uniqueness is enforced by the only triggers of the database.
+pub fn is_unique_violation(err: &sqlx::Error) -> bool {
+    const SQLITE_CONSTRAINT_UNIQUE: &str = "2067";
+    const SQLITE_CONSTRAINT_TRIGGER: &str = "1811";
+    err.as_database_error()
+        .and_then(|err| err.code())
+        .map_or(false, |code| {
+            code == SQLITE_CONSTRAINT_UNIQUE || code == SQLITE_CONSTRAINT_TRIGGER
+        })
+}
+
+// Result of a database operation, which fails if its statement was interrupted by the timeout.
+pub trait Interruptible {
+    fn is_interrupted(&self) -> bool;
+}
+
+impl<T> Interruptible for Result<T, sqlx::Error> {
+    fn is_interrupted(&self) -> bool {
+        const SQLITE_INTERRUPT: &str = "9";
+        match self {
+            Err(sqlx::Error::PoolTimedOut) => true,
+            Err(err) => err
+                .as_database_error()
+                .and_then(|err| err.code())
+                .map_or(false, |code| code == SQLITE_INTERRUPT),
+            Ok(_) => false,
+        }
+    }
+}
+
+// Stores and alias generators discard their errors, an interruption fails them like any other one.
+impl<T> Interruptible for Option<T> {
+    fn is_interrupted(&self) -> bool {
+        false
+    }
+}
+
+// Prevents a slow operation from holding a connection of the (small) pool indefinitely, the pool
+// interrupts its statements once the timeout elapsed.
+pub async fn with_timeout<F>(future: F) -> Result<F::Output, Error>
+where
+    F: Future,
+    F::Output: Interruptible,
+{
+    let output = future.await;
+    if output.is_interrupted() {
+        return Err(Error::DatabaseTimeout);
+    }
+    Ok(output)
+}
+
+// Migrated in-memory database for the tests. Each connection would open its own database.
+#[cfg(test)]
+pub async fn memory_pool() -> SqlitePool {
+    let pool = sqlx::sqlite::SqlitePoolOptions::new()
+        .max_connections(1)
+        .connect("sqlite::memory:")
+        .await
+        .unwrap();
+    migrate(&pool).await.unwrap();
+    pool
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use sqlx::{sqlite::SqlitePoolOptions, SqlitePool};
+
+    use super::{
+        is_unique_violation, memory_pool, migrate, statement_timeout, with_timeout, MIGRATIONS,
+    };
+    use crate::{error::Error, include_query};
+
+    async fn insert(
+        pool: &SqlitePool,
+        id: &str,
+        short: &str,
+        long: &str,
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query(include_query!("insert_file"))
+            .bind(id)
+            .bind("admin")
+            .bind("127.0.0.1")
+            .bind(i64::MAX)
+            .bind(None::<String>)
+            .bind(0)
+            .bind(short)
+            .bind(long)
+            .bind(false)
+            .bind(false)
+            .execute(pool)
+            .await
+            .map(|_| ())
+    }
+
+    #[tokio::test]
+    async fn alias_conflict() {
+        let pool = memory_pool().await;
+
+        // Both uploads claim the same alias at the same time, exactly one wins.
+        let (first, second) = tokio::join!(
+            insert(&pool, "first", "aaaaaa", "first-long-alias"),
+            insert(&pool, "second", "aaaaaa", "second-long-alias"),
+        );
+        assert_eq!(
+            [&first, &second].iter().filter(|res| res.is_ok()).count(),
+            1
+        );
+        assert!([first, second]
+            .into_iter()
+            .any(|res| res.map_err(|err| is_unique_violation(&err)) == Err(true)));
+
+        assert!(insert(&pool, "third", "bbbbbb", "first-long-alias")
+            .await
+            .map_err(|err| is_unique_violation(&err))
+            .unwrap_err());
+    }
+
+    #[tokio::test]
+    async fn previous_alias_conflict() {
+        let pool = memory_pool().await;
+        insert(&pool, "first", "aaaaaa", "first-long-alias")
+            .await
+            .unwrap();
+        sqlx::query(include_query!("update_file_aliases_kept"))
+            .bind("bbbbbb")
+            .bind("renewed-long-alias")
+            .bind("first")
+            .execute(&pool)
+            .await
+            .unwrap();
+
+        // The replaced aliases still lead to the renewed file.
+        for (short, long) in [
+            ("aaaaaa", "second-long-alias"),
+            ("cccccc", "first-long-alias"),
+        ] {
+            assert!(insert(&pool, "second", short, long)
+                .await
+                .map_err(|err| is_unique_violation(&err))
+                .unwrap_err());
+        }
+        insert(&pool, "second", "cccccc", "second-long-alias")
+            .await
+            .unwrap();
+        assert!(sqlx::query(include_query!("update_file_aliases_kept"))
+            .bind("dddddd")
+            .bind("first-long-alias")
+            .bind("second")
+            .execute(&pool)
+            .await
+            .map_err(|err| is_unique_violation(&err))
+            .unwrap_err());
+    }
+
+    #[tokio::test]
+    async fn migrate_duplicate_aliases() {
+        let pool = sqlx::sqlite::SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect("sqlite::memory:")
+            .await
+            .unwrap();
+        // Databases created before aliases were unique may hold duplicates.
+        for migration in &MIGRATIONS[..4] {
+            sqlx::query(migration).execute(&pool).await.unwrap();
+        }
+        sqlx::query("PRAGMA user_version = 4")
+            .execute(&pool)
+            .await
+            .unwrap();
+        for (id, short, long) in [
+            ("first", "aaaaaa", "first-long-alias"),
+            ("second", "aaaaaa", "second-long-alias"),
+            ("third", "bbbbbb", "first-long-alias"),
+        ] {
+            sqlx::query(
+                "INSERT INTO files (id, admin, origin, expiration, size, short_alias, long_alias)
+                VALUES (?, 'admin', '127.0.0.1', 0, 0, ?, ?)",
+            )
+            .bind(id)
+            .bind(short)
+            .bind(long)
+            .execute(&pool)
+            .await
+            .unwrap();
+        }
+
+        // The first file keeps its aliases, the duplicates are renamed.
+        migrate(&pool).await.unwrap();
+        let aliases = sqlx::query_as::<_, (String, String, String)>(
+            "SELECT id, short_alias, long_alias FROM files ORDER BY rowid",
+        )
+        .fetch_all(&pool)
+        .await
+        .unwrap();
+        assert_eq!(aliases[0].1, "aaaaaa");
+        assert_eq!(aliases[0].2, "first-long-alias");
+        assert_ne!(aliases[1].1, "aaaaaa");
+        assert_eq!(aliases[1].2, "second-long-alias");
+        assert_eq!(aliases[2].1, "bbbbbb");
+        assert_ne!(aliases[2].2, "first-long-alias");
+    }
+
+    #[tokio::test]
+    async fn interrupted_statement() {
+        let pool = statement_timeout(
+            SqlitePoolOptions::new().max_connections(1),
+            Duration::from_millis(100),
+        )
+        .connect("sqlite::memory:")
+        .await
+        .unwrap();
+
+        // Never ends unless interrupted.
+        let endless = sqlx::query_as::<_, (i64,)>(
+            "WITH RECURSIVE n(x) AS (SELECT 1 UNION ALL SELECT x + 1 FROM n) SELECT COUNT(*) FROM n",
+        );
+        assert!(matches!(
+            with_timeout(endless.fetch_one(&pool)).await,
+            Err(Error::DatabaseTimeout)
+        ));
+        // The connection is released, the next statement gets the whole timeout.
+        assert!(with_timeout(sqlx::query("SELECT 1").execute(&pool))
+            .await
+            .unwrap()
+            .is_ok());
+    }
+}
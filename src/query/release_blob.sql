@@ -0,0 +1,3 @@
+UPDATE blobs
+SET refs = refs - 1
+WHERE id = (SELECT blob FROM files WHERE id = ?);
\ No newline at end of file
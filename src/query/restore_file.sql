@@ -0,0 +1,3 @@
+UPDATE files
+SET deleted = NULL
+WHERE id = ?;
\ No newline at end of file
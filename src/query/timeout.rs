@@ -0,0 +1,63 @@
+use std::{
+    cell::Cell,
+    ffi::c_void,
+    os::raw::{c_int, c_uint},
+    time::{Duration, Instant},
+};
+
+use libsqlite3_sys::{sqlite3_progress_handler, sqlite3_trace_v2, SQLITE_TRACE_STMT};
+use sqlx::sqlite::SqlitePoolOptions;
+
+// Virtual machine instructions run between two checks of the elapsed time.
+const PROGRESS_INSTRUCTIONS: c_int = 1000;
+
+thread_local! {
+    // Each connection runs its statements on its own worker thread.
+    static STARTED: Cell<Option<Instant>> = Cell::new(None);
+}
+
+// Statements running for longer than the timeout are interrupted by SQLite itself, rather than
+// left running once the request gave up on them. Waiting for a connection is bounded too.
+pub fn statement_timeout(options: SqlitePoolOptions, timeout: Duration) -> SqlitePoolOptions {
+    // Shared by the connections of the pool, which lives as long as the process.
+    let timeout: &'static Duration = Box::leak(Box::new(timeout));
+    options
+        .acquire_timeout(*timeout)
+        .after_connect(move |conn, _| {
+            Box::pin(async move {
+                let mut handle = conn.lock_handle().await?;
+                let db = handle.as_raw_handle().as_ptr();
+                let context = timeout as *const Duration as *mut c_void;
+                unsafe {
+                    sqlite3_trace_v2(
+                        db,
+                        SQLITE_TRACE_STMT as c_uint,
+                        Some(statement_started),
+                        context,
+                    );
+                    sqlite3_progress_handler(db, PROGRESS_INSTRUCTIONS, Some(progress), context);
+                }
+                Ok(())
+            })
+        })
+}
+
+unsafe extern "C" fn statement_started(
+    _: c_uint,
+    _: *mut c_void,
+    _: *mut c_void,
+    _: *mut c_void,
+) -> c_int {
+    STARTED.with(|started| started.set(Some(Instant::now())));
+    0
+}
+
+// A non-zero result interrupts the running statement.
+unsafe extern "C" fn progress(context: *mut c_void) -> c_int {
+    let timeout = &*(context as *const Duration);
+    STARTED.with(|started| {
+        started
+            .get()
+            .map_or(false, |started| started.elapsed() > *timeout)
+    }) as c_int
+}
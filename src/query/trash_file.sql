@@ -0,0 +1,3 @@
+UPDATE files
+SET deleted = CAST(strftime('%s', 'now') AS INTEGER)
+WHERE id = ?;
\ No newline at end of file
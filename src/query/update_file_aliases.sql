@@ -1,3 +1,3 @@
 UPDATE files
-SET short_alias = ?, long_alias = ?
+SET short_alias = ?, long_alias = ?, previous_short_alias = NULL, previous_long_alias = NULL
 WHERE id = ?;
\ No newline at end of file
@@ -0,0 +1,3 @@
+UPDATE files
+SET short_alias = ?, long_alias = ?, previous_short_alias = short_alias, previous_long_alias = long_alias
+WHERE id = ?;
\ No newline at end of file
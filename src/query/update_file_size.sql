@@ -0,0 +1,3 @@
+UPDATE files
+SET size = ?
+WHERE id = ?;
\ No newline at end of file
@@ -0,0 +1,3 @@
+UPDATE files
+SET size = ?, name = ?, hash = ?, mime = ?, compressed = ?, burn = ?, downloads = ?, nonce = ?, password = ?, expiration = ?
+WHERE id = ?;
\ No newline at end of file
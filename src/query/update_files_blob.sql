@@ -0,0 +1,3 @@
+UPDATE files
+SET blob = ?
+WHERE id IN (?, ?);
\ No newline at end of file
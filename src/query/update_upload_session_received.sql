@@ -0,0 +1,3 @@
+UPDATE upload_sessions
+SET received = ?, updated = CAST(strftime('%s', 'now') AS INTEGER)
+WHERE id = ?;
\ No newline at end of file
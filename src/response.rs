@@ -1,9 +1,10 @@
-use axum::{
-    response::{IntoResponse, Response},
-    Json,
-};
+use axum::response::{IntoResponse, Response};
 use http_negotiator::AsNegotiationStr;
-use hyper::{HeaderMap, StatusCode};
+use hyper::{
+    header::{CONTENT_LENGTH, CONTENT_TYPE},
+    http::HeaderValue,
+    HeaderMap, StatusCode,
+};
 use serde::Serialize;
 
 pub trait ApiHeader {
@@ -55,6 +56,25 @@ impl AsNegotiationStr for ResponseType {
 
 pub struct ApiResponse<T>(pub ResponseType, pub T);
 
+// Bodies are fully built beforehand, so their length is always explicitly set.
+pub fn sized_body(content_type: &'static str, body: Vec<u8>) -> Response {
+    (
+        [
+            (CONTENT_TYPE, HeaderValue::from_static(content_type)),
+            (CONTENT_LENGTH, HeaderValue::from(body.len())),
+        ],
+        body,
+    )
+        .into_response()
+}
+
+pub fn json_body<T: Serialize>(data: &T) -> Response {
+    match serde_json::to_vec(data) {
+        Ok(body) => sized_body("application/json", body),
+        Err(_) => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    }
+}
+
 impl<T> IntoResponse for ApiResponse<T>
 where
     T: ApiHeader + Serialize + SingleLine,
@@ -71,15 +91,38 @@ where
                         #[serde(flatten)]
                         data: T,
                     }
-                    Json(JsonResponse {
+                    json_body(&JsonResponse {
                         success: self.1.success(),
-                        data: self.1,
+                        data: &self.1,
                     })
-                    .into_response()
                 }
-                ResponseType::Text => self.1.single_lined().into_response(),
+                ResponseType::Text => {
+                    sized_body("text/plain; charset=utf-8", self.1.single_lined().into())
+                }
             },
         )
             .into_response()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use axum::response::IntoResponse;
+    use hyper::header::CONTENT_LENGTH;
+
+    use super::{ApiResponse, ResponseType};
+    use crate::error::Error;
+
+    #[tokio::test]
+    async fn content_length() {
+        for response_type in [ResponseType::Json, ResponseType::Text] {
+            let response = ApiResponse(response_type, Error::FileNotFound).into_response();
+            let length = response.headers()[CONTENT_LENGTH]
+                .to_str()
+                .unwrap()
+                .to_owned();
+            let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+            assert_eq!(length, body.len().to_string());
+        }
+    }
+}
@@ -0,0 +1,75 @@
+use std::future::pending;
+
+use tokio::{
+    signal::unix::{signal, SignalKind},
+    sync::watch,
+};
+
+// Notifies the server and the background tasks once SIGINT or SIGTERM is received.
+#[derive(Clone)]
+pub struct Shutdown(watch::Receiver<bool>);
+
+impl Shutdown {
+    pub fn listen() -> Self {
+        let (sender, receiver) = watch::channel(false);
+        tokio::spawn(async move {
+            wait_signal().await;
+            tracing::info!("Shutting down, waiting for the active requests to complete");
+            let _ = sender.send(true);
+        });
+        Self(receiver)
+    }
+
+    pub async fn notified(&self) {
+        let mut receiver = self.0.clone();
+        while !*receiver.borrow() {
+            if receiver.changed().await.is_err() {
+                return;
+            }
+        }
+    }
+}
+
+async fn wait_signal() {
+    let (mut interrupt, mut terminate) = match (
+        signal(SignalKind::interrupt()),
+        signal(SignalKind::terminate()),
+    ) {
+        (Ok(interrupt), Ok(terminate)) => (interrupt, terminate),
+        (Err(err), _) | (_, Err(err)) => {
+            tracing::error!("Cannot listen to shutdown signals: {}", err);
+            return pending().await;
+        }
+    };
+    tokio::select! {
+        _ = interrupt.recv() => (),
+        _ = terminate.recv() => (),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use tokio::sync::watch;
+
+    use super::Shutdown;
+
+    #[tokio::test]
+    async fn notify_all() {
+        let (sender, receiver) = watch::channel(false);
+        let shutdown = Shutdown(receiver);
+        let waiting = tokio::spawn({
+            let shutdown = shutdown.clone();
+            async move { shutdown.notified().await }
+        });
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        assert!(!waiting.is_finished());
+
+        sender.send(true).unwrap();
+        drop(sender);
+        waiting.await.unwrap();
+        // Late listeners are notified too.
+        shutdown.notified().await;
+    }
+}
@@ -0,0 +1,46 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+// Files being streamed to clients, their deletion is deferred until their last stream completes.
+#[derive(Clone, Debug, Default)]
+pub struct ActiveStreams(Arc<Mutex<HashMap<String, Entry>>>);
+
+#[derive(Debug, Default)]
+struct Entry {
+    streams: usize,
+    deleted: bool,
+}
+
+impl ActiveStreams {
+    pub fn acquire(&self, id: &str) {
+        let mut active = self.0.lock().unwrap();
+        active.entry(id.to_owned()).or_default().streams += 1;
+    }
+
+    // Returns true if the file was deleted during the stream and should now be removed.
+    pub fn release(&self, id: &str) -> bool {
+        let mut active = self.0.lock().unwrap();
+        let entry = match active.get_mut(id) {
+            Some(entry) => entry,
+            None => return false,
+        };
+        entry.streams -= 1;
+        if entry.streams > 0 {
+            return false;
+        }
+        active.remove(id).map_or(false, |entry| entry.deleted)
+    }
+
+    // Returns false if the file is being streamed, in which case the deletion is deferred.
+    pub fn delete(&self, id: &str) -> bool {
+        match self.0.lock().unwrap().get_mut(id) {
+            Some(entry) => {
+                entry.deleted = true;
+                false
+            }
+            None => true,
+        }
+    }
+}
@@ -0,0 +1,81 @@
+use sqlx::{Acquire, Executor, FromRow, Sqlite};
+
+use crate::include_query;
+
+// Identical uploads share the content stored for the first of them, counting their references
+// so that it's only deleted along with the last one. Trashed files keep their reference until
+// they are purged, their content stays in place.
+#[derive(FromRow)]
+pub struct Content {
+    pub origin: String,
+    pub id: String,
+    // Number of files sharing the content, none if it belongs to the file alone.
+    refs: Option<i64>,
+}
+
+impl Content {
+    pub fn is_shared(&self) -> bool {
+        self.refs.is_some()
+    }
+
+    // Deleting the file deletes its content, no other file references it.
+    pub fn is_last(&self) -> bool {
+        self.refs.map_or(true, |refs| refs <= 1)
+    }
+}
+
+// Where the content of the file is stored, none if the file doesn't exist.
+pub async fn content<'c, E: Executor<'c, Database = Sqlite>>(
+    executor: E,
+    id: &str,
+) -> Result<Option<Content>, sqlx::Error> {
+    sqlx::query_as::<_, Content>(include_query!("get_file_content"))
+        .bind(id)
+        .fetch_optional(executor)
+        .await
+}
+
+// Makes the file reference the content of the original one, the caller deletes its own copy.
+pub async fn share<'c, A: Acquire<'c, Database = Sqlite>>(
+    conn: A,
+    original_id: &str,
+    id: &str,
+) -> Result<(), sqlx::Error> {
+    let mut tx = conn.begin().await?;
+    let content = content(&mut tx, original_id)
+        .await?
+        .ok_or(sqlx::Error::RowNotFound)?;
+    sqlx::query(include_query!("insert_blob"))
+        .bind(&content.id)
+        .bind(&content.origin)
+        .execute(&mut tx)
+        .await?;
+    sqlx::query(include_query!("update_files_blob"))
+        .bind(&content.id)
+        .bind(original_id)
+        .bind(id)
+        .execute(&mut tx)
+        .await?;
+    tx.commit().await
+}
+
+// Deletes the file from the database, releasing its reference to shared content. The caller
+// deletes the content from the storage beforehand if it's the last reference.
+pub async fn delete_file<'c, A: Acquire<'c, Database = Sqlite>>(
+    conn: A,
+    id: &str,
+) -> Result<(), sqlx::Error> {
+    let mut tx = conn.begin().await?;
+    sqlx::query(include_query!("release_blob"))
+        .bind(id)
+        .execute(&mut tx)
+        .await?;
+    sqlx::query(include_query!("delete_blobs_released"))
+        .execute(&mut tx)
+        .await?;
+    sqlx::query(include_query!("delete_file"))
+        .bind(id)
+        .execute(&mut tx)
+        .await?;
+    tx.commit().await
+}
@@ -3,32 +3,91 @@ use std::{
     time::{Duration, SystemTime, UNIX_EPOCH},
 };
 
-use sqlx::SqlitePool;
+use byte_unit::Byte;
+use sqlx::{FromRow, SqliteConnection, SqlitePool};
 
-use crate::{include_query, storage::dir::Dir};
+use crate::{
+    include_query, metrics,
+    shutdown::Shutdown,
+    storage::{blob, dir::Dir},
+    upload::InflightBytes,
+    webhook::{self, Notification, WebhookEvent},
+};
+
+const UPLOAD_SESSION_MAX_IDLE: Duration = Duration::from_secs(24 * 60 * 60);
 
 pub struct Cleaner {
     dir: Dir,
     pool: SqlitePool,
+    inflight: InflightBytes,
+    interval: Duration,
+    disk_threshold: Option<u8>,
+    min_age: Duration,
+    trash_retention: Option<Duration>,
+    download_log_retention: Duration,
 }
 
 impl Cleaner {
-    pub fn new(dir: Dir, pool: SqlitePool) -> Self {
-        Self { dir, pool }
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        dir: Dir,
+        pool: SqlitePool,
+        inflight: InflightBytes,
+        interval: Duration,
+        disk_threshold: Option<u8>,
+        min_age: Duration,
+        trash_retention: Option<Duration>,
+        download_log_retention: Duration,
+    ) -> Self {
+        Self {
+            dir,
+            pool,
+            inflight,
+            interval,
+            disk_threshold,
+            min_age,
+            trash_retention,
+            download_log_retention,
+        }
     }
 
-    pub async fn start(&self) {
+    // The first pass runs at startup, removing the files expired while the server was down. The
+    // interval is waited after each pass so a long one never overlaps the next, nor is interrupted.
+    pub async fn start(&self, shutdown: Shutdown) {
         loop {
-            self.clean_expires().await;
-            tokio::time::sleep(Duration::from_secs(60)).await;
+            let mut reclaimed = Reclaimed::default();
+            self.clean_expires(&mut reclaimed).await;
+            if let Some(threshold) = self.disk_threshold {
+                self.clean_disk_pressure(threshold, &mut reclaimed).await;
+            }
+            if let Some(retention) = self.trash_retention {
+                self.clean_trash(retention, &mut reclaimed).await;
+            }
+            self.clean_upload_sessions().await;
+            self.clean_download_log().await;
+            if reclaimed.files > 0 {
+                tracing::info!(
+                    files = reclaimed.files,
+                    bytes = reclaimed.bytes,
+                    "Cleaning pass reclaimed {} files ({})",
+                    reclaimed.files,
+                    Byte::from_bytes(reclaimed.bytes).get_appropriate_unit(false)
+                );
+            } else {
+                tracing::debug!("Cleaning pass reclaimed nothing");
+            }
+            tokio::select! {
+                _ = tokio::time::sleep(self.interval) => (),
+                _ = shutdown.notified() => return,
+            }
         }
     }
 
-    async fn clean_expires(&self) {
+    async fn clean_expires(&self, reclaimed: &mut Reclaimed) {
         let mut conn = match self.pool.acquire().await {
             Ok(conn) => conn,
             Err(err) => {
-                log::error!("Cannot acquire database connection: {:?}", err);
+                tracing::error!("Cannot acquire database connection: {:?}", err);
                 return;
             }
         };
@@ -36,48 +95,482 @@ impl Cleaner {
         let now_timestamp = match SystemTime::now().duration_since(UNIX_EPOCH) {
             Ok(timestamp) => timestamp.as_secs(),
             Err(err) => {
-                log::error!("Cannot generate timestamp: {}", err);
+                tracing::error!("Cannot generate timestamp: {}", err);
                 return;
             }
         };
 
-        let files = match sqlx::query_as::<_, (String,)>(include_query!("get_files_expired"))
+        let files = match sqlx::query_as::<_, ExpiredFile>(include_query!("get_files_expired"))
             .bind(now_timestamp as i64)
             .fetch_all(&mut conn)
             .await
         {
             Ok(files) => files,
             Err(err) => {
-                log::error!("Cannot fetch expired files: {:?}", err);
+                tracing::error!("Cannot fetch expired files: {:?}", err);
+                return;
+            }
+        };
+
+        for file in files {
+            if self.delete(&mut conn, &file.id).await {
+                reclaimed.add(file.size);
+                webhook::notify(Notification {
+                    event: WebhookEvent::Expire,
+                    short_alias: file.short_alias,
+                    long_alias: file.long_alias,
+                    name: file.name,
+                    size: file.size as u64,
+                    origin: file.origin,
+                    expiration: Some(file.expiration as u64),
+                    downloads_remaining: None,
+                });
+            }
+        }
+    }
+
+    // Keep deleting files until enough disk space is available. Trashed files still use the disk
+    // and are purged first, then the soonest expiring ones.
+    async fn clean_disk_pressure(&self, threshold: u8, reclaimed: &mut Reclaimed) {
+        match self.dir.free_space_percent() {
+            Ok(free) if free >= threshold => return,
+            Ok(_) => (),
+            Err(err) => {
+                tracing::error!("Cannot determine available disk space: {}", err);
+                return;
+            }
+        }
+
+        let mut conn = match self.pool.acquire().await {
+            Ok(conn) => conn,
+            Err(err) => {
+                tracing::error!("Cannot acquire database connection: {:?}", err);
+                return;
+            }
+        };
+
+        let max_created = match SystemTime::now()
+            .checked_sub(self.min_age)
+            .map(|date| date.duration_since(UNIX_EPOCH))
+        {
+            Some(Ok(timestamp)) => timestamp.as_secs(),
+            // Older than the clock goes back, no file can be evicted.
+            None => return,
+            Some(Err(err)) => {
+                tracing::error!("Cannot generate timestamp: {}", err);
+                return;
+            }
+        };
+
+        let files = match evictable(&mut conn, max_created).await {
+            Ok(files) => files,
+            Err(err) => {
+                tracing::error!("Cannot fetch evictable files: {:?}", err);
                 return;
             }
         };
 
-        if !files.is_empty() {
-            for (id,) in files {
-                if let Err(err) = self.dir.delete_file(&id).await {
-                    if err.kind() == ErrorKind::NotFound {
-                        log::warn!(
-                            "File with id {} already deleted of absent from storage directory",
-                            id
-                        );
-                    } else {
-                        log::error!(
-                            "Cannot remove file with id {} from file system: {}",
-                            id,
-                            err
-                        );
-                        continue;
-                    }
+        for (id, size, trashed) in files {
+            let deleted = if trashed {
+                self.purge(&mut conn, &id).await
+            } else {
+                self.delete(&mut conn, &id).await
+            };
+            if deleted {
+                reclaimed.add(size);
+                tracing::warn!(
+                    id = %id,
+                    "File with id {} evicted because of low disk space",
+                    id
+                );
+            }
+            match self.dir.free_space_percent() {
+                Ok(free) if free >= threshold => return,
+                Ok(_) => (),
+                Err(err) => {
+                    tracing::error!("Cannot determine available disk space: {}", err);
+                    return;
                 }
-                if let Err(err) = sqlx::query(include_query!("delete_file"))
-                    .bind(&id)
-                    .execute(&mut conn)
-                    .await
-                {
-                    log::error!("Cannot remove file with id {} from database: {}", id, err);
+            }
+        }
+        tracing::warn!("Disk space still low but no more file can be evicted");
+    }
+
+    // Permanently delete the revoked files that weren't restored in time.
+    async fn clean_trash(&self, retention: Duration, reclaimed: &mut Reclaimed) {
+        let mut conn = match self.pool.acquire().await {
+            Ok(conn) => conn,
+            Err(err) => {
+                tracing::error!("Cannot acquire database connection: {:?}", err);
+                return;
+            }
+        };
+
+        let max_deleted = match SystemTime::now()
+            .checked_sub(retention)
+            .map(|date| date.duration_since(UNIX_EPOCH))
+        {
+            Some(Ok(timestamp)) => timestamp.as_secs(),
+            // Retained for longer than the clock goes back, nothing is purged.
+            None => return,
+            Some(Err(err)) => {
+                tracing::error!("Cannot generate timestamp: {}", err);
+                return;
+            }
+        };
+
+        let files =
+            match sqlx::query_as::<_, (String, i64)>(include_query!("get_files_trash_expired"))
+                .bind(max_deleted as i64)
+                .fetch_all(&mut conn)
+                .await
+            {
+                Ok(files) => files,
+                Err(err) => {
+                    tracing::error!("Cannot fetch trashed files: {:?}", err);
+                    return;
                 }
+            };
+
+        for (id, size) in files {
+            if self.purge(&mut conn, &id).await {
+                reclaimed.add(size);
             }
         }
     }
+
+    // Resumable uploads not resumed for a day are abandoned.
+    async fn clean_upload_sessions(&self) {
+        let mut conn = match self.pool.acquire().await {
+            Ok(conn) => conn,
+            Err(err) => {
+                tracing::error!("Cannot acquire database connection: {:?}", err);
+                return;
+            }
+        };
+
+        let max_updated = match SystemTime::now()
+            .checked_sub(UPLOAD_SESSION_MAX_IDLE)
+            .map(|date| date.duration_since(UNIX_EPOCH))
+        {
+            Some(Ok(timestamp)) => timestamp.as_secs(),
+            None => return,
+            Some(Err(err)) => {
+                tracing::error!("Cannot generate timestamp: {}", err);
+                return;
+            }
+        };
+
+        let sessions =
+            match sqlx::query_as::<_, (String,)>(include_query!("get_upload_sessions_stale"))
+                .bind(max_updated as i64)
+                .fetch_all(&mut conn)
+                .await
+            {
+                Ok(sessions) => sessions,
+                Err(err) => {
+                    tracing::error!("Cannot fetch abandoned upload sessions: {:?}", err);
+                    return;
+                }
+            };
+
+        for (id,) in sessions {
+            if let Err(err) = self.dir.delete_partial(&id).await {
+                if err.kind() != ErrorKind::NotFound {
+                    tracing::error!("Cannot remove upload session {} file: {}", id, err);
+                    continue;
+                }
+            }
+            if let Err(err) = sqlx::query(include_query!("delete_upload_session"))
+                .bind(&id)
+                .execute(&mut conn)
+                .await
+            {
+                tracing::error!("Cannot remove upload session {} from database: {}", id, err);
+                continue;
+            }
+            self.inflight.release(&id);
+        }
+    }
+
+    // Downloads are only kept in the log for the retention duration, even if their file still exists.
+    async fn clean_download_log(&self) {
+        let max_downloaded = match SystemTime::now()
+            .checked_sub(self.download_log_retention)
+            .map(|date| date.duration_since(UNIX_EPOCH))
+        {
+            Some(Ok(timestamp)) => timestamp.as_secs(),
+            // Retained for longer than the clock goes back, nothing is old enough.
+            None => return,
+            Some(Err(err)) => {
+                tracing::error!("Cannot generate timestamp: {}", err);
+                return;
+            }
+        };
+        if let Err(err) = sqlx::query(include_query!("delete_download_log_expired"))
+            .bind(max_downloaded as i64)
+            .execute(&self.pool)
+            .await
+        {
+            tracing::error!("Cannot prune download log: {:?}", err);
+        }
+    }
+
+    // Shared content was left in place when the file was trashed.
+    async fn purge(&self, conn: &mut SqliteConnection, id: &str) -> bool {
+        let content = match blob::content(&mut *conn, id).await {
+            Ok(Some(content)) => content,
+            Ok(None) => return false,
+            Err(err) => {
+                tracing::error!("Cannot fetch content of file with id {}: {}", id, err);
+                return false;
+            }
+        };
+        let purged = if !content.is_shared() {
+            self.dir.purge_file(id).await
+        } else if content.is_last() {
+            self.dir.delete_file(&content.origin, &content.id).await
+        } else {
+            Ok(())
+        };
+        if let Err(err) = purged {
+            if err.kind() != ErrorKind::NotFound {
+                tracing::error!(
+                    "Cannot remove trashed file with id {} from file system: {}",
+                    id,
+                    err
+                );
+                return false;
+            }
+        }
+        if let Err(err) = blob::delete_file(conn, id).await {
+            tracing::error!("Cannot remove file with id {} from database: {}", id, err);
+            return false;
+        }
+        true
+    }
+
+    // Files sharing their content with identical uploads leave it in place, only the last one
+    // deletes it.
+    async fn delete(&self, conn: &mut SqliteConnection, id: &str) -> bool {
+        let content = match blob::content(&mut *conn, id).await {
+            Ok(Some(content)) => content,
+            Ok(None) => return false,
+            Err(err) => {
+                tracing::error!("Cannot fetch content of file with id {}: {}", id, err);
+                return false;
+            }
+        };
+        if content.is_last() {
+            if let Err(err) = self.dir.delete_file(&content.origin, &content.id).await {
+                if err.kind() == ErrorKind::NotFound {
+                    tracing::warn!(
+                        "File with id {} already deleted of absent from storage directory",
+                        id
+                    );
+                } else {
+                    tracing::error!(
+                        "Cannot remove file with id {} from file system: {}",
+                        id,
+                        err
+                    );
+                    return false;
+                }
+            }
+        }
+        if let Err(err) = blob::delete_file(conn, id).await {
+            tracing::error!("Cannot remove file with id {} from database: {}", id, err);
+            return false;
+        }
+        metrics::CLEANED_FILES.increment();
+        true
+    }
+}
+
+// Files deleted under disk pressure, in order, and whether they're trashed. Pinned files and files
+// younger than the minimum age are kept, unless trashed.
+async fn evictable(
+    conn: &mut SqliteConnection,
+    max_created: u64,
+) -> Result<Vec<(String, i64, bool)>, sqlx::Error> {
+    sqlx::query_as::<_, (String, i64, bool)>(include_query!("get_files_evictable"))
+        .bind(max_created as i64)
+        .fetch_all(conn)
+        .await
+}
+
+#[derive(FromRow)]
+struct ExpiredFile {
+    id: String,
+    origin: String,
+    size: i64,
+    short_alias: String,
+    long_alias: String,
+    name: String,
+    expiration: i64,
+}
+
+#[derive(Default)]
+struct Reclaimed {
+    files: u64,
+    bytes: u64,
+}
+
+impl Reclaimed {
+    fn add(&mut self, size: i64) {
+        self.files += 1;
+        self.bytes += size.max(0) as u64;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use sqlx::SqlitePool;
+    use tokio::fs;
+    use uuid::Uuid;
+
+    use super::{evictable, Cleaner, Reclaimed};
+    use crate::{
+        include_query, query,
+        storage::{blob, Dir},
+        upload::InflightBytes,
+    };
+
+    async fn insert(pool: &SqlitePool, id: &str, expiration: i64) {
+        sqlx::query(include_query!("insert_file"))
+            .bind(id)
+            .bind("admin")
+            .bind("127.0.0.1")
+            .bind(expiration)
+            .bind("name")
+            .bind(0)
+            .bind(format!("{}-short", id))
+            .bind(format!("{}-long", id))
+            .bind(false)
+            .bind(false)
+            .execute(pool)
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn shared_content() {
+        let pool = query::memory_pool().await;
+        let path = std::env::temp_dir().join(Uuid::new_v4().as_hyphenated().to_string());
+        fs::create_dir_all(&path).await.unwrap();
+        let dir = Dir::new(&path, false);
+        dir.create_file("127.0.0.1", "first").await.unwrap();
+        insert(&pool, "first", 0).await;
+        insert(&pool, "second", i64::MAX).await;
+        insert(&pool, "third", i64::MAX).await;
+        blob::share(&pool, "first", "second").await.unwrap();
+        blob::share(&pool, "first", "third").await.unwrap();
+        sqlx::query("UPDATE files SET deleted = 0 WHERE id = 'second'")
+            .execute(&pool)
+            .await
+            .unwrap();
+        let cleaner = Cleaner::new(
+            dir.clone(),
+            pool.clone(),
+            InflightBytes::new(None),
+            Duration::from_secs(60),
+            None,
+            Duration::ZERO,
+            Some(Duration::ZERO),
+            Duration::from_secs(60),
+        );
+        let mut reclaimed = Reclaimed::default();
+
+        // The expired file stored the content, the other files keep it.
+        cleaner.clean_expires(&mut reclaimed).await;
+        assert!(dir.open_file("127.0.0.1", "first").await.is_ok());
+        // Purging a trashed file only releases its reference.
+        cleaner.clean_trash(Duration::ZERO, &mut reclaimed).await;
+        assert!(dir.open_file("127.0.0.1", "first").await.is_ok());
+        assert_eq!(reclaimed.files, 2);
+
+        // The last reference deletes the content.
+        sqlx::query("UPDATE files SET expiration = 0 WHERE id = 'third'")
+            .execute(&pool)
+            .await
+            .unwrap();
+        cleaner.clean_expires(&mut reclaimed).await;
+        assert!(dir.open_file("127.0.0.1", "first").await.is_err());
+        let (count,) = sqlx::query_as::<_, (i64,)>("SELECT COUNT(*) FROM files")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(count, 0);
+
+        fs::remove_dir_all(&path).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn disk_pressure() {
+        let pool = query::memory_pool().await;
+        let path = std::env::temp_dir().join(Uuid::new_v4().as_hyphenated().to_string());
+        fs::create_dir_all(&path).await.unwrap();
+        let dir = Dir::new(&path, false);
+        for (id, expiration) in [
+            ("late", 200),
+            ("soon", 100),
+            ("pinned", 50),
+            ("trashed", 300),
+            ("young", 10),
+        ] {
+            dir.create_file("127.0.0.1", id).await.unwrap();
+            insert(&pool, id, expiration).await;
+        }
+        dir.trash_file("127.0.0.1", "trashed").await.unwrap();
+        for update in [
+            "UPDATE files SET created = 0 WHERE id != 'young'",
+            "UPDATE files SET pinned = 1 WHERE id = 'pinned'",
+            "UPDATE files SET deleted = 0 WHERE id = 'trashed'",
+        ] {
+            sqlx::query(update).execute(&pool).await.unwrap();
+        }
+
+        // Trashed files first, then the soonest expiring ones. Pinned and young files are kept.
+        let mut conn = pool.acquire().await.unwrap();
+        let files = evictable(&mut conn, 1).await.unwrap();
+        drop(conn);
+        assert_eq!(
+            files
+                .iter()
+                .map(|(id, _, trashed)| (id.as_str(), *trashed))
+                .collect::<Vec<_>>(),
+            [("trashed", true), ("soon", false), ("late", false)]
+        );
+
+        // Never enough free space, every evictable file is deleted.
+        let cleaner = Cleaner::new(
+            dir.clone(),
+            pool.clone(),
+            InflightBytes::new(None),
+            Duration::from_secs(60),
+            Some(100),
+            Duration::from_secs(60),
+            None,
+            Duration::from_secs(60),
+        );
+        let mut reclaimed = Reclaimed::default();
+        cleaner.clean_disk_pressure(100, &mut reclaimed).await;
+        assert_eq!(reclaimed.files, 3);
+        for id in ["late", "soon"] {
+            assert!(dir.open_file("127.0.0.1", id).await.is_err());
+        }
+        assert!(!path.join(".trash").join("trashed").exists());
+        for id in ["pinned", "young"] {
+            assert!(dir.open_file("127.0.0.1", id).await.is_ok());
+        }
+        let ids = sqlx::query_as::<_, (String,)>("SELECT id FROM files ORDER BY id")
+            .fetch_all(&pool)
+            .await
+            .unwrap();
+        assert_eq!(ids, [("pinned".to_owned(),), ("young".to_owned(),)]);
+
+        fs::remove_dir_all(&path).await.unwrap();
+    }
 }
@@ -1,20 +1,50 @@
 use std::{
     io::{Error as IoError, ErrorKind},
-    path::PathBuf,
+    path::{Path, PathBuf},
 };
 
-use tokio::{fs, fs::File};
+use async_compression::tokio::{bufread::GzipDecoder, write::GzipEncoder};
+use sanitize_filename::{sanitize_with_options, Options as SanitizeOptions};
+use tokio::{
+    fs,
+    fs::File,
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader},
+};
+use uuid::Uuid;
+
+use crate::storage::{
+    active::ActiveStreams,
+    encrypt::{decrypt_reader, EncryptWriter, EncryptionKey, NONCE_SIZE},
+};
+
+pub type FileReader = Box<dyn AsyncRead + Send + Unpin>;
+pub type FileWriter = Box<dyn AsyncWrite + Send + Unpin>;
 
 #[derive(Clone, Debug)]
-pub struct Dir(PathBuf);
+pub struct Dir {
+    path: PathBuf,
+    isolate_origins: bool,
+    active: ActiveStreams,
+    key: Option<EncryptionKey>,
+}
 
 impl Dir {
-    pub fn new<P: Into<PathBuf>>(path: P) -> Self {
-        Self(path.into())
+    pub fn new<P: Into<PathBuf>>(path: P, isolate_origins: bool) -> Self {
+        Self {
+            path: path.into(),
+            isolate_origins,
+            active: ActiveStreams::default(),
+            key: None,
+        }
+    }
+
+    // New files are encrypted at rest, existing plaintext ones stay readable.
+    pub fn with_key(self, key: Option<EncryptionKey>) -> Self {
+        Self { key, ..self }
     }
 
     pub async fn create(&self, should_create: bool) -> Result<(), &'static str> {
-        match File::open(&self.0).await {
+        match File::open(&self.path).await {
             Ok(fd) => match fd.metadata().await {
                 Ok(md) => {
                     if !md.is_dir() {
@@ -28,7 +58,7 @@ impl Dir {
             Err(err) => {
                 if err.kind() == ErrorKind::NotFound {
                     if should_create {
-                        if tokio::fs::create_dir_all(&self.0).await.is_err() {
+                        if tokio::fs::create_dir_all(&self.path).await.is_err() {
                             return Err("Cannot create uploads directory");
                         }
                     } else {
@@ -42,19 +72,472 @@ impl Dir {
         Ok(())
     }
 
-    fn file_path(&self, id: &str) -> PathBuf {
-        self.0.join(id)
+    // Checks that files can be written in the directory, leaving nothing behind.
+    pub async fn probe(&self) -> Result<(), IoError> {
+        let path = self
+            .path
+            .join(format!(".probe-{}", Uuid::new_v4().as_hyphenated()));
+        let written = async {
+            let mut file = File::create(&path).await?;
+            file.write_all(b"ok").await?;
+            file.flush().await
+        }
+        .await;
+        let removed = fs::remove_file(&path).await;
+        written.and(removed)
+    }
+
+    fn origin_path(&self, origin: &str) -> PathBuf {
+        if self.isolate_origins {
+            self.path.join(origin_dir_name(origin))
+        } else {
+            self.path.clone()
+        }
+    }
+
+    fn file_path(&self, origin: &str, id: &str) -> Result<PathBuf, IoError> {
+        Ok(self.origin_path(origin).join(file_name(id)?))
     }
 
-    pub async fn create_file(&self, id: &str) -> Result<File, IoError> {
-        File::create(self.file_path(id)).await
+    pub async fn create_file(&self, origin: &str, id: &str) -> Result<File, IoError> {
+        if self.isolate_origins {
+            fs::create_dir_all(self.origin_path(origin)).await?;
+        }
+        File::create(self.file_path(origin, id)?).await
+    }
+
+    // File written as uploaded, returned with its nonce if it's encrypted on the fly.
+    pub async fn create_writer(
+        &self,
+        origin: &str,
+        id: &str,
+    ) -> Result<(FileWriter, Option<Vec<u8>>), IoError> {
+        let file = self.create_file(origin, id).await?;
+        Ok(match &self.key {
+            Some(key) => {
+                let nonce = key.nonce();
+                (
+                    Box::new(EncryptWriter::new(file, key, &nonce)?),
+                    Some(nonce),
+                )
+            }
+            None => (Box::new(file), None),
+        })
+    }
+
+    pub async fn open_file(&self, origin: &str, id: &str) -> Result<File, IoError> {
+        File::open(self.file_path(origin, id)?).await
+    }
+
+    // Content of the file as uploaded, decrypted and decompressed if it was stored so.
+    pub async fn open_reader(
+        &self,
+        origin: &str,
+        id: &str,
+        compressed: bool,
+        nonce: Option<&[u8]>,
+    ) -> Result<FileReader, IoError> {
+        let file = self.open_file(origin, id).await?;
+        let file: FileReader = match nonce {
+            Some(nonce) => {
+                let key = self.key.as_ref().ok_or_else(|| {
+                    IoError::new(ErrorKind::Other, "file is encrypted but no key is set")
+                })?;
+                decrypt_reader(Box::new(file), key, nonce)?
+            }
+            None => Box::new(file),
+        };
+        Ok(if compressed {
+            Box::new(GzipDecoder::new(BufReader::new(file)))
+        } else {
+            file
+        })
     }
 
-    pub async fn open_file(&self, id: &str) -> Result<File, IoError> {
-        File::open(self.file_path(id)).await
+    // Replace the file with its gzipped version.
+    pub async fn compress_file(&self, origin: &str, id: &str) -> Result<(), IoError> {
+        let path = self.file_path(origin, id)?;
+        let compressed_path = path.with_extension("gz");
+        let compress = async {
+            let mut file = File::open(&path).await?;
+            let mut encoder = GzipEncoder::new(File::create(&compressed_path).await?);
+            tokio::io::copy(&mut file, &mut encoder).await?;
+            encoder.shutdown().await
+        };
+        if let Err(err) = compress.await {
+            let _ = fs::remove_file(&compressed_path).await;
+            return Err(err);
+        }
+        fs::rename(compressed_path, path).await
     }
 
-    pub async fn delete_file(&self, id: &str) -> Result<(), IoError> {
-        fs::remove_file(self.file_path(id)).await
+    // Keep the file on disk until the returned guard is dropped, even if it gets deleted meanwhile.
+    pub fn track(&self, origin: &str, id: &str) -> StreamGuard {
+        self.active.acquire(id);
+        StreamGuard {
+            dir: self.clone(),
+            origin: origin.to_owned(),
+            id: id.to_owned(),
+        }
+    }
+
+    pub async fn delete_file(&self, origin: &str, id: &str) -> Result<(), IoError> {
+        if !self.active.delete(id) {
+            tracing::debug!(
+                "File with id {} is being downloaded, deletion deferred until the end of the transfer",
+                id
+            );
+            return Ok(());
+        }
+        fs::remove_file(self.file_path(origin, id)?).await
+    }
+
+    // Revoked files are kept aside until they are restored or purged.
+    fn trash_path(&self, id: &str) -> Result<PathBuf, IoError> {
+        Ok(self.path.join(".trash").join(file_name(id)?))
+    }
+
+    pub async fn trash_file(&self, origin: &str, id: &str) -> Result<(), IoError> {
+        fs::create_dir_all(self.path.join(".trash")).await?;
+        fs::rename(self.file_path(origin, id)?, self.trash_path(id)?).await
+    }
+
+    pub async fn restore_file(&self, origin: &str, id: &str) -> Result<(), IoError> {
+        if self.isolate_origins {
+            fs::create_dir_all(self.origin_path(origin)).await?;
+        }
+        fs::rename(self.trash_path(id)?, self.file_path(origin, id)?).await
+    }
+
+    pub async fn purge_file(&self, id: &str) -> Result<(), IoError> {
+        fs::remove_file(self.trash_path(id)?).await
+    }
+
+    // Resumable uploads are assembled aside until they are complete. Each chunk is stored in its
+    // own file named after its offset, so it can be encrypted on its own.
+    fn partial_path(&self, id: &str) -> Result<PathBuf, IoError> {
+        Ok(self.path.join(".partial").join(file_name(id)?))
+    }
+
+    pub async fn create_partial(&self, id: &str) -> Result<(), IoError> {
+        fs::create_dir_all(self.partial_path(id)?).await
+    }
+
+    // Chunk starting at the given offset, the chunks written from it are discarded.
+    pub async fn write_partial(&self, id: &str, offset: u64) -> Result<FileWriter, IoError> {
+        let path = self.partial_path(id)?;
+        for (start, chunk) in partial_chunks(&path).await? {
+            if start >= offset {
+                fs::remove_file(chunk).await?;
+            }
+        }
+        let mut file = File::create(path.join(offset.to_string())).await?;
+        Ok(match &self.key {
+            // Each chunk is preceded by its own nonce.
+            Some(key) => {
+                let nonce = key.nonce();
+                file.write_all(&nonce).await?;
+                Box::new(EncryptWriter::new(file, key, &nonce)?)
+            }
+            None => Box::new(file),
+        })
+    }
+
+    // Content of the chunks in order, decrypted.
+    pub async fn open_partial(&self, id: &str) -> Result<FileReader, IoError> {
+        let mut reader: FileReader = Box::new(tokio::io::empty());
+        for (_, chunk) in partial_chunks(&self.partial_path(id)?).await? {
+            let mut file = File::open(chunk).await?;
+            let chunk: FileReader = match &self.key {
+                Some(key) => {
+                    let mut nonce = [0; NONCE_SIZE];
+                    file.read_exact(&mut nonce).await?;
+                    decrypt_reader(Box::new(file), key, &nonce)?
+                }
+                None => Box::new(file),
+            };
+            reader = Box::new(reader.chain(chunk));
+        }
+        Ok(reader)
+    }
+
+    pub async fn delete_partial(&self, id: &str) -> Result<(), IoError> {
+        fs::remove_dir_all(self.partial_path(id)?).await
+    }
+
+    pub fn free_space_percent(&self) -> Result<u8, IoError> {
+        let total = fs2::total_space(&self.path)?;
+        if total == 0 {
+            return Ok(0);
+        }
+        Ok((fs2::available_space(&self.path)? * 100 / total) as u8)
+    }
+}
+
+pub struct StreamGuard {
+    dir: Dir,
+    origin: String,
+    id: String,
+}
+
+impl Drop for StreamGuard {
+    fn drop(&mut self) {
+        if !self.dir.active.release(&self.id) {
+            return;
+        }
+        let path = match self.dir.file_path(&self.origin, &self.id) {
+            Ok(path) => path,
+            Err(err) => {
+                tracing::error!("Cannot remove file with id {}: {}", self.id, err);
+                return;
+            }
+        };
+        let id = self.id.clone();
+        tokio::spawn(async move {
+            if let Err(err) = fs::remove_file(path).await {
+                tracing::error!(
+                    "Cannot remove file with id {} after its last transfer: {}",
+                    id,
+                    err
+                );
+            }
+        });
+    }
+}
+
+// Ids are generated by the server, anything else than their charset could name a path outside of
+// the directory (separators, "..", null bytes).
+fn file_name(id: &str) -> Result<&str, IoError> {
+    if id.is_empty()
+        || !id
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_')
+    {
+        return Err(IoError::new(
+            ErrorKind::InvalidInput,
+            format!("invalid file id {:?}", id),
+        ));
+    }
+    Ok(id)
+}
+
+// Chunks of a resumable upload, ordered by their offset.
+async fn partial_chunks(path: &Path) -> Result<Vec<(u64, PathBuf)>, IoError> {
+    let mut chunks = Vec::new();
+    let mut entries = fs::read_dir(path).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        if let Some(start) = entry
+            .file_name()
+            .to_str()
+            .and_then(|name| name.parse().ok())
+        {
+            chunks.push((start, entry.path()));
+        }
+    }
+    chunks.sort_unstable();
+    Ok(chunks)
+}
+
+// IPv6 addresses and usernames may contain characters that are not allowed in paths.
+fn origin_dir_name(origin: &str) -> String {
+    let name = sanitize_with_options(
+        origin,
+        SanitizeOptions {
+            replacement: "_",
+            ..Default::default()
+        },
+    );
+    if name.is_empty() {
+        "_".to_owned()
+    } else {
+        name
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::ErrorKind,
+        path::{Component, Path},
+        time::Duration,
+    };
+
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use uuid::Uuid;
+
+    use super::Dir;
+    use crate::storage::EncryptionKey;
+
+    #[test]
+    fn file_path() {
+        let dir = Dir::new("uploads", false);
+        assert_eq!(
+            dir.file_path("127.0.0.1", "id").unwrap(),
+            Path::new("uploads/id").to_owned()
+        );
+
+        let dir = Dir::new("uploads", true);
+        assert_eq!(
+            dir.file_path("127.0.0.1", "id").unwrap(),
+            Path::new("uploads/127.0.0.1/id").to_owned()
+        );
+        assert_eq!(
+            dir.file_path("::1", "id").unwrap(),
+            Path::new("uploads/__1/id").to_owned()
+        );
+        assert_eq!(
+            dir.file_path("../admin", "id").unwrap(),
+            Path::new("uploads/.._admin/id").to_owned()
+        );
+        assert_eq!(
+            dir.file_path("..", "id").unwrap(),
+            Path::new("uploads/_/id").to_owned()
+        );
+    }
+
+    #[test]
+    fn traversal() {
+        for isolate_origins in [false, true] {
+            let dir = Dir::new("uploads", isolate_origins);
+            let path = dir
+                .file_path("127.0.0.1", "0b5c4d6e-7f80-4a1b-9c2d-3e4f5a6b7c8d")
+                .unwrap();
+            assert!(path.starts_with("uploads"));
+            assert!(path.components().all(|c| c != Component::ParentDir));
+            for id in [
+                "",
+                ".",
+                "..",
+                "../secret",
+                "..\\secret",
+                "/etc/passwd",
+                "nested/id",
+                "id\0",
+                "id.gz",
+                "ïd",
+            ] {
+                let err = dir.file_path("127.0.0.1", id).unwrap_err();
+                assert_eq!(err.kind(), ErrorKind::InvalidInput);
+                assert!(dir.trash_path(id).is_err());
+                assert!(dir.partial_path(id).is_err());
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn deferred_deletion() {
+        let path = std::env::temp_dir().join(Uuid::new_v4().as_hyphenated().to_string());
+        let dir = Dir::new(&path, false);
+        dir.create(true).await.unwrap();
+        dir.create_file("127.0.0.1", "id").await.unwrap();
+
+        // Deleting a file while it's being downloaded keeps it until the transfer ends.
+        let first = dir.track("127.0.0.1", "id");
+        let second = dir.track("127.0.0.1", "id");
+        dir.delete_file("127.0.0.1", "id").await.unwrap();
+        assert!(dir.file_path("127.0.0.1", "id").exists());
+        drop(first);
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert!(dir.file_path("127.0.0.1", "id").exists());
+        drop(second);
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert!(!dir.file_path("127.0.0.1", "id").exists());
+
+        // Without transfer, the file is deleted immediately.
+        dir.create_file("127.0.0.1", "id").await.unwrap();
+        dir.delete_file("127.0.0.1", "id").await.unwrap();
+        assert!(!dir.file_path("127.0.0.1", "id").exists());
+
+        tokio::fs::remove_dir_all(&path).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn trash() {
+        let path = std::env::temp_dir().join(Uuid::new_v4().as_hyphenated().to_string());
+        let dir = Dir::new(&path, true);
+        dir.create(true).await.unwrap();
+        dir.create_file("127.0.0.1", "id").await.unwrap();
+
+        dir.trash_file("127.0.0.1", "id").await.unwrap();
+        assert!(!dir.file_path("127.0.0.1", "id").exists());
+        assert!(dir.trash_path("id").exists());
+
+        dir.restore_file("127.0.0.1", "id").await.unwrap();
+        assert!(dir.file_path("127.0.0.1", "id").exists());
+
+        dir.trash_file("127.0.0.1", "id").await.unwrap();
+        dir.purge_file("id").await.unwrap();
+        assert!(!dir.trash_path("id").exists());
+
+        tokio::fs::remove_dir_all(&path).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn compress() {
+        let path = std::env::temp_dir().join(Uuid::new_v4().as_hyphenated().to_string());
+        let dir = Dir::new(&path, false);
+        dir.create(true).await.unwrap();
+        let content = "dropit ".repeat(1000);
+        let mut file = dir.create_file("127.0.0.1", "id").await.unwrap();
+        file.write_all(content.as_bytes()).await.unwrap();
+        drop(file);
+
+        dir.compress_file("127.0.0.1", "id").await.unwrap();
+        let stored = tokio::fs::metadata(dir.file_path("127.0.0.1", "id"))
+            .await
+            .unwrap();
+        assert!(stored.len() < content.len() as u64);
+
+        let mut decompressed = String::new();
+        dir.open_reader("127.0.0.1", "id", true, None)
+            .await
+            .unwrap()
+            .read_to_string(&mut decompressed)
+            .await
+            .unwrap();
+        assert_eq!(decompressed, content);
+
+        tokio::fs::remove_dir_all(&path).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn partial() {
+        let path = std::env::temp_dir().join(Uuid::new_v4().as_hyphenated().to_string());
+        tokio::fs::create_dir_all(&path).await.unwrap();
+        let key_path = path.join("key");
+        tokio::fs::write(&key_path, base64::encode([7; 32]))
+            .await
+            .unwrap();
+        let key = EncryptionKey::from_file(&key_path).unwrap();
+
+        for key in [None, Some(key)] {
+            let encrypted = key.is_some();
+            let dir = Dir::new(&path, false).with_key(key);
+            dir.create_partial("id").await.unwrap();
+            for (offset, chunk) in [(0, "drop"), (4, "xx"), (4, "it")] {
+                let mut file = dir.write_partial("id", offset).await.unwrap();
+                file.write_all(chunk.as_bytes()).await.unwrap();
+                file.shutdown().await.unwrap();
+            }
+
+            // The overwritten chunk is discarded.
+            let mut content = String::new();
+            dir.open_partial("id")
+                .await
+                .unwrap()
+                .read_to_string(&mut content)
+                .await
+                .unwrap();
+            assert_eq!(content, "dropit");
+            let stored = tokio::fs::read(dir.partial_path("id").unwrap().join("0"))
+                .await
+                .unwrap();
+            assert_eq!(stored == b"drop", !encrypted);
+
+            dir.delete_partial("id").await.unwrap();
+            assert!(!dir.partial_path("id").unwrap().exists());
+        }
+
+        tokio::fs::remove_dir_all(&path).await.unwrap();
     }
 }
@@ -0,0 +1,241 @@
+use std::{
+    fmt::{self, Debug, Formatter},
+    fs,
+    io::{Error as IoError, ErrorKind},
+    path::Path,
+    pin::Pin,
+    task::{ready, Context, Poll},
+};
+
+use aes_gcm::{
+    aead::{
+        generic_array::GenericArray,
+        stream::{DecryptorBE32, EncryptorBE32},
+        KeyInit,
+    },
+    Aes256Gcm,
+};
+use futures::stream;
+use hyper::body::Bytes;
+use tokio::io::{AsyncReadExt, AsyncWrite};
+use tokio_util::io::StreamReader;
+
+use crate::storage::dir::FileReader;
+
+// Files are encrypted in chunks of this size, each followed by its authentication tag.
+const CHUNK_SIZE: usize = 64 * 1024;
+const TAG_SIZE: usize = 16;
+// The stream construction uses 5 bytes of the 12 bytes nonce of AES-GCM.
+pub(super) const NONCE_SIZE: usize = 7;
+
+// AES-256-GCM key used to encrypt the files at rest.
+#[derive(Clone)]
+pub struct EncryptionKey(Aes256Gcm);
+
+impl EncryptionKey {
+    // The file contains the base64 encoded 32 bytes key.
+    pub fn from_file(path: &Path) -> Result<Self, String> {
+        let content = fs::read_to_string(path)
+            .map_err(|err| format!("Cannot read {}: {}", path.display(), err))?;
+        let key = base64::decode(content.trim())
+            .ok()
+            .filter(|key| key.len() == 32)
+            .ok_or_else(|| {
+                format!(
+                    "{} doesn't contain a base64 encoded 32 bytes key",
+                    path.display()
+                )
+            })?;
+        Ok(Self(Aes256Gcm::new(GenericArray::from_slice(&key))))
+    }
+
+    // Random for each file, a nonce must never be reused with the same key.
+    pub fn nonce(&self) -> Vec<u8> {
+        rand::random::<[u8; NONCE_SIZE]>().to_vec()
+    }
+}
+
+impl Debug for EncryptionKey {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str("EncryptionKey(..)")
+    }
+}
+
+fn crypto_error(_: aes_gcm::Error) -> IoError {
+    IoError::new(
+        ErrorKind::InvalidData,
+        "file encryption or authentication failed",
+    )
+}
+
+fn check_nonce(nonce: &[u8]) -> Result<(), IoError> {
+    if nonce.len() != NONCE_SIZE {
+        return Err(IoError::new(ErrorKind::InvalidData, "invalid file nonce"));
+    }
+    Ok(())
+}
+
+// Encrypt the written bytes, the last chunk is only written on shutdown.
+pub struct EncryptWriter<W> {
+    inner: W,
+    encryptor: Option<EncryptorBE32<Aes256Gcm>>,
+    plain: Vec<u8>,
+    pending: Vec<u8>,
+    written: usize,
+}
+
+impl<W: AsyncWrite + Unpin> EncryptWriter<W> {
+    pub fn new(inner: W, key: &EncryptionKey, nonce: &[u8]) -> Result<Self, IoError> {
+        check_nonce(nonce)?;
+        Ok(Self {
+            inner,
+            encryptor: Some(EncryptorBE32::from_aead(
+                key.0.clone(),
+                GenericArray::from_slice(nonce),
+            )),
+            plain: Vec::with_capacity(CHUNK_SIZE),
+            pending: Vec::new(),
+            written: 0,
+        })
+    }
+
+    fn poll_pending(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), IoError>> {
+        while self.written < self.pending.len() {
+            let written =
+                ready!(Pin::new(&mut self.inner).poll_write(cx, &self.pending[self.written..]))?;
+            if written == 0 {
+                return Poll::Ready(Err(ErrorKind::WriteZero.into()));
+            }
+            self.written += written;
+        }
+        self.pending.clear();
+        self.written = 0;
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<W: AsyncWrite + Unpin> AsyncWrite for EncryptWriter<W> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<Result<usize, IoError>> {
+        let this = self.get_mut();
+        ready!(this.poll_pending(cx))?;
+        let encryptor = this
+            .encryptor
+            .as_mut()
+            .ok_or_else(|| IoError::from(ErrorKind::BrokenPipe))?;
+        let accepted = buf.len().min(CHUNK_SIZE - this.plain.len());
+        this.plain.extend_from_slice(&buf[..accepted]);
+        if this.plain.len() == CHUNK_SIZE {
+            this.pending = encryptor
+                .encrypt_next(this.plain.as_slice())
+                .map_err(crypto_error)?;
+            this.plain.clear();
+        }
+        Poll::Ready(Ok(accepted))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), IoError>> {
+        let this = self.get_mut();
+        ready!(this.poll_pending(cx))?;
+        Pin::new(&mut this.inner).poll_flush(cx)
+    }
+
+    // A file without its last chunk cannot be decrypted, truncations are detected.
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), IoError>> {
+        let this = self.get_mut();
+        ready!(this.poll_pending(cx))?;
+        if let Some(encryptor) = this.encryptor.take() {
+            this.pending = encryptor
+                .encrypt_last(this.plain.as_slice())
+                .map_err(crypto_error)?;
+            this.plain.clear();
+            ready!(this.poll_pending(cx))?;
+        }
+        Pin::new(&mut this.inner).poll_shutdown(cx)
+    }
+}
+
+pub fn decrypt_reader(
+    reader: FileReader,
+    key: &EncryptionKey,
+    nonce: &[u8],
+) -> Result<FileReader, IoError> {
+    check_nonce(nonce)?;
+    let decryptor = DecryptorBE32::from_aead(key.0.clone(), GenericArray::from_slice(nonce));
+    let chunks = stream::try_unfold(
+        (reader, Some(decryptor)),
+        |(mut reader, decryptor)| async move {
+            let mut decryptor = match decryptor {
+                Some(decryptor) => decryptor,
+                None => return Ok(None),
+            };
+            let mut chunk = Vec::with_capacity(CHUNK_SIZE + TAG_SIZE);
+            (&mut reader)
+                .take((CHUNK_SIZE + TAG_SIZE) as u64)
+                .read_to_end(&mut chunk)
+                .await?;
+            // Only the last chunk is shorter than the others, it may even be empty.
+            if chunk.len() == CHUNK_SIZE + TAG_SIZE {
+                let plain = decryptor
+                    .decrypt_next(chunk.as_slice())
+                    .map_err(crypto_error)?;
+                Ok::<_, IoError>(Some((Bytes::from(plain), (reader, Some(decryptor)))))
+            } else {
+                let plain = decryptor
+                    .decrypt_last(chunk.as_slice())
+                    .map_err(crypto_error)?;
+                Ok(Some((Bytes::from(plain), (reader, None))))
+            }
+        },
+    );
+    Ok(Box::new(StreamReader::new(Box::pin(chunks))))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use aes_gcm::{
+        aead::{generic_array::GenericArray, KeyInit},
+        Aes256Gcm,
+    };
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    use super::{decrypt_reader, EncryptWriter, EncryptionKey, CHUNK_SIZE, TAG_SIZE};
+
+    #[tokio::test]
+    async fn round_trip() {
+        let key = EncryptionKey(Aes256Gcm::new(GenericArray::from_slice(&[7; 32])));
+        for size in [0, 10, CHUNK_SIZE, CHUNK_SIZE * 2 + 10] {
+            let content = (0..size).map(|i| i as u8).collect::<Vec<_>>();
+            let nonce = key.nonce();
+            let mut encrypted = Vec::new();
+            let mut writer = EncryptWriter::new(&mut encrypted, &key, &nonce).unwrap();
+            writer.write_all(&content).await.unwrap();
+            writer.shutdown().await.unwrap();
+            // One tag per chunk, including the last one.
+            assert_eq!(encrypted.len(), size + (size / CHUNK_SIZE + 1) * TAG_SIZE);
+
+            let mut decrypted = Vec::new();
+            decrypt_reader(Box::new(Cursor::new(encrypted.clone())), &key, &nonce)
+                .unwrap()
+                .read_to_end(&mut decrypted)
+                .await
+                .unwrap();
+            assert_eq!(decrypted, content);
+
+            // Truncated files are rejected.
+            let truncated = encrypted[..encrypted.len() - 1].to_vec();
+            assert!(
+                decrypt_reader(Box::new(Cursor::new(truncated)), &key, &nonce)
+                    .unwrap()
+                    .read_to_end(&mut Vec::new())
+                    .await
+                    .is_err()
+            );
+        }
+    }
+}
@@ -1,5 +1,11 @@
+mod active;
+pub mod blob;
 mod clean;
 mod dir;
+mod encrypt;
+mod verify;
 
 pub use clean::Cleaner;
-pub use dir::Dir;
+pub use dir::{Dir, FileReader, FileWriter, StreamGuard};
+pub use encrypt::EncryptionKey;
+pub use verify::Verifier;
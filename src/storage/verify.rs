@@ -0,0 +1,98 @@
+use std::{io::ErrorKind, time::Duration};
+
+use sha2::{Digest, Sha256};
+use sqlx::SqlitePool;
+use tokio::io::AsyncReadExt;
+
+use crate::{
+    include_query,
+    storage::dir::{Dir, FileReader},
+};
+
+// Periodically recompute the hashes of the stored files to detect disk corruption.
+pub struct Verifier {
+    dir: Dir,
+    pool: SqlitePool,
+    interval: Duration,
+}
+
+impl Verifier {
+    pub fn new(dir: Dir, pool: SqlitePool, interval: Duration) -> Self {
+        Self {
+            dir,
+            pool,
+            interval,
+        }
+    }
+
+    pub async fn start(&self) {
+        loop {
+            // Content shared by identical uploads is listed once, under the file storing it.
+            let files = match sqlx::query_as::<_, (String, String, String, bool, Option<Vec<u8>>)>(
+                include_query!("get_files_hashed"),
+            )
+            .fetch_all(&self.pool)
+            .await
+            {
+                Ok(files) => files,
+                Err(err) => {
+                    tracing::error!("Cannot fetch files to verify: {:?}", err);
+                    Vec::new()
+                }
+            };
+
+            // Files are checked one at a time to avoid thrashing the disk.
+            for (id, origin, expected, compressed, nonce) in files {
+                self.verify(&id, &origin, &expected, compressed, nonce.as_deref())
+                    .await;
+                tokio::time::sleep(self.interval).await;
+            }
+            tokio::time::sleep(self.interval).await;
+        }
+    }
+
+    // Compressed or encrypted files are hashed as uploaded, tampered encrypted ones fail to read.
+    async fn verify(
+        &self,
+        id: &str,
+        origin: &str,
+        expected: &str,
+        compressed: bool,
+        nonce: Option<&[u8]>,
+    ) {
+        let file = match self.dir.open_reader(origin, id, compressed, nonce).await {
+            Ok(file) => file,
+            // Deleted since the listing.
+            Err(err) if err.kind() == ErrorKind::NotFound => return,
+            Err(err) => {
+                tracing::error!("Cannot open file with id {} for verification: {}", id, err);
+                return;
+            }
+        };
+        match hash(file).await {
+            Ok(hash) if hash == expected => (),
+            Ok(hash) => tracing::error!(
+                "File with id {} is corrupted: expected hash {}, found {}",
+                id,
+                expected,
+                hash
+            ),
+            Err(err) => {
+                tracing::error!("Cannot read file with id {} for verification: {}", id, err)
+            }
+        }
+    }
+}
+
+async fn hash(mut file: FileReader) -> Result<String, std::io::Error> {
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0; 64 * 1024];
+    loop {
+        let read = file.read(&mut buffer).await?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+    }
+    Ok(format!("{:x}", hasher.finalize()))
+}
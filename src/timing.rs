@@ -0,0 +1,91 @@
+use std::{
+    convert::{Infallible, TryFrom},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use async_trait::async_trait;
+use axum::{
+    extract::{FromRequest, RequestParts},
+    middleware::Next,
+    response::Response,
+};
+use hyper::{http::HeaderValue, Body, Request};
+use itertools::Itertools;
+
+// Durations of the steps of a request, sent back using the Server-Timing header.
+// Recording is a no-op unless the layer below is installed.
+#[derive(Clone, Default)]
+pub struct ServerTiming(Option<Arc<Mutex<Vec<(&'static str, Duration)>>>>);
+
+impl ServerTiming {
+    pub fn record(&self, name: &'static str, start: Instant) {
+        if let Some(timings) = &self.0 {
+            timings
+                .lock()
+                .expect("timings lock poisoned")
+                .push((name, start.elapsed()));
+        }
+    }
+
+    fn header(&self) -> Option<HeaderValue> {
+        let timings = self.0.as_ref()?.lock().expect("timings lock poisoned");
+        HeaderValue::try_from(
+            timings
+                .iter()
+                .map(|(name, duration)| {
+                    format!("{};dur={:.1}", name, duration.as_secs_f64() * 1000.0)
+                })
+                .join(", "),
+        )
+        .ok()
+    }
+}
+
+#[async_trait]
+impl FromRequest<Body> for ServerTiming {
+    type Rejection = Infallible;
+
+    async fn from_request(req: &mut RequestParts<Body>) -> Result<Self, Self::Rejection> {
+        Ok(req
+            .extensions()
+            .get::<ServerTiming>()
+            .cloned()
+            .unwrap_or_default())
+    }
+}
+
+// The total doesn't include the streaming of the body, which starts after the headers are sent.
+pub async fn layer(mut req: Request<Body>, next: Next<Body>) -> Response {
+    let start = Instant::now();
+    let timing = ServerTiming(Some(Default::default()));
+    req.extensions_mut().insert(timing.clone());
+    let mut response = next.run(req).await;
+    timing.record("total", start);
+    if let Some(header) = timing.header() {
+        response.headers_mut().insert("Server-Timing", header);
+    }
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+
+    use super::ServerTiming;
+
+    #[test]
+    fn header() {
+        let timing = ServerTiming(Some(Default::default()));
+        timing.record("auth", Instant::now());
+        timing.record("db", Instant::now());
+        let header = timing.header().unwrap();
+        let header = header.to_str().unwrap();
+        assert!(header.starts_with("auth;dur="));
+        assert!(header.contains(", db;dur="));
+
+        let disabled = ServerTiming::default();
+        disabled.record("auth", Instant::now());
+        assert!(disabled.header().is_none());
+    }
+}
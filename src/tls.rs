@@ -0,0 +1,212 @@
+use std::{
+    fs::File,
+    io::{BufReader, Error as IoError},
+    net::SocketAddr,
+    path::Path,
+    sync::Arc,
+    time::Duration,
+};
+
+use axum::{extract::ConnectInfo, Extension, Router};
+use clap::ValueEnum;
+use hyper::server::conn::Http;
+use rustls_pemfile::Item;
+use tokio::{net::TcpListener, sync::mpsc};
+use tokio_rustls::{
+    rustls::{
+        server::AllowAnyAuthenticatedClient, sign, version, Certificate, PrivateKey, RootCertStore,
+        ServerConfig, SignatureScheme, SupportedProtocolVersion,
+    },
+    TlsAcceptor,
+};
+
+use crate::{auth::ClientCertificate, shutdown::Shutdown};
+
+// Clients not completing their handshake in time are disconnected, instead of holding a task.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Copy, Clone, Debug, ValueEnum)]
+pub enum TlsVersion {
+    #[value(name = "1.2")]
+    Tls12,
+    #[value(name = "1.3")]
+    Tls13,
+}
+
+impl TlsVersion {
+    // Versions accepted during handshakes.
+    fn versions(self) -> &'static [&'static SupportedProtocolVersion] {
+        match self {
+            TlsVersion::Tls12 => &[&version::TLS13, &version::TLS12],
+            TlsVersion::Tls13 => &[&version::TLS13],
+        }
+    }
+}
+
+pub fn server_config(
+    cert_path: &Path,
+    key_path: &Path,
+    client_ca_path: Option<&Path>,
+    min_version: TlsVersion,
+) -> Result<ServerConfig, String> {
+    let certs = read_pem(cert_path)?
+        .into_iter()
+        .filter_map(|item| match item {
+            Item::X509Certificate(der) => Some(Certificate(der)),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    if certs.is_empty() {
+        return Err(format!("No certificate found in {}", cert_path.display()));
+    }
+    let key = read_pem(key_path)?
+        .into_iter()
+        .find_map(|item| match item {
+            Item::RSAKey(der) | Item::PKCS8Key(der) | Item::ECKey(der) => Some(PrivateKey(der)),
+            _ => None,
+        })
+        .ok_or_else(|| format!("No private key found in {}", key_path.display()))?;
+    if !key_matches(&certs[0], &key) {
+        return Err(format!(
+            "The private key of {} doesn't match the certificate of {}",
+            key_path.display(),
+            cert_path.display()
+        ));
+    }
+
+    let builder = ServerConfig::builder()
+        .with_safe_default_cipher_suites()
+        .with_safe_default_kx_groups()
+        .with_protocol_versions(min_version.versions())
+        .map_err(|err| format!("Invalid TLS versions: {}", err))?;
+    let builder = match client_ca_path {
+        Some(client_ca_path) => {
+            let mut roots = RootCertStore::empty();
+            for item in read_pem(client_ca_path)? {
+                if let Item::X509Certificate(der) = item {
+                    roots
+                        .add(&Certificate(der))
+                        .map_err(|err| format!("Invalid client CA certificate: {}", err))?;
+                }
+            }
+            if roots.is_empty() {
+                return Err(format!(
+                    "No certificate found in {}",
+                    client_ca_path.display()
+                ));
+            }
+            builder.with_client_cert_verifier(AllowAnyAuthenticatedClient::new(roots))
+        }
+        None => builder.with_no_client_auth(),
+    };
+    let mut config = builder
+        .with_single_cert(certs, key)
+        .map_err(|err| format!("Invalid TLS certificate or private key: {}", err))?;
+    config.alpn_protocols = vec![b"http/1.1".to_vec()];
+    Ok(config)
+}
+
+// Rustls only checks that the key can be parsed, a key of another certificate would only make the
+// handshakes fail. The key has to sign a message the certificate verifies.
+fn key_matches(cert: &Certificate, key: &PrivateKey) -> bool {
+    const MESSAGE: &[u8] = b"dropit";
+    let signer = match sign::any_supported_type(key).ok().and_then(|key| {
+        key.choose_scheme(&[
+            SignatureScheme::ECDSA_NISTP256_SHA256,
+            SignatureScheme::ECDSA_NISTP384_SHA384,
+            SignatureScheme::ED25519,
+            SignatureScheme::RSA_PKCS1_SHA256,
+        ])
+    }) {
+        Some(signer) => signer,
+        None => return false,
+    };
+    let algorithm = match signer.scheme() {
+        SignatureScheme::ECDSA_NISTP256_SHA256 => &webpki::ECDSA_P256_SHA256,
+        SignatureScheme::ECDSA_NISTP384_SHA384 => &webpki::ECDSA_P384_SHA384,
+        SignatureScheme::ED25519 => &webpki::ED25519,
+        _ => &webpki::RSA_PKCS1_2048_8192_SHA256,
+    };
+    match (
+        webpki::EndEntityCert::try_from(cert.0.as_slice()),
+        signer.sign(MESSAGE),
+    ) {
+        (Ok(cert), Ok(signature)) => cert
+            .verify_signature(algorithm, MESSAGE, &signature)
+            .is_ok(),
+        _ => false,
+    }
+}
+
+fn read_pem(path: &Path) -> Result<Vec<Item>, String> {
+    let file =
+        File::open(path).map_err(|err| format!("Cannot open {}: {}", path.display(), err))?;
+    rustls_pemfile::read_all(&mut BufReader::new(file))
+        .map_err(|err| format!("Cannot read {}: {}", path.display(), err))
+}
+
+pub async fn serve(
+    address: SocketAddr,
+    config: Arc<ServerConfig>,
+    router: Router,
+    shutdown: Shutdown,
+) -> Result<(), IoError> {
+    let acceptor = TlsAcceptor::from(config);
+    let listener = TcpListener::bind(address).await?;
+    // Every connection holds a sender, the receiver is closed once all of them are dropped.
+    let (active, mut closed) = mpsc::channel::<()>(1);
+    loop {
+        let accepted = tokio::select! {
+            accepted = listener.accept() => accepted,
+            _ = shutdown.notified() => break,
+        };
+        let (stream, remote) = match accepted {
+            Ok(conn) => conn,
+            Err(err) => {
+                tracing::warn!("Cannot accept TCP connection: {}", err);
+                continue;
+            }
+        };
+        let acceptor = acceptor.clone();
+        let router = router.clone();
+        let shutdown = shutdown.clone();
+        let active = active.clone();
+        tokio::spawn(async move {
+            let _active = active;
+            let stream =
+                match tokio::time::timeout(HANDSHAKE_TIMEOUT, acceptor.accept(stream)).await {
+                    Ok(Ok(stream)) => stream,
+                    Ok(Err(err)) => {
+                        tracing::debug!("TLS handshake with {} failed: {}", remote, err);
+                        return;
+                    }
+                    Err(_) => {
+                        tracing::debug!("TLS handshake with {} timed out", remote);
+                        return;
+                    }
+                };
+            let certificate = ClientCertificate::from_connection(stream.get_ref().1);
+            let service = router
+                .layer(Extension(ConnectInfo(remote)))
+                .layer(Extension(certificate));
+            let connection = Http::new()
+                .http1_title_case_headers(true)
+                .serve_connection(stream, service);
+            tokio::pin!(connection);
+            // The request being processed completes, the connection is closed afterwards.
+            let result = tokio::select! {
+                result = connection.as_mut() => result,
+                _ = shutdown.notified() => {
+                    connection.as_mut().graceful_shutdown();
+                    connection.await
+                }
+            };
+            if let Err(err) = result {
+                tracing::debug!("Connection with {} failed: {}", remote, err);
+            }
+        });
+    }
+    drop(active);
+    closed.recv().await;
+    Ok(())
+}
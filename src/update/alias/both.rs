@@ -1,5 +1,6 @@
-use axum::Extension;
+use axum::{extract::Query, Extension};
 use http_negotiator::{ContentTypeNegotiation, Negotiation};
+use serde::Deserialize;
 use sqlx::SqlitePool;
 
 use crate::{
@@ -7,11 +8,19 @@ use crate::{
     alias::Alias,
     error::{alias as AliasError, Error},
     include_query,
+    query::with_timeout,
     response::{ApiResponse, ResponseType},
     update::{alias::AliasChange, AdminToken},
     upload::DomainUri,
 };
 
+#[derive(Deserialize)]
+pub struct RenewParams {
+    // The replaced aliases keep working as download links, until the next renewal.
+    #[serde(default)]
+    keep: bool,
+}
+
 pub async fn handler(
     Extension(pool): Extension<SqlitePool>,
     alias: Alias,
@@ -19,34 +28,64 @@ pub async fn handler(
     DomainUri(domain_uri): DomainUri,
     response_type: Negotiation<ContentTypeNegotiation, ResponseType>,
 ) -> Result<ApiResponse<AliasChange>, Error> {
-    let (new_short, new_long) = process_change(pool, alias, admin_token).await?;
-    Ok(ApiResponse(
+    let (new_short, new_long) = process_change(pool, alias, admin_token, false).await?;
+    Ok(response(response_type, &domain_uri, new_short, new_long))
+}
+
+pub async fn renew_handler(
+    Extension(pool): Extension<SqlitePool>,
+    alias: Alias,
+    AdminToken(admin_token): AdminToken,
+    DomainUri(domain_uri): DomainUri,
+    Query(params): Query<RenewParams>,
+    response_type: Negotiation<ContentTypeNegotiation, ResponseType>,
+) -> Result<ApiResponse<AliasChange>, Error> {
+    let (new_short, new_long) = process_change(pool, alias, admin_token, params.keep).await?;
+    Ok(response(response_type, &domain_uri, new_short, new_long))
+}
+
+fn response(
+    response_type: Negotiation<ContentTypeNegotiation, ResponseType>,
+    domain_uri: &str,
+    new_short: String,
+    new_long: String,
+) -> ApiResponse<AliasChange> {
+    ApiResponse(
         response_type.into_inner(),
         AliasChange {
             short: Some((new_short.clone(), format!("{}/{}", domain_uri, new_short))),
             long: Some((new_long.clone(), format!("{}/{}", domain_uri, new_long))),
         },
-    ))
+    )
 }
 
 async fn process_change(
     pool: SqlitePool,
     alias: Alias,
     admin_token: String,
+    keep: bool,
 ) -> Result<(String, String), Error> {
-    let (id, _size, mut conn) = super::super::authorize(pool, &alias, &admin_token).await?;
-    let (short, long) = alias::random_unused_aliases(&mut conn)
-        .await
+    let (id, _size, _origin, mut conn) =
+        super::super::authorize(pool, &alias, &admin_token).await?;
+    let (short, long) = with_timeout(alias::random_unused_aliases(&mut conn))
+        .await?
         .ok_or(AliasError::AliasGeneration)?;
 
-    let affected = sqlx::query(include_query!("update_file_aliases"))
-        .bind(&short)
-        .bind(&long)
-        .bind(&id)
-        .execute(&mut conn)
-        .await
-        .map_err(|_| AliasError::Database)?
-        .rows_affected();
+    let query = if keep {
+        include_query!("update_file_aliases_kept")
+    } else {
+        include_query!("update_file_aliases")
+    };
+    let affected = with_timeout(
+        sqlx::query(query)
+            .bind(&short)
+            .bind(&long)
+            .bind(&id)
+            .execute(&mut conn),
+    )
+    .await?
+    .map_err(|_| AliasError::Database)?
+    .rows_affected();
 
     if affected != 1 {
         return Err(AliasError::UnexpectedFileModification);
@@ -54,3 +93,75 @@ async fn process_change(
 
     Ok((short, long))
 }
+
+#[cfg(test)]
+mod tests {
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    use sqlx::SqlitePool;
+
+    use super::process_change;
+    use crate::{alias::Alias, include_query, query};
+
+    // Whether the file info can be fetched with the alias, as downloads do.
+    async fn resolves(pool: &SqlitePool, alias: &str) -> bool {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        sqlx::query(include_query!("get_file_info"))
+            .bind(alias)
+            .bind(alias)
+            .bind(now as i64)
+            .fetch_optional(pool)
+            .await
+            .unwrap()
+            .is_some()
+    }
+
+    #[tokio::test]
+    async fn renew() {
+        let pool = query::memory_pool().await;
+        sqlx::query(include_query!("insert_file"))
+            .bind("id")
+            .bind("admin")
+            .bind("127.0.0.1")
+            .bind(i64::MAX)
+            .bind("name")
+            .bind(0)
+            .bind("aaaaaa")
+            .bind("first-long-alias")
+            .bind(false)
+            .bind(false)
+            .execute(&pool)
+            .await
+            .unwrap();
+        sqlx::query("UPDATE files SET hash = 'hash' WHERE id = 'id'")
+            .execute(&pool)
+            .await
+            .unwrap();
+        let renew = |alias: &str, keep| {
+            process_change(
+                pool.clone(),
+                Alias::Short(alias.to_owned()),
+                "admin".to_owned(),
+                keep,
+            )
+        };
+
+        // The replaced aliases keep working, not the ones replaced before them.
+        let (short, long) = renew("aaaaaa", true).await.unwrap();
+        assert!(resolves(&pool, &short).await && resolves(&pool, &long).await);
+        assert!(resolves(&pool, "aaaaaa").await && resolves(&pool, "first-long-alias").await);
+        let (second_short, _) = renew(&short, true).await.unwrap();
+        assert!(resolves(&pool, &second_short).await && resolves(&pool, &short).await);
+        assert!(!resolves(&pool, "aaaaaa").await);
+        // Kept aliases can't be used to administrate the file.
+        assert!(renew(&short, false).await.is_err());
+
+        // Invalidated right away by default.
+        let (third_short, _) = renew(&second_short, false).await.unwrap();
+        assert!(resolves(&pool, &third_short).await);
+        assert!(!resolves(&pool, &second_short).await && !resolves(&pool, &short).await);
+    }
+}
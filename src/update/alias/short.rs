@@ -7,6 +7,7 @@ use crate::{
     alias::Alias,
     error::{alias as AliasError, Error},
     include_query,
+    query::with_timeout,
     response::{ApiResponse, ResponseType},
     update::{alias::AliasChange, AdminToken},
     upload::DomainUri,
@@ -34,18 +35,21 @@ async fn process_change(
     alias: Alias,
     admin_token: String,
 ) -> Result<String, Error> {
-    let (id, _size, mut conn) = super::super::authorize(pool, &alias, &admin_token).await?;
-    let alias = alias::random_unused_short(&mut conn)
-        .await
+    let (id, _size, _origin, mut conn) =
+        super::super::authorize(pool, &alias, &admin_token).await?;
+    let alias = with_timeout(alias::random_unused_short(&mut conn))
+        .await?
         .ok_or(AliasError::AliasGeneration)?;
 
-    let affected = sqlx::query(include_query!("update_file_short_alias"))
-        .bind(&alias)
-        .bind(&id)
-        .execute(&mut conn)
-        .await
-        .map_err(|_| AliasError::Database)?
-        .rows_affected();
+    let affected = with_timeout(
+        sqlx::query(include_query!("update_file_short_alias"))
+            .bind(&alias)
+            .bind(&id)
+            .execute(&mut conn),
+    )
+    .await?
+    .map_err(|_| AliasError::Database)?
+    .rows_affected();
 
     if affected != 1 {
         return Err(AliasError::UnexpectedFileModification);
@@ -6,6 +6,7 @@ use crate::{
     alias::Alias,
     error::{downloads as DownloadsError, Error},
     include_query,
+    query::with_timeout,
     response::{ApiResponse, ResponseType},
     update::AdminToken,
 };
@@ -29,15 +30,17 @@ async fn process_downloads(
     admin_token: String,
     count: u16,
 ) -> Result<(), Error> {
-    let (id, _size, mut conn) = super::authorize(pool, &alias, &admin_token).await?;
+    let (id, _size, _origin, mut conn) = super::authorize(pool, &alias, &admin_token).await?;
     let count = if count >= 1 { Some(count) } else { None };
 
-    sqlx::query(include_query!("update_file_downloads"))
-        .bind(count)
-        .bind(&id)
-        .execute(&mut conn)
-        .await
-        .map_err(|_| DownloadsError::UnexpectedFileModification)?;
+    with_timeout(
+        sqlx::query(include_query!("update_file_downloads"))
+            .bind(count)
+            .bind(&id)
+            .execute(&mut conn),
+    )
+    .await?
+    .map_err(|_| DownloadsError::UnexpectedFileModification)?;
 
     Ok(())
 }
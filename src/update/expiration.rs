@@ -1,4 +1,8 @@
-use std::{convert::TryFrom, sync::Arc, time::Duration};
+use std::{
+    convert::TryFrom,
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
 
 use axum::{extract::Path, Extension};
 use http_negotiator::{ContentTypeNegotiation, Negotiation};
@@ -7,11 +11,13 @@ use sqlx::SqlitePool;
 
 use crate::{
     alias::Alias,
+    auth::Origin,
     error::{expiration as ExpirationError, Error},
     include_query,
+    query::with_timeout,
     response::{ApiResponse, ResponseType},
     update::AdminToken,
-    upload::{Determiner, Expiration},
+    upload::{Determiner, Expiration, GroupDeterminers},
 };
 
 #[derive(Copy, Clone, Debug)]
@@ -19,6 +25,8 @@ pub enum DurationRequest {
     Initial,
     Maximum,
     Custom(u64),
+    // Absolute RFC 3339 date, e.g. 2022-12-31T23:59:59Z.
+    Until(SystemTime),
 }
 
 impl<'de> Deserialize<'de> for DurationRequest {
@@ -30,6 +38,14 @@ impl<'de> Deserialize<'de> for DurationRequest {
         match input {
             "init" | "initial" => Ok(Self::Initial),
             "max" | "maximum" => Ok(Self::Maximum),
+            _ if input.contains('T') => humantime::parse_rfc3339_weak(input)
+                .map(Self::Until)
+                .map_err(|err| {
+                    serde::de::Error::invalid_value(
+                        Unexpected::Str(input),
+                        &err.to_string().as_str(),
+                    )
+                }),
             _ => input.parse().map(Self::Custom).map_err(|err| {
                 serde::de::Error::invalid_value(Unexpected::Str(input), &err.to_string().as_str())
             }),
@@ -41,30 +57,50 @@ pub async fn handler(
     Extension(pool): Extension<SqlitePool>,
     response_type: Negotiation<ContentTypeNegotiation, ResponseType>,
     Extension(determiner): Extension<Arc<Determiner>>,
+    Extension(groups): Extension<Arc<GroupDeterminers>>,
+    Extension(origin): Extension<Origin>,
     AdminToken(admin_token): AdminToken,
     alias: Alias,
     Path((_, duration)): Path<(String, DurationRequest)>,
 ) -> Result<ApiResponse<Expiration>, ApiResponse<Error>> {
     Ok(ApiResponse(
         *response_type,
-        process_extend(pool, determiner, alias, duration, admin_token)
-            .await
-            .map_err(|err| ApiResponse(*response_type, err))?,
+        process_extend(
+            pool,
+            &determiner,
+            &groups,
+            origin,
+            alias,
+            duration,
+            admin_token,
+        )
+        .await
+        .map_err(|err| ApiResponse(*response_type, err))?,
     ))
 }
 
 async fn process_extend(
     pool: SqlitePool,
-    determiner: Arc<Determiner>,
+    determiner: &Determiner,
+    groups: &GroupDeterminers,
+    origin: Origin,
     alias: Alias,
     duration: DurationRequest,
     admin_token: String,
 ) -> Result<Expiration, Error> {
-    let (id, size, mut conn) = super::authorize(pool, &alias, &admin_token).await?;
+    let (id, size, file_origin, mut conn) = super::authorize(pool, &alias, &admin_token).await?;
+
+    // The thresholds of the uploader's group, only known when the origin of a file is its
+    // uploader's username.
+    let determiner = match origin {
+        Origin::Username => groups.determiner(Some(&file_origin)),
+        Origin::IpAddress => None,
+    }
+    .unwrap_or(determiner);
 
     let (default, allowed) = determiner
         .determine(size)
-        .ok_or(ExpirationError::TooLarge)?;
+        .ok_or_else(|| ExpirationError::ExpirationTooLarge(size, determiner.max_size()))?;
     let expiration = Expiration::try_from(match duration {
         DurationRequest::Initial => default,
         DurationRequest::Maximum => {
@@ -81,14 +117,137 @@ async fn process_extend(
             }
             dur
         }
+        DurationRequest::Until(date) => {
+            let dur = date
+                .duration_since(SystemTime::now())
+                .map_err(|_| ExpirationError::ExpirationPassed)?;
+            if dur > allowed.unwrap_or(default) {
+                return Err(ExpirationError::ExpirationTooHigh);
+            }
+            dur
+        }
     })?;
 
-    sqlx::query(include_query!("extend_file"))
-        .bind(expiration.timestamp() as i64)
-        .bind(id)
-        .execute(&mut conn)
-        .await
-        .map_err(|_| ExpirationError::Database)?;
+    with_timeout(
+        sqlx::query(include_query!("extend_file"))
+            .bind(expiration.timestamp() as i64)
+            .bind(id)
+            .execute(&mut conn),
+    )
+    .await?
+    .map_err(|_| ExpirationError::Database)?;
 
     Ok(expiration)
 }
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+    use hyper::StatusCode;
+
+    use super::{process_extend, DurationRequest};
+    use crate::{
+        alias::Alias,
+        auth::Origin,
+        include_query, query,
+        upload::{Determiner, GroupDeterminers},
+    };
+
+    #[test]
+    fn parse_request() {
+        let parse =
+            |input: &str| serde_json::from_str::<DurationRequest>(&format!("\"{}\"", input));
+        assert!(matches!(parse("max"), Ok(DurationRequest::Maximum)));
+        assert!(matches!(parse("3600"), Ok(DurationRequest::Custom(3600))));
+        assert!(matches!(
+            parse("2022-12-31T23:59:59Z"),
+            Ok(DurationRequest::Until(date)) if date == UNIX_EPOCH + Duration::from_secs(1_672_531_199)
+        ));
+        assert!(parse("2022-12-31T25:00:00Z").is_err());
+        assert!(parse("tomorrow").is_err());
+    }
+
+    #[tokio::test]
+    async fn uploader_group() {
+        let pool = query::memory_pool().await;
+        sqlx::query(include_query!("insert_file"))
+            .bind("id")
+            .bind("admin")
+            .bind("alice")
+            .bind(0)
+            .bind("name")
+            .bind(0)
+            .bind("short")
+            .bind("long")
+            .bind(false)
+            .bind(false)
+            .execute(&pool)
+            .await
+            .unwrap();
+        let determiner = Determiner::new(vec!["1KB:1h".parse().unwrap()]).unwrap();
+        let groups = GroupDeterminers::new(
+            vec!["premium=1GB:30d".parse().unwrap()],
+            vec!["premium=alice".parse().unwrap()],
+        )
+        .unwrap();
+        let extend = |origin| {
+            process_extend(
+                pool.clone(),
+                &determiner,
+                &groups,
+                origin,
+                Alias::Short("short".to_owned()),
+                DurationRequest::Initial,
+                "admin".to_owned(),
+            )
+        };
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+
+        // The origin of the file is its uploader, a member of the group.
+        let expiration = extend(Origin::Username).await.unwrap();
+        assert!(expiration.timestamp() >= now + 30 * 24 * 3600);
+        // The uploader is unknown, the origin is an address.
+        let expiration = extend(Origin::IpAddress).await.unwrap();
+        assert!(expiration.timestamp() < now + 2 * 3600);
+    }
+
+    #[tokio::test]
+    async fn too_large() {
+        let pool = query::memory_pool().await;
+        sqlx::query(include_query!("insert_file"))
+            .bind("id")
+            .bind("admin")
+            .bind("127.0.0.1")
+            .bind(0)
+            .bind("name")
+            .bind(2_000)
+            .bind("short")
+            .bind("long")
+            .bind(false)
+            .bind(false)
+            .execute(&pool)
+            .await
+            .unwrap();
+        let determiner = Determiner::new(vec!["1KB:1h".parse().unwrap()]).unwrap();
+
+        // Stored before the thresholds were lowered, no payload is involved.
+        let extended = process_extend(
+            pool,
+            &determiner,
+            &GroupDeterminers::default(),
+            Origin::IpAddress,
+            Alias::Short("short".to_owned()),
+            DurationRequest::Initial,
+            "admin".to_owned(),
+        )
+        .await;
+        assert!(matches!(
+            extended,
+            Err(err) if err.status_code() == StatusCode::BAD_REQUEST && err.code() == "too_large"
+        ));
+    }
+}
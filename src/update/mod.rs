@@ -3,7 +3,7 @@ use std::sync::Arc;
 use async_trait::async_trait;
 use axum::{
     extract::{FromRequest, RequestParts},
-    routing::{delete, patch},
+    routing::{delete, patch, post},
     Extension, Router,
 };
 use hyper::{header, Body};
@@ -11,10 +11,12 @@ use sqlx::{pool::PoolConnection, Sqlite, SqlitePool};
 
 use crate::{
     alias::Alias,
+    auth::Origin,
     error::{admin as AdminError, Error},
     include_query,
+    query::with_timeout,
     storage::Dir,
-    upload::Determiner,
+    upload::{Determiner, GroupDeterminers},
 };
 
 mod alias;
@@ -22,26 +24,31 @@ mod downloads;
 mod expiration;
 mod revoke;
 
+pub use revoke::SoftDelete;
+
 async fn authorize(
     pool: SqlitePool,
     alias: &Alias,
     admin_token: &str,
-) -> Result<(String, u64, PoolConnection<Sqlite>), Error> {
-    let mut conn = pool.acquire().await.map_err(|_| AdminError::Database)?;
+) -> Result<(String, u64, String, PoolConnection<Sqlite>), Error> {
+    let mut conn = with_timeout(pool.acquire())
+        .await?
+        .map_err(|_| AdminError::Database)?;
 
-    let (id, size, admin) =
-        sqlx::query_as::<_, (String, i64, String)>(include_query!("get_file_admin"))
+    let (id, size, admin, origin) = with_timeout(
+        sqlx::query_as::<_, (String, i64, String, String)>(include_query!("get_file_admin"))
             .bind(alias.inner())
             .bind(alias.inner())
-            .fetch_optional(&mut conn)
-            .await
-            .map_err(|_| AdminError::Database)?
-            .ok_or(AdminError::FileNotFound)?;
+            .fetch_optional(&mut conn),
+    )
+    .await?
+    .map_err(|_| AdminError::Database)?
+    .ok_or(AdminError::FileNotFound)?;
 
     if admin != admin_token.to_ascii_lowercase() {
         return Err(AdminError::InvalidAdminToken);
     }
-    Ok((id, size as u64, conn))
+    Ok((id, size as u64, origin, conn))
 }
 
 pub struct AdminToken(String);
@@ -63,15 +70,27 @@ impl FromRequest<Body> for AdminToken {
     }
 }
 
-pub fn router(pool: SqlitePool, dir: Dir, determiner: Arc<Determiner>) -> Router {
+pub fn router(
+    pool: SqlitePool,
+    dir: Dir,
+    determiner: Arc<Determiner>,
+    groups: Arc<GroupDeterminers>,
+    origin: Origin,
+    soft_delete: SoftDelete,
+) -> Router {
     Router::new()
         .route("/:alias/alias/short", patch(alias::short::handler))
         .route("/:alias/alias/long", patch(alias::long::handler))
         .route("/:alias/alias", patch(alias::both::handler))
+        // Rotating both aliases replaces the previous ones, which stop working right away unless kept.
+        .route("/:alias/renew", post(alias::both::renew_handler))
         .route("/:alias/downloads/:count", patch(downloads::handler))
         .route("/:alias/expiration/:duration", patch(expiration::handler))
         .route("/:alias", delete(revoke::handler))
         .route_layer(Extension(pool))
         .route_layer(Extension(dir))
         .route_layer(Extension(determiner))
+        .route_layer(Extension(groups))
+        .route_layer(Extension(origin))
+        .route_layer(Extension(soft_delete))
 }
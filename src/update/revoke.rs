@@ -4,21 +4,27 @@ use sqlx::SqlitePool;
 
 use crate::{
     alias::Alias,
-    error::{revoke as RevokeError, Error},
+    error::{admin as AdminError, revoke as RevokeError, Error},
     include_query,
+    query::with_timeout,
     response::{ApiResponse, ResponseType},
-    storage::Dir,
+    storage::{blob, Dir},
     update::AdminToken,
 };
 
+// Move revoked files to the trash instead of deleting them.
+#[derive(Copy, Clone, Debug)]
+pub struct SoftDelete(pub bool);
+
 pub async fn handler(
     Extension(pool): Extension<SqlitePool>,
     response_type: Negotiation<ContentTypeNegotiation, ResponseType>,
     AdminToken(admin_token): AdminToken,
     alias: Alias,
     Extension(dir): Extension<Dir>,
+    Extension(SoftDelete(soft_delete)): Extension<SoftDelete>,
 ) -> Result<ApiResponse<()>, ApiResponse<Error>> {
-    process_revoke(pool, alias, admin_token, dir)
+    process_revoke(pool, alias, admin_token, dir, soft_delete)
         .await
         .map_err(|err| ApiResponse(*response_type, err))?;
     Ok(ApiResponse(*response_type, ()))
@@ -29,17 +35,110 @@ async fn process_revoke(
     alias: Alias,
     admin_token: String,
     dir: Dir,
+    soft_delete: bool,
 ) -> Result<(), Error> {
-    let (id, _size, mut conn) = super::authorize(pool, &alias, &admin_token).await?;
+    let (id, _size, _origin, mut conn) = super::authorize(pool, &alias, &admin_token).await?;
+    let content = with_timeout(blob::content(&mut conn, &id))
+        .await?
+        .map_err(|_| AdminError::Database)?
+        .ok_or(AdminError::FileNotFound)?;
 
-    dir.delete_file(&id)
-        .await
-        .map_err(|_| RevokeError::RemoveFile)?;
+    if soft_delete {
+        // Shared content stays in place, the trashed file keeps referencing it.
+        if !content.is_shared() {
+            dir.trash_file(&content.origin, &content.id)
+                .await
+                .map_err(|_| RevokeError::RemoveFile)?;
+        }
+        with_timeout(
+            sqlx::query(include_query!("trash_file"))
+                .bind(&id)
+                .execute(&mut conn),
+        )
+        .await?
+        .map_err(|_| RevokeError::PartialRemove)?;
+        return Ok(());
+    }
 
-    sqlx::query(include_query!("delete_file"))
-        .bind(&id)
-        .execute(&mut conn)
-        .await
+    if content.is_last() {
+        dir.delete_file(&content.origin, &content.id)
+            .await
+            .map_err(|_| RevokeError::RemoveFile)?;
+    }
+
+    with_timeout(blob::delete_file(&mut conn, &id))
+        .await?
         .map_err(|_| RevokeError::PartialRemove)?;
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use sqlx::SqlitePool;
+    use tokio::fs;
+    use uuid::Uuid;
+
+    use super::process_revoke;
+    use crate::{
+        alias::Alias,
+        include_query, query,
+        storage::{blob, Dir},
+    };
+
+    async fn insert(pool: &SqlitePool, id: &str, short: &str) {
+        sqlx::query(include_query!("insert_file"))
+            .bind(id)
+            .bind("admin")
+            .bind("127.0.0.1")
+            .bind(i64::MAX)
+            .bind("name")
+            .bind(0)
+            .bind(short)
+            .bind(format!("{}-long-alias", id))
+            .bind(false)
+            .bind(false)
+            .execute(pool)
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn shared_content() {
+        let pool = query::memory_pool().await;
+        let path = std::env::temp_dir().join(Uuid::new_v4().as_hyphenated().to_string());
+        fs::create_dir_all(&path).await.unwrap();
+        let dir = Dir::new(&path, false);
+        dir.create_file("127.0.0.1", "first").await.unwrap();
+        for (id, short) in [
+            ("first", "aaaaaa"),
+            ("second", "bbbbbb"),
+            ("third", "cccccc"),
+        ] {
+            insert(&pool, id, short).await;
+        }
+        blob::share(&pool, "first", "second").await.unwrap();
+        blob::share(&pool, "second", "third").await.unwrap();
+        let revoke = |short: &str, soft_delete| {
+            process_revoke(
+                pool.clone(),
+                Alias::Short(short.to_owned()),
+                "admin".to_owned(),
+                dir.clone(),
+                soft_delete,
+            )
+        };
+
+        // The file storing the content is revoked first, the others keep reading it.
+        revoke("aaaaaa", false).await.unwrap();
+        assert!(dir.open_file("127.0.0.1", "first").await.is_ok());
+        // Trashing keeps the reference and leaves the content in place.
+        revoke("bbbbbb", true).await.unwrap();
+        assert!(dir.open_file("127.0.0.1", "first").await.is_ok());
+        revoke("cccccc", false).await.unwrap();
+        assert!(dir.open_file("127.0.0.1", "first").await.is_ok());
+        let content = blob::content(&pool, "second").await.unwrap().unwrap();
+        assert_eq!((content.id.as_str(), content.is_last()), ("first", true));
+
+        fs::remove_dir_all(&path).await.unwrap();
+    }
+}
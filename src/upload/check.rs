@@ -0,0 +1,118 @@
+use std::sync::Arc;
+
+use axum::{
+    extract::rejection::JsonRejection,
+    headers::{authorization::Basic, Authorization, Cookie},
+    Extension, Json, TypedHeader,
+};
+use http_negotiator::{ContentTypeNegotiation, Negotiation};
+use serde::{Deserialize, Serialize};
+use sqlx::SqlitePool;
+
+use crate::{
+    access::AccessUser,
+    alias,
+    auth::{Authenticator, ClientCertificate},
+    error::{upload as UploadError, Error},
+    query::with_timeout,
+    response::{ApiHeader, ApiResponse, ResponseType, SingleLine},
+    upload::{
+        file::ExpirationGroup, origin::ClientAddress, Expiration, ExpirationDuration,
+        UploadRequest, UploadState,
+    },
+};
+
+#[derive(Deserialize)]
+pub struct CheckRequest {
+    size: u64,
+    filename: Option<String>,
+    alias: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct UploadCheck {
+    expiration: ExpirationGroup,
+}
+
+impl ApiHeader for UploadCheck {}
+
+impl SingleLine for UploadCheck {
+    fn single_lined(&self) -> String {
+        self.expiration.single_lined()
+    }
+}
+
+// Runs the checks of an upload without storing anything, the upload itself may still be rejected
+// if other files are uploaded in the meantime.
+#[allow(clippy::too_many_arguments)]
+pub async fn handler(
+    Extension(pool): Extension<SqlitePool>,
+    response_type: Negotiation<ContentTypeNegotiation, ResponseType>,
+    authenticator: Extension<Arc<Authenticator>>,
+    auth_header: Option<TypedHeader<Authorization<Basic>>>,
+    cookie: Option<TypedHeader<Cookie>>,
+    certificate: Option<Extension<ClientCertificate>>,
+    user: AccessUser,
+    ClientAddress(client): ClientAddress,
+    Extension(state): Extension<Arc<UploadState>>,
+    request: Result<Json<CheckRequest>, JsonRejection>,
+) -> Result<ApiResponse<UploadCheck>, ApiResponse<Error>> {
+    let certificate = certificate.and_then(|Extension(ClientCertificate(cn))| cn);
+    let check = async {
+        let Json(request) = request.map_err(|_| UploadError::InvalidCheckRequest)?;
+        let username = super::authenticate(
+            &authenticator,
+            auth_header,
+            cookie,
+            certificate.clone(),
+            &user,
+        )
+        .await?;
+        let req = UploadRequest {
+            filename: request.filename,
+            size: request.size,
+            origin: state.origin(client, username.clone(), certificate)?,
+            username,
+        };
+        process_check(&pool, &state, &req, request.alias).await
+    };
+    check
+        .await
+        .map(|check| ApiResponse(*response_type, check))
+        .map_err(|err: Error| ApiResponse(*response_type, err))
+}
+
+// Same checks, in the same order, as a real upload.
+async fn process_check(
+    pool: &SqlitePool,
+    state: &UploadState,
+    req: &UploadRequest,
+    custom_alias: Option<String>,
+) -> Result<UploadCheck, Error> {
+    let mut conn = with_timeout(pool.acquire())
+        .await?
+        .map_err(|_| UploadError::Database)?;
+    super::accept_quota(&mut conn, &state.limiter, req, None).await?;
+    if let (true, Some(filename)) = (state.unique_names, &req.filename) {
+        if let Some(alias) = super::existing_name(&mut conn, &req.origin, filename).await? {
+            return Err(UploadError::NameConflict(alias));
+        }
+    }
+    if let Some(custom) = custom_alias {
+        if !alias::is_valid_custom(&custom) {
+            return Err(UploadError::InvalidAlias);
+        }
+        super::check_custom_alias(&mut conn, &custom).await?;
+    }
+
+    let determiner = state.determiner(req.username.as_deref());
+    let (default_duration, allowed_duration) = determiner
+        .determine(req.size)
+        .ok_or_else(|| UploadError::TooLarge(req.size, determiner.max_size()))?;
+    Ok(UploadCheck {
+        expiration: ExpirationGroup::new(
+            Expiration::try_from(default_duration)?,
+            allowed_duration.map(ExpirationDuration::from),
+        ),
+    })
+}
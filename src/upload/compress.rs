@@ -0,0 +1,45 @@
+// Gzip the uploads that are likely to shrink before keeping them on disk.
+#[derive(Copy, Clone, Debug)]
+pub struct CompressAtRest(pub bool);
+
+impl CompressAtRest {
+    pub(super) fn should_compress(&self, mime: Option<&str>) -> bool {
+        self.0 && compressible(mime)
+    }
+}
+
+// Formats without magic number are mostly plain text, the other detected formats are
+// already compressed, except for a few uncompressed ones.
+fn compressible(mime: Option<&str>) -> bool {
+    match mime {
+        None => true,
+        Some(mime) => {
+            mime.starts_with("text/")
+                || matches!(
+                    mime,
+                    "application/x-tar"
+                        | "application/x-executable"
+                        | "application/x-sqlite3"
+                        | "application/wasm"
+                        | "image/bmp"
+                        | "image/x-icon"
+                        | "audio/x-wav"
+                )
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::CompressAtRest;
+
+    #[test]
+    fn should_compress() {
+        let enabled = CompressAtRest(true);
+        assert!(enabled.should_compress(None));
+        assert!(enabled.should_compress(Some("application/x-tar")));
+        assert!(!enabled.should_compress(Some("application/gzip")));
+        assert!(!enabled.should_compress(Some("image/png")));
+        assert!(!CompressAtRest(false).should_compress(None));
+    }
+}
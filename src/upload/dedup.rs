@@ -0,0 +1,65 @@
+use sqlx::{FromRow, SqlitePool};
+
+use crate::{
+    include_query,
+    query::with_timeout,
+    storage::{blob, Dir},
+};
+
+// Store identical uploads once, their files reference the same content on disk.
+#[derive(Copy, Clone, Debug)]
+pub struct DeduplicateUploads(pub bool);
+
+#[derive(FromRow)]
+struct Original {
+    id: String,
+    compressed: bool,
+    nonce: Option<Vec<u8>>,
+}
+
+impl DeduplicateUploads {
+    // Shares the content of a stored file with the same hash, returning how it's stored
+    // (compressed and nonce). Any failure keeps the upload's own copy.
+    pub(super) async fn deduplicate(
+        &self,
+        pool: &SqlitePool,
+        dir: &Dir,
+        origin: &str,
+        id: &str,
+        hash: &str,
+    ) -> Option<(bool, Option<Vec<u8>>)> {
+        if !self.0 {
+            return None;
+        }
+        let original = with_timeout(
+            sqlx::query_as::<_, Original>(include_query!("get_file_by_hash"))
+                .bind(hash)
+                .bind(id)
+                .fetch_optional(pool),
+        )
+        .await
+        .ok()?
+        .ok()??;
+        let shared = with_timeout(blob::share(pool, &original.id, id))
+            .await
+            .ok()?;
+        if let Err(err) = shared {
+            tracing::warn!(
+                "Cannot share the content of file with id {} with {}: {}",
+                original.id,
+                id,
+                err
+            );
+            return None;
+        }
+        // The shared content is used from now on, a leftover copy only wastes space.
+        if let Err(err) = dir.delete_file(origin, id).await {
+            tracing::warn!(
+                "Cannot remove the duplicate content of file with id {}: {}",
+                id,
+                err
+            );
+        }
+        Some((original.compressed, original.nonce))
+    }
+}
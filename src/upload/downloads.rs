@@ -0,0 +1,38 @@
+use crate::error::{upload as UploadError, Error};
+
+// Highest downloads limit uploaders can set on their files.
+#[derive(Copy, Clone, Debug)]
+pub struct MaxDownloads(pub u16);
+
+impl MaxDownloads {
+    // Requested downloads limit of an upload, empty values keep the file unlimited.
+    pub(super) fn parse(&self, value: &str) -> Result<Option<u16>, Error> {
+        let value = value.trim();
+        if value.is_empty() {
+            return Ok(None);
+        }
+        value
+            .parse::<u16>()
+            .ok()
+            .filter(|count| (1..=self.0).contains(count))
+            .map(Some)
+            .ok_or(UploadError::InvalidMaxDownloads(self.0))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::MaxDownloads;
+
+    #[test]
+    fn parse() {
+        let max = MaxDownloads(10);
+        assert_eq!(max.parse("").unwrap(), None);
+        assert_eq!(max.parse("1").unwrap(), Some(1));
+        assert_eq!(max.parse(" 10 ").unwrap(), Some(10));
+        assert!(max.parse("0").is_err());
+        assert!(max.parse("11").is_err());
+        assert!(max.parse("-1").is_err());
+        assert!(max.parse("many").is_err());
+    }
+}
@@ -74,6 +74,15 @@ impl Determiner {
         Ok(Self(thresholds))
     }
 
+    pub fn thresholds(&self) -> &[Threshold] {
+        &self.0
+    }
+
+    pub fn max_size(&self) -> u64 {
+        self.0.last().map_or(0, |t| t.size)
+    }
+
+    // Durations of the smallest threshold the size fits in, none if it's larger than all of them.
     pub fn determine(&self, size: u64) -> Option<(Duration, Option<Duration>)> {
         self.0
             .iter()
@@ -237,6 +246,10 @@ mod tests {
             },
         ])
         .unwrap();
+        assert_eq!(
+            determiner.determine(0),
+            Some((Duration::from_secs(2), None))
+        ); // Smaller than all the thresholds.
         assert_eq!(
             determiner.determine(1),
             Some((Duration::from_secs(2), None))
@@ -254,5 +267,6 @@ mod tests {
             Some((Duration::from_secs(1), None))
         ); // Exactly on the threshold.
         assert_eq!(determiner.determine(5), None);
+        assert_eq!(determiner.max_size(), 4);
     }
 }
@@ -0,0 +1,59 @@
+use std::time::Duration;
+
+use crate::{
+    error::{upload as UploadError, Error},
+    misc::format_duration,
+};
+
+// Duration of an upload requesting its own expiration. Uploaders can only shorten the duration given
+// by the thresholds, longer ones are clamped to it with a warning. Empty values keep the default.
+pub(super) fn resolve(value: &str, default: Duration) -> Result<(Duration, Option<String>), Error> {
+    let value = value.trim();
+    if value.is_empty() {
+        return Ok((default, None));
+    }
+    let requested: Duration = value
+        .parse::<humantime::Duration>()
+        .map_err(|_| UploadError::InvalidExpireIn)?
+        .into();
+    if requested.is_zero() {
+        return Err(UploadError::InvalidExpireIn);
+    }
+    if requested > default {
+        return Ok((
+            default,
+            Some(format!(
+                "requested expiration exceeds the allowed duration, clamped to {}",
+                format_duration(default)
+            )),
+        ));
+    }
+    Ok((requested, None))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::resolve;
+
+    #[test]
+    fn resolution() {
+        let day = Duration::from_secs(24 * 60 * 60);
+        assert_eq!(resolve("", day).unwrap(), (day, None));
+        assert_eq!(
+            resolve("30m", day).unwrap(),
+            (Duration::from_secs(30 * 60), None)
+        );
+        assert_eq!(
+            resolve(" 2h ", day).unwrap(),
+            (Duration::from_secs(2 * 60 * 60), None)
+        );
+        assert_eq!(resolve("1d", day).unwrap(), (day, None));
+        let (clamped, warning) = resolve("7d", day).unwrap();
+        assert_eq!(clamped, day);
+        assert!(warning.is_some());
+        assert!(resolve("0s", day).is_err());
+        assert!(resolve("soon", day).is_err());
+    }
+}
@@ -3,14 +3,15 @@ use std::{
     time::{Duration, SystemTime, UNIX_EPOCH},
 };
 
-use axum::http::StatusCode;
+use axum::http::{header, HeaderMap, HeaderValue, StatusCode};
 use byte_unit::Byte;
 use humantime::format_rfc3339_seconds;
-use serde::Serialize;
+use serde::{ser::SerializeStruct, Serialize, Serializer};
 
 use crate::{
+    assets,
     error::{upload as UploadError, Error},
-    misc::format_duration,
+    misc::{escape_html, format_duration},
     response::{ApiHeader, SingleLine},
 };
 
@@ -22,6 +23,8 @@ pub struct UploadInfo {
     alias: Aliases,
     link: Links,
     expiration: ExpirationGroup,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    warning: Option<String>,
 }
 
 impl UploadInfo {
@@ -44,21 +47,59 @@ impl UploadInfo {
             link: Links {
                 short: format!("{}/{}", link_base, &alias.0),
                 long: format!("{}/{}", link_base, &alias.1),
+                qr: format!("{}/{}/qr", link_base, &alias.0),
             },
-            expiration: ExpirationGroup {
-                current: expiration.0.clone(),
-                allowed: expiration
-                    .1
-                    .unwrap_or_else(|| expiration.0.duration.clone()),
-            },
+            expiration: ExpirationGroup::new(expiration.0, expiration.1),
+            warning: None,
         }
     }
+
+    // Options of the upload that couldn't be honored as requested.
+    pub fn with_warning(mut self, warning: Option<String>) -> Self {
+        self.warning = warning;
+        self
+    }
+}
+
+impl UploadInfo {
+    pub fn size(&self) -> u64 {
+        self.size.bytes
+    }
+
+    // Page displayed to browsers uploading using the no-JS form.
+    pub fn html(&self) -> Option<String> {
+        Some(
+            assets::template("/uploaded.html")?
+                .replace("$NAME", &escape_html(&self.name))
+                .replace("$SIZE", &self.size.readable)
+                .replace("$SHORT_LINK", &escape_html(&self.link.short))
+                .replace("$LONG_LINK", &escape_html(&self.link.long))
+                .replace("$EXPIRATION", &self.expiration.current.date.readable)
+                .replace("$ADMIN", &self.admin),
+        )
+    }
 }
 
 impl ApiHeader for UploadInfo {
     fn status_code(&self) -> StatusCode {
         StatusCode::CREATED
     }
+
+    // Both downloading and managing (PATCH and DELETE) the file use the alias path. The created
+    // resource is the download link.
+    fn additional_headers(&self) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        if let Ok(location) = HeaderValue::try_from(&self.link.long) {
+            headers.insert(header::LOCATION, location);
+        }
+        if let Ok(link) = HeaderValue::try_from(format!(
+            r#"</{0}>; rel="download", </{0}>; rel="edit""#,
+            self.alias.short
+        )) {
+            headers.insert(header::LINK, link);
+        }
+        headers
+    }
 }
 
 impl SingleLine for UploadInfo {
@@ -67,10 +108,80 @@ impl SingleLine for UploadInfo {
     }
 }
 
+// Results of a form holding several files, in the order of the files.
+pub struct UploadBatch(pub Vec<Result<UploadInfo, Error>>);
+
+impl ApiHeader for UploadBatch {
+    fn status_code(&self) -> StatusCode {
+        if self.success() {
+            StatusCode::CREATED
+        } else {
+            StatusCode::MULTI_STATUS
+        }
+    }
+
+    fn success(&self) -> bool {
+        self.0.iter().all(Result::is_ok)
+    }
+}
+
+impl Serialize for UploadBatch {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        #[derive(Serialize)]
+        struct Entry<'a, T> {
+            success: bool,
+            #[serde(flatten)]
+            data: &'a T,
+        }
+
+        #[derive(Serialize)]
+        #[serde(untagged)]
+        enum FileResult<'a> {
+            Uploaded(Entry<'a, UploadInfo>),
+            Failed(Entry<'a, Error>),
+        }
+
+        let files = self
+            .0
+            .iter()
+            .map(|result| match result {
+                Ok(info) => FileResult::Uploaded(Entry {
+                    success: true,
+                    data: info,
+                }),
+                Err(err) => FileResult::Failed(Entry {
+                    success: false,
+                    data: err,
+                }),
+            })
+            .collect::<Vec<_>>();
+        let mut state = serializer.serialize_struct("UploadBatch", 1)?;
+        state.serialize_field("files", &files)?;
+        state.end()
+    }
+}
+
+// One line per file, its short link or the reason of its failure.
+impl SingleLine for UploadBatch {
+    fn single_lined(&self) -> String {
+        self.0
+            .iter()
+            .map(|result| match result {
+                Ok(info) => info.single_lined(),
+                Err(err) => err.single_lined(),
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+}
+
 #[derive(Serialize)]
 pub struct Size {
-    bytes: u64,
-    readable: String,
+    pub bytes: u64,
+    pub readable: String,
 }
 
 impl From<u64> for Size {
@@ -84,6 +195,18 @@ impl From<u64> for Size {
     }
 }
 
+impl Size {
+    // Binary units (KiB, MiB, GiB), used to report sizes against limits.
+    pub fn binary(bytes: u64) -> Self {
+        Self {
+            bytes,
+            readable: Byte::from_bytes(bytes)
+                .get_appropriate_unit(true)
+                .to_string(),
+        }
+    }
+}
+
 #[derive(Serialize)]
 pub struct Aliases {
     short: String,
@@ -94,6 +217,8 @@ pub struct Aliases {
 pub struct Links {
     short: String,
     long: String,
+    // QR code of the short link, for phones.
+    qr: String,
 }
 
 #[derive(Serialize)]
@@ -102,6 +227,22 @@ pub struct ExpirationGroup {
     allowed: ExpirationDuration,
 }
 
+impl ExpirationGroup {
+    // Files without an extended duration can only be refreshed to their default one.
+    pub fn new(current: Expiration, allowed: Option<ExpirationDuration>) -> Self {
+        Self {
+            allowed: allowed.unwrap_or_else(|| current.duration.clone()),
+            current,
+        }
+    }
+}
+
+impl SingleLine for ExpirationGroup {
+    fn single_lined(&self) -> String {
+        self.current.single_lined()
+    }
+}
+
 #[derive(Serialize, Clone)]
 pub struct Expiration {
     duration: ExpirationDuration,
@@ -159,16 +300,23 @@ impl TryFrom<Duration> for ExpirationDate {
 
     fn try_from(duration: Duration) -> Result<Self, Self::Error> {
         let expiration = SystemTime::now() + duration;
-        Ok(Self {
-            timestamp: expiration
+        Ok(Self::from_timestamp(
+            expiration
                 .duration_since(UNIX_EPOCH)
                 .map_err(|_| UploadError::TimeCalculation)?
                 .as_secs(),
-            readable: {
-                let mut full = format_rfc3339_seconds(expiration).to_string();
-                full.truncate(full.len() - 4);
-                full.replace('T', " ").replace('-', "/")
-            },
-        })
+        ))
+    }
+}
+
+impl ExpirationDate {
+    pub fn from_timestamp(timestamp: u64) -> Self {
+        let mut full =
+            format_rfc3339_seconds(UNIX_EPOCH + Duration::from_secs(timestamp)).to_string();
+        full.truncate(full.len() - 4);
+        Self {
+            timestamp,
+            readable: full.replace('T', " ").replace('-', "/"),
+        }
     }
 }
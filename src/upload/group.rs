@@ -0,0 +1,157 @@
+use std::str::FromStr;
+
+use crate::upload::{Determiner, Threshold};
+
+// Threshold of a group of users, replacing the default ones for their uploads.
+#[derive(Clone, Debug)]
+pub struct GroupThreshold {
+    pub group: String,
+    pub threshold: Threshold,
+}
+
+impl FromStr for GroupThreshold {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once('=') {
+            Some((group, threshold)) if !group.is_empty() => Ok(Self {
+                group: group.to_owned(),
+                threshold: threshold.parse()?,
+            }),
+            _ => Err("invalid format (should be GROUP=SIZE:DURATION[:DURATION])"),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct GroupMembers {
+    pub group: String,
+    pub usernames: Vec<String>,
+}
+
+impl FromStr for GroupMembers {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once('=') {
+            Some((group, usernames)) if !group.is_empty() && !usernames.is_empty() => Ok(Self {
+                group: group.to_owned(),
+                usernames: usernames.split(',').map(ToOwned::to_owned).collect(),
+            }),
+            _ => Err("invalid format (should be GROUP=USERNAME[,USERNAME...])"),
+        }
+    }
+}
+
+// Thresholds of the groups, the first group listing a user applies to their uploads.
+#[derive(Debug, Default)]
+pub struct GroupDeterminers {
+    determiners: Vec<(String, Determiner)>,
+    members: Vec<GroupMembers>,
+}
+
+impl GroupDeterminers {
+    pub fn new(
+        thresholds: Vec<GroupThreshold>,
+        members: Vec<GroupMembers>,
+    ) -> Result<Self, String> {
+        let mut grouped = Vec::<(String, Vec<Threshold>)>::new();
+        for GroupThreshold { group, threshold } in thresholds {
+            match grouped.iter_mut().find(|(name, _)| *name == group) {
+                Some((_, thresholds)) => thresholds.push(threshold),
+                None => grouped.push((group, vec![threshold])),
+            }
+        }
+        let determiners = grouped
+            .into_iter()
+            .map(|(group, thresholds)| {
+                Determiner::new(thresholds)
+                    .map(|determiner| (group.clone(), determiner))
+                    .map_err(|err| format!("{} (group {})", err, group))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        if let Some(members) = members
+            .iter()
+            .find(|members| determiners.iter().all(|(group, _)| *group != members.group))
+        {
+            return Err(format!("group {} has no thresholds", members.group));
+        }
+        Ok(Self {
+            determiners,
+            members,
+        })
+    }
+
+    // Anonymous and ungrouped users get the default thresholds.
+    pub fn determiner(&self, username: Option<&str>) -> Option<&Determiner> {
+        let username = username?;
+        let members = self
+            .members
+            .iter()
+            .find(|members| members.usernames.iter().any(|member| member == username))?;
+        self.determiners
+            .iter()
+            .find(|(group, _)| *group == members.group)
+            .map(|(_, determiner)| determiner)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{GroupDeterminers, GroupMembers, GroupThreshold};
+
+    fn groups(thresholds: &[&str], members: &[&str]) -> Result<GroupDeterminers, String> {
+        GroupDeterminers::new(
+            thresholds.iter().map(|t| t.parse().unwrap()).collect(),
+            members.iter().map(|m| m.parse().unwrap()).collect(),
+        )
+    }
+
+    #[test]
+    fn parse() {
+        let threshold = "premium=1GB:30d".parse::<GroupThreshold>().unwrap();
+        assert_eq!(threshold.group, "premium");
+        assert_eq!(threshold.threshold.size, 1_000_000_000);
+        assert!("premium".parse::<GroupThreshold>().is_err());
+        assert!("=1GB:30d".parse::<GroupThreshold>().is_err());
+        assert!("premium=1GB".parse::<GroupThreshold>().is_err());
+
+        let members = "premium=alice,bob".parse::<GroupMembers>().unwrap();
+        assert_eq!(members.usernames, ["alice", "bob"]);
+        assert!("premium=".parse::<GroupMembers>().is_err());
+        assert!("alice".parse::<GroupMembers>().is_err());
+    }
+
+    #[test]
+    fn resolution() {
+        let groups = groups(
+            &["staff=100MB:7d", "premium=1GB:30d", "premium=10GB:7d"],
+            &["premium=alice,bob", "staff=bob,carol"],
+        )
+        .unwrap();
+        assert_eq!(
+            groups.determiner(Some("alice")).unwrap().max_size(),
+            10_000_000_000
+        );
+        // Listed in both groups, the first membership wins.
+        assert_eq!(
+            groups.determiner(Some("bob")).unwrap().max_size(),
+            10_000_000_000
+        );
+        assert_eq!(
+            groups.determiner(Some("carol")).unwrap().max_size(),
+            100_000_000
+        );
+        // Defaults.
+        assert!(groups.determiner(Some("dave")).is_none());
+        assert!(groups.determiner(None).is_none());
+    }
+
+    #[test]
+    fn invalid() {
+        // Thresholds of a group follow the same ordering rules as the default ones.
+        assert!(groups(&["premium=10GB:30d", "premium=1GB:7d"], &[]).is_err());
+        assert!(groups(&["premium=1GB:30d"], &["staff=carol"]).is_err());
+        assert!(groups(&[], &[]).is_ok());
+    }
+}
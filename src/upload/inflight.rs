@@ -0,0 +1,125 @@
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+};
+
+use futures::{Stream, StreamExt};
+use hyper::body::Bytes;
+
+// Sum of the bytes received by uploads that are still being processed.
+#[derive(Clone, Debug)]
+pub struct InflightBytes {
+    current: Arc<AtomicU64>,
+    // Whole size of the upload sessions, reserved until they complete or are abandoned.
+    sessions: Arc<Mutex<HashMap<String, u64>>>,
+    max: Option<u64>,
+}
+
+impl InflightBytes {
+    pub fn new(max: Option<u64>) -> Self {
+        Self {
+            current: Arc::new(AtomicU64::new(0)),
+            sessions: Arc::new(Mutex::new(HashMap::new())),
+            max,
+        }
+    }
+
+    pub fn is_saturated(&self) -> bool {
+        match self.max {
+            Some(max) => {
+                let reserved = self.sessions.lock().unwrap().values().sum::<u64>();
+                self.current.load(Ordering::Relaxed) + reserved >= max
+            }
+            None => false,
+        }
+    }
+
+    // Reserving a session again is a no-op, sessions left by a previous run are reserved on their
+    // next chunk.
+    pub fn reserve(&self, id: &str, size: u64) {
+        self.sessions.lock().unwrap().insert(id.to_owned(), size);
+    }
+
+    pub fn release(&self, id: &str) {
+        self.sessions.lock().unwrap().remove(id);
+    }
+
+    // Received bytes are released once the stream is dropped, whether the upload completed or was aborted.
+    pub fn track<S, E>(&self, body: S) -> impl Stream<Item = Result<Bytes, E>> + Unpin
+    where
+        S: Stream<Item = Result<Bytes, E>> + Unpin,
+    {
+        let mut guard = Guard {
+            current: Arc::clone(&self.current),
+            count: 0,
+        };
+        body.map(move |chunk| {
+            if let Ok(data) = &chunk {
+                guard.add(data.len() as u64);
+            }
+            chunk
+        })
+    }
+}
+
+struct Guard {
+    current: Arc<AtomicU64>,
+    count: u64,
+}
+
+impl Guard {
+    fn add(&mut self, count: u64) {
+        self.current.fetch_add(count, Ordering::Relaxed);
+        self.count += count;
+    }
+}
+
+impl Drop for Guard {
+    fn drop(&mut self) {
+        self.current.fetch_sub(self.count, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::Infallible;
+
+    use futures::{stream, StreamExt};
+    use hyper::body::Bytes;
+
+    use super::InflightBytes;
+
+    #[tokio::test]
+    async fn track() {
+        let inflight = InflightBytes::new(Some(4));
+        let mut body = inflight.track(stream::iter(vec![
+            Ok::<_, Infallible>(Bytes::from_static(b"abc")),
+            Ok(Bytes::from_static(b"def")),
+        ]));
+        assert!(!inflight.is_saturated());
+        body.next().await;
+        assert!(!inflight.is_saturated());
+        body.next().await;
+        assert!(inflight.is_saturated());
+        drop(body);
+        assert!(!inflight.is_saturated());
+
+        assert!(!InflightBytes::new(None).is_saturated());
+    }
+
+    #[test]
+    fn sessions() {
+        let inflight = InflightBytes::new(Some(4));
+        inflight.reserve("id", 2);
+        inflight.reserve("id", 2);
+        assert!(!inflight.is_saturated());
+        inflight.reserve("other", 2);
+        assert!(inflight.is_saturated());
+        inflight.release("other");
+        inflight.release("unknown");
+        assert!(!inflight.is_saturated());
+    }
+}
@@ -1,43 +1,175 @@
-use std::{convert::TryFrom, net::SocketAddr, sync::Arc};
+use std::{
+    convert::TryFrom,
+    net::IpAddr,
+    sync::Arc,
+    time::{Instant, SystemTime, UNIX_EPOCH},
+};
 
 use axum::{
-    extract::{BodyStream, ConnectInfo},
+    extract::BodyStream,
     headers::{authorization::Basic, Authorization, ContentLength, Cookie},
-    routing::post,
+    response::{Html, IntoResponse, Redirect, Response},
+    routing::{get, patch, post},
     Extension, Router, TypedHeader,
 };
-use file::UploadInfo;
+use file::{UploadBatch, UploadInfo};
 use filename::Filename;
-use futures::StreamExt;
+use futures::{stream, Stream, StreamExt};
 use http_negotiator::{ContentTypeNegotiation, Negotiation};
-use sqlx::SqlitePool;
-use tokio::{fs::File, io::AsyncWriteExt};
+use hyper::{body::Bytes, header, HeaderMap, StatusCode};
+use multer::{Field, Multipart};
+use sanitize_filename::sanitize;
+use sha2::{Digest, Sha256};
+use sqlx::{SqliteConnection, SqlitePool};
+use tokio::io::{AsyncWrite, AsyncWriteExt};
 use uuid::Uuid;
 
 use crate::{
-    alias,
-    auth::{AuthStatus, Authenticator, Features, Origin},
+    access::AccessUser,
+    alias::{self, Alias},
+    auth::{AuthStatus, Authenticator, ClientCertificate, Features, Origin},
+    download::hash_password,
     error::{auth as AuthError, upload as UploadError, Error},
     include_query,
-    limit::{Chain as ChainLimiter, Limiter},
+    limit::{Chain as ChainLimiter, Limiter, Rate as RateLimiter, Remaining, SqliteStore},
+    metrics,
+    query::{is_unique_violation, with_timeout},
     response::{ApiResponse, ResponseType},
-    storage::Dir,
-    upload::{file::ExpirationDuration, origin::ForwardedForHeader},
+    storage::{blob, Dir},
+    timing::ServerTiming,
+    upload::origin::ClientAddress,
+    webhook::{self, Notification, WebhookEvent},
 };
 
+mod check;
+mod compress;
+mod dedup;
+mod downloads;
 mod expiration;
+mod expire_in;
 mod file;
 mod filename;
+mod group;
+mod inflight;
 mod origin;
+mod password;
+mod pending;
+mod quota;
+mod session;
+mod shed;
+mod sniff;
+mod transform;
 
+pub use compress::CompressAtRest;
+pub use dedup::DeduplicateUploads;
+pub use downloads::MaxDownloads;
 pub use expiration::{Determiner, Threshold};
-pub use file::Expiration;
-pub use origin::{DomainUri, RealIp};
+pub use file::{Expiration, ExpirationDate, ExpirationDuration, Size};
+pub use group::{GroupDeterminers, GroupMembers, GroupThreshold};
+pub use inflight::InflightBytes;
+pub use origin::{trusts_peer, DomainUri, RealIp};
+pub use password::{CharacterClass, PasswordPolicy};
+pub use pending::PendingUploads;
+use session::ActiveSessions;
+pub use shed::LoadShedder;
+pub use sniff::SniffBytes;
+pub use transform::Transform;
+
+enum UploadBody<'a, S> {
+    Raw(S),
+    Form(&'a mut UploadForm),
+}
+
+impl<'a> UploadBody<'a, stream::Empty<Result<Bytes, std::io::Error>>> {
+    // Forms are read through their multipart parser, the type of their raw body doesn't matter.
+    fn form(form: &'a mut UploadForm) -> Self {
+        Self::Form(form)
+    }
+}
+
+// Upload processing state, shared by all the uploads.
+struct UploadState {
+    origin: Origin,
+    limiter: ChainLimiter,
+    rate: RateLimiter,
+    determiner: Arc<Determiner>,
+    groups: Arc<GroupDeterminers>,
+    dir: Dir,
+    inflight: InflightBytes,
+    shedder: LoadShedder,
+    transform: Transform,
+    sniff: SniffBytes,
+    compress: CompressAtRest,
+    dedup: DeduplicateUploads,
+    max_downloads: MaxDownloads,
+    password_policy: PasswordPolicy,
+    unique_names: bool,
+    sessions: ActiveSessions,
+    pending: PendingUploads,
+}
+
+impl UploadState {
+    // Uploader's identity, used for the quotas.
+    fn origin(
+        &self,
+        client: Option<IpAddr>,
+        username: Option<String>,
+        certificate: Option<String>,
+    ) -> Result<String, Error> {
+        match self.origin {
+            Origin::IpAddress => client.map(|ip| ip.to_string()),
+            Origin::Username => username.or(certificate),
+        }
+        .ok_or(UploadError::Origin)
+    }
+
+    // Thresholds applying to the uploads of the user.
+    fn determiner(&self, username: Option<&str>) -> &Determiner {
+        self.groups.determiner(username).unwrap_or(&self.determiner)
+    }
+}
+
+// Options of an upload form, sent along its file field.
+#[derive(Default)]
+struct FormOptions {
+    filename: Option<String>,
+    alias: Option<String>,
+    burn: bool,
+    max_downloads: Option<String>,
+    expire_in: Option<String>,
+    password: Option<String>,
+}
+
+struct FormFile {
+    options: FormOptions,
+    declared: Option<String>,
+    field: Field<'static>,
+}
+
+// Upload form holding one or several files. Its option fields apply to all the files sent after
+// them, except the alias which only applies to the next one. Those sent after the last file apply
+// to it.
+struct UploadForm {
+    multipart: Multipart<'static>,
+    options: FormOptions,
+    // Found ahead, to know whether the form holds another file.
+    next: Option<FormFile>,
+    // The file found ahead was reached by reading the previous one, it isn't skipped.
+    ahead: bool,
+    started: bool,
+}
+
+struct Stored {
+    size: u64,
+    hash: String,
+    mime: Option<String>,
+}
 
 pub struct UploadRequest {
     pub filename: Option<String>,
     pub size: u64,
     pub origin: String,
+    pub username: Option<String>,
 }
 
 #[allow(clippy::too_many_arguments)]
@@ -47,135 +179,802 @@ pub async fn handler(
     authenticator: Extension<Arc<Authenticator>>,
     auth_header: Option<TypedHeader<Authorization<Basic>>>,
     cookie: Option<TypedHeader<Cookie>>,
-    Extension(real_ip): Extension<RealIp>,
-    ConnectInfo(addr): ConnectInfo<SocketAddr>,
-    forwarded_address: Option<ForwardedForHeader>,
-    Extension(origin): Extension<Origin>,
-    Extension(limiter): Extension<Arc<ChainLimiter>>,
-    Extension(determiner): Extension<Arc<Determiner>>,
-    Extension(dir): Extension<Dir>,
+    certificate: Option<Extension<ClientCertificate>>,
+    user: AccessUser,
+    ClientAddress(client): ClientAddress,
+    Extension(state): Extension<Arc<UploadState>>,
     DomainUri(domain_uri): DomainUri,
     TypedHeader(ContentLength(size)): TypedHeader<ContentLength>,
     Filename(filename): Filename,
+    headers: HeaderMap,
+    timing: ServerTiming,
     body: BodyStream,
-) -> Result<ApiResponse<UploadInfo>, ApiResponse<Error>> {
-    let username = match authenticator
+) -> Result<Response, ApiResponse<Error>> {
+    let start = Instant::now();
+    let certificate = certificate.and_then(|Extension(ClientCertificate(cn))| cn);
+    let username = authenticate(
+        &authenticator,
+        auth_header,
+        cookie,
+        certificate.clone(),
+        &user,
+    )
+    .await
+    .map_err(|err| ApiResponse(*response_type, err))?;
+    timing.record("auth", start);
+
+    // Non-expiring files would stay forever on public instances, only administrators can pin them.
+    let pinned = headers.contains_key("X-No-Expire");
+    if pinned
+        && !username
+            .as_deref()
+            .map_or(false, |username| authenticator.is_admin(username))
+    {
+        return Err(ApiResponse(*response_type, UploadError::NoExpireForbidden));
+    }
+    // Listed in the feed of recent uploads, when enabled.
+    let public = headers.contains_key("X-Public");
+    // Deleted after its first complete download.
+    let burn = headers.contains_key("X-Burn");
+    // Deleted once downloaded this many times.
+    let max_downloads = match headers.get("X-Max-Downloads") {
+        Some(header) => state
+            .max_downloads
+            .parse(&String::from_utf8_lossy(header.as_bytes()))
+            .map_err(|err| ApiResponse(*response_type, err))?,
+        None => None,
+    };
+    // Required to download the file, on top of the instance's authentication.
+    let password = headers
+        .get("X-Download-Password")
+        .map(|header| String::from_utf8_lossy(header.as_bytes()).into_owned());
+    if let Some(password) = &password {
+        state
+            .password_policy
+            .check(password)
+            .map_err(|err| ApiResponse(*response_type, err))?;
+    }
+    // Memorable links chosen by the uploader replace the random long alias.
+    let custom_alias = match headers.get("X-Custom-Alias") {
+        Some(header) => Some(
+            header
+                .to_str()
+                .ok()
+                .filter(|alias| alias::is_valid_custom(alias))
+                .ok_or(ApiResponse(*response_type, UploadError::InvalidAlias))?
+                .to_owned(),
+        ),
+        None => None,
+    };
+
+    let origin = state
+        .origin(client, username.clone(), certificate)
+        .map_err(|err| ApiResponse(*response_type, err))?;
+    state
+        .rate
+        .acquire(&origin, username.as_deref())
+        .map_err(|retry| ApiResponse(*response_type, UploadError::RateLimited(retry)))?;
+
+    if state.inflight.is_saturated() || state.shedder.is_overloaded() {
+        return Err(ApiResponse(*response_type, UploadError::ServerBusy));
+    }
+
+    // Browsers submitting the no-JS form send a multipart body.
+    let boundary = headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|ct| ct.to_str().ok())
+        .and_then(|ct| multer::parse_boundary(ct).ok());
+    let body = state.inflight.track(body);
+    let start = Instant::now();
+    let mut results = match boundary {
+        Some(boundary) => {
+            let mut form = UploadForm::new(Multipart::new(body, boundary));
+            let mut results = Vec::new();
+            // The headers only apply to the first file. The whole body is an upper bound of its
+            // size, the next files have to fit in what's left.
+            let (mut filename, mut custom_alias, mut remaining) = (filename, custom_alias, size);
+            loop {
+                let result = process_upload(
+                    pool.clone(),
+                    &state,
+                    origin.clone(),
+                    username.clone(),
+                    domain_uri.clone(),
+                    remaining,
+                    filename.take(),
+                    custom_alias.take(),
+                    pinned,
+                    public,
+                    burn,
+                    max_downloads,
+                    password.clone(),
+                    None,
+                    UploadBody::form(&mut form),
+                )
+                .await;
+                if let Ok(info) = &result {
+                    remaining = remaining.saturating_sub(info.size());
+                }
+                let failed = result.is_err();
+                results.push(result);
+                // Rejected before reading the body, like single file uploads.
+                if failed && !form.started {
+                    break;
+                }
+                // Rejected before its field was read, the file is skipped.
+                form.skip_next();
+                match form.has_next().await {
+                    Ok(true) => filename = form.next_filename(),
+                    Ok(false) => break,
+                    Err(err) => {
+                        // A broken body already failed the previous file.
+                        if !failed {
+                            results.push(Err(err));
+                        }
+                        break;
+                    }
+                }
+            }
+            results
+        }
+        None => vec![
+            process_upload(
+                pool,
+                &state,
+                origin,
+                username,
+                domain_uri,
+                size,
+                filename,
+                custom_alias,
+                pinned,
+                public,
+                burn,
+                max_downloads,
+                password,
+                None,
+                UploadBody::Raw(body),
+            )
+            .await,
+        ],
+    };
+    timing.record("storage", start);
+    // Forms holding several files get the result of each of them, even if some failed.
+    if results.len() > 1 {
+        return Ok(ApiResponse(*response_type, UploadBatch(results)).into_response());
+    }
+    let info = results
+        .remove(0)
+        .map_err(|err| ApiResponse(*response_type, err))?;
+
+    let accepts_html = headers
+        .get(header::ACCEPT)
+        .and_then(|accept| accept.to_str().ok())
+        .map_or(false, |accept| accept.contains("text/html"));
+    if accepts_html {
+        if let Some(page) = info.html() {
+            return Ok((StatusCode::CREATED, Html(page)).into_response());
+        }
+    }
+    Ok(ApiResponse(*response_type, info).into_response())
+}
+
+// Username of the uploader, if authenticated.
+async fn authenticate(
+    authenticator: &Authenticator,
+    auth_header: Option<TypedHeader<Authorization<Basic>>>,
+    cookie: Option<TypedHeader<Cookie>>,
+    certificate: Option<String>,
+    user: &AccessUser,
+) -> Result<Option<String>, Error> {
+    match authenticator
         .allows(
             auth_header.map(|h| h.0),
             cookie.map(|h| h.0),
+            certificate,
             Features::UPLOAD,
+            user,
         )
         .await
     {
-        AuthStatus::NotNeeded => None,
-        AuthStatus::Valid(username) => Some(username),
-        AuthStatus::Error(err) => return Err(ApiResponse(*response_type, err)),
-        AuthStatus::Prompt => {
-            return Err(ApiResponse(*response_type, AuthError::MissingAuthorization));
+        AuthStatus::NotNeeded => Ok(None),
+        AuthStatus::Valid(username) => Ok(Some(username)),
+        AuthStatus::Error(err) => Err(err),
+        AuthStatus::Prompt => Err(AuthError::MissingAuthorization),
+    }
+}
+
+impl UploadForm {
+    fn new(multipart: Multipart<'static>) -> Self {
+        Self {
+            multipart,
+            options: FormOptions::default(),
+            next: None,
+            ahead: false,
+            started: false,
         }
-    };
+    }
 
-    let origin = match origin {
-        Origin::IpAddress => real_ip
-            .resolve(addr.ip(), forwarded_address.map(|fa| fa.0))
-            .ok_or(ApiResponse(*response_type, UploadError::Origin))?
-            .to_string(),
-        Origin::Username => username.ok_or(ApiResponse(*response_type, UploadError::Origin))?,
-    };
+    // Find the next file field of the form, and the option fields sent before it.
+    async fn next_file(&mut self) -> Result<Option<FormFile>, Error> {
+        if let Some(file) = self.next.take() {
+            return Ok(Some(file));
+        }
+        self.started = true;
+        let options = &mut self.options;
+        while let Some(field) = self
+            .multipart
+            .next_field()
+            .await
+            .map_err(|_| UploadError::InvalidForm)?
+        {
+            if field.name() == Some("alias") {
+                options.alias = Some(field.text().await.map_err(|_| UploadError::InvalidForm)?)
+                    .filter(|alias| !alias.is_empty());
+            } else if field.name() == Some("burn") {
+                let value = field.text().await.map_err(|_| UploadError::InvalidForm)?;
+                // Checkboxes are sent as "on".
+                options.burn = matches!(value.as_str(), "true" | "1" | "on");
+            } else if field.name() == Some("max_downloads") {
+                options.max_downloads =
+                    Some(field.text().await.map_err(|_| UploadError::InvalidForm)?);
+            } else if field.name() == Some("expire_in") {
+                options.expire_in = Some(field.text().await.map_err(|_| UploadError::InvalidForm)?);
+            } else if field.name() == Some("password") {
+                options.password = Some(field.text().await.map_err(|_| UploadError::InvalidForm)?)
+                    .filter(|password| !password.is_empty());
+            } else if field.name() == Some("file") {
+                let declared = field
+                    .content_type()
+                    .and_then(|mime| sniff::declared_mime(mime.essence_str()));
+                return Ok(Some(FormFile {
+                    options: FormOptions {
+                        filename: field
+                            .file_name()
+                            .map(sanitize)
+                            .filter(|filename| !filename.is_empty()),
+                        alias: options.alias.take(),
+                        burn: options.burn,
+                        max_downloads: options.max_downloads.clone(),
+                        expire_in: options.expire_in.clone(),
+                        password: options.password.clone(),
+                    },
+                    declared,
+                    field,
+                }));
+            }
+        }
+        Ok(None)
+    }
 
-    let info = process_upload(
-        pool, limiter, origin, determiner, domain_uri, dir, size, filename, body,
-    )
-    .await
-    .map_err(|err| ApiResponse(*response_type, err))?;
-    Ok(ApiResponse(*response_type, info))
+    // Reads the body up to the next file field, the previous file must have been processed.
+    async fn has_next(&mut self) -> Result<bool, Error> {
+        if self.next.is_none() {
+            self.next = self.next_file().await?;
+        }
+        Ok(self.next.is_some())
+    }
+
+    // Reads the body up to the next file field once a file is processed, the option fields
+    // sent after the last file apply to it.
+    async fn read_ahead(&mut self, options: &mut FormOptions) -> Result<(), Error> {
+        self.next = self.next_file().await?;
+        self.ahead = true;
+        if self.next.is_none() {
+            options.alias = self.options.alias.take().or(options.alias.take());
+            options.burn = self.options.burn;
+            options.max_downloads = self.options.max_downloads.clone();
+            options.expire_in = self.options.expire_in.clone();
+            options.password = self.options.password.clone();
+        }
+        Ok(())
+    }
+
+    // Drops the file found ahead, its content is skipped when reading the next field. A file
+    // reached by reading the previous one is kept.
+    fn skip_next(&mut self) {
+        if !std::mem::take(&mut self.ahead) {
+            self.next = None;
+        }
+    }
+
+    fn next_filename(&self) -> Option<String> {
+        self.next
+            .as_ref()
+            .and_then(|file| file.options.filename.clone())
+    }
 }
 
 #[allow(clippy::too_many_arguments)]
 async fn process_upload(
     pool: SqlitePool,
-    limiter: Arc<ChainLimiter>,
+    state: &UploadState,
     origin: String,
-    determiner: Arc<Determiner>,
+    username: Option<String>,
     domain_uri: String,
-    dir: Dir,
     size: u64,
     filename: Option<String>,
-    body: BodyStream,
+    custom_alias: Option<String>,
+    pinned: bool,
+    public: bool,
+    burn: bool,
+    max_downloads: Option<u16>,
+    password: Option<String>,
+    session: Option<&str>,
+    body: UploadBody<'_, impl Stream<Item = Result<Bytes, impl std::error::Error>> + Unpin>,
 ) -> Result<UploadInfo, Error> {
+    let UploadState {
+        limiter,
+        dir,
+        transform,
+        sniff,
+        compress,
+        dedup,
+        max_downloads: max_downloads_limit,
+        password_policy,
+        unique_names,
+        pending,
+        ..
+    } = state;
     let upload_req = UploadRequest {
         filename,
         size,
         origin,
+        username,
     };
-    let mut conn = pool.acquire().await.map_err(|_| UploadError::Database)?;
+    // The Content-Length of a form is only an upper bound of the file size, the quotas and the
+    // expiration of its file are determined once it's written.
+    let exact = matches!(body, UploadBody::Raw(_));
+    let reserved = if exact { upload_req.size } else { 0 };
+    let determiner = state.determiner(upload_req.username.as_deref());
+    let mut conn = with_timeout(pool.acquire())
+        .await?
+        .map_err(|_| UploadError::Database)?;
 
     // Quota.
-    if !limiter
-        .accept(&upload_req, &mut conn)
-        .await
-        .ok_or(UploadError::QuotaAccess)?
-    {
-        return Err(UploadError::QuotaExceeded);
+    let reserved_req = UploadRequest {
+        filename: upload_req.filename.clone(),
+        size: reserved,
+        origin: upload_req.origin.clone(),
+        username: upload_req.username.clone(),
+    };
+    accept_quota(&mut conn, limiter, &reserved_req, session).await?;
+
+    // Uploads sent using a form only know their filename once the body is read, they aren't checked.
+    if let (true, Some(filename)) = (*unique_names, &upload_req.filename) {
+        if let Some(alias) = existing_name(&mut conn, &upload_req.origin, filename).await? {
+            return Err(UploadError::NameConflict(alias));
+        }
     }
 
     // Aliases and links.
-    let (short, long) = alias::random_unused_aliases(&mut conn)
-        .await
-        .ok_or(UploadError::AliasGeneration)?;
+    let long_is_random = custom_alias.is_none();
+    let (short, long) = match custom_alias {
+        Some(custom) => {
+            check_custom_alias(&mut conn, &custom).await?;
+            let short = with_timeout(alias::random_unused_short(&mut conn))
+                .await?
+                .ok_or(UploadError::AliasGeneration)?;
+            (short, custom)
+        }
+        None => with_timeout(alias::random_unused_aliases(&mut conn))
+            .await?
+            .ok_or(UploadError::AliasGeneration)?,
+    };
 
     // Expiration.
     let (default_duration, allowed_duration) = determiner
-        .determine(upload_req.size)
-        .ok_or(UploadError::TooLarge)?;
+        .determine(reserved)
+        .ok_or_else(|| UploadError::TooLarge(reserved, determiner.max_size()))?;
     let default_expiration = Expiration::try_from(default_duration)?;
 
     let id = Uuid::new_v4().as_hyphenated().to_string();
     let admin = Uuid::new_v4().as_hyphenated().to_string();
 
-    sqlx::query(include_query!("insert_file"))
-        .bind(&id)
-        .bind(&admin)
-        .bind(upload_req.origin.to_string())
-        .bind(default_expiration.timestamp() as i64)
-        .bind(&upload_req.filename)
-        .bind(upload_req.size as i64)
-        .bind(&short)
-        .bind(&long)
-        .execute(&mut conn)
-        .await
-        .map_err(|_| UploadError::Database)?;
+    with_timeout(
+        sqlx::query(include_query!("insert_file"))
+            .bind(&id)
+            .bind(&admin)
+            .bind(upload_req.origin.to_string())
+            .bind(default_expiration.timestamp() as i64)
+            .bind(&upload_req.filename)
+            .bind(reserved as i64)
+            .bind(&short)
+            .bind(&long)
+            .bind(pinned)
+            .bind(public)
+            .execute(&mut conn),
+    )
+    .await?
+    .map_err(|err| {
+        if is_unique_violation(&err) {
+            UploadError::AliasConflict
+        } else {
+            UploadError::Database
+        }
+    })?;
     drop(conn);
+    let _pending = pending.track(&upload_req.origin, &id);
 
-    // Copy body to file system.
-    let file = dir
-        .create_file(&id)
+    // Copy body to file system. The body isn't polled before this point, so clients
+    // waiting for a "100 Continue" get the result of the checks above without sending it.
+    let (file, nonce) = dir
+        .create_writer(&upload_req.origin, &id)
         .await
         .map_err(|_| UploadError::CreateFile)?;
-    if let Err(err) = write_file(&upload_req, body, file).await {
-        clean_failed_upload(&dir, &id, &pool).await;
+    let max_size = determiner.max_size();
+    let copied = match body {
+        UploadBody::Raw(body) => transform
+            .store(&upload_req, true, body, file, max_size, *sniff)
+            .await
+            .map(|copied| (FormOptions::default(), copied)),
+        // The Content-Length of a form is only an upper bound of the file size.
+        UploadBody::Form(form) => match form.next_file().await {
+            Ok(Some(file_field)) => {
+                match transform
+                    .store(&upload_req, false, file_field.field, file, max_size, *sniff)
+                    .await
+                {
+                    Ok(mut copied) => {
+                        // Text files aren't sniffed, the declared type of the field is used
+                        // instead. It describes the upload, not the output of a transform command.
+                        if !transform.is_enabled() {
+                            copied.mime = copied.mime.or(file_field.declared);
+                        }
+                        let mut options = file_field.options;
+                        form.read_ahead(&mut options)
+                            .await
+                            .map(|_| (options, copied))
+                    }
+                    Err(err) => Err(err),
+                }
+            }
+            Ok(None) => Err(UploadError::InvalidForm),
+            Err(err) => Err(err),
+        },
+    };
+    let (form, stored) = match copied {
+        Ok(copied) => copied,
+        Err(err) => {
+            clean_failed_upload(dir, &upload_req.origin, &id, &pool).await;
+            return Err(err);
+        }
+    };
+    let written = stored.size;
+    // A transformed upload may have grown and a form's file wasn't reserved, the quotas are checked
+    // again with the written size.
+    if written > reserved {
+        let written_req = UploadRequest {
+            filename: None,
+            size: written,
+            origin: upload_req.origin.clone(),
+            username: upload_req.username.clone(),
+        };
+        if let Err(err) = accept_resize(&pool, limiter, &written_req, &id, session).await {
+            clean_failed_upload(dir, &upload_req.origin, &id, &pool).await;
+            return Err(err);
+        }
+    }
+    let (default_duration, allowed_duration, default_expiration) = if exact {
+        (default_duration, allowed_duration, default_expiration)
+    } else {
+        let determined = determiner
+            .determine(written)
+            .ok_or_else(|| UploadError::TooLarge(written, determiner.max_size()))
+            .and_then(|(default_duration, allowed_duration)| {
+                Ok((
+                    default_duration,
+                    allowed_duration,
+                    Expiration::try_from(default_duration)?,
+                ))
+            });
+        match determined {
+            Ok(determined) => determined,
+            Err(err) => {
+                clean_failed_upload(dir, &upload_req.origin, &id, &pool).await;
+                return Err(err);
+            }
+        }
+    };
+    // The alias field of a form is only known once the body is read, the header one prevails.
+    let long = match form.alias.filter(|_| long_is_random) {
+        Some(custom) => match replace_long_alias(&pool, &id, custom).await {
+            Ok(custom) => custom,
+            Err(err) => {
+                clean_failed_upload(dir, &upload_req.origin, &id, &pool).await;
+                return Err(err);
+            }
+        },
+        None => long,
+    };
+    // Quotas keep accounting the uploaded size, even if the file takes less space on disk.
+    // Encrypted content doesn't compress, it would have to be done before encrypting.
+    // Content already stored is shared as is, whether it's compressed or encrypted.
+    let (compressed, nonce) = match dedup
+        .deduplicate(&pool, dir, &upload_req.origin, &id, &stored.hash)
+        .await
+    {
+        Some(shared) => shared,
+        None => (
+            nonce.is_none()
+                && compress.should_compress(stored.mime.as_deref())
+                && compress_file(dir, &upload_req.origin, &id).await,
+            nonce,
+        ),
+    };
+    // The header prevails over the form field, which is only known once the body is read.
+    let max_downloads = match (max_downloads, form.max_downloads) {
+        (Some(count), _) => Some(count),
+        (None, Some(value)) => match max_downloads_limit.parse(&value) {
+            Ok(count) => count,
+            Err(err) => {
+                clean_failed_upload(dir, &upload_req.origin, &id, &pool).await;
+                return Err(err);
+            }
+        },
+        (None, None) => None,
+    };
+    let (expiration, warning) = match form.expire_in {
+        Some(value) => match expire_in::resolve(&value, default_duration)
+            .and_then(|(duration, warning)| Ok((Expiration::try_from(duration)?, warning)))
+        {
+            Ok(resolved) => resolved,
+            Err(err) => {
+                clean_failed_upload(dir, &upload_req.origin, &id, &pool).await;
+                return Err(err);
+            }
+        },
+        None => (default_expiration, None),
+    };
+    let filename = upload_req.filename.clone().or(form.filename);
+    let password = match password.or(form.password) {
+        Some(password) => {
+            // The form field is only checked once the body is read.
+            let hashed = match password_policy.check(&password) {
+                Ok(()) => hash_password(password).await,
+                Err(err) => Err(err),
+            };
+            match hashed {
+                Ok(hash) => Some(hash),
+                Err(err) => {
+                    clean_failed_upload(dir, &upload_req.origin, &id, &pool).await;
+                    return Err(err);
+                }
+            }
+        }
+        None => None,
+    };
+    if let Err(err) = with_timeout(
+        sqlx::query(include_query!("update_file_uploaded"))
+            .bind(written as i64)
+            .bind(&filename)
+            .bind(&stored.hash)
+            .bind(&stored.mime)
+            .bind(compressed)
+            .bind(burn || form.burn)
+            .bind(max_downloads)
+            .bind(&nonce)
+            .bind(&password)
+            .bind(expiration.timestamp() as i64)
+            .bind(&id)
+            .execute(&pool),
+    )
+    .await
+    .and_then(|res| res.map_err(|_| UploadError::Database))
+    {
+        clean_failed_upload(dir, &upload_req.origin, &id, &pool).await;
         return Err(err);
     }
+    metrics::UPLOADS.increment();
+    metrics::UPLOADED_BYTES.add(written);
+    webhook::notify(Notification {
+        event: WebhookEvent::Upload,
+        short_alias: short.clone(),
+        long_alias: long.clone(),
+        name: filename.clone().unwrap_or_else(|| long.clone()),
+        size: written,
+        origin: upload_req.origin.clone(),
+        expiration: (!pinned).then(|| expiration.timestamp()),
+        downloads_remaining: max_downloads.map(u64::from),
+    });
 
     Ok(UploadInfo::new(
         admin,
-        upload_req.filename.unwrap_or_else(|| long.clone()),
-        upload_req.size,
+        filename.unwrap_or_else(|| long.clone()),
+        written,
         (short, long),
         domain_uri,
         (
-            default_expiration,
-            allowed_duration.map(ExpirationDuration::from),
+            expiration,
+            // A shortened expiration can still be refreshed up to the default one.
+            Some(ExpirationDuration::from(
+                allowed_duration.unwrap_or(default_duration),
+            )),
         ),
+    )
+    .with_warning(warning))
+}
+
+// The session completed by the upload, if any, no longer counts in the quotas.
+async fn accept_quota(
+    conn: &mut SqliteConnection,
+    limiter: &ChainLimiter,
+    req: &UploadRequest,
+    session: Option<&str>,
+) -> Result<(), Error> {
+    if with_timeout(limiter.accept(req, &mut SqliteStore::new(conn).excluding_session(session)))
+        .await?
+        .ok_or(UploadError::QuotaAccess)?
+    {
+        return Ok(());
+    }
+    let left = quota_left(conn, limiter, req, session).await?;
+    // Only uploaders identified by their username are told about their own files, when they're
+    // out of files rather than space.
+    if left.is_none() && req.username.as_deref() == Some(req.origin.as_str()) {
+        if let Some((alias, expiration)) = with_timeout(
+            sqlx::query_as::<_, (String, i64)>(include_query!("get_file_next_expiring"))
+                .bind(&req.origin)
+                .fetch_optional(&mut *conn),
+        )
+        .await?
+        .map_err(|_| UploadError::Database)?
+        {
+            return Err(UploadError::QuotaExceededUntil(alias, expiration as u64));
+        }
+    }
+    Err(UploadError::QuotaExceeded(req.size, left))
+}
+
+// Size left in the quotas, if it's what the upload exceeds rather than the files count.
+async fn quota_left(
+    conn: &mut SqliteConnection,
+    limiter: &ChainLimiter,
+    req: &UploadRequest,
+    session: Option<&str>,
+) -> Result<Option<u64>, Error> {
+    let mut remaining = Remaining::default();
+    with_timeout(limiter.remaining(
+        req,
+        &mut SqliteStore::new(conn).excluding_session(session),
+        &mut remaining,
     ))
+    .await?
+    .ok_or(UploadError::QuotaAccess)?;
+    Ok(size_left(&remaining, req.size))
 }
 
-async fn write_file(
+fn size_left(remaining: &Remaining, size: u64) -> Option<u64> {
+    if remaining.origin_files == Some(0) {
+        return None;
+    }
+    [remaining.origin_size, remaining.global_size]
+        .into_iter()
+        .flatten()
+        .min()
+        .filter(|&left| left < size)
+}
+
+async fn check_custom_alias(conn: &mut SqliteConnection, custom: &str) -> Result<(), Error> {
+    if with_timeout(Alias::Long(custom.to_owned()).is_used(conn))
+        .await?
+        .ok_or(UploadError::Database)?
+    {
+        return Err(UploadError::AliasConflict);
+    }
+    Ok(())
+}
+
+// Short alias of a non-expired file with the same name uploaded from the same origin.
+async fn existing_name(
+    conn: &mut SqliteConnection,
+    origin: &str,
+    filename: &str,
+) -> Result<Option<String>, Error> {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_err(|_| UploadError::TimeCalculation)?
+        .as_secs();
+    Ok(with_timeout(
+        sqlx::query_as::<_, (String,)>(include_query!("get_file_by_name"))
+            .bind(origin)
+            .bind(filename)
+            .bind(now as i64)
+            .fetch_optional(conn),
+    )
+    .await?
+    .map_err(|_| UploadError::Database)?
+    .map(|(alias,)| alias))
+}
+
+async fn replace_long_alias(pool: &SqlitePool, id: &str, custom: String) -> Result<String, Error> {
+    if !alias::is_valid_custom(&custom) {
+        return Err(UploadError::InvalidAlias);
+    }
+    with_timeout(
+        sqlx::query(include_query!("update_file_long_alias"))
+            .bind(&custom)
+            .bind(id)
+            .execute(pool),
+    )
+    .await?
+    .map_err(|err| {
+        if is_unique_violation(&err) {
+            UploadError::AliasConflict
+        } else {
+            UploadError::Database
+        }
+    })?;
+    Ok(custom)
+}
+
+// The size of the file is updated before the check, in the same transaction: concurrent uploads
+// wait for each other instead of all fitting in what's left of the quotas.
+async fn accept_resize(
+    pool: &SqlitePool,
+    limiter: &ChainLimiter,
     req: &UploadRequest,
-    mut body: BodyStream,
-    mut file: File,
+    id: &str,
+    session: Option<&str>,
 ) -> Result<(), Error> {
+    let mut tx = with_timeout(pool.begin())
+        .await?
+        .map_err(|_| UploadError::Database)?;
+    with_timeout(
+        sqlx::query(include_query!("update_file_size"))
+            .bind(req.size as i64)
+            .bind(id)
+            .execute(&mut tx),
+    )
+    .await?
+    .map_err(|_| UploadError::Database)?;
+    if !with_timeout(
+        limiter.accept(
+            req,
+            &mut SqliteStore::new(&mut tx)
+                .excluding_file(Some(id))
+                .excluding_session(session),
+        ),
+    )
+    .await?
+    .ok_or(UploadError::QuotaAccess)?
+    {
+        let mut remaining = Remaining::default();
+        with_timeout(
+            limiter.remaining(
+                req,
+                &mut SqliteStore::new(&mut tx)
+                    .excluding_file(Some(id))
+                    .excluding_session(session),
+                &mut remaining,
+            ),
+        )
+        .await?
+        .ok_or(UploadError::QuotaAccess)?;
+        return Err(UploadError::QuotaExceeded(
+            req.size,
+            size_left(&remaining, req.size),
+        ));
+    }
+    with_timeout(tx.commit())
+        .await?
+        .map_err(|_| UploadError::Database)
+}
+
+// Reject the upload if the streamed body doesn't match the declared Content-Length,
+// or exceeds it when the size isn't exact.
+async fn write_file<S, E, W>(
+    req: &UploadRequest,
+    exact: bool,
+    mut body: S,
+    mut file: W,
+) -> Result<(u64, String), Error>
+where
+    S: Stream<Item = Result<Bytes, E>> + Unpin,
+    W: AsyncWrite + Unpin,
+{
     let mut written = 0;
+    let mut hasher = Sha256::new();
     while let Some(chunk) = body.next().await {
         let data = chunk.map_err(|_| UploadError::CopyFile)?;
 
@@ -183,54 +982,759 @@ async fn write_file(
             return Err(UploadError::SizeMismatch);
         }
         written += data.len() as u64;
+        hasher.update(&data);
 
         if file.write_all(&data).await.is_err() {
             return Err(UploadError::CopyFile);
         }
     }
     // Check difference just in case, but inferior check should be enough.
-    if written != req.size {
+    if exact && written != req.size {
         return Err(UploadError::SizeMismatch);
     }
+    // Encrypted files get their last chunk on shutdown.
+    if file.shutdown().await.is_err() {
+        return Err(UploadError::CopyFile);
+    }
 
-    Ok(())
+    Ok((written, format!("{:x}", hasher.finalize())))
+}
+
+// A file that cannot be compressed is kept as uploaded.
+async fn compress_file(dir: &Dir, origin: &str, id: &str) -> bool {
+    match dir.compress_file(origin, id).await {
+        Ok(_) => true,
+        Err(err) => {
+            tracing::warn!("Cannot compress file with id {}: {}", id, err);
+            false
+        }
+    }
 }
 
-async fn clean_failed_upload(dir: &Dir, id: &str, pool: &SqlitePool) {
-    if let Err(err) = dir.delete_file(id).await {
-        log::error!(
+async fn clean_failed_upload(dir: &Dir, origin: &str, id: &str, pool: &SqlitePool) {
+    let content = match blob::content(pool, id).await {
+        Ok(content) => content,
+        Err(err) => {
+            tracing::error!(
+                "Cannot fetch content of file with id {}, file will retain quota: {:?}",
+                id,
+                err
+            );
+            return;
+        }
+    };
+    // A deduplicated upload only releases its reference to the shared content.
+    let deleted = match content {
+        Some(content) if !content.is_last() => Ok(()),
+        Some(content) => dir.delete_file(&content.origin, &content.id).await,
+        None => dir.delete_file(origin, id).await,
+    };
+    if let Err(err) = deleted {
+        tracing::error!(
             "Cannot remove file with id {} from file system, file will retain quota: {}",
             id,
             err
         );
         return;
     }
-    if let Err(err) = sqlx::query(include_query!("delete_file"))
-        .bind(&id)
-        .execute(pool)
-        .await
-    {
-        log::error!("Cannot remove file with id {} from database: {:?}", id, err);
+    if let Err(err) = blob::delete_file(pool, id).await {
+        tracing::error!("Cannot remove file with id {} from database: {:?}", id, err);
     }
 }
 
+#[allow(clippy::too_many_arguments)]
 pub fn router(
     pool: SqlitePool,
     auth: Arc<Authenticator>,
     real_ip: RealIp,
     origin: Origin,
     limiters: ChainLimiter,
+    rate: RateLimiter,
     determiner: Arc<Determiner>,
+    groups: Arc<GroupDeterminers>,
     dir: Dir,
+    inflight: InflightBytes,
+    shedder: LoadShedder,
+    transform: Transform,
+    sniff: SniffBytes,
+    compress: CompressAtRest,
+    dedup: DeduplicateUploads,
+    max_downloads: MaxDownloads,
+    password_policy: PasswordPolicy,
+    unique_names: bool,
+    pending: PendingUploads,
 ) -> Router {
     Router::new()
         .route("/", post(handler))
-        .route("/upload", post(handler))
+        // Browsers visiting the upload endpoint are sent to the web UI.
+        .route("/upload", get(|| async { Redirect::to("/") }).post(handler))
+        .route("/upload/check", post(check::handler))
+        .route("/upload/sessions", post(session::create))
+        .route("/quota", get(quota::handler))
+        .route(
+            "/upload/sessions/:id",
+            patch(session::append).head(session::offset),
+        )
         .route_layer(Extension(pool))
         .route_layer(Extension(auth))
         .route_layer(Extension(real_ip))
-        .route_layer(Extension(origin))
-        .route_layer(Extension(Arc::new(limiters)))
-        .route_layer(Extension(determiner))
-        .route_layer(Extension(dir))
+        .route_layer(Extension(Arc::new(UploadState {
+            origin,
+            limiter: limiters,
+            rate,
+            determiner,
+            groups,
+            dir,
+            inflight,
+            shedder,
+            transform,
+            sniff,
+            compress,
+            dedup,
+            max_downloads,
+            password_policy,
+            unique_names,
+            sessions: ActiveSessions::default(),
+            pending,
+        })))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{convert::Infallible, net::SocketAddr, path::PathBuf, sync::Arc};
+
+    use futures::{stream, Stream};
+    use http_negotiator::{ContentTypeNegotiation, Negotiator};
+    use hyper::{body::Bytes, Server};
+    use tokio::{
+        io::{sink, AsyncReadExt, AsyncWriteExt},
+        net::TcpStream,
+    };
+    use uuid::Uuid;
+
+    use super::{
+        accept_quota, accept_resize, existing_name, write_file, CharacterClass, CompressAtRest,
+        DeduplicateUploads, Determiner, GroupDeterminers, InflightBytes, LoadShedder, MaxDownloads,
+        PasswordPolicy, PendingUploads, RealIp, SniffBytes, Transform, UploadRequest,
+    };
+    use crate::{
+        auth::{Authenticator, Features, Origin},
+        error::Error,
+        include_query,
+        limit::{Chain as ChainLimiter, Origin as OriginLimiter, Rate as RateLimiter},
+        query,
+        response::ResponseType,
+        storage::Dir,
+    };
+
+    fn request(size: u64) -> UploadRequest {
+        UploadRequest {
+            filename: None,
+            size,
+            origin: "127.0.0.1".to_owned(),
+            username: None,
+        }
+    }
+
+    fn body(chunks: &[&'static [u8]]) -> impl Stream<Item = Result<Bytes, Infallible>> + Unpin {
+        stream::iter(
+            chunks
+                .iter()
+                .map(|&chunk| Ok(Bytes::from_static(chunk)))
+                .collect::<Vec<_>>(),
+        )
+    }
+
+    #[tokio::test]
+    async fn size_mismatch() {
+        // Exact size.
+        assert!(
+            write_file(&request(6), true, body(&[b"abc", b"def"]), sink())
+                .await
+                .is_ok()
+        );
+
+        // Undershoot (truncated body).
+        assert!(matches!(
+            write_file(&request(8), true, body(&[b"abc", b"def"]), sink()).await,
+            Err(Error::SizeMismatch)
+        ));
+
+        // Overshoot (body longer than declared).
+        assert!(matches!(
+            write_file(&request(4), true, body(&[b"abc", b"def"]), sink()).await,
+            Err(Error::SizeMismatch)
+        ));
+
+        // Empty body with a non-zero declared size.
+        assert!(matches!(
+            write_file(&request(1), true, body(&[]), sink()).await,
+            Err(Error::SizeMismatch)
+        ));
+    }
+
+    #[tokio::test]
+    async fn size_upper_bound() {
+        // Shorter body than the declared size.
+        assert!(matches!(
+            write_file(&request(8), false, body(&[b"abc", b"def"]), sink()).await,
+            Ok((6, _))
+        ));
+
+        // Overshoot is still rejected.
+        assert!(matches!(
+            write_file(&request(4), false, body(&[b"abc", b"def"]), sink()).await,
+            Err(Error::SizeMismatch)
+        ));
+    }
+
+    #[tokio::test]
+    async fn hash() {
+        let (_, hash) = write_file(&request(6), true, body(&[b"abc", b"def"]), sink())
+            .await
+            .unwrap();
+        assert_eq!(
+            hash,
+            "bef57ec7f53a6d40beb640a780a639c83bc29ac8a9816f1fc6c5c6dcd93c4721"
+        );
+    }
+
+    #[tokio::test]
+    async fn unique_names() {
+        let pool = query::memory_pool().await;
+        for (id, origin, expiration, short) in [
+            ("current", "127.0.0.1", i64::MAX, "aaaaaa"),
+            ("expired", "127.0.0.2", 0, "bbbbbb"),
+        ] {
+            sqlx::query(include_query!("insert_file"))
+                .bind(id)
+                .bind("admin")
+                .bind(origin)
+                .bind(expiration)
+                .bind("report.pdf")
+                .bind(0)
+                .bind(short)
+                .bind(id)
+                .bind(false)
+                .bind(false)
+                .execute(&pool)
+                .await
+                .unwrap();
+        }
+
+        let mut conn = pool.acquire().await.unwrap();
+        assert_eq!(
+            existing_name(&mut conn, "127.0.0.1", "report.pdf")
+                .await
+                .unwrap()
+                .as_deref(),
+            Some("aaaaaa")
+        );
+        assert_eq!(
+            existing_name(&mut conn, "127.0.0.1", "other.pdf")
+                .await
+                .unwrap(),
+            None
+        );
+        // Other origin, and expired file.
+        assert_eq!(
+            existing_name(&mut conn, "127.0.0.3", "report.pdf")
+                .await
+                .unwrap(),
+            None
+        );
+        assert_eq!(
+            existing_name(&mut conn, "127.0.0.2", "report.pdf")
+                .await
+                .unwrap(),
+            None
+        );
+    }
+
+    #[tokio::test]
+    async fn quota_exceeded_until() {
+        let pool = query::memory_pool().await;
+        sqlx::query(include_query!("insert_file"))
+            .bind("id")
+            .bind("admin")
+            .bind("user")
+            .bind(4_000_000_000i64)
+            .bind("report.pdf")
+            .bind(100)
+            .bind("aaaaaa")
+            .bind("id")
+            .bind(false)
+            .bind(false)
+            .execute(&pool)
+            .await
+            .unwrap();
+        let request = |size: u64, username: Option<&str>| UploadRequest {
+            filename: None,
+            size,
+            origin: "user".to_owned(),
+            username: username.map(ToOwned::to_owned),
+        };
+
+        // A single file per uploader, its expiration is only disclosed to its owner.
+        let mut conn = pool.acquire().await.unwrap();
+        let limiter = ChainLimiter::new(vec![Box::new(OriginLimiter::new(1000, 1, Vec::new()))]);
+        assert!(matches!(
+            accept_quota(&mut conn, &limiter, &request(10, Some("user")), None).await,
+            Err(Error::QuotaExceededUntil(alias, 4_000_000_000)) if alias == "aaaaaa"
+        ));
+        assert!(matches!(
+            accept_quota(&mut conn, &limiter, &request(10, None), None).await,
+            Err(Error::QuotaExceeded(10, None))
+        ));
+
+        // Out of space, the size left is reported instead.
+        let limiter = ChainLimiter::new(vec![Box::new(OriginLimiter::new(150, 10, Vec::new()))]);
+        assert!(matches!(
+            accept_quota(&mut conn, &limiter, &request(100, Some("user")), None).await,
+            Err(Error::QuotaExceeded(100, Some(50)))
+        ));
+    }
+
+    #[tokio::test]
+    async fn resize_rechecks_quota() {
+        let pool = query::memory_pool().await;
+        for (id, size) in [("first", 50), ("second", 0)] {
+            sqlx::query(include_query!("insert_file"))
+                .bind(id)
+                .bind(id)
+                .bind("127.0.0.1")
+                .bind(4_000_000_000i64)
+                .bind(id)
+                .bind(size)
+                .bind(id)
+                .bind(id)
+                .bind(false)
+                .bind(false)
+                .execute(&pool)
+                .await
+                .unwrap();
+        }
+        let size = |id: &'static str| {
+            sqlx::query_as::<_, (i64,)>("SELECT size FROM files WHERE id = ?")
+                .bind(id)
+                .fetch_one(&pool)
+        };
+
+        // The file being resized isn't counted twice, the other one is.
+        let limiter = ChainLimiter::new(vec![Box::new(OriginLimiter::new(100, 2, Vec::new()))]);
+        assert!(accept_resize(&pool, &limiter, &request(50), "second", None)
+            .await
+            .is_ok());
+        assert_eq!(size("second").await.unwrap(), (50,));
+        assert!(matches!(
+            accept_resize(&pool, &limiter, &request(60), "second", None).await,
+            Err(Error::QuotaExceeded(60, Some(50)))
+        ));
+        assert_eq!(size("second").await.unwrap(), (50,));
+    }
+
+    // Server accepting files up to 1KB, stored in a temporary directory removed with it.
+    struct TestServer {
+        address: SocketAddr,
+        path: PathBuf,
+    }
+
+    impl TestServer {
+        async fn send(&self, request: impl AsRef<str>) -> String {
+            let mut stream = TcpStream::connect(self.address).await.unwrap();
+            stream.write_all(request.as_ref().as_bytes()).await.unwrap();
+            let mut response = String::new();
+            stream.read_to_string(&mut response).await.unwrap();
+            response
+        }
+
+        // Number of files stored.
+        async fn stored(&self) -> usize {
+            let mut entries = tokio::fs::read_dir(&self.path).await.unwrap();
+            let mut count = 0;
+            while entries.next_entry().await.unwrap().is_some() {
+                count += 1;
+            }
+            count
+        }
+    }
+
+    impl Drop for TestServer {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.path);
+        }
+    }
+
+    async fn serve(limiters: ChainLimiter) -> TestServer {
+        let path = std::env::temp_dir().join(Uuid::new_v4().as_hyphenated().to_string());
+        let dir = Dir::new(&path, false);
+        dir.create(true).await.unwrap();
+        let pool = query::memory_pool().await;
+        let router = super::router(
+            pool,
+            Arc::new(Authenticator::new(
+                Features::empty(),
+                Vec::new(),
+                None,
+                Vec::new(),
+            )),
+            RealIp::new(false),
+            Origin::IpAddress,
+            limiters,
+            RateLimiter::new(None),
+            Arc::new(Determiner::new(vec!["1KB:1h".parse().unwrap()]).unwrap()),
+            Arc::new(GroupDeterminers::default()),
+            dir,
+            InflightBytes::new(None),
+            LoadShedder::new(None),
+            Transform::new(None),
+            SniffBytes(512),
+            CompressAtRest(false),
+            DeduplicateUploads(false),
+            MaxDownloads(100),
+            PasswordPolicy::new(4, vec![CharacterClass::Digit]),
+            false,
+            PendingUploads::default(),
+        )
+        .route_layer(
+            Negotiator::<ContentTypeNegotiation, _>::new([ResponseType::Json, ResponseType::Text])
+                .unwrap(),
+        );
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(
+            Server::from_tcp(listener)
+                .unwrap()
+                .serve(router.into_make_service_with_connect_info::<SocketAddr>()),
+        );
+        TestServer { address, path }
+    }
+
+    // Raw upload of "hello", with additional headers.
+    fn raw(headers: &str) -> String {
+        format!(
+            "POST /upload HTTP/1.1\r\n\
+            Host: localhost\r\n\
+            {}Content-Length: 5\r\n\
+            Connection: close\r\n\r\n\
+            hello",
+            headers
+        )
+    }
+
+    // Form upload of the parts, in order.
+    fn multipart(parts: &[String]) -> String {
+        let mut body = String::new();
+        for part in parts {
+            body.push_str(&format!("--boundary\r\n{}\r\n", part));
+        }
+        body.push_str("--boundary--\r\n");
+        format!(
+            "POST /upload HTTP/1.1\r\n\
+            Host: localhost\r\n\
+            Content-Type: multipart/form-data; boundary=boundary\r\n\
+            Content-Length: {}\r\n\
+            Connection: close\r\n\r\n{}",
+            body.len(),
+            body
+        )
+    }
+
+    fn field(name: &str, value: &str) -> String {
+        format!(
+            "Content-Disposition: form-data; name=\"{}\"\r\n\r\n{}",
+            name, value
+        )
+    }
+
+    fn file(filename: &str, content: &str) -> String {
+        format!(
+            "Content-Disposition: form-data; name=\"file\"; filename=\"{}\"\r\n\r\n{}",
+            filename, content
+        )
+    }
+
+    fn json(response: &str) -> serde_json::Value {
+        let (_, body) = response.split_once("\r\n\r\n").unwrap();
+        serde_json::from_str(body).unwrap()
+    }
+
+    #[tokio::test]
+    async fn expect_continue_rejected() {
+        let server = serve(ChainLimiter::new(Vec::new())).await;
+
+        // The client waits for a "100 Continue" before sending its too large body.
+        let mut stream = TcpStream::connect(server.address).await.unwrap();
+        stream
+            .write_all(
+                b"POST /upload HTTP/1.1\r\n\
+                Host: localhost\r\n\
+                Content-Length: 1048576\r\n\
+                Expect: 100-continue\r\n\r\n",
+            )
+            .await
+            .unwrap();
+        let mut response = vec![0; 1024];
+        let read = stream.read(&mut response).await.unwrap();
+        let response = String::from_utf8_lossy(&response[..read]);
+        assert!(response.starts_with("HTTP/1.1 413"));
+        // The allowed maximum is part of the error.
+        assert!(response.contains(r#""limit":{"bytes":1000,"readable":"1000 B"}"#));
+    }
+
+    #[tokio::test]
+    async fn location() {
+        let server = serve(ChainLimiter::new(Vec::new())).await;
+
+        let response = server
+            .send(
+                "POST /upload HTTP/1.1\r\n\
+                Host: dropit.example\r\n\
+                X-Custom-Alias: report-q3\r\n\
+                Content-Length: 5\r\n\
+                Connection: close\r\n\r\n\
+                hello",
+            )
+            .await;
+        assert!(response.starts_with("HTTP/1.1 201"));
+        assert!(response.contains("\r\nlocation: http://dropit.example/report-q3\r\n"));
+        // The body keeps the links.
+        assert!(response.contains(r#""long":"http://dropit.example/report-q3""#));
+    }
+
+    #[tokio::test]
+    async fn concurrent_custom_alias() {
+        let server = serve(ChainLimiter::new(Vec::new())).await;
+
+        let request = raw("X-Custom-Alias: report-q3\r\n");
+        let (first, second) = tokio::join!(server.send(&request), server.send(&request));
+
+        // Exactly one of them claims the alias, nothing is stored for the other one.
+        let mut statuses = [&first[..12], &second[..12]];
+        statuses.sort_unstable();
+        assert_eq!(statuses, ["HTTP/1.1 201", "HTTP/1.1 409"]);
+        assert!(first.contains("alias_conflict") || second.contains("alias_conflict"));
+        assert_eq!(server.stored().await, 1);
+    }
+
+    #[tokio::test]
+    async fn check() {
+        let server = serve(ChainLimiter::new(Vec::new())).await;
+
+        let check = |body: &str| {
+            server.send(format!(
+                "POST /upload/check HTTP/1.1\r\n\
+                Host: localhost\r\n\
+                Content-Type: application/json\r\n\
+                Content-Length: {}\r\n\
+                Connection: close\r\n\r\n{}",
+                body.len(),
+                body
+            ))
+        };
+
+        let response = check(r#"{"size":5,"alias":"report-q3"}"#).await;
+        assert!(response.starts_with("HTTP/1.1 200"));
+        assert!(response.contains(r#""duration":{"seconds":3600"#));
+        // Nothing was stored.
+        assert_eq!(server.stored().await, 0);
+
+        let response = check(r#"{"size":2000}"#).await;
+        assert!(response.starts_with("HTTP/1.1 413"));
+        let response = check(r#"{"size":5,"alias":"no"}"#).await;
+        assert!(response.starts_with("HTTP/1.1 400"));
+        let response = check(r#"{"alias":"report-q3"}"#).await;
+        assert!(response.contains(r#""code":"invalid_check_request""#));
+
+        server.send(raw("X-Custom-Alias: report-q3\r\n")).await;
+        let response = check(r#"{"size":5,"alias":"report-q3"}"#).await;
+        assert!(response.starts_with("HTTP/1.1 409"));
+    }
+
+    #[tokio::test]
+    async fn multiple_files() {
+        // A single file per uploader.
+        let server = serve(ChainLimiter::new(vec![Box::new(OriginLimiter::new(
+            1000,
+            1,
+            Vec::new(),
+        ))]))
+        .await;
+
+        let response = server
+            .send(multipart(&[
+                file("first.txt", "first"),
+                file("second.txt", "second"),
+                file("third.txt", "third"),
+            ]))
+            .await;
+        assert!(response.starts_with("HTTP/1.1 207"));
+        let body = json(&response);
+        assert_eq!(body["success"], false);
+        let files = body["files"].as_array().unwrap();
+        assert_eq!(files.len(), 3);
+        assert_eq!(files[0]["success"], true);
+        assert_eq!(files[0]["name"], "first.txt");
+        assert_eq!(files[0]["size"]["bytes"], 5);
+        // Rejected before their content is read, the next files are still reported.
+        for file in &files[1..] {
+            assert_eq!(file["success"], false);
+            assert_eq!(file["code"], "quota_exceeded");
+        }
+    }
+
+    #[tokio::test]
+    async fn form_size() {
+        // Smaller than the whole form.
+        let server = serve(ChainLimiter::new(vec![Box::new(OriginLimiter::new(
+            100,
+            10,
+            Vec::new(),
+        ))]))
+        .await;
+
+        let response = server
+            .send(multipart(&[
+                field("comment", &"a".repeat(2000)),
+                file("file.txt", "hello"),
+            ]))
+            .await;
+        // The file alone is accounted, and determines the expiration.
+        assert!(response.starts_with("HTTP/1.1 201"));
+        let body = json(&response);
+        assert_eq!(body["size"]["bytes"], 5);
+        assert_eq!(body["expiration"]["allowed"]["seconds"], 3600);
+    }
+
+    #[tokio::test]
+    async fn session_reserved() {
+        // A single file per uploader.
+        let server = serve(ChainLimiter::new(vec![Box::new(OriginLimiter::new(
+            1000,
+            1,
+            Vec::new(),
+        ))]))
+        .await;
+        let create = |size: u64| {
+            server.send(format!(
+                "POST /upload/sessions HTTP/1.1\r\n\
+                Host: localhost\r\n\
+                Upload-Length: {}\r\n\
+                Content-Length: 0\r\n\
+                Connection: close\r\n\r\n",
+                size
+            ))
+        };
+
+        // No chunk could complete an empty session.
+        assert!(create(0).await.starts_with("HTTP/1.1 400"));
+
+        let response = create(5).await;
+        assert!(response.starts_with("HTTP/1.1 201"));
+        let location = response
+            .lines()
+            .find_map(|line| line.strip_prefix("location: "))
+            .unwrap()
+            .to_owned();
+
+        // The session holds the only file allowed.
+        let response = server.send(raw("")).await;
+        assert!(response.contains(r#""code":"quota_exceeded""#));
+
+        // But not against itself.
+        let response = server
+            .send(format!(
+                "PATCH {} HTTP/1.1\r\n\
+                Host: localhost\r\n\
+                Content-Range: bytes 0-4/5\r\n\
+                Content-Length: 5\r\n\
+                Connection: close\r\n\r\n\
+                hello",
+                location
+            ))
+            .await;
+        assert!(response.starts_with("HTTP/1.1 201"));
+    }
+
+    #[tokio::test]
+    async fn expire_in() {
+        let server = serve(ChainLimiter::new(Vec::new())).await;
+        let upload = |expire_in: &str| {
+            server.send(multipart(&[
+                field("expire_in", expire_in),
+                file("file.txt", "hello"),
+            ]))
+        };
+
+        // Shorter than the threshold, honored.
+        let response = upload("30m").await;
+        assert!(response.starts_with("HTTP/1.1 201"));
+        let body = json(&response);
+        assert_eq!(body["expiration"]["current"]["duration"]["seconds"], 1800);
+        assert_eq!(body["expiration"]["allowed"]["seconds"], 3600);
+        assert!(body.get("warning").is_none());
+        // Longer, clamped.
+        let response = upload("1d").await;
+        assert!(response.starts_with("HTTP/1.1 201"));
+        let body = json(&response);
+        assert_eq!(body["expiration"]["current"]["duration"]["seconds"], 3600);
+        assert!(body["warning"].is_string());
+        let response = upload("later").await;
+        assert!(response.starts_with("HTTP/1.1 400"));
+        assert_eq!(json(&response)["code"], "invalid_expire_in");
+    }
+
+    #[tokio::test]
+    async fn trailing_options() {
+        let server = serve(ChainLimiter::new(Vec::new())).await;
+
+        // Above the limit of 100 downloads, wherever it's sent.
+        let max_downloads = field("max_downloads", "1000");
+        for parts in [
+            [max_downloads.clone(), file("file.txt", "hello")],
+            [file("file.txt", "hello"), max_downloads],
+        ] {
+            let response = server.send(multipart(&parts)).await;
+            assert!(response.starts_with("HTTP/1.1 400"));
+            assert_eq!(json(&response)["code"], "invalid_max_downloads");
+        }
+        // Sent after the last file, applied to it.
+        let response = server
+            .send(multipart(&[
+                file("file.txt", "hello"),
+                field("expire_in", "30m"),
+            ]))
+            .await;
+        assert!(response.starts_with("HTTP/1.1 201"));
+        assert_eq!(
+            json(&response)["expiration"]["current"]["duration"]["seconds"],
+            1800
+        );
+    }
+
+    #[tokio::test]
+    async fn weak_password() {
+        let server = serve(ChainLimiter::new(Vec::new())).await;
+        let header = |password: &str| raw(&format!("X-Download-Password: {}\r\n", password));
+        let form =
+            |password: &str| multipart(&[field("password", password), file("file.txt", "hello")]);
+
+        // At least 4 characters, including a digit.
+        let response = server.send(header("abc")).await;
+        assert!(response.starts_with("HTTP/1.1 400"));
+        assert!(response.contains("weak_download_password"));
+        assert!(response.contains("at least 4 characters, a digit"));
+        assert!(server
+            .send(header("abcd"))
+            .await
+            .starts_with("HTTP/1.1 400"));
+        assert!(server
+            .send(header("abc1"))
+            .await
+            .starts_with("HTTP/1.1 201"));
+        let response = server.send(form("abcd")).await;
+        assert!(response.starts_with("HTTP/1.1 400"));
+        assert!(response.contains("weak_download_password"));
+        assert!(server.send(form("abc1")).await.starts_with("HTTP/1.1 201"));
+    }
 }
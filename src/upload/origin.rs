@@ -1,47 +1,92 @@
-use std::net::IpAddr;
+use std::{
+    net::{IpAddr, SocketAddr},
+    sync::Arc,
+};
 
 use async_trait::async_trait;
-use axum::extract::{FromRequest, RequestParts};
-use hyper::Body;
+use axum::extract::{ConnectInfo, FromRequest, RequestParts};
+use hyper::{http::Extensions, Body, HeaderMap};
+use ipnet::IpNet;
 
-use crate::error::Error;
+use crate::{cidr::CidrFilter, error::Error};
 
-#[derive(Copy, Clone, Debug)]
-pub struct RealIp(bool);
+// Behind a reverse proxy, the X-Forwarded-For header is only honored for requests sent by one of
+// the trusted proxies, or by any peer if none is listed. Other peers are identified by their own
+// address, whatever they forward.
+#[derive(Clone, Debug)]
+pub struct RealIp {
+    behind_proxy: bool,
+    trusted_proxies: Arc<CidrFilter>,
+}
 
 impl RealIp {
     pub fn new(behind_proxy: bool) -> Self {
-        Self(behind_proxy)
+        Self {
+            behind_proxy,
+            trusted_proxies: Arc::new(CidrFilter::default()),
+        }
     }
 
-    pub fn resolve(&self, real: IpAddr, forwarded: Option<IpAddr>) -> Option<IpAddr> {
-        if self.0 {
-            forwarded
-        } else {
-            Some(real)
+    pub fn with_trusted_proxies(self, trusted_proxies: Vec<IpNet>) -> Self {
+        Self {
+            trusted_proxies: Arc::new(CidrFilter::new(trusted_proxies, Vec::new())),
+            ..self
         }
     }
+
+    // Whether the forwarded headers of the peer are honored.
+    pub fn trusts(&self, peer: IpAddr) -> bool {
+        self.behind_proxy && self.trusted_proxies.allows(peer)
+    }
+
+    // Each proxy appends the address of its own peer to the X-Forwarded-For chain, the client is
+    // the right-most hop which isn't a trusted proxy, hops on its left may have been sent by
+    // anyone. Without a trust list, only the peer is trusted.
+    pub fn resolve(&self, peer: IpAddr, forwarded: &str) -> Option<IpAddr> {
+        if !self.trusts(peer) {
+            return Some(peer);
+        }
+        let mut client = None;
+        for hop in forwarded.rsplit(',').map(str::trim) {
+            let hop = hop.parse::<IpAddr>().ok()?;
+            client = Some(hop);
+            if self.trusted_proxies.is_empty() || !self.trusted_proxies.allows(hop) {
+                break;
+            }
+        }
+        client
+    }
+
+    // Address of the client of a request, as seen by the quotas, the address filters and the logs.
+    pub fn client(&self, headers: &HeaderMap, extensions: &Extensions) -> Option<IpAddr> {
+        // Repeated headers are a single comma separated list.
+        let forwarded = headers
+            .get_all("X-Forwarded-For")
+            .iter()
+            .map(|h| h.to_str().ok())
+            .collect::<Option<Vec<_>>>()?
+            .join(",");
+        extensions
+            .get::<ConnectInfo<SocketAddr>>()
+            .and_then(|ConnectInfo(addr)| self.resolve(addr.ip(), &forwarded))
+    }
 }
 
-pub struct ForwardedForHeader(pub IpAddr);
+// Address of the client resolved by the RealIp of the routes, if it could be resolved.
+pub struct ClientAddress(pub Option<IpAddr>);
 
 #[async_trait]
-impl FromRequest<Body> for ForwardedForHeader {
+impl FromRequest<Body> for ClientAddress {
     type Rejection = Error;
 
     async fn from_request(req: &mut RequestParts<Body>) -> Result<Self, Self::Rejection> {
-        Ok(Self(
-            req.headers()
-                .get("X-Forwarded-For")
-                .ok_or(Error::Origin)?
-                .to_str()
-                .map_err(|_| Error::Origin)?
-                .parse::<IpAddr>()
-                .map_err(|_| Error::Origin)?,
-        ))
+        let real_ip = req.extensions().get::<RealIp>().ok_or(Error::Generic)?;
+        Ok(Self(real_ip.client(req.headers(), req.extensions())))
     }
 }
 
+// Public URL of the instance, the X-Forwarded-Proto and X-Forwarded-Host headers are only honored
+// for the peers whose X-Forwarded-For header is.
 pub struct DomainUri(pub String);
 
 #[async_trait]
@@ -49,18 +94,173 @@ impl FromRequest<Body> for DomainUri {
     type Rejection = Error;
 
     async fn from_request(req: &mut RequestParts<Body>) -> Result<Self, Self::Rejection> {
-        let protocol = if let Some(header) = req.headers().get("X-Forwarded-Proto") {
-            header.to_str().map_err(|_| Error::Target)?
-        } else {
-            "http"
+        let headers = req.headers();
+        let forwarded = |name| {
+            headers
+                .get(name)
+                .filter(|_| trusts_peer(req.extensions()))
+                .map(|header| header.to_str().map_err(|_| Error::Target))
+                .transpose()
         };
-        let host = if let Some(header) = req.headers().get("X-Forwarded-Host") {
-            header.to_str().map_err(|_| Error::Target)?
-        } else if let Some(header) = req.headers().get("Host") {
-            header.to_str().map_err(|_| Error::Target)?
-        } else {
-            return Err(Error::Target);
+        let protocol = forwarded("X-Forwarded-Proto")?.unwrap_or("http");
+        let host = match forwarded("X-Forwarded-Host")? {
+            Some(host) => host,
+            None => headers
+                .get("Host")
+                .ok_or(Error::Target)?
+                .to_str()
+                .map_err(|_| Error::Target)?,
         };
         Ok(DomainUri(format!("{}://{}", protocol, host)))
     }
 }
+
+// Whether the forwarded headers of the request's peer are honored, never without a RealIp.
+pub fn trusts_peer(extensions: &Extensions) -> bool {
+    match (
+        extensions.get::<RealIp>(),
+        extensions.get::<ConnectInfo<SocketAddr>>(),
+    ) {
+        (Some(real_ip), Some(ConnectInfo(addr))) => real_ip.trusts(addr.ip()),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{IpAddr, SocketAddr};
+
+    use axum::extract::{ConnectInfo, FromRequest, RequestParts};
+    use hyper::{http::Extensions, Body, HeaderMap, Request};
+
+    use super::{DomainUri, RealIp};
+
+    fn ip(ip: &str) -> IpAddr {
+        ip.parse().unwrap()
+    }
+
+    #[test]
+    fn trusted_proxies() {
+        let real_ip = RealIp::new(true).with_trusted_proxies(vec!["10.0.0.0/8".parse().unwrap()]);
+        assert_eq!(
+            real_ip.resolve(ip("10.0.0.1"), "203.0.113.7"),
+            Some(ip("203.0.113.7"))
+        );
+        // Requests sent through the proxy always have the header.
+        assert_eq!(real_ip.resolve(ip("10.0.0.1"), ""), None);
+        // Mapped addresses of dual-stack sockets.
+        assert_eq!(
+            real_ip.resolve(ip("::ffff:10.0.0.1"), "203.0.113.7"),
+            Some(ip("203.0.113.7"))
+        );
+
+        // Without a trust list, any peer is trusted.
+        let real_ip = RealIp::new(true);
+        assert_eq!(
+            real_ip.resolve(ip("198.51.100.1"), "203.0.113.7"),
+            Some(ip("203.0.113.7"))
+        );
+        // Not behind a proxy, the header is ignored.
+        let real_ip = RealIp::new(false);
+        assert_eq!(
+            real_ip.resolve(ip("198.51.100.1"), "203.0.113.7"),
+            Some(ip("198.51.100.1"))
+        );
+    }
+
+    #[test]
+    fn forwarded_chain() {
+        let real_ip = RealIp::new(true).with_trusted_proxies(vec!["10.0.0.0/8".parse().unwrap()]);
+        // The client prepended a fake hop, the proxies appended the real ones.
+        assert_eq!(
+            real_ip.resolve(ip("10.0.0.1"), "192.0.2.1, 203.0.113.7, 10.0.0.2"),
+            Some(ip("203.0.113.7"))
+        );
+        assert_eq!(
+            real_ip.resolve(ip("10.0.0.1"), "not an address, 203.0.113.7"),
+            Some(ip("203.0.113.7"))
+        );
+        // Only proxies in the chain, the client is the first of them.
+        assert_eq!(
+            real_ip.resolve(ip("10.0.0.1"), "10.0.0.3, 10.0.0.2"),
+            Some(ip("10.0.0.3"))
+        );
+        assert_eq!(
+            real_ip.resolve(ip("10.0.0.1"), "203.0.113.7, garbage"),
+            None
+        );
+
+        // Without a trust list, the peer only vouches for the last hop.
+        let real_ip = RealIp::new(true);
+        assert_eq!(
+            real_ip.resolve(ip("198.51.100.1"), "192.0.2.1, 203.0.113.7"),
+            Some(ip("203.0.113.7"))
+        );
+    }
+
+    #[test]
+    fn spoofing() {
+        let real_ip = RealIp::new(true).with_trusted_proxies(vec!["10.0.0.0/8".parse().unwrap()]);
+        let mut headers = HeaderMap::new();
+        headers.insert("X-Forwarded-For", "203.0.113.7".parse().unwrap());
+        let mut extensions = Extensions::new();
+        extensions.insert(ConnectInfo(
+            "198.51.100.1:4000".parse::<SocketAddr>().unwrap(),
+        ));
+        // An untrusted client can neither pick its address, nor hide it.
+        assert_eq!(
+            real_ip.client(&headers, &extensions),
+            Some(ip("198.51.100.1"))
+        );
+        assert_eq!(
+            real_ip.client(&HeaderMap::new(), &extensions),
+            Some(ip("198.51.100.1"))
+        );
+
+        let mut extensions = Extensions::new();
+        extensions.insert(ConnectInfo("10.1.2.3:4000".parse::<SocketAddr>().unwrap()));
+        assert_eq!(
+            real_ip.client(&headers, &extensions),
+            Some(ip("203.0.113.7"))
+        );
+        // Repeated headers are a single chain.
+        headers.append("X-Forwarded-For", "10.0.0.2".parse().unwrap());
+        assert_eq!(
+            real_ip.client(&headers, &extensions),
+            Some(ip("203.0.113.7"))
+        );
+    }
+
+    #[tokio::test]
+    async fn forwarded_host() {
+        let domain_uri = |peer: &str| {
+            let mut request = Request::builder()
+                .header("Host", "dropit.internal")
+                .header("X-Forwarded-Host", "drop.example.com")
+                .header("X-Forwarded-Proto", "https")
+                .body(Body::empty())
+                .unwrap();
+            request.extensions_mut().insert(
+                RealIp::new(true).with_trusted_proxies(vec!["10.0.0.0/8".parse().unwrap()]),
+            );
+            request
+                .extensions_mut()
+                .insert(ConnectInfo(peer.parse::<SocketAddr>().unwrap()));
+            async move {
+                let DomainUri(uri) = DomainUri::from_request(&mut RequestParts::new(request))
+                    .await
+                    .unwrap();
+                uri
+            }
+        };
+        assert_eq!(
+            domain_uri("10.0.0.1:4000").await,
+            "https://drop.example.com"
+        );
+        // Links of the instance cannot be pointed to another host by a client.
+        assert_eq!(
+            domain_uri("198.51.100.1:4000").await,
+            "http://dropit.internal"
+        );
+    }
+}
@@ -0,0 +1,98 @@
+use clap::ValueEnum;
+use itertools::Itertools;
+
+use crate::error::{upload as UploadError, Error};
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum)]
+pub enum CharacterClass {
+    Lowercase,
+    Uppercase,
+    Digit,
+    Symbol,
+}
+
+impl CharacterClass {
+    fn matches(self, c: char) -> bool {
+        match self {
+            Self::Lowercase => c.is_lowercase(),
+            Self::Uppercase => c.is_uppercase(),
+            Self::Digit => c.is_numeric(),
+            Self::Symbol => !c.is_alphanumeric() && !c.is_whitespace(),
+        }
+    }
+
+    fn describe(self) -> &'static str {
+        match self {
+            Self::Lowercase => "a lowercase letter",
+            Self::Uppercase => "an uppercase letter",
+            Self::Digit => "a digit",
+            Self::Symbol => "a symbol",
+        }
+    }
+}
+
+// Minimum strength of the download passwords chosen at upload, any password is accepted by default.
+#[derive(Clone, Debug, Default)]
+pub struct PasswordPolicy {
+    min_length: usize,
+    classes: Vec<CharacterClass>,
+}
+
+impl PasswordPolicy {
+    pub fn new(min_length: usize, classes: Vec<CharacterClass>) -> Self {
+        Self {
+            min_length,
+            classes: classes.into_iter().unique().collect(),
+        }
+    }
+
+    // Weak passwords are rejected with all the requirements of the policy.
+    pub(super) fn check(&self, password: &str) -> Result<(), Error> {
+        let long_enough = password.chars().count() >= self.min_length;
+        let complex = self
+            .classes
+            .iter()
+            .all(|class| password.chars().any(|c| class.matches(c)));
+        if long_enough && complex {
+            return Ok(());
+        }
+        let requirements = (self.min_length > 0)
+            .then(|| format!("at least {} characters", self.min_length))
+            .into_iter()
+            .chain(self.classes.iter().map(|class| class.describe().to_owned()))
+            .join(", ");
+        Err(UploadError::WeakDownloadPassword(requirements))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{CharacterClass, PasswordPolicy};
+
+    #[test]
+    fn check() {
+        let policy = PasswordPolicy::default();
+        assert!(policy.check("a").is_ok());
+
+        let policy = PasswordPolicy::new(
+            8,
+            vec![
+                CharacterClass::Lowercase,
+                CharacterClass::Digit,
+                CharacterClass::Symbol,
+                CharacterClass::Digit,
+            ],
+        );
+        assert!(policy.check("corr3ct-horse").is_ok());
+        // Characters are counted, not bytes.
+        assert!(policy.check("é1-é1-é1").is_ok());
+        assert!(policy.check("c0r-e").is_err());
+        assert!(policy.check("correct-horse").is_err());
+        assert!(policy.check("CORR3CT-HORSE").is_err());
+        assert_eq!(
+            policy.check("password").unwrap_err().to_string(),
+            "weak download password, it must contain at least 8 characters, a lowercase letter, \
+            a digit, a symbol"
+        );
+    }
+}
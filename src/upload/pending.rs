@@ -0,0 +1,61 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+use sqlx::SqlitePool;
+
+use crate::{storage::Dir, upload::clean_failed_upload};
+
+// Uploads being written to the disk, those still running when the server stops are removed.
+#[derive(Clone, Default)]
+pub struct PendingUploads(Arc<Mutex<HashMap<String, String>>>);
+
+impl PendingUploads {
+    pub(super) fn track(&self, origin: &str, id: &str) -> PendingGuard {
+        self.0
+            .lock()
+            .unwrap()
+            .insert(id.to_owned(), origin.to_owned());
+        PendingGuard {
+            pending: self.clone(),
+            id: id.to_owned(),
+        }
+    }
+
+    pub async fn clean(&self, dir: &Dir, pool: &SqlitePool) {
+        let interrupted = self.0.lock().unwrap().drain().collect::<Vec<_>>();
+        for (id, origin) in interrupted {
+            tracing::warn!("Removing upload with id {} interrupted by shutdown", id);
+            clean_failed_upload(dir, &origin, &id, pool).await;
+        }
+    }
+}
+
+pub(super) struct PendingGuard {
+    pending: PendingUploads,
+    id: String,
+}
+
+impl Drop for PendingGuard {
+    fn drop(&mut self) {
+        self.pending.0.lock().unwrap().remove(&self.id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::PendingUploads;
+
+    #[test]
+    fn untrack_completed() {
+        let pending = PendingUploads::default();
+        let completed = pending.track("127.0.0.1", "completed");
+        let _interrupted = pending.track("127.0.0.1", "interrupted");
+        drop(completed);
+        assert_eq!(
+            pending.0.lock().unwrap().keys().collect::<Vec<_>>(),
+            ["interrupted"]
+        );
+    }
+}
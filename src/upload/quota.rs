@@ -0,0 +1,117 @@
+use std::sync::Arc;
+
+use axum::{
+    headers::{authorization::Basic, Authorization, Cookie},
+    Extension, TypedHeader,
+};
+use http_negotiator::{ContentTypeNegotiation, Negotiation};
+use serde::Serialize;
+use sqlx::SqlitePool;
+
+use crate::{
+    access::AccessUser,
+    auth::{Authenticator, ClientCertificate},
+    error::{upload as UploadError, Error},
+    limit::{Limiter, Remaining, SqliteStore},
+    query::with_timeout,
+    response::{ApiHeader, ApiResponse, ResponseType, SingleLine},
+    upload::{origin::ClientAddress, Size, UploadRequest, UploadState},
+};
+
+#[derive(Serialize)]
+pub struct Quota {
+    origin: OriginQuota,
+    global: GlobalQuota,
+}
+
+#[derive(Serialize)]
+struct OriginQuota {
+    size: Option<Size>,
+    files: Option<usize>,
+}
+
+#[derive(Serialize)]
+struct GlobalQuota {
+    size: Option<Size>,
+}
+
+impl From<Remaining> for Quota {
+    fn from(remaining: Remaining) -> Self {
+        Self {
+            origin: OriginQuota {
+                size: remaining.origin_size.map(Size::from),
+                files: remaining.origin_files,
+            },
+            global: GlobalQuota {
+                size: remaining.global_size.map(Size::from),
+            },
+        }
+    }
+}
+
+impl ApiHeader for Quota {}
+
+impl SingleLine for Quota {
+    fn single_lined(&self) -> String {
+        let size = |size: &Option<Size>| {
+            size.as_ref()
+                .map_or_else(|| "unlimited".to_owned(), |size| size.readable.clone())
+        };
+        format!(
+            "{}, {} files ({} globally)",
+            size(&self.origin.size),
+            self.origin
+                .files
+                .map_or_else(|| "unlimited".to_owned(), |files| files.to_string()),
+            size(&self.global.size)
+        )
+    }
+}
+
+// What's left of the quotas for the caller, identified like an uploader.
+#[allow(clippy::too_many_arguments)]
+pub async fn handler(
+    Extension(pool): Extension<SqlitePool>,
+    response_type: Negotiation<ContentTypeNegotiation, ResponseType>,
+    authenticator: Extension<Arc<Authenticator>>,
+    auth_header: Option<TypedHeader<Authorization<Basic>>>,
+    cookie: Option<TypedHeader<Cookie>>,
+    certificate: Option<Extension<ClientCertificate>>,
+    user: AccessUser,
+    ClientAddress(client): ClientAddress,
+    Extension(state): Extension<Arc<UploadState>>,
+) -> Result<ApiResponse<Quota>, ApiResponse<Error>> {
+    let certificate = certificate.and_then(|Extension(ClientCertificate(cn))| cn);
+    let remaining = async {
+        let username = super::authenticate(
+            &authenticator,
+            auth_header,
+            cookie,
+            certificate.clone(),
+            &user,
+        )
+        .await?;
+        let req = UploadRequest {
+            filename: None,
+            size: 0,
+            origin: state.origin(client, username.clone(), certificate)?,
+            username,
+        };
+        let mut conn = with_timeout(pool.acquire())
+            .await?
+            .map_err(|_| UploadError::Database)?;
+        let mut remaining = Remaining::default();
+        with_timeout(state.limiter.remaining(
+            &req,
+            &mut SqliteStore::new(&mut conn),
+            &mut remaining,
+        ))
+        .await?
+        .ok_or(UploadError::QuotaAccess)?;
+        Ok(remaining)
+    };
+    remaining
+        .await
+        .map(|remaining| ApiResponse(*response_type, Quota::from(remaining)))
+        .map_err(|err: Error| ApiResponse(*response_type, err))
+}
@@ -0,0 +1,494 @@
+use std::{
+    collections::HashSet,
+    net::IpAddr,
+    sync::{Arc, Mutex},
+};
+
+use axum::{
+    extract::{BodyStream, Path},
+    headers::{authorization::Basic, Authorization, Cookie},
+    response::{IntoResponse, Response},
+    Extension, TypedHeader,
+};
+use http_negotiator::{ContentTypeNegotiation, Negotiation};
+use hyper::{
+    header::{self, HeaderName},
+    http::HeaderValue,
+    HeaderMap, StatusCode,
+};
+use serde::Serialize;
+use sqlx::{FromRow, SqlitePool};
+use tokio_util::io::ReaderStream;
+use uuid::Uuid;
+
+use crate::{
+    access::AccessUser,
+    auth::{Authenticator, ClientCertificate},
+    error::{upload as UploadError, Error},
+    include_query,
+    query::with_timeout,
+    response::{ApiHeader, ApiResponse, ResponseType, SingleLine},
+    upload::{
+        accept_quota, authenticate, filename::Filename, origin::ClientAddress, process_upload,
+        write_file, DomainUri, UploadBody, UploadRequest, UploadState,
+    },
+};
+
+const UPLOAD_OFFSET: &str = "upload-offset";
+const UPLOAD_LENGTH: &str = "upload-length";
+
+// Sessions receiving a chunk, a session only accepts one chunk at a time.
+#[derive(Clone, Default)]
+pub struct ActiveSessions(Arc<Mutex<HashSet<String>>>);
+
+impl ActiveSessions {
+    fn acquire(&self, id: &str) -> Option<SessionGuard> {
+        self.0
+            .lock()
+            .unwrap()
+            .insert(id.to_owned())
+            .then(|| SessionGuard {
+                sessions: self.clone(),
+                id: id.to_owned(),
+            })
+    }
+}
+
+struct SessionGuard {
+    sessions: ActiveSessions,
+    id: String,
+}
+
+impl Drop for SessionGuard {
+    fn drop(&mut self) {
+        self.sessions.0.lock().unwrap().remove(&self.id);
+    }
+}
+
+#[derive(Serialize)]
+pub struct UploadSession {
+    id: String,
+    size: u64,
+}
+
+impl ApiHeader for UploadSession {
+    fn status_code(&self) -> StatusCode {
+        StatusCode::CREATED
+    }
+
+    fn additional_headers(&self) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        if let Ok(location) = HeaderValue::try_from(format!("/upload/sessions/{}", self.id)) {
+            headers.insert(header::LOCATION, location);
+        }
+        headers
+    }
+}
+
+impl SingleLine for UploadSession {
+    fn single_lined(&self) -> String {
+        self.id.clone()
+    }
+}
+
+#[derive(FromRow)]
+struct SessionInfo {
+    origin: String,
+    username: Option<String>,
+    name: Option<String>,
+    size: i64,
+    received: i64,
+}
+
+enum Appended {
+    Partial(u64),
+    Complete(Response),
+}
+
+// The whole upload has to fit in the quotas and thresholds before any chunk is sent.
+#[allow(clippy::too_many_arguments)]
+pub async fn create(
+    Extension(pool): Extension<SqlitePool>,
+    response_type: Negotiation<ContentTypeNegotiation, ResponseType>,
+    authenticator: Extension<Arc<Authenticator>>,
+    auth_header: Option<TypedHeader<Authorization<Basic>>>,
+    cookie: Option<TypedHeader<Cookie>>,
+    certificate: Option<Extension<ClientCertificate>>,
+    user: AccessUser,
+    ClientAddress(client): ClientAddress,
+    Extension(state): Extension<Arc<UploadState>>,
+    Filename(filename): Filename,
+    headers: HeaderMap,
+) -> Result<ApiResponse<UploadSession>, ApiResponse<Error>> {
+    let (origin, username) = session_origin(
+        &state,
+        &authenticator,
+        auth_header,
+        cookie,
+        certificate,
+        &user,
+        client,
+    )
+    .await
+    .map_err(|err| ApiResponse(*response_type, err))?;
+    state
+        .rate
+        .acquire(&origin, username.as_deref())
+        .map_err(|retry| ApiResponse(*response_type, UploadError::RateLimited(retry)))?;
+    let size = headers
+        .get(UPLOAD_LENGTH)
+        .and_then(|h| h.to_str().ok())
+        .and_then(|h| h.parse::<u64>().ok())
+        // No chunk could complete an empty session, empty files are uploaded directly.
+        .filter(|&size| size > 0)
+        .ok_or(ApiResponse(*response_type, UploadError::InvalidUploadRange))?;
+    let request = UploadRequest {
+        filename,
+        size,
+        origin,
+        username,
+    };
+    Ok(ApiResponse(
+        *response_type,
+        create_session(&pool, &state, request)
+            .await
+            .map_err(|err| ApiResponse(*response_type, err))?,
+    ))
+}
+
+// Uploader's identity, sessions are only reachable by the uploader who created them.
+async fn session_origin(
+    state: &UploadState,
+    authenticator: &Authenticator,
+    auth_header: Option<TypedHeader<Authorization<Basic>>>,
+    cookie: Option<TypedHeader<Cookie>>,
+    certificate: Option<Extension<ClientCertificate>>,
+    user: &AccessUser,
+    client: Option<IpAddr>,
+) -> Result<(String, Option<String>), Error> {
+    let certificate = certificate.and_then(|Extension(ClientCertificate(cn))| cn);
+    let username = authenticate(
+        authenticator,
+        auth_header,
+        cookie,
+        certificate.clone(),
+        user,
+    )
+    .await?;
+    let origin = state.origin(client, username.clone(), certificate)?;
+    Ok((origin, username))
+}
+
+async fn create_session(
+    pool: &SqlitePool,
+    state: &UploadState,
+    request: UploadRequest,
+) -> Result<UploadSession, Error> {
+    if state.inflight.is_saturated() || state.shedder.is_overloaded() {
+        return Err(UploadError::ServerBusy);
+    }
+    let determiner = state.determiner(request.username.as_deref());
+    if determiner.determine(request.size).is_none() {
+        return Err(UploadError::TooLarge(request.size, determiner.max_size()));
+    }
+    let mut conn = with_timeout(pool.acquire())
+        .await?
+        .map_err(|_| UploadError::Database)?;
+    accept_quota(&mut conn, &state.limiter, &request, None).await?;
+
+    let id = Uuid::new_v4().as_hyphenated().to_string();
+    state
+        .dir
+        .create_partial(&id)
+        .await
+        .map_err(|_| UploadError::CreateFile)?;
+    with_timeout(
+        sqlx::query(include_query!("insert_upload_session"))
+            .bind(&id)
+            .bind(&request.origin)
+            .bind(&request.username)
+            .bind(&request.filename)
+            .bind(request.size as i64)
+            .execute(&mut conn),
+    )
+    .await?
+    .map_err(|_| UploadError::Database)?;
+    state.inflight.reserve(&id, request.size);
+    Ok(UploadSession {
+        id,
+        size: request.size,
+    })
+}
+
+// Append the chunk of the Content-Range header, the upload is stored once all its chunks are received.
+#[allow(clippy::too_many_arguments)]
+pub async fn append(
+    Extension(pool): Extension<SqlitePool>,
+    response_type: Negotiation<ContentTypeNegotiation, ResponseType>,
+    authenticator: Extension<Arc<Authenticator>>,
+    auth_header: Option<TypedHeader<Authorization<Basic>>>,
+    cookie: Option<TypedHeader<Cookie>>,
+    certificate: Option<Extension<ClientCertificate>>,
+    user: AccessUser,
+    ClientAddress(client): ClientAddress,
+    Extension(state): Extension<Arc<UploadState>>,
+    Path(id): Path<String>,
+    DomainUri(domain_uri): DomainUri,
+    headers: HeaderMap,
+    body: BodyStream,
+) -> Result<Response, ApiResponse<Error>> {
+    let (origin, _) = session_origin(
+        &state,
+        &authenticator,
+        auth_header,
+        cookie,
+        certificate,
+        &user,
+        client,
+    )
+    .await
+    .map_err(|err| ApiResponse(*response_type, err))?;
+    let range = headers
+        .get(header::CONTENT_RANGE)
+        .and_then(|h| h.to_str().ok())
+        .and_then(parse_content_range)
+        .ok_or(ApiResponse(*response_type, UploadError::InvalidUploadRange))?;
+    match append_chunk(
+        pool,
+        &state,
+        &id,
+        &origin,
+        range,
+        domain_uri,
+        *response_type,
+        body,
+    )
+    .await
+    {
+        Ok(Appended::Partial(received)) => Ok(offset_response(received, None)),
+        Ok(Appended::Complete(response)) => Ok(response),
+        Err(err) => Err(ApiResponse(*response_type, err)),
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn append_chunk(
+    pool: SqlitePool,
+    state: &UploadState,
+    id: &str,
+    origin: &str,
+    (start, end, total): (u64, u64, u64),
+    domain_uri: String,
+    response_type: ResponseType,
+    body: BodyStream,
+) -> Result<Appended, Error> {
+    // The size of the session was reserved in the inflight bytes when it was created.
+    if state.shedder.is_overloaded() {
+        return Err(UploadError::ServerBusy);
+    }
+    let _guard = state
+        .sessions
+        .acquire(id)
+        .ok_or(UploadError::UploadSessionBusy)?;
+    let session = fetch_session(&pool, id, origin).await?;
+    state.inflight.reserve(id, session.size as u64);
+    if total != session.size as u64 {
+        return Err(UploadError::InvalidUploadRange);
+    }
+    // Chunks must follow each other, overlapping or out of order ones are rejected.
+    if start != session.received as u64 {
+        return Err(UploadError::RangeMismatch(session.received as u64));
+    }
+
+    // A previously failed chunk may have been partially written, it is overwritten.
+    let mut file = state
+        .dir
+        .write_partial(id, start)
+        .await
+        .map_err(|_| UploadError::CopyFile)?;
+    let chunk = UploadRequest {
+        filename: None,
+        size: end - start + 1,
+        origin: session.origin.clone(),
+        username: None,
+    };
+    write_file(&chunk, true, body, &mut file).await?;
+    drop(file);
+
+    let received = end + 1;
+    with_timeout(
+        sqlx::query(include_query!("update_upload_session_received"))
+            .bind(received as i64)
+            .bind(id)
+            .execute(&pool),
+    )
+    .await?
+    .map_err(|_| UploadError::Database)?;
+    if received < total {
+        return Ok(Appended::Partial(received));
+    }
+
+    // Complete uploads go through the same checks and processing as the other ones, the session
+    // no longer counting in the quotas.
+    let file = state
+        .dir
+        .open_partial(id)
+        .await
+        .map_err(|_| UploadError::CopyFile)?;
+    let info = process_upload(
+        pool.clone(),
+        state,
+        session.origin,
+        session.username,
+        domain_uri,
+        total,
+        session.name,
+        None,
+        false,
+        false,
+        false,
+        None,
+        None,
+        Some(id),
+        UploadBody::Raw(ReaderStream::new(file)),
+    )
+    .await;
+    match info {
+        Ok(info) => {
+            end_session(&pool, state, id).await;
+            if let Err(err) = state.dir.delete_partial(id).await {
+                tracing::error!("Cannot remove upload session {} file: {}", id, err);
+            }
+            Ok(Appended::Complete(
+                ApiResponse(response_type, info).into_response(),
+            ))
+        }
+        // The session is kept, rewound to the start of its last chunk so it can be sent again.
+        Err(err) => {
+            if let Err(err) = sqlx::query(include_query!("update_upload_session_received"))
+                .bind(start as i64)
+                .bind(id)
+                .execute(&pool)
+                .await
+            {
+                tracing::error!("Cannot rewind upload session {}: {:?}", id, err);
+            }
+            Err(err)
+        }
+    }
+}
+
+// Offset from which the client has to resume the upload.
+#[allow(clippy::too_many_arguments)]
+pub async fn offset(
+    Extension(pool): Extension<SqlitePool>,
+    authenticator: Extension<Arc<Authenticator>>,
+    auth_header: Option<TypedHeader<Authorization<Basic>>>,
+    cookie: Option<TypedHeader<Cookie>>,
+    certificate: Option<Extension<ClientCertificate>>,
+    user: AccessUser,
+    ClientAddress(client): ClientAddress,
+    Extension(state): Extension<Arc<UploadState>>,
+    Path(id): Path<String>,
+) -> Result<Response, Error> {
+    let (origin, _) = session_origin(
+        &state,
+        &authenticator,
+        auth_header,
+        cookie,
+        certificate,
+        &user,
+        client,
+    )
+    .await?;
+    let session = fetch_session(&pool, &id, &origin).await?;
+    Ok(offset_response(
+        session.received as u64,
+        Some(session.size as u64),
+    ))
+}
+
+fn offset_response(received: u64, size: Option<u64>) -> Response {
+    let mut headers = HeaderMap::new();
+    headers.insert(
+        HeaderName::from_static(UPLOAD_OFFSET),
+        HeaderValue::from(received),
+    );
+    if let Some(size) = size {
+        headers.insert(
+            HeaderName::from_static(UPLOAD_LENGTH),
+            HeaderValue::from(size),
+        );
+    }
+    (StatusCode::NO_CONTENT, headers).into_response()
+}
+
+// The sessions of other uploaders are reported as not found.
+async fn fetch_session(pool: &SqlitePool, id: &str, origin: &str) -> Result<SessionInfo, Error> {
+    with_timeout(
+        sqlx::query_as::<_, SessionInfo>(include_query!("get_upload_session"))
+            .bind(id)
+            .fetch_optional(pool),
+    )
+    .await?
+    .map_err(|_| UploadError::Database)?
+    .filter(|session| session.origin == origin)
+    .ok_or(UploadError::UploadSessionNotFound)
+}
+
+// The partial file is removed separately, it is still read once the session ends.
+async fn end_session(pool: &SqlitePool, state: &UploadState, id: &str) {
+    state.inflight.release(id);
+    if let Err(err) = sqlx::query(include_query!("delete_upload_session"))
+        .bind(id)
+        .execute(pool)
+        .await
+    {
+        tracing::error!(
+            "Cannot remove upload session {} from database: {:?}",
+            id,
+            err
+        );
+    }
+}
+
+// Only the "bytes start-end/total" form, with a known total size, is supported.
+fn parse_content_range(header: &str) -> Option<(u64, u64, u64)> {
+    let (range, total) = header.strip_prefix("bytes ")?.split_once('/')?;
+    let (start, end) = range.split_once('-')?;
+    let (start, end, total) = (
+        start.trim().parse::<u64>().ok()?,
+        end.trim().parse::<u64>().ok()?,
+        total.trim().parse::<u64>().ok()?,
+    );
+    (start <= end && end < total).then_some((start, end, total))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_content_range, ActiveSessions};
+
+    #[test]
+    fn content_range() {
+        assert_eq!(parse_content_range("bytes 0-99/200"), Some((0, 99, 200)));
+        assert_eq!(
+            parse_content_range("bytes 100-199/200"),
+            Some((100, 199, 200))
+        );
+        assert_eq!(parse_content_range("bytes 100-200/200"), None);
+        assert_eq!(parse_content_range("bytes 99-0/200"), None);
+        assert_eq!(parse_content_range("bytes 0-99/*"), None);
+        assert_eq!(parse_content_range("bytes */200"), None);
+        assert_eq!(parse_content_range("0-99/200"), None);
+    }
+
+    #[test]
+    fn one_chunk_at_a_time() {
+        let sessions = ActiveSessions::default();
+        let guard = sessions.acquire("id").unwrap();
+        assert!(sessions.acquire("id").is_none());
+        assert!(sessions.acquire("other").is_some());
+        drop(guard);
+        assert!(sessions.acquire("id").is_some());
+    }
+}
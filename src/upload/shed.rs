@@ -0,0 +1,82 @@
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+
+// Reject new uploads while the 1-minute load average of the host is above a threshold.
+#[derive(Clone, Debug)]
+pub struct LoadShedder {
+    threshold: Option<f64>,
+    shedding: Arc<AtomicBool>,
+}
+
+impl LoadShedder {
+    pub fn new(threshold: Option<f64>) -> Self {
+        Self {
+            threshold,
+            shedding: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    pub fn is_overloaded(&self) -> bool {
+        if self.threshold.is_none() {
+            return false;
+        }
+        match std::fs::read_to_string("/proc/loadavg")
+            .ok()
+            .as_deref()
+            .and_then(parse_load_average)
+        {
+            Some(load) => self.update(load),
+            // Never reject uploads if the load cannot be determined.
+            None => false,
+        }
+    }
+
+    fn update(&self, load: f64) -> bool {
+        let threshold = match self.threshold {
+            Some(threshold) => threshold,
+            None => return false,
+        };
+        let overloaded = load > threshold;
+        if self.shedding.swap(overloaded, Ordering::Relaxed) != overloaded {
+            if overloaded {
+                tracing::warn!(
+                    "Load average {:.2} above {:.2}, rejecting new uploads",
+                    load,
+                    threshold
+                );
+            } else {
+                tracing::info!("Load average back to {:.2}, accepting uploads again", load);
+            }
+        }
+        overloaded
+    }
+}
+
+fn parse_load_average(content: &str) -> Option<f64> {
+    content.split_whitespace().next()?.parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_load_average, LoadShedder};
+
+    #[test]
+    fn shedding() {
+        assert_eq!(
+            parse_load_average("0.52 0.58 0.59 1/467 12345\n"),
+            Some(0.52)
+        );
+        assert_eq!(parse_load_average(""), None);
+
+        let shedder = LoadShedder::new(Some(4.0));
+        assert!(!shedder.update(1.0));
+        assert!(shedder.update(4.5));
+        assert!(shedder.update(5.0));
+        assert!(!shedder.update(3.9));
+
+        assert!(!LoadShedder::new(None).update(100.0));
+        assert!(!LoadShedder::new(None).is_overloaded());
+    }
+}
@@ -0,0 +1,109 @@
+use std::{
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use futures::Stream;
+use hyper::body::Bytes;
+
+// Number of leading bytes of the uploads kept to detect their MIME type.
+#[derive(Copy, Clone, Debug)]
+pub struct SniffBytes(pub usize);
+
+// Copy the first bytes of the stream, which is left untouched, to detect its MIME type.
+pub struct Sniffer<S> {
+    inner: S,
+    sample: Vec<u8>,
+    limit: usize,
+}
+
+impl<S> Sniffer<S> {
+    pub fn new(inner: S, SniffBytes(limit): SniffBytes) -> Self {
+        Self {
+            inner,
+            sample: Vec::with_capacity(limit),
+            limit,
+        }
+    }
+
+    pub fn mime(&self) -> Option<String> {
+        infer::get(&self.sample).map(|kind| kind.mime_type().to_owned())
+    }
+}
+
+// Type declared by the client, only kept when it cannot make browsers run anything.
+pub fn declared_mime(declared: &str) -> Option<String> {
+    let safe = match declared {
+        "image/svg+xml" => false,
+        "text/plain" | "text/csv" | "application/json" | "application/pdf" => true,
+        _ => ["image/", "audio/", "video/"]
+            .iter()
+            .any(|prefix| declared.starts_with(prefix)),
+    };
+    safe.then(|| declared.to_owned())
+}
+
+impl<S, E> Stream for Sniffer<S>
+where
+    S: Stream<Item = Result<Bytes, E>> + Unpin,
+{
+    type Item = S::Item;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let polled = Pin::new(&mut self.inner).poll_next(cx);
+        if let Poll::Ready(Some(Ok(data))) = &polled {
+            let missing = self.limit - self.sample.len();
+            if missing > 0 {
+                let data = &data[..missing.min(data.len())];
+                self.sample.extend_from_slice(data);
+            }
+        }
+        polled
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::Infallible;
+
+    use futures::{stream, StreamExt};
+    use hyper::body::Bytes;
+
+    use super::{declared_mime, SniffBytes, Sniffer};
+
+    #[tokio::test]
+    async fn sniff() {
+        let png = b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR";
+        let mut sniffer = Sniffer::new(
+            stream::iter([
+                Ok::<_, Infallible>(Bytes::from_static(&png[..4])),
+                Ok(Bytes::from_static(&png[4..])),
+            ]),
+            SniffBytes(512),
+        );
+        let mut streamed = Vec::new();
+        while let Some(chunk) = sniffer.next().await {
+            streamed.extend_from_slice(&chunk.unwrap());
+        }
+        // Nothing is lost while sniffing.
+        assert_eq!(streamed, png);
+        assert_eq!(sniffer.mime().as_deref(), Some("image/png"));
+
+        // Not enough bytes sampled.
+        let mut sniffer = Sniffer::new(
+            stream::iter([Ok::<_, Infallible>(Bytes::from_static(png))]),
+            SniffBytes(2),
+        );
+        while sniffer.next().await.is_some() {}
+        assert_eq!(sniffer.mime(), None);
+    }
+
+    #[test]
+    fn declared() {
+        assert_eq!(declared_mime("text/plain").as_deref(), Some("text/plain"));
+        assert_eq!(declared_mime("video/mp4").as_deref(), Some("video/mp4"));
+        assert_eq!(declared_mime("text/html"), None);
+        assert_eq!(declared_mime("image/svg+xml"), None);
+        assert_eq!(declared_mime("application/octet-stream"), None);
+    }
+}
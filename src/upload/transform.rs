@@ -0,0 +1,211 @@
+use std::{
+    io::{self, ErrorKind},
+    pin::Pin,
+    process::Stdio,
+    sync::Arc,
+    task::{ready, Context, Poll},
+};
+
+use futures::Stream;
+use hyper::body::Bytes;
+use tokio::{
+    io::AsyncWrite,
+    process::{ChildStdin, Command},
+};
+use tokio_util::io::ReaderStream;
+
+use crate::{
+    error::{upload as UploadError, Error},
+    storage::FileWriter,
+    upload::{
+        sniff::{SniffBytes, Sniffer},
+        write_file, Stored, UploadRequest,
+    },
+};
+
+// External command the uploads are piped through before being stored.
+#[derive(Clone, Debug)]
+pub struct Transform(Option<Arc<String>>);
+
+impl Transform {
+    pub fn new(command: Option<String>) -> Self {
+        Self(command.map(Arc::new))
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.0.is_some()
+    }
+
+    // Store the body, or the output of the command if any. The output is limited to max_size.
+    pub(super) async fn store<S, E>(
+        &self,
+        req: &UploadRequest,
+        exact: bool,
+        body: S,
+        file: FileWriter,
+        max_size: u64,
+        sniff: SniffBytes,
+    ) -> Result<Stored, Error>
+    where
+        S: Stream<Item = Result<Bytes, E>> + Unpin,
+    {
+        let command = match &self.0 {
+            Some(command) => command,
+            None => {
+                let mut body = Sniffer::new(body, sniff);
+                let (size, hash) = write_file(req, exact, &mut body, file).await?;
+                return Ok(Stored {
+                    size,
+                    hash,
+                    mime: body.mime(),
+                });
+            }
+        };
+
+        let mut child = Command::new("sh")
+            .arg("-c")
+            .arg(command.as_str())
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()
+            .map_err(|err| {
+                tracing::error!("Cannot spawn upload transform command: {}", err);
+                UploadError::TransformFailed
+            })?;
+        let stdin = CommandInput {
+            stdin: child.stdin.take().ok_or(UploadError::TransformFailed)?,
+            closed: false,
+        };
+        let stdout = child.stdout.take().ok_or(UploadError::TransformFailed)?;
+
+        let output_req = UploadRequest {
+            filename: None,
+            size: max_size,
+            origin: req.origin.clone(),
+            username: None,
+        };
+        let mut output = Sniffer::new(ReaderStream::new(stdout), sniff);
+        let (_, (size, hash)) = tokio::try_join!(write_file(req, exact, body, stdin), async {
+            write_file(&output_req, false, &mut output, file)
+                .await
+                .map_err(|err| match err {
+                    Error::SizeMismatch => UploadError::OutputTooLarge(max_size),
+                    err => err,
+                })
+        })?;
+
+        let status = child
+            .wait()
+            .await
+            .map_err(|_| UploadError::TransformFailed)?;
+        if !status.success() {
+            tracing::warn!("Upload transform command exited with {}", status);
+            return Err(UploadError::TransformFailed);
+        }
+        Ok(Stored {
+            size,
+            hash,
+            mime: output.mime(),
+        })
+    }
+}
+
+// Commands may exit without reading their whole input (e.g. head), the rest of the body is then
+// discarded instead of failing the upload. Their exit status tells whether they succeeded.
+struct CommandInput {
+    stdin: ChildStdin,
+    closed: bool,
+}
+
+impl CommandInput {
+    fn ignore_closed<T>(&mut self, result: io::Result<T>, closed: T) -> io::Result<T> {
+        match result {
+            Err(err) if err.kind() == ErrorKind::BrokenPipe => {
+                self.closed = true;
+                Ok(closed)
+            }
+            result => result,
+        }
+    }
+}
+
+impl AsyncWrite for CommandInput {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        if self.closed {
+            return Poll::Ready(Ok(buf.len()));
+        }
+        let result = ready!(Pin::new(&mut self.stdin).poll_write(cx, buf));
+        Poll::Ready(self.ignore_closed(result, buf.len()))
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        if self.closed {
+            return Poll::Ready(Ok(()));
+        }
+        let result = ready!(Pin::new(&mut self.stdin).poll_flush(cx));
+        Poll::Ready(self.ignore_closed(result, ()))
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        if self.closed {
+            return Poll::Ready(Ok(()));
+        }
+        let result = ready!(Pin::new(&mut self.stdin).poll_shutdown(cx));
+        Poll::Ready(self.ignore_closed(result, ()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+
+    use futures::stream;
+    use hyper::body::Bytes;
+    use tokio::fs::{self, File};
+    use uuid::Uuid;
+
+    use super::Transform;
+    use crate::{
+        error::Error,
+        upload::{SniffBytes, UploadRequest},
+    };
+
+    #[tokio::test]
+    async fn partial_input() {
+        let path = std::env::temp_dir().join(Uuid::new_v4().as_hyphenated().to_string());
+        let store = |command: &str| {
+            let (path, command) = (path.clone(), command.to_owned());
+            async move {
+                // Far more than the pipe buffer, the command exits before reading all of it.
+                let chunks = (0..4).map(|_| Ok::<_, io::Error>(Bytes::from(vec![b'a'; 1 << 20])));
+                Transform::new(Some(command))
+                    .store(
+                        &UploadRequest {
+                            filename: None,
+                            size: 4 << 20,
+                            origin: "127.0.0.1".to_owned(),
+                            username: None,
+                        },
+                        true,
+                        stream::iter(chunks),
+                        Box::new(File::create(&path).await.unwrap()),
+                        1024,
+                        SniffBytes(0),
+                    )
+                    .await
+            }
+        };
+
+        let stored = store("head -c 4").await.unwrap();
+        assert_eq!(stored.size, 4);
+        assert_eq!(fs::read(&path).await.unwrap(), b"aaaa");
+        // Only the exit status of the command fails the upload.
+        assert!(matches!(store("exit 1").await, Err(Error::TransformFailed)));
+        fs::remove_file(&path).await.unwrap();
+    }
+}
@@ -0,0 +1,295 @@
+use std::{
+    sync::OnceLock,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use clap::ValueEnum;
+use reqwest::Client;
+use serde::Serialize;
+use tokio::task::JoinHandle;
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+const ATTEMPTS: u32 = 3;
+
+static WEBHOOK: OnceLock<Webhook> = OnceLock::new();
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum WebhookEvent {
+    Upload,
+    Download,
+    Expire,
+}
+
+struct Webhook {
+    client: Client,
+    url: String,
+    events: Vec<WebhookEvent>,
+    // Delay before the first retry, doubled for each of the next ones.
+    backoff: Duration,
+}
+
+#[derive(Serialize)]
+pub struct Notification {
+    pub event: WebhookEvent,
+    pub short_alias: String,
+    pub long_alias: String,
+    pub name: String,
+    pub size: u64,
+    pub origin: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expiration: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub downloads_remaining: Option<u64>,
+}
+
+// Called once at startup, a second call would silently keep notifying the first URL.
+pub fn init(url: String, events: Vec<WebhookEvent>) -> Result<(), String> {
+    set(&WEBHOOK, url, events)
+}
+
+fn set(cell: &OnceLock<Webhook>, url: String, events: Vec<WebhookEvent>) -> Result<(), String> {
+    let webhook = Webhook::new(url, events, Duration::from_secs(1))
+        .map_err(|err| format!("Cannot create webhook client: {}", err))?;
+    cell.set(webhook)
+        .map_err(|_| "Webhook already initialized".to_owned())
+}
+
+pub fn notify(notification: Notification) {
+    if let Some(webhook) = WEBHOOK.get() {
+        webhook.dispatch(notification);
+    }
+}
+
+impl Webhook {
+    fn new(
+        url: String,
+        events: Vec<WebhookEvent>,
+        backoff: Duration,
+    ) -> Result<Self, reqwest::Error> {
+        Ok(Self {
+            client: Client::builder().timeout(TIMEOUT).build()?,
+            url,
+            events,
+            backoff,
+        })
+    }
+
+    // Sent in the background, a slow or failing webhook never delays nor fails the request.
+    fn dispatch(&'static self, notification: Notification) -> Option<JoinHandle<bool>> {
+        if !self.events.contains(&notification.event) {
+            return None;
+        }
+        Some(tokio::spawn(self.deliver(notification)))
+    }
+
+    async fn deliver(&self, notification: Notification) -> bool {
+        #[derive(Serialize)]
+        struct Payload {
+            #[serde(flatten)]
+            notification: Notification,
+            timestamp: u64,
+        }
+        let payload = Payload {
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |d| d.as_secs()),
+            notification,
+        };
+
+        for attempt in 1..=ATTEMPTS {
+            let err = match self
+                .client
+                .post(&self.url)
+                .json(&payload)
+                .send()
+                .await
+                .and_then(|resp| resp.error_for_status())
+            {
+                Ok(_) => return true,
+                Err(err) => err,
+            };
+            tracing::warn!(
+                "Webhook notification failed (attempt {}/{}): {}",
+                attempt,
+                ATTEMPTS,
+                err
+            );
+            if attempt < ATTEMPTS {
+                tokio::time::sleep(self.backoff * attempt).await;
+            }
+        }
+        tracing::error!(
+            "Giving up webhook notification for file {}",
+            payload.notification.short_alias
+        );
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        convert::Infallible,
+        net::SocketAddr,
+        sync::{Arc, Mutex, OnceLock},
+        time::Duration,
+    };
+
+    use hyper::{
+        service::{make_service_fn, service_fn},
+        Body, Request, Response, Server, StatusCode,
+    };
+
+    use super::{set, Notification, Webhook, WebhookEvent};
+
+    fn notification(event: WebhookEvent) -> Notification {
+        Notification {
+            event,
+            short_alias: "aaaaaa".to_owned(),
+            long_alias: "boat-surface-soon".to_owned(),
+            name: "report.pdf".to_owned(),
+            size: 1000,
+            origin: "127.0.0.1".to_owned(),
+            expiration: None,
+            downloads_remaining: None,
+        }
+    }
+
+    // Receiver answering with the given statuses in turn, then 200. The payloads are recorded.
+    fn receiver(statuses: Vec<StatusCode>) -> (SocketAddr, Arc<Mutex<Vec<serde_json::Value>>>) {
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let statuses = Arc::new(Mutex::new(statuses.into_iter()));
+        let make_service = make_service_fn({
+            let received = Arc::clone(&received);
+            move |_| {
+                let (received, statuses) = (Arc::clone(&received), Arc::clone(&statuses));
+                async move {
+                    Ok::<_, Infallible>(service_fn(move |req: Request<Body>| {
+                        let (received, statuses) = (Arc::clone(&received), Arc::clone(&statuses));
+                        async move {
+                            let body = hyper::body::to_bytes(req.into_body()).await.unwrap();
+                            received
+                                .lock()
+                                .unwrap()
+                                .push(serde_json::from_slice(&body).unwrap());
+                            let status = statuses.lock().unwrap().next().unwrap_or(StatusCode::OK);
+                            let mut response = Response::new(Body::empty());
+                            *response.status_mut() = status;
+                            Ok::<_, Infallible>(response)
+                        }
+                    }))
+                }
+            }
+        });
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(Server::from_tcp(listener).unwrap().serve(make_service));
+        (address, received)
+    }
+
+    fn webhook(address: SocketAddr, events: Vec<WebhookEvent>) -> &'static Webhook {
+        Box::leak(Box::new(
+            Webhook::new(
+                format!("http://{}/hook", address),
+                events,
+                Duration::from_millis(10),
+            )
+            .unwrap(),
+        ))
+    }
+
+    #[test]
+    fn payload() {
+        let notification = Notification {
+            event: WebhookEvent::Upload,
+            short_alias: "aaaaaa".to_owned(),
+            long_alias: "boat-surface-soon".to_owned(),
+            name: "report.pdf".to_owned(),
+            size: 1000,
+            origin: "127.0.0.1".to_owned(),
+            expiration: Some(1_672_531_199),
+            downloads_remaining: None,
+        };
+        assert_eq!(
+            serde_json::to_value(&notification).unwrap(),
+            serde_json::json!({
+                "event": "upload",
+                "short_alias": "aaaaaa",
+                "long_alias": "boat-surface-soon",
+                "name": "report.pdf",
+                "size": 1000,
+                "origin": "127.0.0.1",
+                "expiration": 1_672_531_199,
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn delivery() {
+        let (address, received) = receiver(Vec::new());
+        let sender = webhook(address, vec![WebhookEvent::Upload]);
+        assert!(sender.deliver(notification(WebhookEvent::Upload)).await);
+
+        let received = received.lock().unwrap();
+        assert_eq!(received.len(), 1);
+        assert_eq!(received[0]["event"], "upload");
+        assert_eq!(received[0]["short_alias"], "aaaaaa");
+        assert!(received[0]["timestamp"].as_u64().unwrap() > 0);
+    }
+
+    #[tokio::test]
+    async fn retries() {
+        // Server errors are retried until an attempt succeeds.
+        let (address, received) = receiver(vec![
+            StatusCode::INTERNAL_SERVER_ERROR,
+            StatusCode::SERVICE_UNAVAILABLE,
+        ]);
+        let retried = webhook(address, vec![WebhookEvent::Upload]);
+        assert!(retried.deliver(notification(WebhookEvent::Upload)).await);
+        assert_eq!(received.lock().unwrap().len(), 3);
+
+        // Given up after the last attempt.
+        let (address, received) = receiver(vec![StatusCode::BAD_GATEWAY; 4]);
+        let failing = webhook(address, vec![WebhookEvent::Upload]);
+        assert!(!failing.deliver(notification(WebhookEvent::Upload)).await);
+        assert_eq!(received.lock().unwrap().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn events() {
+        let (address, received) = receiver(Vec::new());
+        let sender = webhook(address, vec![WebhookEvent::Upload, WebhookEvent::Expire]);
+        for event in [
+            WebhookEvent::Upload,
+            WebhookEvent::Download,
+            WebhookEvent::Expire,
+        ] {
+            if let Some(delivery) = sender.dispatch(notification(event)) {
+                assert!(delivery.await.unwrap());
+            }
+        }
+
+        // Only the subscribed events are sent.
+        assert_eq!(
+            received
+                .lock()
+                .unwrap()
+                .iter()
+                .map(|payload| payload["event"].as_str().unwrap().to_owned())
+                .collect::<Vec<_>>(),
+            ["upload", "expire"]
+        );
+    }
+
+    #[test]
+    fn single_init() {
+        let cell = OnceLock::new();
+        set(&cell, "http://127.0.0.1/first".to_owned(), Vec::new()).unwrap();
+        assert_eq!(
+            set(&cell, "http://127.0.0.1/second".to_owned(), Vec::new()),
+            Err("Webhook already initialized".to_owned())
+        );
+        assert_eq!(cell.get().unwrap().url, "http://127.0.0.1/first");
+    }
+}